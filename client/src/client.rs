@@ -0,0 +1,293 @@
+use reqwest::{
+    header::{HeaderValue, AUTHORIZATION, RETRY_AFTER},
+    Method, Response, Url,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    error::ClientError,
+    retry::{is_retryable_error, is_retryable_status, RetryPolicy},
+    sdk_request, sdk_response, UserId, WebhookId,
+};
+
+/// Client of the sdk authenticated with an api key
+#[derive(Clone)]
+pub struct SdkClient {
+    http: reqwest::Client,
+    base_url: Url,
+    authorization: HeaderValue,
+    retry: RetryPolicy,
+}
+
+impl SdkClient {
+    /// Client of the sdk served at the base url, e.g. `https://example.com/sdk/v0`
+    pub fn new(base_url: &str, api_key: &str) -> Result<Self, ClientError> {
+        // without the trailing slash the last segment is replaced by the routes
+        let base_url = match base_url.ends_with('/') {
+            true => base_url.to_string(),
+            false => format!("{base_url}/"),
+        };
+        let base_url = Url::parse(&base_url).map_err(|e| ClientError::InvalidUrl(e.to_string()))?;
+        let mut authorization = HeaderValue::from_str(&format!("x-api-key {api_key}"))
+            .map_err(|_| ClientError::InvalidApiKey)?;
+        authorization.set_sensitive(true);
+        Ok(SdkClient {
+            http: reqwest::Client::new(),
+            base_url,
+            authorization,
+            retry: RetryPolicy::default(),
+        })
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Use the http client, e.g. configured with timeouts or a proxy
+    pub fn with_http_client(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// Returns the user if it exists with all the information
+    pub async fn get_user(&self, id: &UserId) -> Result<sdk_response::User, ClientError> {
+        self.send_json(Method::GET, &format!("user/{id}"), None::<&()>)
+            .await
+    }
+
+    /// Create new user providing required attributes, returns its identifier
+    pub async fn create_user(
+        &self,
+        payload: &sdk_request::CreateUser,
+    ) -> Result<String, ClientError> {
+        self.send_json(Method::POST, "user", Some(payload)).await
+    }
+
+    /// Subscribe a new webhook to the events
+    ///
+    /// The response contains the secret used to sign deliveries, it is not returned again
+    pub async fn create_webhook(
+        &self,
+        payload: &sdk_request::CreateWebhook,
+    ) -> Result<sdk_response::CreatedWebhook, ClientError> {
+        self.send_json(Method::POST, "webhook", Some(payload)).await
+    }
+
+    /// Returns the webhooks owned by the api key user
+    pub async fn list_webhooks(&self) -> Result<Vec<sdk_response::Webhook>, ClientError> {
+        self.send_json(Method::GET, "webhook", None::<&()>).await
+    }
+
+    /// Returns the webhook if it exists and it is owned by the api key user
+    pub async fn get_webhook(&self, id: &WebhookId) -> Result<sdk_response::Webhook, ClientError> {
+        self.send_json(Method::GET, &format!("webhook/{id}"), None::<&()>)
+            .await
+    }
+
+    /// Update url, events or enabled flag of the webhook
+    pub async fn update_webhook(
+        &self,
+        id: &WebhookId,
+        payload: &sdk_request::UpdateWebhook,
+    ) -> Result<sdk_response::Webhook, ClientError> {
+        self.send_json(Method::PATCH, &format!("webhook/{id}"), Some(payload))
+            .await
+    }
+
+    /// Delete the webhook with its delivery history
+    pub async fn delete_webhook(&self, id: &WebhookId) -> Result<(), ClientError> {
+        self.send(Method::DELETE, &format!("webhook/{id}"), None::<&()>)
+            .await?;
+        Ok(())
+    }
+
+    /// Returns every delivery attempt made to the webhook
+    pub async fn list_webhook_deliveries(
+        &self,
+        id: &WebhookId,
+    ) -> Result<Vec<sdk_response::WebhookDelivery>, ClientError> {
+        self.send_json(Method::GET, &format!("webhook/{id}/delivery"), None::<&()>)
+            .await
+    }
+
+    /// Returns the requests of the api key owner by route and api key with the monthly quota
+    ///
+    /// The range defaults to the current month
+    pub async fn get_usage(
+        &self,
+        query: &sdk_request::UsageQuery,
+    ) -> Result<sdk_response::Usage, ClientError> {
+        let parameters: Vec<String> = [("from", query.from), ("to", query.to)]
+            .into_iter()
+            .filter_map(|(name, time)| time.map(|time| format!("{name}={time}")))
+            .collect();
+        let path = match parameters.is_empty() {
+            true => "usage".to_string(),
+            false => format!("usage?{}", parameters.join("&")),
+        };
+        self.send_json(Method::GET, &path, None::<&()>).await
+    }
+
+    async fn send_json<B: Serialize, T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&B>,
+    ) -> Result<T, ClientError> {
+        Ok(self.send(method, path, body).await?.json().await?)
+    }
+
+    /// Send the request retrying it according to the policy
+    async fn send<B: Serialize>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&B>,
+    ) -> Result<Response, ClientError> {
+        let url = self
+            .base_url
+            .join(path)
+            .map_err(|e| ClientError::InvalidUrl(e.to_string()))?;
+        let mut retry = 0;
+        loop {
+            let mut request = self
+                .http
+                .request(method.clone(), url.clone())
+                .header(AUTHORIZATION, self.authorization.clone());
+            if let Some(body) = body {
+                request = request.json(body);
+            }
+            let retryable = retry < self.retry.max_retries;
+            let delay = match request.send().await {
+                Ok(response) if response.status().is_success() => return Ok(response),
+                Ok(response) if retryable && is_retryable_status(&method, response.status()) => {
+                    retry_after(&response).map(|seconds| self.retry.retry_after(seconds))
+                }
+                Ok(response) => {
+                    let status = response.status();
+                    let body = response.text().await.unwrap_or_default();
+                    return Err(ClientError::from_response(status, &body));
+                }
+                Err(e) if retryable && is_retryable_error(&method, &e) => None,
+                Err(e) => return Err(e.into()),
+            };
+            tokio::time::sleep(delay.unwrap_or_else(|| self.retry.backoff(retry))).await;
+            retry += 1;
+        }
+    }
+}
+
+/// Seconds to wait requested by the server
+fn retry_after(response: &Response) -> Option<u64> {
+    response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use axum::{
+        extract::State,
+        http::{HeaderMap, StatusCode},
+        response::IntoResponse,
+        routing::{get, post},
+        Json, Router,
+    };
+    use serde_json::json;
+
+    use crate::{sdk_request, ClientError, RetryPolicy, Role, SdkClient, UserId};
+
+    /// Serve the router on a local port returning the base url of the sdk
+    async fn serve(router: Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        format!("http://{address}/sdk/v0")
+    }
+
+    fn retry_policy() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(10),
+        }
+    }
+
+    #[tokio::test]
+    async fn retry_idempotent_requests_test() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let record = |calls: &Arc<Mutex<Vec<&'static str>>>, method| {
+            let mut calls = calls.lock().unwrap();
+            calls.push(method);
+            calls.len()
+        };
+        let router = Router::new()
+            .route(
+                "/sdk/v0/user/:id",
+                get(
+                    move |State(calls): State<Arc<Mutex<Vec<&'static str>>>>,
+                          headers: HeaderMap| async move {
+                        assert_eq!(headers["authorization"], "x-api-key secret");
+                        match record(&calls, "GET") {
+                            1 => StatusCode::SERVICE_UNAVAILABLE.into_response(),
+                            _ => Json(json!({ "id": { "$oid": "65f0a1b2c3d4e5f601234567" }, "username": "john" }))
+                                .into_response(),
+                        }
+                    },
+                ),
+            )
+            .route(
+                "/sdk/v0/user",
+                post(
+                    move |State(calls): State<Arc<Mutex<Vec<&'static str>>>>| async move {
+                        record(&calls, "POST");
+                        StatusCode::SERVICE_UNAVAILABLE
+                    },
+                ),
+            )
+            .with_state(calls.clone());
+        let client = SdkClient::new(&serve(router).await, "secret")
+            .unwrap()
+            .with_retry_policy(retry_policy());
+
+        let user = client.get_user(&UserId::new()).await.unwrap();
+        assert_eq!(user.username, "john");
+
+        // a creation handled by the server is never sent again
+        let payload = sdk_request::CreateUser {
+            username: "john".into(),
+            password: "password".into(),
+            role: Role::User,
+        };
+        let error = client.create_user(&payload).await.unwrap_err();
+        assert!(matches!(error, ClientError::Server { status: 503, .. }));
+        assert_eq!(*calls.lock().unwrap(), vec!["GET", "GET", "POST"]);
+    }
+
+    #[tokio::test]
+    async fn error_response_test() {
+        let router = Router::new().route(
+            "/sdk/v0/user/:id",
+            get(|| async {
+                (
+                    StatusCode::NOT_FOUND,
+                    Json(json!({ "message": "User not found" })),
+                )
+            }),
+        );
+        let client = SdkClient::new(&serve(router).await, "secret")
+            .unwrap()
+            .with_retry_policy(retry_policy());
+
+        let error = client.get_user(&UserId::new()).await.unwrap_err();
+        assert!(matches!(error, ClientError::NotFound(message) if message == "User not found"));
+    }
+}
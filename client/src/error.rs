@@ -0,0 +1,60 @@
+use reqwest::StatusCode;
+use sandbox_rust_web_app_dtos::error::ErrorResponse;
+use thiserror::Error;
+
+/// Error of a request to the sdk
+///
+/// Error responses of the server carry the message of the error body.
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("Bad request: {0}")]
+    BadRequest(String),
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+    #[error("Not found: {0}")]
+    NotFound(String),
+    #[error("Too many requests: {0}")]
+    TooManyRequests(String),
+    #[error("Server error {status}: {message}")]
+    Server { status: u16, message: String },
+    #[error("Unexpected response {status}: {message}")]
+    Unexpected { status: u16, message: String },
+    #[error("Api key is not a valid header value")]
+    InvalidApiKey,
+    #[error("Invalid base url: {0}")]
+    InvalidUrl(String),
+    /// The request could not be sent or the response could not be read
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+}
+
+impl ClientError {
+    /// Map the status and the body of an error response
+    pub(crate) fn from_response(status: StatusCode, body: &str) -> Self {
+        let message = serde_json::from_str::<ErrorResponse>(body)
+            .map(|error| error.message)
+            .unwrap_or_else(|_| {
+                status
+                    .canonical_reason()
+                    .unwrap_or("Unknown error")
+                    .to_string()
+            });
+        match status {
+            StatusCode::BAD_REQUEST => ClientError::BadRequest(message),
+            StatusCode::UNAUTHORIZED => ClientError::Unauthorized(message),
+            StatusCode::FORBIDDEN => ClientError::Forbidden(message),
+            StatusCode::NOT_FOUND => ClientError::NotFound(message),
+            StatusCode::TOO_MANY_REQUESTS => ClientError::TooManyRequests(message),
+            status if status.is_server_error() => ClientError::Server {
+                status: status.as_u16(),
+                message,
+            },
+            status => ClientError::Unexpected {
+                status: status.as_u16(),
+                message,
+            },
+        }
+    }
+}
@@ -0,0 +1,20 @@
+//! Typed client of the sdk routes served under `/sdk/v0`.
+//!
+//! The client authenticates with an api key, of a user or of a service
+//! account, and exposes a method for every sdk route. Requests and responses
+//! are the dtos shared with the server, so the client cannot drift from the routes.
+//!
+//! Failed requests are retried according to the [`RetryPolicy`], error bodies
+//! are mapped back into [`ClientError`].
+
+mod client;
+mod error;
+mod retry;
+
+pub use client::SdkClient;
+pub use error::ClientError;
+pub use retry::RetryPolicy;
+
+// Re-export the dtos and the types used by the client methods
+pub use sandbox_rust_web_app_dtos::enums::{Role, WebhookEvent};
+pub use sandbox_rust_web_app_dtos::{sdk_request, sdk_response, UserId, WebhookId};
@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use rand::Rng;
+use reqwest::{Method, StatusCode};
+
+/// How failed requests are retried
+///
+/// Idempotent requests are retried when the server is unavailable or rate
+/// limited, after gateway errors and when the connection fails or times out.
+/// The others, e.g. creating entities, are retried only when the connection
+/// fails, so that they are never handled twice.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// retries after the first attempt
+    pub max_retries: u32,
+    /// delay before the first retry, it doubles at each retry
+    pub initial_backoff: Duration,
+    /// longest delay between two attempts, `Retry-After` included
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(200),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Policy sending every request once
+    pub fn none() -> Self {
+        RetryPolicy {
+            max_retries: 0,
+            ..Default::default()
+        }
+    }
+
+    /// Delay before the retry, the upper half is random to spread the clients
+    pub(crate) fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff);
+        let half = backoff / 2;
+        half + half.mul_f64(rand::thread_rng().gen::<f64>())
+    }
+
+    /// Delay requested by the server, bounded by the longest delay
+    pub(crate) fn retry_after(&self, seconds: u64) -> Duration {
+        Duration::from_secs(seconds).min(self.max_backoff)
+    }
+}
+
+/// Returns true if the request may be sent again after the response status
+pub(crate) fn is_retryable_status(method: &Method, status: StatusCode) -> bool {
+    method.is_idempotent()
+        && matches!(
+            status,
+            StatusCode::TOO_MANY_REQUESTS
+                | StatusCode::SERVICE_UNAVAILABLE
+                | StatusCode::BAD_GATEWAY
+                | StatusCode::GATEWAY_TIMEOUT
+        )
+}
+
+/// Returns true if the request may be sent again after the error
+pub(crate) fn is_retryable_error(method: &Method, error: &reqwest::Error) -> bool {
+    error.is_connect() || (error.is_timeout() && method.is_idempotent())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::RetryPolicy;
+
+    #[test]
+    fn backoff_test() {
+        let policy = RetryPolicy::default();
+        let first = policy.backoff(0);
+        assert!(first >= Duration::from_millis(100) && first <= Duration::from_millis(200));
+        let third = policy.backoff(2);
+        assert!(third >= Duration::from_millis(400) && third <= Duration::from_millis(800));
+        // delays are bounded
+        assert!(policy.backoff(30) <= policy.max_backoff);
+        assert_eq!(policy.retry_after(3600), policy.max_backoff);
+    }
+}
@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "openapi")]
+use utoipa::ToSchema;
+
+/// Enumeration with roles assigned to Users
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub enum Role {
+    /// Basic user
+    User,
+    /// Admin user
+    Admin,
+}
+
+/// Enumeration of events that can be notified to webhook subscriptions
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub enum WebhookEvent {
+    /// A new user has been created
+    #[serde(rename = "user.created")]
+    UserCreated,
+}
@@ -0,0 +1,25 @@
+//! Request and response dtos of the sdk shared by the server and the client.
+//!
+//! The server derives their api documentation with the `openapi` feature, the
+//! client only needs their serialization.
+
+use std::fmt;
+
+use bson::oid::ObjectId;
+
+pub mod enums;
+pub mod error;
+pub mod sdk_request;
+pub mod sdk_response;
+
+pub type UserId = ObjectId;
+pub type WebhookId = ObjectId;
+
+/// Debug value of the passwords and secrets, so that they are never logged
+struct Redacted;
+
+impl fmt::Debug for Redacted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[redacted]")
+    }
+}
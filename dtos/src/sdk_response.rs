@@ -0,0 +1,110 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "openapi")]
+use utoipa::ToSchema;
+
+use crate::{enums::WebhookEvent, Redacted, UserId, WebhookId};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+#[cfg_attr(feature = "openapi", schema(as = SdkUser))]
+pub struct User {
+    #[cfg_attr(feature = "openapi", schema(value_type = Id))]
+    pub id: UserId,
+    pub username: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct Webhook {
+    #[cfg_attr(feature = "openapi", schema(value_type = Id))]
+    pub id: WebhookId,
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    pub enabled: bool,
+    pub consecutive_failures: u32,
+}
+
+/// Webhook returned on creation, it is the only time the signing secret is shown
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct CreatedWebhook {
+    #[serde(flatten)]
+    pub webhook: Webhook,
+    pub secret: String,
+}
+
+impl fmt::Debug for CreatedWebhook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CreatedWebhook")
+            .field("webhook", &self.webhook)
+            .field("secret", &Redacted)
+            .finish()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct WebhookDelivery {
+    pub event_id: String,
+    pub event: WebhookEvent,
+    pub attempt: u32,
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+    pub success: bool,
+    /// attempt time as unix timestamp in milliseconds
+    pub created_at: i64,
+}
+
+/// Successful response of the OAuth2 token endpoint
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct AccessToken {
+    pub access_token: String,
+    pub token_type: String,
+    /// lifetime of the token in seconds
+    pub expires_in: u64,
+    pub scope: String,
+}
+
+impl fmt::Debug for AccessToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AccessToken")
+            .field("access_token", &Redacted)
+            .field("token_type", &self.token_type)
+            .field("expires_in", &self.expires_in)
+            .field("scope", &self.scope)
+            .finish()
+    }
+}
+
+/// Usage of the api key owner, times are unix timestamps in milliseconds
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct Usage {
+    pub from: i64,
+    pub to: i64,
+    /// requests of the current month, counted against the quota
+    pub month_requests: u64,
+    /// requests allowed per month by the plan, missing if they are unlimited
+    pub monthly_quota: Option<u64>,
+    pub routes: Vec<RouteUsage>,
+}
+
+/// Requests sent on a route with an api key
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(ToSchema))]
+pub struct RouteUsage {
+    /// method and path of the route, e.g. `GET /sdk/v0/user/:id`
+    pub route: String,
+    /// displayed prefix of the api key, missing for tokens
+    pub api_key: Option<String>,
+    pub requests: u64,
+    /// requests answered with a 4xx status
+    pub client_errors: u64,
+    /// requests answered with a 5xx status
+    pub server_errors: u64,
+    pub average_latency_ms: f64,
+    pub max_latency_ms: u64,
+}
@@ -1,7 +1,9 @@
+use std::net::{IpAddr, SocketAddr};
+
 use axum::{
     async_trait,
-    extract::FromRequestParts,
-    http::{request::Parts, HeaderValue},
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header::USER_AGENT, request::Parts, HeaderValue},
     RequestPartsExt,
 };
 use axum_extra::{
@@ -11,40 +13,417 @@ use axum_extra::{
     },
     TypedHeader,
 };
-use jsonwebtoken::{decode, encode, Header, Validation};
+use jsonwebtoken::{
+    decode, decode_header, encode, errors::ErrorKind, get_current_timestamp, Algorithm, Header,
+    Validation,
+};
 
-use mongodb::bson::doc;
-use serde::{Deserialize, Serialize};
+use mongodb::bson::{doc, serde_helpers::serialize_object_id_as_hex_string};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::json;
 
 use crate::{
+    enums::{AuditAction, Role, Scope, TargetKind},
     error::{AppError, AuthError},
-    model::user::User,
+    model::{audit_event::AuditActor, service_account::ServiceAccount, user::User},
     service::{
+        audit::{self, AuditRecord},
         db::{get_database_service, DatabaseDocument},
         environment::ENVIRONMENT,
+        service_account, session,
+        signing_key::{get_keyring, is_asymmetric},
     },
-    UserId,
+    InvitationId, ServiceAccountId, SessionId, UserId,
 };
 
 /// Trait for auth info objects that need to return specific information
 pub trait AuthInfo {
     fn user_id(&self) -> &UserId;
+
+    /// Session of the login, if the credentials come from one
+    fn session_id(&self) -> Option<&SessionId> {
+        None
+    }
+
+    /// Admin impersonating the user, if any
+    fn actor(&self) -> Option<&ActorClaim> {
+        None
+    }
+
+    /// Service account making the request, `user_id` is its identifier
+    fn service_account(&self) -> Option<&ServiceAccountPrincipal> {
+        None
+    }
+}
+
+/// Auth info can be borrowed by access control and still be used afterwards
+impl<T: AuthInfo> AuthInfo for &T {
+    fn user_id(&self) -> &UserId {
+        (*self).user_id()
+    }
+
+    fn session_id(&self) -> Option<&SessionId> {
+        (*self).session_id()
+    }
+
+    fn actor(&self) -> Option<&ActorClaim> {
+        (*self).actor()
+    }
+
+    fn service_account(&self) -> Option<&ServiceAccountPrincipal> {
+        (*self).service_account()
+    }
+}
+
+/// Recipient of a jwt token written in the `aud` claim
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenAudience {
+    /// tokens used by the web application routes
+    WebApp,
+    /// tokens returned by the password check when the second factor is required,
+    /// they can only complete the login
+    MfaChallenge,
+    /// tokens sent by email to accept an invitation, they can only create the user
+    Invitation,
+    /// tokens of the internal services, issued to service accounts by the client
+    /// credentials grant, they can only be used on the sdk routes
+    Service,
+}
+
+impl TokenAudience {
+    fn value(&self) -> &'static str {
+        match self {
+            TokenAudience::WebApp => &ENVIRONMENT.authentication.web_app_audience,
+            TokenAudience::MfaChallenge => "mfa-challenge",
+            TokenAudience::Invitation => "invitation",
+            TokenAudience::Service => &ENVIRONMENT.authentication.service_audience,
+        }
+    }
+
+    fn lifetime(&self) -> usize {
+        match self {
+            TokenAudience::MfaChallenge => ENVIRONMENT.mfa.challenge_lifetime.as_secs() as usize,
+            TokenAudience::Invitation => ENVIRONMENT.invitation.ttl.as_secs() as usize,
+            TokenAudience::Service => ENVIRONMENT
+                .authentication
+                .client_credentials_lifetime
+                .as_secs() as usize,
+            _ => ENVIRONMENT.authentication.token_lifetime.as_secs() as usize,
+        }
+    }
 }
 
 /// Struct containing information that will be encoded inside the jwt token
 #[derive(Debug, Serialize, Deserialize)]
 pub struct JWTAuthClaim {
     pub exp: usize,
+    pub iat: usize,
+    pub nbf: usize,
+    pub iss: String,
+    pub aud: String,
     pub user_id: UserId,
     pub username: String,
+    /// session of the login, missing in tokens that do not start one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sid: Option<SessionId>,
+    /// admin impersonating the user, set only in impersonation tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub act: Option<ActorClaim>,
+}
+
+/// Actor of a token issued to someone acting on behalf of its user as
+/// described by RFC 8693
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ActorClaim {
+    pub sub: UserId,
+    pub username: String,
 }
 
 impl JWTAuthClaim {
-    pub fn build_token(&self, header: &Header) -> Result<String, AuthError> {
-        let token = encode(header, &self, &ENVIRONMENT.authentication.jwt_encoding)
-            .map_err(|_| AuthError::TokenCreation)?;
-        Ok(token)
+    /// Create a claim valid from now for the lifetime configured for the audience
+    pub fn new(user_id: UserId, username: String, audience: TokenAudience) -> Self {
+        let now = get_current_timestamp() as usize;
+        JWTAuthClaim {
+            exp: now + audience.lifetime(),
+            iat: now,
+            nbf: now,
+            iss: ENVIRONMENT.authentication.issuer.clone(),
+            aud: audience.value().into(),
+            user_id,
+            username,
+            sid: None,
+            act: None,
+        }
     }
+
+    /// Create a web app claim of the user for the admin impersonating it
+    ///
+    /// It is valid for the impersonation lifetime and it does not belong to any session
+    pub fn impersonation(user_id: UserId, username: String, actor: ActorClaim) -> Self {
+        let mut claim = Self::new(user_id, username, TokenAudience::WebApp);
+        claim.exp =
+            claim.iat + ENVIRONMENT.authentication.impersonation_lifetime.as_secs() as usize;
+        claim.act = Some(actor);
+        claim
+    }
+
+    /// Sign the claim with the configured algorithm
+    pub async fn build_token(&self) -> Result<String, AuthError> {
+        encode_claims(self).await
+    }
+
+    /// Verify the token signature and its claims according to the validation policy
+    /// returning the claim
+    pub async fn decode_token(token: &str, audience: TokenAudience) -> Result<Self, AuthError> {
+        decode_claims(token, audience).await
+    }
+}
+
+/// Claims of the token sent by email to accept an invitation
+///
+/// The nonce must match the one of the invitation, hence, resending or
+/// cancelling the invitation invalidates the previous tokens
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InvitationClaim {
+    pub exp: usize,
+    pub iat: usize,
+    pub nbf: usize,
+    pub iss: String,
+    pub aud: String,
+    pub invitation_id: InvitationId,
+    pub nonce: String,
+}
+
+impl InvitationClaim {
+    pub fn new(invitation_id: InvitationId, nonce: String) -> Self {
+        let now = get_current_timestamp() as usize;
+        let audience = TokenAudience::Invitation;
+        InvitationClaim {
+            exp: now + audience.lifetime(),
+            iat: now,
+            nbf: now,
+            iss: ENVIRONMENT.authentication.issuer.clone(),
+            aud: audience.value().into(),
+            invitation_id,
+            nonce,
+        }
+    }
+
+    pub async fn build_token(&self) -> Result<String, AuthError> {
+        encode_claims(self).await
+    }
+
+    pub async fn decode_token(token: &str) -> Result<Self, AuthError> {
+        decode_claims(token, TokenAudience::Invitation).await
+    }
+}
+
+/// Claims of the token issued to a service account by the client credentials grant
+///
+/// The scopes are the ones granted when the token was issued, the account is
+/// loaded again on every request so that disabling it refuses its tokens
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ServiceAccountClaim {
+    pub exp: usize,
+    pub iat: usize,
+    pub nbf: usize,
+    pub iss: String,
+    pub aud: String,
+    /// the registered `sub` claim is a string, the hex identifier of the account
+    #[serde(serialize_with = "serialize_object_id_as_hex_string")]
+    pub sub: ServiceAccountId,
+    /// space separated list of the granted scopes
+    pub scope: String,
+}
+
+impl ServiceAccountClaim {
+    pub fn new(service_account_id: ServiceAccountId, scopes: &[Scope]) -> Self {
+        let now = get_current_timestamp() as usize;
+        let audience = TokenAudience::Service;
+        ServiceAccountClaim {
+            exp: now + audience.lifetime(),
+            iat: now,
+            nbf: now,
+            iss: ENVIRONMENT.authentication.issuer.clone(),
+            aud: audience.value().into(),
+            sub: service_account_id,
+            scope: Scope::format_list(scopes),
+        }
+    }
+
+    pub async fn build_token(&self) -> Result<String, AuthError> {
+        encode_claims(self).await
+    }
+
+    pub async fn decode_token(token: &str) -> Result<Self, AuthError> {
+        decode_claims(token, TokenAudience::Service).await
+    }
+}
+
+/// Claims signed in our tokens
+trait RegisteredClaims: Serialize + DeserializeOwned {
+    fn iat(&self) -> usize;
+}
+
+impl RegisteredClaims for JWTAuthClaim {
+    fn iat(&self) -> usize {
+        self.iat
+    }
+}
+
+impl RegisteredClaims for InvitationClaim {
+    fn iat(&self) -> usize {
+        self.iat
+    }
+}
+
+impl RegisteredClaims for ServiceAccountClaim {
+    fn iat(&self) -> usize {
+        self.iat
+    }
+}
+
+/// Sign the claims with the configured algorithm
+///
+/// Asymmetric keys write their identifier in the `kid` header
+async fn encode_claims<T: RegisteredClaims>(claims: &T) -> Result<String, AuthError> {
+    let algorithm = ENVIRONMENT.authentication.algorithm;
+    let token = if is_asymmetric(algorithm) {
+        let keyring = get_keyring().await.map_err(|e| {
+            tracing::error!("Cannot load signing keys {:?}", e);
+            AuthError::TokenCreation
+        })?;
+        let keys = keyring.keys();
+        let signing = keys.signing.as_ref().ok_or(AuthError::TokenCreation)?;
+        let mut header = Header::new(signing.algorithm);
+        header.kid = Some(signing.kid.clone());
+        encode(&header, claims, &signing.key)
+    } else {
+        let keys = ENVIRONMENT
+            .authentication
+            .keys()
+            .ok_or(AuthError::TokenCreation)?;
+        encode(&Header::new(algorithm), claims, &keys.encoding)
+    }
+    .map_err(|_| AuthError::TokenCreation)?;
+    Ok(token)
+}
+
+/// Verify the token signature and its claims according to the validation policy
+///
+/// The header algorithm must be one of the accepted algorithms, the issuer
+/// must be ours and the audience must be the requested one.
+async fn decode_claims<T: RegisteredClaims>(
+    token: &str,
+    audience: TokenAudience,
+) -> Result<T, AuthError> {
+    let header = decode_header(token).map_err(|_| AuthError::InvalidToken)?;
+    if !ENVIRONMENT
+        .authentication
+        .accepted_algorithms
+        .contains(&header.alg)
+    {
+        return Err(AuthError::InvalidToken);
+    }
+    let validation = validation_policy(header.alg, audience);
+
+    let token_data = if is_asymmetric(header.alg) {
+        // asymmetric keys are looked up by the identifier in the header
+        let kid = header.kid.ok_or(AuthError::InvalidToken)?;
+        let keyring = get_keyring().await.map_err(|e| {
+            tracing::error!("Cannot load signing keys {:?}", e);
+            AuthError::InvalidToken
+        })?;
+        let keys = keyring.keys();
+        let (key_algorithm, key) = keys.verifying.get(&kid).ok_or(AuthError::InvalidToken)?;
+        if *key_algorithm != header.alg {
+            return Err(AuthError::InvalidToken);
+        }
+        decode::<T>(token, key, &validation)
+    } else {
+        // tokens signed before a secret rotation are verified with the previous key
+        let keys = ENVIRONMENT
+            .authentication
+            .keys()
+            .ok_or(AuthError::InvalidToken)?;
+        decode::<T>(token, &keys.decoding, &validation).or_else(|e| {
+            match (&keys.previous_decoding, e.kind()) {
+                (Some(previous), ErrorKind::InvalidSignature) => {
+                    decode::<T>(token, previous, &validation)
+                }
+                _ => Err(e),
+            }
+        })
+    }
+    .map_err(|e| {
+        tracing::error!("Got error {}", e);
+        AuthError::InvalidToken
+    })?;
+
+    // jsonwebtoken does not validate `iat`, tokens cannot be issued in the future
+    let leeway = ENVIRONMENT.authentication.leeway.as_secs() as usize;
+    if token_data.claims.iat() > get_current_timestamp() as usize + leeway {
+        return Err(AuthError::InvalidToken);
+    }
+    Ok(token_data.claims)
+}
+
+impl JWTAuthClaim {
+    /// Refuse tokens of deleted users, tokens issued before the user revoked them,
+    /// tokens of revoked sessions and impersonation tokens of former admins
+    async fn check_revocation(&self) -> Result<(), AppError> {
+        let db = &get_database_service().await.db();
+        let collection = db.collection::<User>(User::collection_name());
+        let user = collection
+            .find_one(doc! { "_id": self.user_id }, None)
+            .await?
+            .ok_or(AuthError::InvalidToken)?;
+        match user.tokens_valid_after {
+            Some(valid_after) if (self.iat as i64) * 1000 < valid_after.timestamp_millis() => {
+                return Err(AuthError::InvalidToken)?;
+            }
+            _ => {}
+        }
+        if let Some(session_id) = &self.sid {
+            if !session::touch(session_id, &self.user_id).await? {
+                return Err(AuthError::InvalidToken)?;
+            }
+        }
+        if let Some(actor) = &self.act {
+            let actor = collection
+                .find_one(doc! { "_id": actor.sub }, None)
+                .await?
+                .ok_or(AuthError::InvalidToken)?;
+            if actor.role != Role::Admin {
+                return Err(AuthError::InvalidToken)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Audit the request made by an admin impersonating the user
+    async fn audit_impersonation(&self, parts: &Parts) {
+        if self.act.is_some() {
+            audit::record(
+                AuditRecord::new(AuditActor::of(self), AuditAction::ImpersonatedRequest)
+                    .target(TargetKind::User, self.user_id)
+                    .after(json!({ "method": parts.method.as_str(), "path": parts.uri.path() })),
+            )
+            .await;
+        }
+    }
+}
+
+/// Build the validation requiring every registered claim we issue
+fn validation_policy(algorithm: Algorithm, audience: TokenAudience) -> Validation {
+    let mut validation = Validation::new(algorithm);
+    validation.leeway = ENVIRONMENT.authentication.leeway.as_secs();
+    validation.validate_exp = true;
+    validation.validate_nbf = true;
+    validation.set_issuer(&[&ENVIRONMENT.authentication.issuer]);
+    validation.set_audience(&[audience.value()]);
+    validation.set_required_spec_claims(&["exp", "nbf", "iss", "aud"]);
+    validation
 }
 
 #[async_trait]
@@ -60,19 +439,11 @@ where
             .extract::<TypedHeader<Authorization<Bearer>>>()
             .await
             .map_err(|_| AuthError::InvalidToken)?;
-        tracing::debug!("Got bearer token {}", bearer.token());
         // Decode the user data
-        let token_data = decode::<JWTAuthClaim>(
-            bearer.token(),
-            &ENVIRONMENT.authentication.jwt_decoding,
-            &Validation::default(),
-        )
-        .map_err(|e| {
-            tracing::error!("Got error {}", e);
-            AuthError::InvalidToken
-        })?;
-
-        Ok(token_data.claims)
+        let claim = JWTAuthClaim::decode_token(bearer.token(), TokenAudience::WebApp).await?;
+        claim.check_revocation().await?;
+        claim.audit_impersonation(parts).await;
+        Ok(claim)
     }
 }
 
@@ -81,13 +452,178 @@ impl AuthInfo for JWTAuthClaim {
     fn user_id(&self) -> &UserId {
         &self.user_id
     }
+
+    fn session_id(&self) -> Option<&SessionId> {
+        self.sid.as_ref()
+    }
+
+    fn actor(&self) -> Option<&ActorClaim> {
+        self.act.as_ref()
+    }
 }
 
-/// Struct containing api key authentication
-#[derive(Debug, Serialize, Deserialize)]
+/// Claim of the tokens allowed to enroll multi-factor authentication
+///
+/// Besides web app tokens, it accepts the challenge token of users that must
+/// enroll before completing their first login with a second factor
+pub struct MfaEnrollmentClaim {
+    pub claim: JWTAuthClaim,
+    /// true if the claim comes from a challenge token
+    pub challenge: bool,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for MfaEnrollmentClaim
+where
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let TypedHeader(Authorization(bearer)) = parts
+            .extract::<TypedHeader<Authorization<Bearer>>>()
+            .await
+            .map_err(|_| AuthError::InvalidToken)?;
+        if let Ok(claim) = JWTAuthClaim::decode_token(bearer.token(), TokenAudience::WebApp).await {
+            claim.check_revocation().await?;
+            claim.audit_impersonation(parts).await;
+            return Ok(MfaEnrollmentClaim {
+                claim,
+                challenge: false,
+            });
+        }
+        let claim = JWTAuthClaim::decode_token(bearer.token(), TokenAudience::MfaChallenge).await?;
+        Ok(MfaEnrollmentClaim {
+            claim,
+            challenge: true,
+        })
+    }
+}
+
+#[async_trait]
+impl AuthInfo for MfaEnrollmentClaim {
+    fn user_id(&self) -> &UserId {
+        &self.claim.user_id
+    }
+
+    fn actor(&self) -> Option<&ActorClaim> {
+        self.claim.act.as_ref()
+    }
+}
+
+/// Address of the client making the request
+///
+/// Behind a trusted proxy it is the last address of `X-Forwarded-For`, the one
+/// added by the proxy itself. It is missing if the server is not started with
+/// connection information.
+pub struct ClientIp(pub Option<IpAddr>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if ENVIRONMENT.server.trust_forwarded_for {
+            let forwarded = parts
+                .headers
+                .get_all("x-forwarded-for")
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .last()
+                .and_then(|address| address.trim().parse().ok());
+            if forwarded.is_some() {
+                return Ok(ClientIp(forwarded));
+            }
+        }
+        Ok(ClientIp(
+            parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(address)| address.ip()),
+        ))
+    }
+}
+
+/// Longest user agent recorded, longer values are truncated
+const MAX_USER_AGENT_LENGTH: usize = 512;
+
+/// Address and user agent of the client, they are recorded by logins and sessions
+pub struct ClientInfo {
+    pub ip: Option<IpAddr>,
+    pub user_agent: Option<String>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientInfo
+where
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let ClientIp(ip) = ClientIp::from_request_parts(parts, state).await?;
+        let user_agent = parts
+            .headers
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.chars().take(MAX_USER_AGENT_LENGTH).collect());
+        Ok(ClientInfo { ip, user_agent })
+    }
+}
+
+/// Struct containing the authentication of the sdk routes
+///
+/// The principal is either a user with its api key or a service account with
+/// one of its api keys or a token issued by the client credentials grant.
+#[derive(Debug, Clone)]
 pub struct APIKeyAuthClaim {
-    pub key: String,
+    /// identifier of the user or of the service account
     pub user_id: UserId,
+    pub service_account: Option<ServiceAccountPrincipal>,
+    /// rate limit plan of the principal
+    pub plan: Option<String>,
+    /// displayed prefix of the api key, `None` with tokens
+    pub key_prefix: Option<String>,
+}
+
+/// Service account authenticated on the sdk routes
+#[derive(Debug, Clone)]
+pub struct ServiceAccountPrincipal {
+    pub id: ServiceAccountId,
+    pub role: Role,
+    /// scopes of the account, restricted to the granted ones with tokens
+    pub scopes: Vec<Scope>,
+}
+
+impl APIKeyAuthClaim {
+    fn from_service_account(
+        service_account: ServiceAccount,
+        granted: Option<&[Scope]>,
+        key: Option<&str>,
+    ) -> Self {
+        let id = service_account
+            .id
+            .expect("Service account id must be not missing since it comes from a db query");
+        let scopes = service_account
+            .scopes
+            .into_iter()
+            .filter(|scope| granted.is_none_or(|granted| granted.contains(scope)))
+            .collect();
+        APIKeyAuthClaim {
+            user_id: id,
+            plan: service_account.plan,
+            key_prefix: key.map(service_account::displayed_prefix),
+            service_account: Some(ServiceAccountPrincipal {
+                id,
+                role: service_account.role,
+                scopes,
+            }),
+        }
+    }
 }
 
 #[async_trait]
@@ -98,12 +634,43 @@ where
     type Rejection = AppError;
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        // already authenticated by the usage metering of the route
+        if let Some(claim) = parts.extensions.remove::<APIKeyAuthClaim>() {
+            return Ok(claim);
+        }
+        // tokens of the client credentials grant
+        if let Ok(TypedHeader(Authorization(bearer))) =
+            parts.extract::<TypedHeader<Authorization<Bearer>>>().await
+        {
+            let claim = ServiceAccountClaim::decode_token(bearer.token()).await?;
+            let granted = Scope::parse_list(&claim.scope).ok_or(AuthError::InvalidToken)?;
+            let service_account = service_account::find_enabled(&claim.sub)
+                .await?
+                .ok_or(AuthError::InvalidToken)?;
+            return Ok(APIKeyAuthClaim::from_service_account(
+                service_account,
+                Some(&granted),
+                None,
+            ));
+        }
+
         let TypedHeader(Authorization(api_key)) = parts
             .extract::<TypedHeader<Authorization<ApiKey>>>()
             .await
             .map_err(|_| AuthError::InvalidToken)?;
 
-        let db = &get_database_service().await.db;
+        if service_account::is_service_account_key(api_key.key()) {
+            return match service_account::authenticate_key(api_key.key()).await? {
+                Some(service_account) => Ok(APIKeyAuthClaim::from_service_account(
+                    service_account,
+                    None,
+                    Some(api_key.key()),
+                )),
+                None => Err(AppError::AuthorizationError(AuthError::InvalidApiKey)),
+            };
+        }
+
+        let db = &get_database_service().await.db();
         let collection = db.collection::<User>(User::collection_name());
         let filter = doc! { "api_key": api_key.key() };
         let query_result = collection.find_one(filter, None).await?;
@@ -112,7 +679,9 @@ where
                 user_id: user_document
                     .id
                     .expect("User id must be not missing since we have an api key"),
-                key: api_key.key().into(),
+                service_account: None,
+                plan: user_document.plan,
+                key_prefix: Some(service_account::displayed_prefix(api_key.key())),
             };
 
             Ok(auth_data)
@@ -127,6 +696,10 @@ impl AuthInfo for APIKeyAuthClaim {
     fn user_id(&self) -> &UserId {
         &self.user_id
     }
+
+    fn service_account(&self) -> Option<&ServiceAccountPrincipal> {
+        self.service_account.as_ref()
+    }
 }
 
 #[derive(Clone, PartialEq, Debug)]
@@ -156,3 +729,112 @@ impl Credentials for ApiKey {
         HeaderValue::from_str(&self.0).unwrap()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::{encode, EncodingKey, Header};
+
+    use crate::{enums::Scope, service::environment::ENVIRONMENT, SessionId, UserId};
+
+    use super::{ActorClaim, InvitationClaim, JWTAuthClaim, ServiceAccountClaim, TokenAudience};
+
+    #[tokio::test]
+    async fn token_validation_policy_test() {
+        let user_id = UserId::new();
+        let mut claim = JWTAuthClaim::new(user_id, "John".into(), TokenAudience::WebApp);
+        claim.sid = Some(SessionId::new());
+        let token = claim.build_token().await.unwrap();
+
+        let decoded = JWTAuthClaim::decode_token(&token, TokenAudience::WebApp)
+            .await
+            .unwrap();
+        assert_eq!(decoded.user_id, user_id);
+        assert_eq!(decoded.sid, claim.sid);
+        assert_eq!(decoded.iss, ENVIRONMENT.authentication.issuer);
+
+        // a web app token cannot be used by services
+        assert!(JWTAuthClaim::decode_token(&token, TokenAudience::Service)
+            .await
+            .is_err());
+
+        // a challenge token cannot be used before completing the second factor
+        let challenge = JWTAuthClaim::new(user_id, "John".into(), TokenAudience::MfaChallenge);
+        assert!(challenge.exp < claim.exp);
+        let token = challenge.build_token().await.unwrap();
+        assert!(JWTAuthClaim::decode_token(&token, TokenAudience::WebApp)
+            .await
+            .is_err());
+
+        // impersonation tokens carry the actor and are shorter
+        let actor = ActorClaim {
+            sub: UserId::new(),
+            username: "admin".into(),
+        };
+        let impersonation = JWTAuthClaim::impersonation(user_id, "John".into(), actor.clone());
+        assert!(impersonation.exp < claim.exp);
+        let token = impersonation.build_token().await.unwrap();
+        let decoded = JWTAuthClaim::decode_token(&token, TokenAudience::WebApp)
+            .await
+            .unwrap();
+        assert_eq!(decoded.act, Some(actor));
+        assert_eq!(decoded.sid, None);
+
+        // an invitation token cannot be used to log in
+        let invitation = InvitationClaim::new(UserId::new(), "nonce".into());
+        let token = invitation.build_token().await.unwrap();
+        assert_eq!(
+            InvitationClaim::decode_token(&token).await.unwrap().nonce,
+            "nonce"
+        );
+        assert!(JWTAuthClaim::decode_token(&token, TokenAudience::WebApp)
+            .await
+            .is_err());
+
+        // a service account token is only valid on the sdk routes
+        let service = ServiceAccountClaim::new(UserId::new(), &[Scope::WebhooksRead]);
+        let token = service.build_token().await.unwrap();
+        let decoded = ServiceAccountClaim::decode_token(&token).await.unwrap();
+        assert_eq!(decoded.sub, service.sub);
+        assert_eq!(decoded.scope, "webhooks:read");
+        assert_eq!(decoded.aud, ENVIRONMENT.authentication.service_audience);
+        assert!(JWTAuthClaim::decode_token(&token, TokenAudience::WebApp)
+            .await
+            .is_err());
+        let token = claim.build_token().await.unwrap();
+        assert!(ServiceAccountClaim::decode_token(&token).await.is_err());
+
+        // issuer of another environment
+        let mut other = JWTAuthClaim::new(user_id, "John".into(), TokenAudience::WebApp);
+        other.iss = "sandbox-rust-web-app/other".into();
+        let token = other.build_token().await.unwrap();
+        assert!(JWTAuthClaim::decode_token(&token, TokenAudience::WebApp)
+            .await
+            .is_err());
+
+        // expired and not yet valid tokens
+        let mut expired = JWTAuthClaim::new(user_id, "John".into(), TokenAudience::WebApp);
+        expired.exp = expired.iat - 10;
+        let token = expired.build_token().await.unwrap();
+        assert!(JWTAuthClaim::decode_token(&token, TokenAudience::WebApp)
+            .await
+            .is_err());
+        let mut future = JWTAuthClaim::new(user_id, "John".into(), TokenAudience::WebApp);
+        future.nbf += 100;
+        future.iat += 100;
+        let token = future.build_token().await.unwrap();
+        assert!(JWTAuthClaim::decode_token(&token, TokenAudience::WebApp)
+            .await
+            .is_err());
+
+        // algorithm not accepted
+        let token = encode(
+            &Header::new(jsonwebtoken::Algorithm::HS512),
+            &claim,
+            &EncodingKey::from_secret(b"testing_secret"),
+        )
+        .unwrap();
+        assert!(JWTAuthClaim::decode_token(&token, TokenAudience::WebApp)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,26 @@
+//! Utility to produce values for the local encrypted secrets file.
+//!
+//! Usage: `SECRETS_KEY=<hex key> encrypt_secret <NAME> <VALUE>`
+//!
+//! It prints the json entry to add to the file configured in `secrets.file`.
+
+use sandbox_rust_web_app::service::environment::secret::{encrypt_secret, SECRETS_KEY};
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let (Some(name), Some(value)) = (args.next(), args.next()) else {
+        eprintln!("Usage: {SECRETS_KEY}=<hex key> encrypt_secret <NAME> <VALUE>");
+        std::process::exit(1);
+    };
+    let key = std::env::var(SECRETS_KEY).unwrap_or_else(|_| {
+        eprintln!("{SECRETS_KEY} must be set");
+        std::process::exit(1);
+    });
+    match encrypt_secret(&key, &value) {
+        Ok(encrypted) => println!("{}", serde_json::json!({ name: encrypted })),
+        Err(e) => {
+            eprintln!("{e:#}");
+            std::process::exit(1);
+        }
+    }
+}
@@ -4,18 +4,42 @@
 //! Information is shared by `requests` and `responses` and they are specific for each
 //! route, therefore, we have SDK requests and responses and Web app requests and responses.
 
+use std::fmt;
+
 use axum::{
     extract::FromRequest,
     response::{IntoResponse, Response},
 };
+use utoipa::ToSchema;
 
 use crate::error::AppError;
 
-pub mod sdk_request;
-pub mod sdk_response;
+pub use sandbox_rust_web_app_dtos::{sdk_request, sdk_response};
+
+pub mod sdk_v1_request;
+pub mod sdk_v1_response;
 pub mod web_app_request;
 pub mod web_app_response;
 
+/// Identifier of an entity, it is serialized as MongoDB extended json
+///
+/// It only describes the identifiers in the api documentation.
+#[derive(ToSchema)]
+#[allow(dead_code)]
+pub struct Id {
+    #[schema(rename = "$oid", example = "65f0a1b2c3d4e5f601234567")]
+    oid: String,
+}
+
+/// Debug value of the passwords and secrets, so that they are never logged
+struct Redacted;
+
+impl fmt::Debug for Redacted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[redacted]")
+    }
+}
+
 // Create our own JSON extractor by wrapping `axum::Json`. This makes it easy to override the
 // rejection and provide our own which formats errors to match our application.
 //
@@ -0,0 +1,109 @@
+//! Requests of the sdk v1, adapted to the requests of the facades
+
+use std::fmt;
+
+use anyhow::anyhow;
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::{
+    dtos::{sdk_request, Redacted},
+    enums::{Role, WebhookEvent},
+    error::AppError,
+};
+
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = V1CreateUser)]
+pub struct CreateUser {
+    pub username: String,
+    pub password: String,
+    pub role: Role,
+}
+
+impl fmt::Debug for CreateUser {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CreateUser")
+            .field("username", &self.username)
+            .field("password", &Redacted)
+            .field("role", &self.role)
+            .finish()
+    }
+}
+
+impl From<CreateUser> for sdk_request::CreateUser {
+    fn from(value: CreateUser) -> Self {
+        sdk_request::CreateUser {
+            username: value.username,
+            password: value.password,
+            role: value.role,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = V1CreateWebhook)]
+pub struct CreateWebhook {
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+}
+
+impl From<CreateWebhook> for sdk_request::CreateWebhook {
+    fn from(value: CreateWebhook) -> Self {
+        sdk_request::CreateWebhook {
+            url: value.url,
+            events: value.events,
+        }
+    }
+}
+
+/// Partial update of a webhook, missing fields are left unchanged
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = V1UpdateWebhook)]
+pub struct UpdateWebhook {
+    pub url: Option<String>,
+    pub events: Option<Vec<WebhookEvent>>,
+    pub enabled: Option<bool>,
+}
+
+impl From<UpdateWebhook> for sdk_request::UpdateWebhook {
+    fn from(value: UpdateWebhook) -> Self {
+        sdk_request::UpdateWebhook {
+            url: value.url,
+            events: value.events,
+            enabled: value.enabled,
+        }
+    }
+}
+
+/// Time range of the usage report as RFC 3339 times, the current month by default
+#[derive(Debug, Serialize, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UsageQuery {
+    #[param(example = "2026-01-01T00:00:00Z")]
+    pub from: Option<String>,
+    #[param(example = "2026-02-01T00:00:00Z")]
+    pub to: Option<String>,
+}
+
+impl TryFrom<UsageQuery> for sdk_request::UsageQuery {
+    type Error = AppError;
+
+    fn try_from(value: UsageQuery) -> Result<Self, Self::Error> {
+        let parse = |time: Option<String>| {
+            time.map(|time| {
+                DateTime::parse_rfc3339_str(&time)
+                    .map(|time| time.timestamp_millis())
+                    .map_err(|_| AppError::InvalidRequest(anyhow!("Invalid RFC 3339 time {time}")))
+            })
+            .transpose()
+        };
+        Ok(sdk_request::UsageQuery {
+            from: parse(value.from)?,
+            to: parse(value.to)?,
+        })
+    }
+}
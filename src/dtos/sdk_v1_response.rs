@@ -0,0 +1,172 @@
+//! Responses of the sdk v1, adapted from the responses of the facades
+//!
+//! Identifiers are hex strings and times are RFC 3339 strings.
+
+use std::fmt;
+
+use mongodb::bson::DateTime;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    dtos::{sdk_response, Redacted},
+    enums::WebhookEvent,
+};
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = V1User)]
+pub struct User {
+    pub id: String,
+    pub username: String,
+}
+
+impl From<sdk_response::User> for User {
+    fn from(value: sdk_response::User) -> Self {
+        User {
+            id: value.id.to_hex(),
+            username: value.username,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = V1Webhook)]
+pub struct Webhook {
+    pub id: String,
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    pub enabled: bool,
+    pub consecutive_failures: u32,
+}
+
+impl From<sdk_response::Webhook> for Webhook {
+    fn from(value: sdk_response::Webhook) -> Self {
+        Webhook {
+            id: value.id.to_hex(),
+            url: value.url,
+            events: value.events,
+            enabled: value.enabled,
+            consecutive_failures: value.consecutive_failures,
+        }
+    }
+}
+
+/// Webhook returned on creation, it is the only time the signing secret is shown
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = V1CreatedWebhook)]
+pub struct CreatedWebhook {
+    #[serde(flatten)]
+    pub webhook: Webhook,
+    pub secret: String,
+}
+
+impl fmt::Debug for CreatedWebhook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CreatedWebhook")
+            .field("webhook", &self.webhook)
+            .field("secret", &Redacted)
+            .finish()
+    }
+}
+
+impl From<sdk_response::CreatedWebhook> for CreatedWebhook {
+    fn from(value: sdk_response::CreatedWebhook) -> Self {
+        CreatedWebhook {
+            webhook: value.webhook.into(),
+            secret: value.secret,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = V1WebhookDelivery)]
+pub struct WebhookDelivery {
+    pub event_id: String,
+    pub event: WebhookEvent,
+    pub attempt: u32,
+    pub status_code: Option<u16>,
+    pub error: Option<String>,
+    pub success: bool,
+    #[schema(example = "2026-01-01T00:00:00Z")]
+    pub created_at: String,
+}
+
+impl From<sdk_response::WebhookDelivery> for WebhookDelivery {
+    fn from(value: sdk_response::WebhookDelivery) -> Self {
+        WebhookDelivery {
+            event_id: value.event_id,
+            event: value.event,
+            attempt: value.attempt,
+            status_code: value.status_code,
+            error: value.error,
+            success: value.success,
+            created_at: DateTime::from_millis(value.created_at)
+                .try_to_rfc3339_string()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = V1Usage)]
+pub struct Usage {
+    #[schema(example = "2026-01-01T00:00:00Z")]
+    pub from: String,
+    #[schema(example = "2026-01-19T08:54:24Z")]
+    pub to: String,
+    /// requests of the current month, counted against the quota
+    pub month_requests: u64,
+    /// requests allowed per month by the plan, missing if they are unlimited
+    pub monthly_quota: Option<u64>,
+    pub routes: Vec<RouteUsage>,
+}
+
+impl From<sdk_response::Usage> for Usage {
+    fn from(value: sdk_response::Usage) -> Self {
+        let rfc3339 = |millis| {
+            DateTime::from_millis(millis)
+                .try_to_rfc3339_string()
+                .unwrap_or_default()
+        };
+        Usage {
+            from: rfc3339(value.from),
+            to: rfc3339(value.to),
+            month_requests: value.month_requests,
+            monthly_quota: value.monthly_quota,
+            routes: value.routes.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+#[schema(as = V1RouteUsage)]
+pub struct RouteUsage {
+    #[schema(example = "GET /sdk/v1/user/:id")]
+    pub route: String,
+    pub api_key: Option<String>,
+    pub requests: u64,
+    pub client_errors: u64,
+    pub server_errors: u64,
+    pub average_latency_ms: f64,
+    pub max_latency_ms: u64,
+}
+
+impl From<sdk_response::RouteUsage> for RouteUsage {
+    fn from(value: sdk_response::RouteUsage) -> Self {
+        RouteUsage {
+            route: value.route,
+            api_key: value.api_key,
+            requests: value.requests,
+            client_errors: value.client_errors,
+            server_errors: value.server_errors,
+            average_latency_ms: value.average_latency_ms,
+            max_latency_ms: value.max_latency_ms,
+        }
+    }
+}
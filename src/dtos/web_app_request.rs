@@ -1,19 +1,173 @@
+use mongodb::bson::oid::ObjectId;
 use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
 
-use crate::enums::Role;
+use crate::enums::{Role, Scope};
 
 /// Authorization payload for jwt token
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct JWTAuthPayload {
     pub username: String,
     pub password: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct CreateUser {
     pub username: String,
     pub password: String,
+    /// a verification link is sent to the email
+    pub email: Option<String>,
     pub role: Role,
 }
+
+/// Query parameters of the redirect from the OpenID Connect provider
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct OidcCallback {
+    pub code: Option<String>,
+    pub state: String,
+    /// set by the provider when the login failed or the user denied it
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+/// Second step of the login completing the challenge with a totp code or a recovery code
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MfaLoginPayload {
+    pub challenge_token: String,
+    pub code: Option<String>,
+    pub recovery_code: Option<String>,
+}
+
+/// Totp code generated by the authenticator app
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MfaCode {
+    pub code: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangePassword {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+/// Request of a reset link for the user with the email
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ForgotPassword {
+    pub email: String,
+}
+
+/// New password with the token received by email
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ResetPassword {
+    pub token: String,
+    pub new_password: String,
+}
+
+/// New email of the user, it must be verified again
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ChangeEmail {
+    pub email: String,
+}
+
+/// Token received by email verifying it
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyEmail {
+    pub token: String,
+}
+
+/// Request of a new verification link for the email
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ResendVerification {
+    pub email: String,
+}
+
+/// Invitation of a new user with the role
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct InviteUser {
+    pub email: String,
+    pub role: Role,
+}
+
+/// Username and password chosen by the invitee with the token received by email
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AcceptInvitation {
+    pub token: String,
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateServiceAccount {
+    pub name: String,
+    pub role: Role,
+    pub scopes: Vec<Scope>,
+    /// rate limit plan, the default plan when missing
+    pub plan: Option<String>,
+}
+
+/// Rate limit plan of a user, the default plan when missing
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SetPlan {
+    pub plan: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateServiceAccountKey {
+    /// label recognizing the key, e.g. the integration using it
+    pub name: String,
+}
+
+/// Filter of the audit events, times are unix timestamps in milliseconds
+#[derive(Deserialize, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+pub struct AuditQuery {
+    /// user or service account performing the actions, impersonators included
+    #[param(value_type = Option<String>)]
+    pub actor: Option<ObjectId>,
+    #[param(value_type = Option<String>)]
+    pub target: Option<ObjectId>,
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    /// most events returned by the search, ignored by the export
+    pub limit: Option<i64>,
+}
+
+/// Filter of the sdk usage, times are unix timestamps in milliseconds
+///
+/// The range defaults to the current month
+#[derive(Deserialize, IntoParams)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+pub struct UsageQuery {
+    /// user or service account sending the requests
+    #[param(value_type = Option<String>)]
+    pub owner: Option<ObjectId>,
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+/// Directives replacing the filter of the logs, e.g. `info` and `mongodb=trace`
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SetLogFilter {
+    pub directives: Vec<String>,
+    /// seconds before the configured filter is restored, at most a day, never if missing
+    pub revert_after_seconds: Option<u64>,
+}
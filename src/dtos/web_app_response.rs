@@ -1,18 +1,243 @@
+use mongodb::bson::oid::ObjectId;
 use serde::Serialize;
+use utoipa::ToSchema;
 
-use crate::UserId;
+use crate::{
+    enums::{
+        ActorKind, AuditAction, LoginFailure, LoginMethod, Role, Scope, SdkVersion, TargetKind,
+    },
+    InvitationId, ServiceAccountId, ServiceAccountKeyId, SessionId, UserId,
+};
 
 /// Authorization response for jwt token
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct JWTAuthResponse {
     pub token: String,
     pub token_type: String,
 }
 
-#[derive(Serialize)]
+/// Response of the login, a challenge is returned when a second factor is required
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum LoginResponse {
+    Token(JWTAuthResponse),
+    MfaChallenge(MfaChallenge),
+}
+
+/// Challenge to complete with the second factor at `/login/mfa`
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MfaChallenge {
+    pub challenge_token: String,
+    /// true if the user must enroll a second factor before completing the login
+    pub enrollment_required: bool,
+}
+
+/// Totp secret to add to the authenticator app
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TotpEnrollment {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+/// Recovery codes of a confirmed enrollment
+///
+/// The token is returned when the enrollment completes a login challenge
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MfaConfirmation {
+    pub recovery_codes: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<JWTAuthResponse>,
+}
+
+/// Recovery codes replacing the previous ones
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct User {
+    #[schema(value_type = Id)]
     pub id: UserId,
     pub username: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+}
+
+/// Pending invitation, times are unix timestamps in milliseconds
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Invitation {
+    #[schema(value_type = Id)]
+    pub id: InvitationId,
+    pub email: String,
+    pub role: Role,
+    #[schema(value_type = Id)]
+    pub invited_by: UserId,
+    pub expires_at: i64,
+    pub created_at: i64,
+}
+
+/// Active session, times are unix timestamps in milliseconds
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Session {
+    #[schema(value_type = Id)]
+    pub id: SessionId,
+    pub method: LoginMethod,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: i64,
+    pub last_seen_at: i64,
+    pub expires_at: i64,
+    /// true for the session making the request
+    pub current: bool,
+}
+
+/// Successful or failed login, times are unix timestamps in milliseconds
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LoginAttempt {
+    pub username: String,
+    pub method: LoginMethod,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub success: bool,
+    pub failure: Option<LoginFailure>,
+    pub created_at: i64,
+}
+
+/// Service account, times are unix timestamps in milliseconds
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceAccount {
+    #[schema(value_type = Id)]
+    pub id: ServiceAccountId,
+    pub name: String,
+    pub role: Role,
+    pub scopes: Vec<Scope>,
+    #[schema(value_type = Id)]
+    pub created_by: UserId,
+    pub disabled: bool,
+    pub created_at: i64,
+    /// rate limit plan, the default plan when missing
+    pub plan: Option<String>,
+}
+
+/// Api key of a service account, times are unix timestamps in milliseconds
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceAccountKey {
+    #[schema(value_type = Id)]
+    pub id: ServiceAccountKeyId,
+    pub name: String,
+    /// first characters of the key
+    pub prefix: String,
+    #[schema(value_type = Id)]
+    pub created_by: UserId,
+    pub created_at: i64,
+    pub last_used_at: Option<i64>,
+}
+
+/// Api key returned on creation, it is the only time the key is shown
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatedServiceAccountKey {
+    #[serde(flatten)]
+    pub key: ServiceAccountKey,
+    pub api_key: String,
+}
+
+/// Entry of the audit log, times are unix timestamps in milliseconds
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEvent {
+    pub sequence: i64,
+    pub actor: AuditActor,
+    pub action: AuditAction,
+    pub target: Option<AuditTarget>,
+    pub before: Option<serde_json::Value>,
+    pub after: Option<serde_json::Value>,
+    pub ip: Option<String>,
+    pub request_id: Option<String>,
+    pub created_at: i64,
+    pub previous_hash: String,
+    pub hash: String,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditActor {
+    pub kind: ActorKind,
+    #[schema(value_type = Option<Id>)]
+    pub id: Option<ObjectId>,
+    #[schema(value_type = Option<Id>)]
+    pub impersonator: Option<UserId>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditTarget {
+    pub kind: TargetKind,
+    #[schema(value_type = Id)]
+    pub id: ObjectId,
+}
+
+/// Outcome of the verification of the audit chain
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditVerification {
+    pub valid: bool,
+    /// events verified before the end of the chain or the first broken event
+    pub verified: u64,
+    /// sequence of the first event that does not match the chain
+    pub broken_at: Option<i64>,
+}
+
+/// Lifecycle and usage of a sdk version since the start of the instance,
+/// times are unix timestamps in milliseconds
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SdkVersionUsage {
+    pub version: SdkVersion,
+    pub deprecated_at: Option<i64>,
+    pub sunset_at: Option<i64>,
+    pub requests: u64,
+    /// requests answered with a 4xx status
+    pub client_errors: u64,
+    /// requests answered with a 5xx status
+    pub server_errors: u64,
+}
+
+/// Sdk requests of an owner on a route with an api key
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OwnerUsage {
+    #[schema(value_type = Id)]
+    pub owner: ObjectId,
+    pub owner_kind: ActorKind,
+    /// displayed prefix of the api key, missing for tokens
+    pub api_key: Option<String>,
+    /// method and path of the route, e.g. `GET /sdk/v0/user/:id`
+    pub route: String,
+    pub requests: u64,
+    pub client_errors: u64,
+    pub server_errors: u64,
+    pub average_latency_ms: f64,
+    pub max_latency_ms: u64,
+}
+
+/// Filter of the logs of the instance, times are unix timestamps in milliseconds
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LogFilter {
+    pub directives: Vec<String>,
+    /// when the configured filter is restored, missing if the change is permanent
+    pub revert_at: Option<i64>,
 }
@@ -1,10 +1,210 @@
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
-/// Enumeration with roles assigned to Users
-#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
-pub enum Role {
-    /// Basic user
+pub use sandbox_rust_web_app_dtos::enums::{Role, WebhookEvent};
+
+/// Permission granted to a service account on the sdk routes
+///
+/// Users authenticated with their own api key are granted every scope.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, ToSchema)]
+pub enum Scope {
+    #[serde(rename = "users:read")]
+    UsersRead,
+    #[serde(rename = "users:write")]
+    UsersWrite,
+    #[serde(rename = "webhooks:read")]
+    WebhooksRead,
+    #[serde(rename = "webhooks:write")]
+    WebhooksWrite,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 4] = [
+        Scope::UsersRead,
+        Scope::UsersWrite,
+        Scope::WebhooksRead,
+        Scope::WebhooksWrite,
+    ];
+
+    /// Name of the scope in the `scope` claim and parameter of OAuth2
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::UsersRead => "users:read",
+            Scope::UsersWrite => "users:write",
+            Scope::WebhooksRead => "webhooks:read",
+            Scope::WebhooksWrite => "webhooks:write",
+        }
+    }
+
+    /// Parse the space separated list of scopes, unknown names are refused
+    pub fn parse_list(value: &str) -> Option<Vec<Scope>> {
+        value
+            .split_whitespace()
+            .map(|name| Scope::ALL.into_iter().find(|scope| scope.as_str() == name))
+            .collect()
+    }
+
+    /// Format the scopes as a space separated list
+    pub fn format_list(scopes: &[Scope]) -> String {
+        scopes
+            .iter()
+            .map(Scope::as_str)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Security-relevant action recorded by the audit log
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, ToSchema)]
+pub enum AuditAction {
+    #[serde(rename = "login.succeeded")]
+    LoginSucceeded,
+    #[serde(rename = "login.failed")]
+    LoginFailed,
+    /// a username or a client address is locked after too many failed logins
+    #[serde(rename = "login.locked_out")]
+    LoginLockedOut,
+    #[serde(rename = "user.created")]
+    UserCreated,
+    #[serde(rename = "user.unlocked")]
+    UserUnlocked,
+    #[serde(rename = "user.plan_changed")]
+    UserPlanChanged,
+    #[serde(rename = "password.changed")]
+    PasswordChanged,
+    #[serde(rename = "password.reset")]
+    PasswordReset,
+    #[serde(rename = "email.changed")]
+    EmailChanged,
+    #[serde(rename = "email.verified")]
+    EmailVerified,
+    #[serde(rename = "mfa.enabled")]
+    MfaEnabled,
+    #[serde(rename = "mfa.recovery_codes_regenerated")]
+    RecoveryCodesRegenerated,
+    #[serde(rename = "invitation.created")]
+    InvitationCreated,
+    #[serde(rename = "invitation.resent")]
+    InvitationResent,
+    #[serde(rename = "invitation.cancelled")]
+    InvitationCancelled,
+    #[serde(rename = "invitation.accepted")]
+    InvitationAccepted,
+    #[serde(rename = "session.revoked")]
+    SessionRevoked,
+    #[serde(rename = "impersonation.started")]
+    ImpersonationStarted,
+    /// request made with an impersonation token
+    #[serde(rename = "impersonation.request")]
+    ImpersonatedRequest,
+    #[serde(rename = "service_account.created")]
+    ServiceAccountCreated,
+    #[serde(rename = "service_account.disabled")]
+    ServiceAccountDisabled,
+    #[serde(rename = "service_account.key_created")]
+    ServiceAccountKeyCreated,
+    #[serde(rename = "service_account.key_revoked")]
+    ServiceAccountKeyRevoked,
+    #[serde(rename = "service_account.token_issued")]
+    ServiceAccountTokenIssued,
+    #[serde(rename = "webhook.created")]
+    WebhookCreated,
+    #[serde(rename = "webhook.updated")]
+    WebhookUpdated,
+    #[serde(rename = "webhook.deleted")]
+    WebhookDeleted,
+    #[serde(rename = "logging.filter_changed")]
+    LogFilterChanged,
+}
+
+/// Kind of the principal performing an audited action
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ActorKind {
+    User,
+    ServiceAccount,
+    /// unauthenticated requests, e.g. failed logins and password resets
+    Anonymous,
+}
+
+/// Kind of the entity affected by an audited action
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetKind {
     User,
-    /// Admin user
-    Admin,
+    Invitation,
+    Session,
+    ServiceAccount,
+    ServiceAccountKey,
+    Webhook,
+}
+
+/// Way a user logged in
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LoginMethod {
+    /// username and password, with the second factor when required
+    Password,
+    /// OpenID Connect provider
+    Oidc,
+    /// acceptance of an invitation
+    Invitation,
+}
+
+/// Reason of a failed login
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LoginFailure {
+    WrongCredentials,
+    /// the username or the client address is locked after too many failures
+    LockedOut,
+    InvalidMfaCode,
+    EmailNotVerified,
+}
+
+/// Version of the sdk routes served under `/sdk/<version>`
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Hash, Clone, Copy, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SdkVersion {
+    V0,
+    V1,
+}
+
+impl SdkVersion {
+    /// Every served version, the oldest first
+    pub const ALL: [SdkVersion; 2] = [SdkVersion::V0, SdkVersion::V1];
+
+    /// Segment of the path of the version and name of its configuration section
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SdkVersion::V0 => "v0",
+            SdkVersion::V1 => "v1",
+        }
+    }
+
+    /// Version replacing this one, none for the latest
+    pub fn successor(&self) -> Option<SdkVersion> {
+        match self {
+            SdkVersion::V0 => Some(SdkVersion::V1),
+            SdkVersion::V1 => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Scope;
+
+    #[test]
+    fn scope_list_test() {
+        let scopes = Scope::parse_list("users:read  webhooks:write").unwrap();
+        assert_eq!(scopes, vec![Scope::UsersRead, Scope::WebhooksWrite]);
+        assert_eq!(Scope::format_list(&scopes), "users:read webhooks:write");
+        assert_eq!(Scope::parse_list(""), Some(vec![]));
+        assert!(Scope::parse_list("users:read users:delete").is_none());
+        for scope in Scope::ALL {
+            let name = serde_json::to_value(scope).unwrap();
+            assert_eq!(name, scope.as_str());
+        }
+    }
 }
@@ -1,11 +1,23 @@
+use std::collections::BTreeMap;
+
 use axum::{
     extract::rejection::JsonRejection,
-    http::StatusCode,
+    http::{header::RETRY_AFTER, StatusCode},
     response::{IntoResponse, Response},
 };
+use mongodb::bson::DateTime;
 use serde::Serialize;
+use utoipa::{
+    openapi::{ContentBuilder, Ref, RefOr, Response as ApiResponse, ResponseBuilder},
+    IntoResponses, ToSchema,
+};
 
-use crate::dtos::AppJson;
+pub use sandbox_rust_web_app_dtos::error::ErrorResponse;
+
+use crate::{
+    dtos::AppJson,
+    service::{metrics, request_id},
+};
 
 /// AppError enumeration of different error typologies that the application
 /// can return to clients.
@@ -27,16 +39,27 @@ pub enum AppError {
     DoesNotExist(anyhow::Error),
     /// The user does not have role to perform the operation
     AccessControlError,
+    /// The request is well formed but contains invalid values
+    InvalidRequest(anyhow::Error),
+    /// The resource is not served anymore, e.g. a sunset api version
+    Gone(anyhow::Error),
+    /// The client sent too many requests, it can retry after the seconds
+    TooManyRequests { retry_after: u64 },
+    /// The monthly quota of requests of the plan is exhausted until the time
+    QuotaExceeded { quota: u64, resets_at: DateTime },
 }
 
 // Tell axum how to convert `AppError` into a response.
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        // How we want errors responses to be serialized
-        #[derive(Serialize)]
-        struct ErrorResponse {
-            message: String,
-        }
+        let retry_after = match &self {
+            AppError::TooManyRequests { retry_after } => Some(*retry_after),
+            AppError::QuotaExceeded { resets_at, .. } => {
+                let now = DateTime::now().timestamp_millis();
+                Some(((resets_at.timestamp_millis() - now).max(0) as u64).div_ceil(1000))
+            }
+            _ => None,
+        };
         // Define StatusCode and message for every enum variant
         let (status, message) = match self {
             AppError::JsonRejection(rejection) => {
@@ -47,14 +70,71 @@ impl IntoResponse for AppError {
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Something went wrong".into(),
             ),
-            AppError::AuthorizationError(auth_error) => auth_error.to_status_message(),
+            AppError::AuthorizationError(auth_error) => {
+                metrics::record_auth_failure(&auth_error);
+                auth_error.to_status_message()
+            }
             AppError::DoesNotExist(_) => (StatusCode::NOT_FOUND, "Entity not found".into()),
             AppError::AccessControlError => (
                 StatusCode::UNAUTHORIZED,
                 "Not sufficient permissions".into(),
             ),
+            AppError::InvalidRequest(error) => (StatusCode::BAD_REQUEST, error.to_string()),
+            AppError::Gone(error) => (StatusCode::GONE, error.to_string()),
+            AppError::TooManyRequests { .. } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many requests, retry later".into(),
+            ),
+            AppError::QuotaExceeded { quota, resets_at } => (
+                StatusCode::TOO_MANY_REQUESTS,
+                format!(
+                    "Monthly quota of {quota} requests is exhausted until {}",
+                    resets_at
+                        .try_to_rfc3339_string()
+                        .unwrap_or_else(|_| resets_at.to_string())
+                ),
+            ),
         };
-        (status, AppJson(ErrorResponse { message })).into_response()
+        let mut response = (
+            status,
+            AppJson(ErrorResponse {
+                message,
+                request_id: request_id::current(),
+            }),
+        )
+            .into_response();
+        if let Some(retry_after) = retry_after {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, retry_after.into());
+        }
+        response
+    }
+}
+
+// Describe the error responses in the api documentation
+impl IntoResponses for AppError {
+    fn responses() -> BTreeMap<String, RefOr<ApiResponse>> {
+        [
+            ("400", "The request is not valid or the token is not valid"),
+            ("401", "Wrong credentials or not sufficient permissions"),
+            ("403", "The email address is not verified"),
+            ("404", "Entity not found"),
+            ("410", "The api version is not served anymore"),
+            (
+                "429",
+                "Too many requests, failed attempts or monthly quota exhausted",
+            ),
+            ("500", "Something went wrong"),
+        ]
+        .into_iter()
+        .map(|(status, description)| {
+            (
+                status.to_string(),
+                error_response(description, "ErrorResponse"),
+            )
+        })
+        .collect()
     }
 }
 
@@ -82,6 +162,12 @@ impl From<mongodb::error::Error> for AppError {
     }
 }
 
+impl From<amqprs::error::Error> for AppError {
+    fn from(value: amqprs::error::Error) -> Self {
+        Self::InternalServerError(anyhow::Error::new(value))
+    }
+}
+
 /// AuthError is an internal error used by authentication modules to explain why
 /// authentication is failed.
 /// They are translated to `AppError` when exposed to the client
@@ -92,9 +178,32 @@ pub enum AuthError {
     TokenCreation,
     InvalidToken,
     InvalidApiKey,
+    /// The login with the external identity provider failed
+    ExternalLoginFailed,
+    /// The second factor code is wrong or already used
+    InvalidMfaCode,
+    /// The username or the client address is locked after too many failures
+    TooManyAttempts,
+    /// The login requires a verified email and the user has not verified it
+    EmailNotVerified,
 }
 
 impl AuthError {
+    /// Name of the variant, the label of the failures in the metrics
+    pub fn reason(&self) -> &'static str {
+        match self {
+            AuthError::WrongCredentials => "wrong_credentials",
+            AuthError::MissingCredentials => "missing_credentials",
+            AuthError::TokenCreation => "token_creation",
+            AuthError::InvalidToken => "invalid_token",
+            AuthError::InvalidApiKey => "invalid_api_key",
+            AuthError::ExternalLoginFailed => "external_login_failed",
+            AuthError::InvalidMfaCode => "invalid_mfa_code",
+            AuthError::TooManyAttempts => "too_many_attempts",
+            AuthError::EmailNotVerified => "email_not_verified",
+        }
+    }
+
     fn to_status_message(&self) -> (StatusCode, String) {
         let (status, message) = match self {
             AuthError::WrongCredentials => {
@@ -110,7 +219,121 @@ impl AuthError {
                 "Token creation error".to_string(),
             ),
             AuthError::InvalidToken => (StatusCode::BAD_REQUEST, "Invalid token".to_string()),
+            AuthError::ExternalLoginFailed => (
+                StatusCode::UNAUTHORIZED,
+                "External login failed".to_string(),
+            ),
+            AuthError::TooManyAttempts => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many failed attempts, retry later".to_string(),
+            ),
+            AuthError::EmailNotVerified => (
+                StatusCode::FORBIDDEN,
+                "Email address is not verified".to_string(),
+            ),
+            AuthError::InvalidMfaCode => (
+                StatusCode::UNAUTHORIZED,
+                "Invalid authentication code".to_string(),
+            ),
         };
         (status, message)
     }
 }
+
+/// Response of an error described by the schema
+fn error_response(description: &str, schema: &str) -> RefOr<ApiResponse> {
+    ResponseBuilder::new()
+        .description(description)
+        .content(
+            "application/json",
+            ContentBuilder::new()
+                .schema(Ref::from_schema_name(schema))
+                .build(),
+        )
+        .build()
+        .into()
+}
+
+/// Error of the OAuth2 token endpoint
+///
+/// It is serialized as described by RFC 6749 section 5.2 instead of the
+/// message of `AppError`, so that OAuth2 clients understand it
+#[derive(Debug)]
+pub enum OAuthError {
+    /// The request misses a parameter or it is malformed
+    InvalidRequest(String),
+    /// The client is unknown, disabled or its secret is wrong
+    InvalidClient,
+    UnsupportedGrantType,
+    /// The requested scope is unknown or not granted to the client
+    InvalidScope,
+    ServerError(AppError),
+}
+
+/// Error response of the OAuth2 token endpoint
+#[derive(Serialize, ToSchema)]
+pub struct OAuthErrorResponse {
+    #[schema(example = "invalid_client")]
+    error: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_description: Option<String>,
+}
+
+impl IntoResponse for OAuthError {
+    fn into_response(self) -> Response {
+        let (status, error, error_description) = match self {
+            OAuthError::InvalidRequest(description) => (
+                StatusCode::BAD_REQUEST,
+                "invalid_request",
+                Some(description),
+            ),
+            OAuthError::InvalidClient => (StatusCode::UNAUTHORIZED, "invalid_client", None),
+            OAuthError::UnsupportedGrantType => {
+                (StatusCode::BAD_REQUEST, "unsupported_grant_type", None)
+            }
+            OAuthError::InvalidScope => (StatusCode::BAD_REQUEST, "invalid_scope", None),
+            OAuthError::ServerError(e) => {
+                tracing::error!("Cannot issue client credentials token: {e:?}");
+                (StatusCode::INTERNAL_SERVER_ERROR, "server_error", None)
+            }
+        };
+        let body = OAuthErrorResponse {
+            error,
+            error_description,
+        };
+        (status, AppJson(body)).into_response()
+    }
+}
+
+impl IntoResponses for OAuthError {
+    fn responses() -> BTreeMap<String, RefOr<ApiResponse>> {
+        [
+            (
+                "400",
+                "Invalid request, unsupported grant type or invalid scope",
+            ),
+            ("401", "Unknown or disabled client, or wrong secret"),
+            ("500", "Something went wrong"),
+        ]
+        .into_iter()
+        .map(|(status, description)| {
+            (
+                status.to_string(),
+                error_response(description, "OAuthErrorResponse"),
+            )
+        })
+        .collect()
+    }
+}
+
+impl From<AppError> for OAuthError {
+    fn from(value: AppError) -> Self {
+        Self::ServerError(value)
+    }
+}
+
+impl From<AuthError> for OAuthError {
+    fn from(value: AuthError) -> Self {
+        Self::ServerError(value.into())
+    }
+}
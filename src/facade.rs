@@ -5,3 +5,4 @@
 
 pub mod sdk;
 pub mod web_app;
+pub mod well_known;
@@ -1,14 +1,75 @@
-use tracing::debug;
+use anyhow::anyhow;
+use serde_json::json;
+use tracing::{debug, info, instrument};
 
 use crate::{
-    auth::AuthInfo,
+    auth::{APIKeyAuthClaim, AuthInfo, ServiceAccountClaim},
     dtos::{sdk_request, sdk_response},
-    error::AppError,
+    enums::{AuditAction, Scope, TargetKind},
+    error::{AppError, OAuthError},
+    model::{audit_event::AuditActor, webhook::WebhookSubscription},
     service::access_control::AccessControl,
-    service::user,
-    UserId,
+    service::audit::{self, AuditRecord},
+    service::environment::ENVIRONMENT,
+    service::{service_account, usage, user, webhook},
+    ServiceAccountId, UserId, WebhookId,
 };
 
+/// Issue a token to the service account with the OAuth2 client credentials grant
+///
+/// The client id is the identifier of the service account and the client
+/// secret is one of its api keys
+#[instrument(level = "debug", skip_all)]
+pub async fn issue_client_credentials_token(
+    client_id: &str,
+    client_secret: &str,
+    payload: sdk_request::TokenRequest,
+) -> Result<sdk_response::AccessToken, OAuthError> {
+    if payload.grant_type != "client_credentials" {
+        return Err(OAuthError::UnsupportedGrantType);
+    }
+    let client_id: ServiceAccountId = client_id.parse().map_err(|_| OAuthError::InvalidClient)?;
+    let service_account = match service_account::authenticate_key(client_secret).await? {
+        Some(service_account) if service_account.id == Some(client_id) => service_account,
+        _ => return Err(OAuthError::InvalidClient),
+    };
+    let scopes = match payload.scope.as_deref() {
+        Some(scope) => {
+            let requested = Scope::parse_list(scope).ok_or(OAuthError::InvalidScope)?;
+            if requested
+                .iter()
+                .any(|scope| !service_account.scopes.contains(scope))
+            {
+                return Err(OAuthError::InvalidScope);
+            }
+            requested
+        }
+        None => service_account.scopes,
+    };
+    let claim = ServiceAccountClaim::new(client_id, &scopes);
+    let access_token = claim.build_token().await?;
+    info!("Issued client credentials token to service account {client_id}");
+    audit::record(
+        AuditRecord::new(
+            AuditActor::service_account(client_id),
+            AuditAction::ServiceAccountTokenIssued,
+        )
+        .target(TargetKind::ServiceAccount, client_id)
+        .after(json!({ "scope": &claim.scope })),
+    )
+    .await;
+    Ok(sdk_response::AccessToken {
+        access_token,
+        token_type: "Bearer".into(),
+        expires_in: ENVIRONMENT
+            .authentication
+            .client_credentials_lifetime
+            .as_secs(),
+        scope: claim.scope,
+    })
+}
+
+#[instrument(level = "debug", skip_all)]
 pub async fn get_user(
     auth_info: impl AuthInfo,
     user_id: UserId,
@@ -18,7 +79,10 @@ pub async fn get_user(
         "Making access control for auth_info with user {}",
         auth_info.user_id()
     );
-    AccessControl::new(auth_info).is_admin().await?;
+    AccessControl::new(auth_info)
+        .has_scope(Scope::UsersRead)?
+        .is_admin()
+        .await?;
     let user_model = user::get_user(&user_id).await?;
     Ok(sdk_response::User {
         id: user_model
@@ -28,6 +92,7 @@ pub async fn get_user(
     })
 }
 
+#[instrument(level = "debug", skip_all)]
 pub async fn create_user(
     auth_info: impl AuthInfo,
     payload: sdk_request::CreateUser,
@@ -37,6 +102,184 @@ pub async fn create_user(
         "Making access control for auth_info with user {}",
         auth_info.user_id()
     );
-    AccessControl::new(auth_info).is_admin().await?;
-    user::create_user(payload.username, payload.password, payload.role).await
+    let actor = AuditActor::of(&auth_info);
+    AccessControl::new(auth_info)
+        .has_scope(Scope::UsersWrite)?
+        .is_admin()
+        .await?;
+    let audited = json!({ "username": &payload.username, "role": payload.role });
+    let user_id = user::create_user(payload.username, payload.password, None, payload.role).await?;
+    let id = user_id
+        .parse()
+        .map_err(|_| AppError::InternalServerError(anyhow!("Created user id is not valid")))?;
+    audit::record(
+        AuditRecord::new(actor, AuditAction::UserCreated)
+            .target(TargetKind::User, id)
+            .after(audited),
+    )
+    .await;
+    Ok(user_id)
+}
+
+#[instrument(level = "debug", skip_all)]
+pub async fn create_webhook(
+    auth_info: impl AuthInfo,
+    payload: sdk_request::CreateWebhook,
+) -> Result<sdk_response::CreatedWebhook, AppError> {
+    let access_control = AccessControl::new(&auth_info).has_scope(Scope::WebhooksWrite)?;
+    if payload.events.iter().copied().any(webhook::is_global) {
+        access_control.is_admin().await?;
+    }
+    let subscription =
+        webhook::create_subscription(auth_info.user_id(), payload.url, payload.events).await?;
+    audit::record(
+        AuditRecord::new(AuditActor::of(&auth_info), AuditAction::WebhookCreated)
+            .target(
+                TargetKind::Webhook,
+                subscription.id.expect("Webhook id must be not missing"),
+            )
+            .after(webhook_audited_fields(&subscription)),
+    )
+    .await;
+    let secret = subscription.secret.clone();
+    Ok(sdk_response::CreatedWebhook {
+        webhook: webhook_to_response(subscription),
+        secret,
+    })
+}
+
+#[instrument(level = "debug", skip_all)]
+pub async fn list_webhooks(
+    auth_info: impl AuthInfo,
+) -> Result<Vec<sdk_response::Webhook>, AppError> {
+    AccessControl::new(&auth_info).has_scope(Scope::WebhooksRead)?;
+    let subscriptions = webhook::list_subscriptions(auth_info.user_id()).await?;
+    Ok(subscriptions.into_iter().map(webhook_to_response).collect())
+}
+
+#[instrument(level = "debug", skip_all)]
+pub async fn get_webhook(
+    auth_info: impl AuthInfo,
+    webhook_id: WebhookId,
+) -> Result<sdk_response::Webhook, AppError> {
+    AccessControl::new(&auth_info).has_scope(Scope::WebhooksRead)?;
+    let subscription = webhook::get_subscription(auth_info.user_id(), &webhook_id).await?;
+    Ok(webhook_to_response(subscription))
+}
+
+#[instrument(level = "debug", skip_all)]
+pub async fn update_webhook(
+    auth_info: impl AuthInfo,
+    webhook_id: WebhookId,
+    payload: sdk_request::UpdateWebhook,
+) -> Result<sdk_response::Webhook, AppError> {
+    let access_control = AccessControl::new(&auth_info).has_scope(Scope::WebhooksWrite)?;
+    let before = webhook::get_subscription(auth_info.user_id(), &webhook_id).await?;
+    let events = payload.events.as_ref().unwrap_or(&before.events);
+    if events.iter().copied().any(webhook::is_global) {
+        access_control.is_admin().await?;
+    }
+    let subscription = webhook::update_subscription(
+        auth_info.user_id(),
+        &webhook_id,
+        payload.url,
+        payload.events,
+        payload.enabled,
+    )
+    .await?;
+    audit::record(
+        AuditRecord::new(AuditActor::of(&auth_info), AuditAction::WebhookUpdated)
+            .target(TargetKind::Webhook, webhook_id)
+            .before(webhook_audited_fields(&before))
+            .after(webhook_audited_fields(&subscription)),
+    )
+    .await;
+    Ok(webhook_to_response(subscription))
+}
+
+#[instrument(level = "debug", skip_all)]
+pub async fn delete_webhook(
+    auth_info: impl AuthInfo,
+    webhook_id: WebhookId,
+) -> Result<(), AppError> {
+    AccessControl::new(&auth_info).has_scope(Scope::WebhooksWrite)?;
+    webhook::delete_subscription(auth_info.user_id(), &webhook_id).await?;
+    audit::record(
+        AuditRecord::new(AuditActor::of(&auth_info), AuditAction::WebhookDeleted)
+            .target(TargetKind::Webhook, webhook_id),
+    )
+    .await;
+    Ok(())
+}
+
+#[instrument(level = "debug", skip_all)]
+pub async fn list_webhook_deliveries(
+    auth_info: impl AuthInfo,
+    webhook_id: WebhookId,
+) -> Result<Vec<sdk_response::WebhookDelivery>, AppError> {
+    AccessControl::new(&auth_info).has_scope(Scope::WebhooksRead)?;
+    let deliveries = webhook::list_deliveries(auth_info.user_id(), &webhook_id).await?;
+    Ok(deliveries
+        .into_iter()
+        .map(|delivery| sdk_response::WebhookDelivery {
+            event_id: delivery.event_id,
+            event: delivery.event,
+            attempt: delivery.attempt,
+            status_code: delivery.status_code,
+            error: delivery.error,
+            success: delivery.success,
+            created_at: delivery.created_at.timestamp_millis(),
+        })
+        .collect())
+}
+
+/// Returns the requests of the api key owner by route and api key in the time range
+///
+/// Requests of the last seconds may not be counted yet
+#[instrument(level = "debug", skip_all)]
+pub async fn get_usage(
+    api_key: APIKeyAuthClaim,
+    query: sdk_request::UsageQuery,
+) -> Result<sdk_response::Usage, AppError> {
+    let (from, to) = usage::report_range(query.from, query.to)?;
+    let totals = usage::totals(Some(&api_key.user_id), from, to).await?;
+    Ok(sdk_response::Usage {
+        from: from.timestamp_millis(),
+        to: to.timestamp_millis(),
+        month_requests: usage::month_requests(&api_key.user_id).await?,
+        monthly_quota: usage::monthly_quota(api_key.plan.as_deref()),
+        routes: totals
+            .into_iter()
+            .map(|totals| sdk_response::RouteUsage {
+                average_latency_ms: totals.counts.average_latency_ms(),
+                requests: totals.counts.requests as u64,
+                client_errors: totals.counts.client_errors as u64,
+                server_errors: totals.counts.server_errors as u64,
+                max_latency_ms: totals.counts.latency_max_ms as u64,
+                route: totals.route,
+                api_key: totals.api_key,
+            })
+            .collect(),
+    })
+}
+
+/// Fields of the subscription recorded by the audit log, the secret excluded
+fn webhook_audited_fields(subscription: &WebhookSubscription) -> serde_json::Value {
+    json!({
+        "url": &subscription.url,
+        "events": &subscription.events,
+        "enabled": subscription.enabled,
+    })
+}
+
+fn webhook_to_response(subscription: WebhookSubscription) -> sdk_response::Webhook {
+    sdk_response::Webhook {
+        id: subscription
+            .id
+            .expect("field id should exist since the model comes from a db query"),
+        url: subscription.url,
+        events: subscription.events,
+        enabled: subscription.enabled,
+        consecutive_failures: subscription.consecutive_failures,
+    }
 }
@@ -1,28 +1,264 @@
-use jsonwebtoken::Header;
+use std::time::Duration;
 
-use tracing::debug;
+use anyhow::anyhow;
+use futures::{Stream, TryStreamExt};
+use mongodb::bson::{Bson, DateTime};
+use serde_json::json;
+use tracing::{debug, instrument};
 
 use crate::{
-    auth::{AuthInfo, JWTAuthClaim},
+    auth::{ActorClaim, AuthInfo, ClientInfo, JWTAuthClaim, MfaEnrollmentClaim, TokenAudience},
     dtos::{web_app_request, web_app_response},
-    error::AppError,
+    enums::{AuditAction, LoginFailure, LoginMethod, Role, SdkVersion, TargetKind},
+    error::{AppError, AuthError},
+    model::{
+        audit_event::{AuditActor, AuditEvent},
+        invitation::Invitation,
+        login_attempt::LoginAttempt,
+        service_account::ServiceAccount,
+        service_account_key::ServiceAccountKey,
+        session::Session,
+        user::User,
+    },
     service::access_control::AccessControl,
-    service::user,
-    UserId,
+    service::environment::ENVIRONMENT,
+    service::{
+        audit::{self, AuditFilter, AuditRecord},
+        email_verification, invitation,
+        logging::{self, LogFilter},
+        login_history, login_protection, mfa,
+        oidc::{self, OidcLogin},
+        password, sdk_version, service_account, session, usage, user,
+    },
+    InvitationId, ServiceAccountId, ServiceAccountKeyId, SessionId, UserId,
 };
 
+/// Audit events returned by a search without limit
+const DEFAULT_AUDIT_LIMIT: i64 = 100;
+/// Most audit events returned by a search, the export has no limit
+const MAX_AUDIT_LIMIT: i64 = 1000;
+/// Longest delay before a changed filter of the logs is reverted
+const MAX_LOG_FILTER_REVERT: Duration = Duration::from_secs(24 * 3600);
+
+/// Authorize the user with username and password
+///
+/// Failures are counted per username and client address, whether the user
+/// exists or not, and both are locked after too many of them
+#[instrument(level = "debug", skip_all)]
 pub async fn authenticate_user(
     username: &str,
     password: &str,
+    client: &ClientInfo,
+) -> Result<web_app_response::LoginResponse, AppError> {
+    let method = LoginMethod::Password;
+    if let Err(e) = login_protection::check(username, client.ip).await {
+        if let AppError::AuthorizationError(AuthError::TooManyAttempts) = e {
+            login_history::record_failure(None, username, method, LoginFailure::LockedOut, client)
+                .await;
+        }
+        return Err(e);
+    }
+    match user::login(username, password).await {
+        Ok(user_model) => complete_authentication(user_model, method, client).await,
+        Err(AppError::AuthorizationError(AuthError::WrongCredentials)) => {
+            login_protection::record_failure(username, client.ip).await?;
+            login_history::record_failure(
+                None,
+                username,
+                method,
+                LoginFailure::WrongCredentials,
+                client,
+            )
+            .await;
+            Err(AuthError::WrongCredentials)?
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Complete the login challenge with the second factor
+///
+/// Wrong codes count as failed logins of the user
+#[instrument(level = "debug", skip_all)]
+pub async fn authenticate_mfa(
+    payload: web_app_request::MfaLoginPayload,
+    client: &ClientInfo,
 ) -> Result<web_app_response::JWTAuthResponse, AppError> {
-    let user_model = user::login(username, password).await?;
+    let claim =
+        JWTAuthClaim::decode_token(&payload.challenge_token, TokenAudience::MfaChallenge).await?;
+    login_protection::check(&claim.username, client.ip).await?;
+    let verification = mfa::verify_second_factor(
+        &claim.user_id,
+        payload.code.as_deref(),
+        payload.recovery_code.as_deref(),
+    )
+    .await;
+    let user_model = user::get_user(&claim.user_id).await?;
+    if let Err(AppError::AuthorizationError(AuthError::InvalidMfaCode)) = verification {
+        login_protection::record_failure(&claim.username, client.ip).await?;
+        login_history::record_failure(
+            Some(&user_model),
+            &claim.username,
+            LoginMethod::Password,
+            LoginFailure::InvalidMfaCode,
+            client,
+        )
+        .await;
+    }
+    verification?;
+    start_session(user_model, LoginMethod::Password, client).await
+}
 
-    let claims = JWTAuthClaim {
-        exp: 2000000000,
-        user_id: user_model.id.expect("User id must be not missing"),
-        username: user_model.username,
+/// Start the totp enrollment of the user
+#[instrument(level = "debug", skip_all)]
+pub async fn enroll_totp(
+    auth_info: MfaEnrollmentClaim,
+) -> Result<web_app_response::TotpEnrollment, AppError> {
+    AccessControl::new(&auth_info).not_impersonated()?;
+    let (secret, otpauth_uri) = mfa::begin_enrollment(auth_info.user_id()).await?;
+    Ok(web_app_response::TotpEnrollment {
+        secret,
+        otpauth_uri,
+    })
+}
+
+/// Confirm the totp enrollment, when it comes from a login challenge the login is completed
+#[instrument(level = "debug", skip_all)]
+pub async fn confirm_totp(
+    auth_info: MfaEnrollmentClaim,
+    payload: web_app_request::MfaCode,
+    client: &ClientInfo,
+) -> Result<web_app_response::MfaConfirmation, AppError> {
+    AccessControl::new(&auth_info).not_impersonated()?;
+    let recovery_codes = mfa::confirm_enrollment(auth_info.user_id(), &payload.code).await?;
+    audit::record(
+        AuditRecord::new(AuditActor::of(&auth_info), AuditAction::MfaEnabled)
+            .target(TargetKind::User, *auth_info.user_id()),
+    )
+    .await;
+    let token = if auth_info.challenge {
+        let user_model = user::get_user(auth_info.user_id()).await?;
+        Some(start_session(user_model, LoginMethod::Password, client).await?)
+    } else {
+        None
     };
-    let token = claims.build_token(&Header::default())?;
+    Ok(web_app_response::MfaConfirmation {
+        recovery_codes,
+        token,
+    })
+}
+
+#[instrument(level = "debug", skip_all)]
+pub async fn regenerate_recovery_codes(
+    auth_info: impl AuthInfo,
+    payload: web_app_request::MfaCode,
+) -> Result<web_app_response::RecoveryCodes, AppError> {
+    AccessControl::new(&auth_info).not_impersonated()?;
+    let recovery_codes = mfa::regenerate_recovery_codes(auth_info.user_id(), &payload.code).await?;
+    audit::record(
+        AuditRecord::new(
+            AuditActor::of(&auth_info),
+            AuditAction::RecoveryCodesRegenerated,
+        )
+        .target(TargetKind::User, *auth_info.user_id()),
+    )
+    .await;
+    Ok(web_app_response::RecoveryCodes { recovery_codes })
+}
+
+/// Returns the url of the OpenID Connect provider where the user logs in and
+/// the cookie binding the login to the browser
+#[instrument(level = "debug", skip_all)]
+pub async fn oidc_login() -> Result<OidcLogin, AppError> {
+    oidc::begin_login().await
+}
+
+/// Authorize the user coming back from the OpenID Connect provider
+#[instrument(level = "debug", skip_all)]
+pub async fn authenticate_oidc_user(
+    payload: web_app_request::OidcCallback,
+    state_cookie: Option<&str>,
+    client: &ClientInfo,
+) -> Result<web_app_response::LoginResponse, AppError> {
+    if let Some(error) = payload.error {
+        return Err(AppError::InvalidRequest(anyhow!(
+            "Identity provider returned {error}: {}",
+            payload.error_description.unwrap_or_default()
+        )));
+    }
+    oidc::verify_login_state(state_cookie, &payload.state)?;
+    let code = payload
+        .code
+        .ok_or_else(|| AppError::InvalidRequest(anyhow!("Authorization code is missing")))?;
+    let user_model = oidc::complete_login(&code, &payload.state).await?;
+    complete_authentication(user_model, LoginMethod::Oidc, client).await
+}
+
+/// Start the session or, when the second factor is required, issue the challenge to complete
+///
+/// Users without a verified email are refused when the configuration requires it
+async fn complete_authentication(
+    user_model: User,
+    method: LoginMethod,
+    client: &ClientInfo,
+) -> Result<web_app_response::LoginResponse, AppError> {
+    if ENVIRONMENT.email_verification.required_for_login && !user_model.email_verified {
+        login_history::record_failure(
+            Some(&user_model),
+            &user_model.username,
+            method,
+            LoginFailure::EmailNotVerified,
+            client,
+        )
+        .await;
+        return Err(AuthError::EmailNotVerified)?;
+    }
+    if !mfa::is_required(&user_model) {
+        return start_session(user_model, method, client)
+            .await
+            .map(web_app_response::LoginResponse::Token);
+    }
+    let enrollment_required = !user_model.mfa.enabled;
+    let claims = JWTAuthClaim::new(
+        user_model.id.expect("User id must be not missing"),
+        user_model.username,
+        TokenAudience::MfaChallenge,
+    );
+    let challenge_token = claims.build_token().await?;
+    Ok(web_app_response::LoginResponse::MfaChallenge(
+        web_app_response::MfaChallenge {
+            challenge_token,
+            enrollment_required,
+        },
+    ))
+}
+
+/// Record the successful login and issue the token of a new session
+///
+/// The failures of the username are forgotten only here, once every factor is checked
+async fn start_session(
+    user_model: User,
+    method: LoginMethod,
+    client: &ClientInfo,
+) -> Result<web_app_response::JWTAuthResponse, AppError> {
+    let user_id = user_model.id.expect("User id must be not missing");
+    let session_id = session::create(&user_id, method, client).await?;
+    login_protection::record_success(&user_model.username).await?;
+    login_history::record_success(&user_model, method, client).await;
+    issue_token(user_model, Some(session_id)).await
+}
+
+async fn issue_token(
+    user_model: User,
+    session_id: Option<SessionId>,
+) -> Result<web_app_response::JWTAuthResponse, AppError> {
+    let mut claims = JWTAuthClaim::new(
+        user_model.id.expect("User id must be not missing"),
+        user_model.username,
+        TokenAudience::WebApp,
+    );
+    claims.sid = session_id;
+    let token = claims.build_token().await?;
 
     Ok(web_app_response::JWTAuthResponse {
         token,
@@ -30,6 +266,7 @@ pub async fn authenticate_user(
     })
 }
 
+#[instrument(level = "debug", skip_all)]
 pub async fn get_user(
     auth_info: impl AuthInfo,
     user_id: UserId,
@@ -46,9 +283,12 @@ pub async fn get_user(
             .id
             .expect("field user_id should exist since the model comes from a db query"),
         username: user_model.username,
+        email: user_model.email,
+        email_verified: user_model.email_verified,
     })
 }
 
+#[instrument(level = "debug", skip_all)]
 pub async fn create_user(
     auth_info: impl AuthInfo,
     payload: web_app_request::CreateUser,
@@ -58,6 +298,757 @@ pub async fn create_user(
         "Making access control for auth_info with user {}",
         auth_info.user_id()
     );
+    let actor = AuditActor::of(&auth_info);
+    AccessControl::new(auth_info).is_admin().await?;
+    let email = payload
+        .email
+        .as_deref()
+        .map(email_verification::normalize)
+        .transpose()?;
+    let audited = json!({ "username": &payload.username, "role": payload.role, "email": &email });
+    let send_link = email.is_some();
+    let user_id =
+        user::create_user(payload.username, payload.password, email, payload.role).await?;
+    let id = user_id
+        .parse()
+        .map_err(|_| AppError::InternalServerError(anyhow!("Created user id is not valid")))?;
+    audit::record(
+        AuditRecord::new(actor, AuditAction::UserCreated)
+            .target(TargetKind::User, id)
+            .after(audited),
+    )
+    .await;
+    if send_link {
+        email_verification::send_link(&id).await?;
+    }
+    Ok(user_id)
+}
+
+/// Remove the lockout of the user after too many failed logins
+#[instrument(level = "debug", skip_all)]
+pub async fn unlock_user(auth_info: impl AuthInfo, user_id: UserId) -> Result<(), AppError> {
+    debug!(
+        "Making access control for auth_info with user {}",
+        auth_info.user_id()
+    );
+    let actor = AuditActor::of(&auth_info);
+    AccessControl::new(auth_info).is_admin().await?;
+    let user_model = user::get_user(&user_id).await?;
+    if login_protection::unlock(&user_model.username).await? {
+        tracing::info!("User {user_id} has been unlocked");
+        audit::record(
+            AuditRecord::new(actor, AuditAction::UserUnlocked).target(TargetKind::User, user_id),
+        )
+        .await;
+    }
+    Ok(())
+}
+
+/// Replace the rate limit plan of the user
+#[instrument(level = "debug", skip_all)]
+pub async fn set_user_plan(
+    auth_info: impl AuthInfo,
+    user_id: UserId,
+    payload: web_app_request::SetPlan,
+) -> Result<(), AppError> {
+    let actor = AuditActor::of(&auth_info);
+    AccessControl::new(auth_info).is_admin().await?;
+    let previous = user::set_plan(&user_id, payload.plan.as_deref()).await?;
+    tracing::info!("Plan of user {user_id} changed to {:?}", payload.plan);
+    audit::record(
+        AuditRecord::new(actor, AuditAction::UserPlanChanged)
+            .target(TargetKind::User, user_id)
+            .before(json!({ "plan": previous }))
+            .after(json!({ "plan": payload.plan })),
+    )
+    .await;
+    Ok(())
+}
+
+/// Change the password of the user revoking its other sessions
+///
+/// Returns a new token replacing the one used by the request
+#[instrument(level = "debug", skip_all)]
+pub async fn change_password(
+    auth_info: impl AuthInfo,
+    payload: web_app_request::ChangePassword,
+) -> Result<web_app_response::JWTAuthResponse, AppError> {
+    AccessControl::new(&auth_info).not_impersonated()?;
+    let user_model = password::change_password(
+        auth_info.user_id(),
+        &payload.current_password,
+        &payload.new_password,
+        auth_info.session_id(),
+    )
+    .await?;
+    audit::record(
+        AuditRecord::new(AuditActor::of(&auth_info), AuditAction::PasswordChanged)
+            .target(TargetKind::User, *auth_info.user_id()),
+    )
+    .await;
+    issue_token(user_model, auth_info.session_id().copied()).await
+}
+
+#[instrument(level = "debug", skip_all)]
+pub async fn forgot_password(payload: web_app_request::ForgotPassword) -> Result<(), AppError> {
+    password::request_reset(&payload.email).await
+}
+
+#[instrument(level = "debug", skip_all)]
+pub async fn reset_password(payload: web_app_request::ResetPassword) -> Result<(), AppError> {
+    let user_id = password::reset_password(&payload.token, &payload.new_password).await?;
+    audit::record(
+        AuditRecord::new(AuditActor::anonymous(), AuditAction::PasswordReset)
+            .target(TargetKind::User, user_id),
+    )
+    .await;
+    Ok(())
+}
+
+/// Set the email of the user sending the link verifying it
+#[instrument(level = "debug", skip_all)]
+pub async fn change_email(
+    auth_info: impl AuthInfo,
+    payload: web_app_request::ChangeEmail,
+) -> Result<(), AppError> {
+    AccessControl::new(&auth_info).not_impersonated()?;
+    let previous = user::get_user(auth_info.user_id()).await?.email;
+    email_verification::change_email(auth_info.user_id(), &payload.email).await?;
+    let email = email_verification::normalize(&payload.email)?;
+    if previous.as_deref() != Some(email.as_str()) {
+        audit::record(
+            AuditRecord::new(AuditActor::of(&auth_info), AuditAction::EmailChanged)
+                .target(TargetKind::User, *auth_info.user_id())
+                .before(json!({ "email": previous }))
+                .after(json!({ "email": email })),
+        )
+        .await;
+    }
+    Ok(())
+}
+
+/// Verify the email with the token received by email
+#[instrument(level = "debug", skip_all)]
+pub async fn verify_email(payload: web_app_request::VerifyEmail) -> Result<(), AppError> {
+    let user_id = email_verification::confirm(&payload.token).await?;
+    audit::record(
+        AuditRecord::new(AuditActor::anonymous(), AuditAction::EmailVerified)
+            .target(TargetKind::User, user_id),
+    )
+    .await;
+    Ok(())
+}
+
+/// Send again the link verifying the email
+#[instrument(level = "debug", skip_all)]
+pub async fn resend_email_verification(
+    payload: web_app_request::ResendVerification,
+) -> Result<(), AppError> {
+    email_verification::resend(&payload.email).await
+}
+
+/// Invite the email to create a user with the role
+#[instrument(level = "debug", skip_all)]
+pub async fn invite_user(
+    auth_info: impl AuthInfo,
+    payload: web_app_request::InviteUser,
+) -> Result<web_app_response::Invitation, AppError> {
+    let invited_by = *auth_info.user_id();
+    let actor = AuditActor::of(&auth_info);
+    AccessControl::new(auth_info).is_admin().await?;
+    let invitation = invitation::invite(&invited_by, &payload.email, payload.role).await?;
+    audit::record(
+        AuditRecord::new(actor, AuditAction::InvitationCreated)
+            .target(
+                TargetKind::Invitation,
+                invitation.id.expect("Invitation id must be not missing"),
+            )
+            .after(json!({ "email": &invitation.email, "role": invitation.role })),
+    )
+    .await;
+    Ok(invitation_to_response(invitation))
+}
+
+#[instrument(level = "debug", skip_all)]
+pub async fn list_invitations(
+    auth_info: impl AuthInfo,
+) -> Result<Vec<web_app_response::Invitation>, AppError> {
+    AccessControl::new(auth_info).is_admin().await?;
+    let invitations = invitation::list().await?;
+    Ok(invitations
+        .into_iter()
+        .map(invitation_to_response)
+        .collect())
+}
+
+#[instrument(level = "debug", skip_all)]
+pub async fn resend_invitation(
+    auth_info: impl AuthInfo,
+    invitation_id: InvitationId,
+) -> Result<web_app_response::Invitation, AppError> {
+    let actor = AuditActor::of(&auth_info);
+    AccessControl::new(auth_info).is_admin().await?;
+    let invitation = invitation::resend(&invitation_id).await?;
+    audit::record(
+        AuditRecord::new(actor, AuditAction::InvitationResent)
+            .target(TargetKind::Invitation, invitation_id),
+    )
+    .await;
+    Ok(invitation_to_response(invitation))
+}
+
+#[instrument(level = "debug", skip_all)]
+pub async fn cancel_invitation(
+    auth_info: impl AuthInfo,
+    invitation_id: InvitationId,
+) -> Result<(), AppError> {
+    let actor = AuditActor::of(&auth_info);
+    AccessControl::new(auth_info).is_admin().await?;
+    invitation::cancel(&invitation_id).await?;
+    audit::record(
+        AuditRecord::new(actor, AuditAction::InvitationCancelled)
+            .target(TargetKind::Invitation, invitation_id),
+    )
+    .await;
+    Ok(())
+}
+
+/// Create the invited user and log it in
+#[instrument(level = "debug", skip_all)]
+pub async fn accept_invitation(
+    payload: web_app_request::AcceptInvitation,
+    client: &ClientInfo,
+) -> Result<web_app_response::LoginResponse, AppError> {
+    let user_model = invitation::accept(&payload.token, payload.username, payload.password).await?;
+    let user_id = user_model.id.expect("User id must be not missing");
+    audit::record(
+        AuditRecord::new(AuditActor::user(user_id), AuditAction::InvitationAccepted)
+            .target(TargetKind::User, user_id)
+            .after(json!({
+                "username": &user_model.username,
+                "role": user_model.role,
+                "email": &user_model.email,
+            })),
+    )
+    .await;
+    complete_authentication(user_model, LoginMethod::Invitation, client).await
+}
+
+fn invitation_to_response(invitation: Invitation) -> web_app_response::Invitation {
+    web_app_response::Invitation {
+        id: invitation
+            .id
+            .expect("field id should exist since the model comes from a db query"),
+        email: invitation.email,
+        role: invitation.role,
+        invited_by: invitation.invited_by,
+        expires_at: invitation.expires_at.timestamp_millis(),
+        created_at: invitation.created_at.timestamp_millis(),
+    }
+}
+
+/// Returns the active sessions of the user marking the one making the request
+#[instrument(level = "debug", skip_all)]
+pub async fn list_sessions(
+    auth_info: impl AuthInfo,
+) -> Result<Vec<web_app_response::Session>, AppError> {
+    let sessions = session::list_active(auth_info.user_id()).await?;
+    let current = auth_info.session_id();
+    Ok(sessions
+        .into_iter()
+        .map(|session| session_to_response(session, current))
+        .collect())
+}
+
+/// Revoke a session of the user, it can be the one making the request
+#[instrument(level = "debug", skip_all)]
+pub async fn revoke_session(
+    auth_info: impl AuthInfo,
+    session_id: SessionId,
+) -> Result<(), AppError> {
+    AccessControl::new(&auth_info).not_impersonated()?;
+    session::revoke(auth_info.user_id(), &session_id).await?;
+    audit::record(
+        AuditRecord::new(AuditActor::of(&auth_info), AuditAction::SessionRevoked)
+            .target(TargetKind::Session, session_id),
+    )
+    .await;
+    Ok(())
+}
+
+/// Returns the recent successful and failed logins of the user
+#[instrument(level = "debug", skip_all)]
+pub async fn get_login_history(
+    auth_info: impl AuthInfo,
+    user_id: UserId,
+) -> Result<Vec<web_app_response::LoginAttempt>, AppError> {
+    AccessControl::new(auth_info).is_admin().await?;
+    let user_model = user::get_user(&user_id).await?;
+    let attempts = login_history::list(&user_model).await?;
+    Ok(attempts
+        .into_iter()
+        .map(login_attempt_to_response)
+        .collect())
+}
+
+fn session_to_response(session: Session, current: Option<&SessionId>) -> web_app_response::Session {
+    let id = session
+        .id
+        .expect("field id should exist since the model comes from a db query");
+    web_app_response::Session {
+        id,
+        method: session.method,
+        ip: session.ip,
+        user_agent: session.user_agent,
+        created_at: session.created_at.timestamp_millis(),
+        last_seen_at: session.last_seen_at.timestamp_millis(),
+        expires_at: session.expires_at.timestamp_millis(),
+        current: current == Some(&id),
+    }
+}
+
+fn login_attempt_to_response(attempt: LoginAttempt) -> web_app_response::LoginAttempt {
+    web_app_response::LoginAttempt {
+        username: attempt.username,
+        method: attempt.method,
+        ip: attempt.ip,
+        user_agent: attempt.user_agent,
+        success: attempt.failure.is_none(),
+        failure: attempt.failure,
+        created_at: attempt.created_at.timestamp_millis(),
+    }
+}
+
+/// Issue a short-lived token of the user for the admin impersonating it
+///
+/// Admins cannot be impersonated and impersonation tokens cannot start
+/// another impersonation
+#[instrument(level = "debug", skip_all)]
+pub async fn impersonate_user(
+    auth_info: impl AuthInfo,
+    user_id: UserId,
+) -> Result<web_app_response::JWTAuthResponse, AppError> {
+    let admin_id = *auth_info.user_id();
+    AccessControl::new(auth_info).is_admin().await?;
+    let admin = user::get_user(&admin_id).await?;
+    let user_model = user::get_user(&user_id).await?;
+    if user_model.role == Role::Admin {
+        return Err(AppError::InvalidRequest(anyhow!(
+            "Admins cannot be impersonated"
+        )));
+    }
+    let claims = JWTAuthClaim::impersonation(
+        user_id,
+        user_model.username,
+        ActorClaim {
+            sub: admin_id,
+            username: admin.username,
+        },
+    );
+    let token = claims.build_token().await?;
+    audit::record(
+        AuditRecord::new(
+            AuditActor::user(admin_id),
+            AuditAction::ImpersonationStarted,
+        )
+        .target(TargetKind::User, user_id),
+    )
+    .await;
+    Ok(web_app_response::JWTAuthResponse {
+        token,
+        token_type: "Bearer".into(),
+    })
+}
+
+#[instrument(level = "debug", skip_all)]
+pub async fn create_service_account(
+    auth_info: impl AuthInfo,
+    payload: web_app_request::CreateServiceAccount,
+) -> Result<web_app_response::ServiceAccount, AppError> {
+    let created_by = *auth_info.user_id();
+    let actor = AuditActor::of(&auth_info);
+    AccessControl::new(auth_info).is_admin().await?;
+    let service_account = service_account::create(
+        &created_by,
+        &payload.name,
+        payload.role,
+        payload.scopes,
+        payload.plan,
+    )
+    .await?;
+    audit::record(
+        AuditRecord::new(actor, AuditAction::ServiceAccountCreated)
+            .target(
+                TargetKind::ServiceAccount,
+                service_account
+                    .id
+                    .expect("Service account id must be not missing"),
+            )
+            .after(json!({
+                "name": &service_account.name,
+                "role": service_account.role,
+                "scopes": &service_account.scopes,
+                "plan": &service_account.plan,
+            })),
+    )
+    .await;
+    Ok(service_account_to_response(service_account))
+}
+
+#[instrument(level = "debug", skip_all)]
+pub async fn list_service_accounts(
+    auth_info: impl AuthInfo,
+) -> Result<Vec<web_app_response::ServiceAccount>, AppError> {
+    AccessControl::new(auth_info).is_admin().await?;
+    let service_accounts = service_account::list().await?;
+    Ok(service_accounts
+        .into_iter()
+        .map(service_account_to_response)
+        .collect())
+}
+
+#[instrument(level = "debug", skip_all)]
+pub async fn disable_service_account(
+    auth_info: impl AuthInfo,
+    service_account_id: ServiceAccountId,
+) -> Result<(), AppError> {
+    let actor = AuditActor::of(&auth_info);
+    AccessControl::new(auth_info).is_admin().await?;
+    service_account::disable(&service_account_id).await?;
+    audit::record(
+        AuditRecord::new(actor, AuditAction::ServiceAccountDisabled)
+            .target(TargetKind::ServiceAccount, service_account_id)
+            .after(json!({ "disabled": true })),
+    )
+    .await;
+    Ok(())
+}
+
+#[instrument(level = "debug", skip_all)]
+pub async fn create_service_account_key(
+    auth_info: impl AuthInfo,
+    service_account_id: ServiceAccountId,
+    payload: web_app_request::CreateServiceAccountKey,
+) -> Result<web_app_response::CreatedServiceAccountKey, AppError> {
+    let created_by = *auth_info.user_id();
+    let actor = AuditActor::of(&auth_info);
     AccessControl::new(auth_info).is_admin().await?;
-    user::create_user(payload.username, payload.password, payload.role).await
+    let (key, api_key) =
+        service_account::create_key(&service_account_id, &created_by, &payload.name).await?;
+    audit::record(
+        AuditRecord::new(actor, AuditAction::ServiceAccountKeyCreated)
+            .target(
+                TargetKind::ServiceAccountKey,
+                key.id.expect("Key id must be not missing"),
+            )
+            .after(json!({
+                "service_account_id": service_account_id.to_hex(),
+                "name": &key.name,
+                "prefix": &key.prefix,
+            })),
+    )
+    .await;
+    Ok(web_app_response::CreatedServiceAccountKey {
+        key: service_account_key_to_response(key),
+        api_key,
+    })
+}
+
+#[instrument(level = "debug", skip_all)]
+pub async fn list_service_account_keys(
+    auth_info: impl AuthInfo,
+    service_account_id: ServiceAccountId,
+) -> Result<Vec<web_app_response::ServiceAccountKey>, AppError> {
+    AccessControl::new(auth_info).is_admin().await?;
+    let keys = service_account::list_keys(&service_account_id).await?;
+    Ok(keys
+        .into_iter()
+        .map(service_account_key_to_response)
+        .collect())
+}
+
+#[instrument(level = "debug", skip_all)]
+pub async fn revoke_service_account_key(
+    auth_info: impl AuthInfo,
+    service_account_id: ServiceAccountId,
+    key_id: ServiceAccountKeyId,
+) -> Result<(), AppError> {
+    let actor = AuditActor::of(&auth_info);
+    AccessControl::new(auth_info).is_admin().await?;
+    service_account::revoke_key(&service_account_id, &key_id).await?;
+    audit::record(
+        AuditRecord::new(actor, AuditAction::ServiceAccountKeyRevoked)
+            .target(TargetKind::ServiceAccountKey, key_id)
+            .before(json!({ "service_account_id": service_account_id.to_hex() })),
+    )
+    .await;
+    Ok(())
+}
+
+fn service_account_to_response(
+    service_account: ServiceAccount,
+) -> web_app_response::ServiceAccount {
+    web_app_response::ServiceAccount {
+        id: service_account
+            .id
+            .expect("field id should exist since the model comes from a db query"),
+        name: service_account.name,
+        role: service_account.role,
+        scopes: service_account.scopes,
+        created_by: service_account.created_by,
+        disabled: service_account.disabled,
+        created_at: service_account.created_at.timestamp_millis(),
+        plan: service_account.plan,
+    }
+}
+
+fn service_account_key_to_response(key: ServiceAccountKey) -> web_app_response::ServiceAccountKey {
+    web_app_response::ServiceAccountKey {
+        id: key
+            .id
+            .expect("field id should exist since the model comes from a db query"),
+        name: key.name,
+        prefix: key.prefix,
+        created_by: key.created_by,
+        created_at: key.created_at.timestamp_millis(),
+        last_used_at: key.last_used_at.map(|time| time.timestamp_millis()),
+    }
+}
+
+/// Returns the audit events matching the query, the most recent first
+#[instrument(level = "debug", skip_all)]
+pub async fn search_audit_events(
+    auth_info: impl AuthInfo,
+    query: web_app_request::AuditQuery,
+) -> Result<Vec<web_app_response::AuditEvent>, AppError> {
+    AccessControl::new(auth_info).is_admin().await?;
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_AUDIT_LIMIT)
+        .clamp(1, MAX_AUDIT_LIMIT);
+    let events = audit::search(&audit_filter(&query), limit).await?;
+    Ok(events.into_iter().map(audit_event_to_response).collect())
+}
+
+/// Returns the audit events matching the query as json lines, the oldest first
+#[instrument(level = "debug", skip_all)]
+pub async fn export_audit_events(
+    auth_info: impl AuthInfo,
+    query: web_app_request::AuditQuery,
+) -> Result<impl Stream<Item = Result<String, mongodb::error::Error>>, AppError> {
+    AccessControl::new(auth_info).is_admin().await?;
+    let events = audit::export(&audit_filter(&query)).await?;
+    Ok(events.map_ok(|event| {
+        let mut line = serde_json::to_string(&audit_event_to_response(event))
+            .expect("Audit event is serializable");
+        line.push('\n');
+        line
+    }))
+}
+
+/// Verify that no audit event has been changed or removed
+#[instrument(level = "debug", skip_all)]
+pub async fn verify_audit_log(
+    auth_info: impl AuthInfo,
+) -> Result<web_app_response::AuditVerification, AppError> {
+    AccessControl::new(auth_info).is_admin().await?;
+    let verification = audit::verify().await?;
+    Ok(web_app_response::AuditVerification {
+        valid: verification.broken_at.is_none(),
+        verified: verification.verified,
+        broken_at: verification.broken_at,
+    })
+}
+
+/// Returns the lifecycle and the usage of every sdk version
+#[instrument(level = "debug", skip_all)]
+pub async fn list_sdk_versions(
+    auth_info: impl AuthInfo,
+) -> Result<Vec<web_app_response::SdkVersionUsage>, AppError> {
+    AccessControl::new(auth_info).is_admin().await?;
+    Ok(SdkVersion::ALL
+        .into_iter()
+        .map(|version| {
+            let lifecycle = sdk_version::lifecycle(version);
+            let usage = sdk_version::usage(version);
+            web_app_response::SdkVersionUsage {
+                version,
+                deprecated_at: lifecycle.deprecated_at.map(|time| time.timestamp_millis()),
+                sunset_at: lifecycle.sunset_at.map(|time| time.timestamp_millis()),
+                requests: usage.requests,
+                client_errors: usage.client_errors,
+                server_errors: usage.server_errors,
+            }
+        })
+        .collect())
+}
+
+/// Returns the sdk requests by owner, route and api key in the time range
+#[instrument(level = "debug", skip_all)]
+pub async fn search_usage(
+    auth_info: impl AuthInfo,
+    query: web_app_request::UsageQuery,
+) -> Result<Vec<web_app_response::OwnerUsage>, AppError> {
+    AccessControl::new(auth_info).is_admin().await?;
+    let (from, to) = usage::report_range(query.from, query.to)?;
+    let totals = usage::totals(query.owner.as_ref(), from, to).await?;
+    Ok(totals
+        .into_iter()
+        .map(|totals| web_app_response::OwnerUsage {
+            owner: totals.owner,
+            owner_kind: totals.owner_kind,
+            average_latency_ms: totals.counts.average_latency_ms(),
+            requests: totals.counts.requests as u64,
+            client_errors: totals.counts.client_errors as u64,
+            server_errors: totals.counts.server_errors as u64,
+            max_latency_ms: totals.counts.latency_max_ms as u64,
+            route: totals.route,
+            api_key: totals.api_key,
+        })
+        .collect())
+}
+
+/// Returns the filter of the logs of the instance handling the request
+#[instrument(level = "debug", skip_all)]
+pub async fn get_log_filter(
+    auth_info: impl AuthInfo,
+) -> Result<web_app_response::LogFilter, AppError> {
+    AccessControl::new(auth_info).is_admin().await?;
+    Ok(log_filter_to_response(logging::log_filter()))
+}
+
+/// Replace the filter of the logs of the instance handling the request
+#[instrument(level = "debug", skip_all)]
+pub async fn set_log_filter(
+    auth_info: impl AuthInfo,
+    payload: web_app_request::SetLogFilter,
+) -> Result<web_app_response::LogFilter, AppError> {
+    let actor = AuditActor::of(&auth_info);
+    AccessControl::new(auth_info).is_admin().await?;
+    if payload.revert_after_seconds == Some(0) {
+        return Err(AppError::InvalidRequest(anyhow!(
+            "Revert delay must be greater than zero"
+        )));
+    }
+    if payload
+        .revert_after_seconds
+        .is_some_and(|seconds| seconds > MAX_LOG_FILTER_REVERT.as_secs())
+    {
+        return Err(AppError::InvalidRequest(anyhow!(
+            "Revert delay must be at most {} seconds",
+            MAX_LOG_FILTER_REVERT.as_secs()
+        )));
+    }
+    let previous = logging::log_filter();
+    let filter = logging::set_log_filter(
+        payload.directives,
+        payload.revert_after_seconds.map(Duration::from_secs),
+    )?;
+    audit_log_filter_change(actor, previous, &filter).await;
+    Ok(log_filter_to_response(filter))
+}
+
+/// Restore the configured filter of the logs of the instance handling the request
+#[instrument(level = "debug", skip_all)]
+pub async fn reset_log_filter(
+    auth_info: impl AuthInfo,
+) -> Result<web_app_response::LogFilter, AppError> {
+    let actor = AuditActor::of(&auth_info);
+    AccessControl::new(auth_info).is_admin().await?;
+    let previous = logging::log_filter();
+    let filter = logging::reset_log_filter()?;
+    audit_log_filter_change(actor, previous, &filter).await;
+    Ok(log_filter_to_response(filter))
+}
+
+async fn audit_log_filter_change(actor: AuditActor, previous: LogFilter, filter: &LogFilter) {
+    audit::record(
+        AuditRecord::new(actor, AuditAction::LogFilterChanged)
+            .before(json!({ "directives": previous.directives }))
+            .after(json!({
+                "directives": &filter.directives,
+                "revert_at": filter.revert_at.map(|time| time.timestamp_millis()),
+            })),
+    )
+    .await;
+}
+
+fn log_filter_to_response(filter: LogFilter) -> web_app_response::LogFilter {
+    web_app_response::LogFilter {
+        directives: filter.directives,
+        revert_at: filter.revert_at.map(|time| time.timestamp_millis()),
+    }
+}
+
+fn audit_filter(query: &web_app_request::AuditQuery) -> AuditFilter {
+    AuditFilter {
+        actor: query.actor,
+        target: query.target,
+        from: query.from.map(DateTime::from_millis),
+        to: query.to.map(DateTime::from_millis),
+    }
+}
+
+fn audit_event_to_response(event: AuditEvent) -> web_app_response::AuditEvent {
+    web_app_response::AuditEvent {
+        sequence: event.sequence,
+        actor: web_app_response::AuditActor {
+            kind: event.actor.kind,
+            id: event.actor.id,
+            impersonator: event.actor.impersonator,
+        },
+        action: event.action,
+        target: event.target.map(|target| web_app_response::AuditTarget {
+            kind: target.kind,
+            id: target.id,
+        }),
+        before: event
+            .before
+            .map(|fields| Bson::Document(fields).into_relaxed_extjson()),
+        after: event
+            .after
+            .map(|fields| Bson::Document(fields).into_relaxed_extjson()),
+        ip: event.ip,
+        request_id: event.request_id,
+        created_at: event.created_at.timestamp_millis(),
+        previous_hash: event.previous_hash,
+        hash: event.hash,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::oid::ObjectId;
+
+    use crate::{
+        auth::{APIKeyAuthClaim, ServiceAccountPrincipal},
+        dtos::web_app_request::SetLogFilter,
+        enums::Role,
+        error::AppError,
+    };
+
+    use super::{set_log_filter, MAX_LOG_FILTER_REVERT};
+
+    #[tokio::test]
+    async fn log_filter_revert_bound_test() {
+        let id = ObjectId::new();
+        for seconds in [0, MAX_LOG_FILTER_REVERT.as_secs() + 1, u64::MAX] {
+            let admin = APIKeyAuthClaim {
+                user_id: id,
+                service_account: Some(ServiceAccountPrincipal {
+                    id,
+                    role: Role::Admin,
+                    scopes: vec![],
+                }),
+                plan: None,
+                key_prefix: None,
+            };
+            let payload = SetLogFilter {
+                directives: vec!["trace".into()],
+                revert_after_seconds: Some(seconds),
+            };
+            let result = set_log_filter(admin, payload).await;
+            assert!(matches!(result, Err(AppError::InvalidRequest(_))));
+        }
+    }
 }
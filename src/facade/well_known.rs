@@ -0,0 +1,27 @@
+use jsonwebtoken::jwk::JwkSet;
+use tracing::instrument;
+
+use crate::{
+    error::AppError,
+    service::{
+        environment::ENVIRONMENT,
+        signing_key::{get_keyring, is_asymmetric},
+    },
+};
+
+/// Returns the public keys verifying jwt tokens
+///
+/// The set is empty with symmetric algorithms since their key must stay secret
+#[instrument(level = "debug", skip_all)]
+pub async fn get_jwks() -> Result<JwkSet, AppError> {
+    let uses_keys = ENVIRONMENT
+        .authentication
+        .accepted_algorithms
+        .iter()
+        .any(|algorithm| is_asymmetric(*algorithm));
+    if uses_keys {
+        Ok(get_keyring().await?.jwks())
+    } else {
+        Ok(JwkSet { keys: vec![] })
+    }
+}
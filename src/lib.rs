@@ -1,13 +1,17 @@
 use mongodb::bson::oid::ObjectId;
 
 mod auth;
-mod dtos;
-mod enums;
-mod error;
+pub mod dtos;
+pub mod enums;
+pub mod error;
 mod facade;
 pub mod middleware;
 mod model;
 pub mod router;
 pub mod service;
 
-type UserId = ObjectId;
+pub use sandbox_rust_web_app_dtos::{UserId, WebhookId};
+pub type InvitationId = ObjectId;
+pub type SessionId = ObjectId;
+pub type ServiceAccountId = ObjectId;
+pub type ServiceAccountKeyId = ObjectId;
@@ -1,3 +1,5 @@
+use std::net::SocketAddr;
+
 use axum::{
     http::StatusCode,
     response::{Html, IntoResponse},
@@ -5,48 +7,102 @@ use axum::{
     Router,
 };
 use sandbox_rust_web_app::{
-    middleware::{add_cors_middleware, add_logging_middleware},
-    router::{SDK_ROUTER, WEB_APP_ROUTER},
-    service::{db::get_database_service, environment::ENVIRONMENT},
+    middleware::{
+        add_audit_context_middleware, add_cors_middleware, add_logging_middleware,
+        add_metrics_middleware, add_rate_limit_middleware, add_request_id_middleware,
+    },
+    router::{METRICS_ROUTER, OPENAPI_ROUTER, SDK_ROUTER, WEB_APP_ROUTER, WELL_KNOWN_ROUTER},
+    service::{
+        db::{get_database_service, spawn_index_creation},
+        environment::{spawn_secrets_refresh, ENVIRONMENT},
+        logging::init_logging,
+        mailer::spawn_outbox_delivery,
+        signing_key::spawn_key_rotation,
+        usage::spawn_usage_flush,
+    },
 };
-use tracing_subscriber::fmt::writer::MakeWriterExt;
 
 #[tokio::main]
 async fn main() {
-    let logfile = tracing_appender::rolling::hourly(".logs", "application_logs");
-    let (non_blocking, _guard) = tracing_appender::non_blocking(logfile);
-    let stdout = std::io::stdout.with_max_level(ENVIRONMENT.logging.level);
+    // load and validate the configuration before anything else so that
+    // problems are reported immediately
+    once_cell::sync::Lazy::force(&ENVIRONMENT);
 
-    // initialize tracing logging with level defined by the environment service
-    tracing_subscriber::fmt()
-        .with_max_level(ENVIRONMENT.logging.level)
-        .with_ansi(true)
-        .with_writer(stdout.and(non_blocking))
-        .init();
+    // initialize tracing logging as defined by the environment service,
+    // the guard flushes the log files and the exported spans on exit
+    let _guard = init_logging();
 
     // initialize database service
     get_database_service().await;
+    // keep rotating secrets up to date
+    spawn_secrets_refresh();
+    // generate and rotate jwt signing keys
+    spawn_key_rotation();
+    // deliver emails waiting in the outbox
+    spawn_outbox_delivery();
+    // write the usage counts of the sdk consumers
+    spawn_usage_flush();
+    // unique emails, expiration of sessions and login history, audit chain,
+    // shared rate limit buckets
+    spawn_index_creation();
 
     // build our application two routes, one for the sdk and the other for web application
     let mut app = Router::new()
         // `GET /` goes to `root`
         .route("/", get(handler))
-        // SDK v0 user
-        .nest("/sdk/v0", SDK_ROUTER.to_owned())
+        // every SDK version under its path
+        .nest("/sdk", SDK_ROUTER.to_owned())
+        // public keys and other metadata
+        .nest("/.well-known", WELL_KNOWN_ROUTER.to_owned())
+        // OpenAPI specification and documentation page
+        .merge(OPENAPI_ROUTER.to_owned())
         // Web application router
         .nest("/", WEB_APP_ROUTER.to_owned());
+    // metrics are served on their own port when it is set, so that they are
+    // not exposed with the application
+    if ENVIRONMENT.metrics.enabled {
+        match ENVIRONMENT.metrics.port {
+            Some(port) => spawn_metrics_server(port).await,
+            None => app = app.merge(METRICS_ROUTER.to_owned()),
+        }
+    }
 
     // add 404 for unknown path
     app = app.fallback(handler_404);
+    app = add_metrics_middleware(app);
+    app = add_rate_limit_middleware(app);
+    app = add_audit_context_middleware(app);
     // Add middlewares to our application.
     // Layers are accessed from bottom to up, hence the order is very important
     app = add_logging_middleware(app);
+    app = add_request_id_middleware(app);
     app = add_cors_middleware(app);
 
-    // run our app with hyper, listening globally on port 3000
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
+    // run our app with hyper, listening on the configured address
+    let listener = tokio::net::TcpListener::bind(ENVIRONMENT.server.address())
+        .await
+        .unwrap();
     tracing::info!("listening on {}", listener.local_addr().unwrap());
-    axum::serve(listener, app).await.unwrap();
+    // client addresses are used to protect the login
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
+}
+
+/// Serve the metrics on the port of the configured host
+async fn spawn_metrics_server(port: u16) {
+    let listener = tokio::net::TcpListener::bind((ENVIRONMENT.server.host.as_str(), port))
+        .await
+        .unwrap_or_else(|e| panic!("Cannot serve the metrics on port {port}: {e}"));
+    tracing::info!("metrics on {}", listener.local_addr().unwrap());
+    tokio::spawn(async move {
+        axum::serve(listener, METRICS_ROUTER.to_owned())
+            .await
+            .unwrap();
+    });
 }
 
 async fn handler() -> Html<&'static str> {
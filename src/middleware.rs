@@ -2,14 +2,47 @@
 //!
 //! All the functions receive a `Router` object and return it adding a new `layer`.
 
-use axum::Router;
+use axum::{
+    body::Body,
+    extract::{FromRequestParts, MatchedPath, Request},
+    http::{header::AUTHORIZATION, request::Parts, HeaderMap, HeaderName, HeaderValue},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    RequestPartsExt, Router,
+};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
 use tower_http::{
     cors::{Any, CorsLayer},
-    trace::{DefaultMakeSpan, DefaultOnRequest, DefaultOnResponse, TraceLayer},
+    trace::{DefaultOnRequest, DefaultOnResponse, TraceLayer},
     LatencyUnit,
 };
 
-use crate::service::environment::ENVIRONMENT;
+use std::time::Instant;
+
+use tracing::{debug, debug_span, error, Span};
+
+use crate::{
+    auth::{APIKeyAuthClaim, ApiKey, ClientIp, JWTAuthClaim, TokenAudience},
+    enums::ActorKind,
+    error::AppError,
+    service::{
+        audit::{self, RequestContext},
+        environment::ENVIRONMENT,
+        logging, metrics,
+        rate_limit::{self, RateLimitClient, RateLimitDecision},
+        request_id::{self, REQUEST_ID_HEADER},
+        telemetry,
+        usage::{self, UsageOwner},
+    },
+};
+
+const RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+const RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+const RATELIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+const RATELIMIT_POLICY: HeaderName = HeaderName::from_static("ratelimit-policy");
 
 /// Create CorsLayer for application
 ///
@@ -20,7 +53,8 @@ pub fn add_cors_middleware(router: Router) -> Router {
         CorsLayer::new()
             .allow_origin(Any)
             .allow_methods(Any)
-            .allow_headers(Any),
+            .allow_headers(Any)
+            .expose_headers([REQUEST_ID_HEADER]),
     )
 }
 
@@ -28,9 +62,7 @@ pub fn add_cors_middleware(router: Router) -> Router {
 pub fn add_logging_middleware(router: Router) -> Router {
     router.layer(
         TraceLayer::new_for_http()
-            .make_span_with(
-                DefaultMakeSpan::new().include_headers(ENVIRONMENT.logging.include_headers),
-            )
+            .make_span_with(make_request_span)
             .on_request(DefaultOnRequest::new().level(ENVIRONMENT.logging.level))
             .on_response(
                 DefaultOnResponse::new()
@@ -39,3 +71,321 @@ pub fn add_logging_middleware(router: Router) -> Router {
             ),
     )
 }
+
+/// Span of the request with its id, headers are included redacted if `logging.include_headers`
+///
+/// The span is named after the matched route when it is exported and it
+/// continues the trace of the `traceparent` header.
+fn make_request_span(request: &Request<Body>) -> Span {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let name = match request.extensions().get::<MatchedPath>() {
+        Some(route) => format!("{} {}", request.method(), route.as_str()),
+        None => request.method().to_string(),
+    };
+    let span = match ENVIRONMENT.logging.include_headers {
+        true => debug_span!(
+            "request",
+            method = %request.method(),
+            uri = %request.uri(),
+            version = ?request.version(),
+            request_id = %request_id,
+            headers = ?logging::redact_headers(request.headers()),
+            otel.name = %name,
+            otel.kind = "server",
+        ),
+        false => debug_span!(
+            "request",
+            method = %request.method(),
+            uri = %request.uri(),
+            version = ?request.version(),
+            request_id = %request_id,
+            otel.name = %name,
+            otel.kind = "server",
+        ),
+    };
+    telemetry::set_remote_parent(&span, request.headers());
+    span
+}
+
+/// Create middleware identifying every request with the `x-request-id` header
+///
+/// The id sent by the client is kept when it is valid, otherwise a new one is
+/// generated. It is returned in the response and it is available to the handlers.
+/// This layer must wrap the logging one, whose span reads the id from the request.
+pub fn add_request_id_middleware(router: Router) -> Router {
+    router.layer(middleware::from_fn(request_id))
+}
+
+async fn request_id(mut request: Request, next: Next) -> Response {
+    let id = request_id::accept_or_generate(request.headers().get(REQUEST_ID_HEADER));
+    let value = HeaderValue::from_str(&id).expect("Request ids are visible ascii characters");
+    request
+        .headers_mut()
+        .insert(REQUEST_ID_HEADER, value.clone());
+    let mut response = request_id::scope(id, next.run(request)).await;
+    response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    response
+}
+
+/// Create middleware counting the requests and their latency by matched route
+///
+/// Requests not matching any route are counted under the `unmatched` route
+/// so that scanned paths do not create new series.
+pub fn add_metrics_middleware(router: Router) -> Router {
+    if !ENVIRONMENT.metrics.enabled {
+        return router;
+    }
+    router.layer(middleware::from_fn(record_metrics))
+}
+
+async fn record_metrics(request: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let method = request.method().clone();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched".to_string(), |path| path.as_str().to_string());
+    let response = next.run(request).await;
+    metrics::record_http_request(method.as_str(), &route, response.status(), start.elapsed());
+    response
+}
+
+/// Create middleware recording client address and request id of the audited actions
+pub fn add_audit_context_middleware(router: Router) -> Router {
+    router.layer(middleware::from_fn(audit_context))
+}
+
+async fn audit_context(request: Request, next: Next) -> Response {
+    let (mut parts, body) = request.into_parts();
+    let ClientIp(ip) = ClientIp::from_request_parts(&mut parts, &())
+        .await
+        .unwrap_or(ClientIp(None));
+    let context = RequestContext {
+        ip: ip.map(|ip| ip.to_string()),
+        request_id: request_id::current(),
+    };
+    let request = Request::from_parts(parts, body);
+    audit::with_request_context(context, next.run(request)).await
+}
+
+/// Create middleware limiting the requests of every client
+///
+/// Nothing is added when `rate_limit.enabled` is false
+pub fn add_rate_limit_middleware(router: Router) -> Router {
+    if !ENVIRONMENT.rate_limit.enabled {
+        return router;
+    }
+    router.layer(middleware::from_fn(rate_limit))
+}
+
+async fn rate_limit(request: Request, next: Next) -> Response {
+    let (mut parts, body) = request.into_parts();
+    let client = rate_limit_client(&mut parts).await;
+    let result = rate_limit::check(&client, parts.method.as_str(), parts.uri.path()).await;
+    let request = Request::from_parts(parts, body);
+    let decision = match result {
+        Ok(decision) => decision,
+        // an unavailable store does not stop the requests
+        Err(e) => {
+            error!("Cannot check the rate limit of {}: {e:?}", client.key);
+            return next.run(request).await;
+        }
+    };
+    let mut response = if decision.allowed {
+        next.run(request).await
+    } else {
+        debug!("Request of {} refused by the rate limit", client.key);
+        AppError::TooManyRequests {
+            retry_after: decision.retry_after_seconds(),
+        }
+        .into_response()
+    };
+    add_rate_limit_headers(response.headers_mut(), &decision);
+    response
+}
+
+/// Client of the request identified by its credentials, or by its address
+/// when they are missing or not valid
+///
+/// Clients with valid credentials only take tokens of their own bucket. Refused
+/// credentials are remembered so that sending them again costs no token
+/// decoding nor database lookup.
+async fn rate_limit_client(parts: &mut Parts) -> RateLimitClient {
+    let credentials = parts
+        .headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    if let Some(credentials) = credentials {
+        if let Some(client) = rate_limit::cached_client(&credentials) {
+            return client;
+        }
+        if !rate_limit::is_rejected(&credentials) {
+            match authenticated_client(parts).await {
+                Ok(client) => {
+                    rate_limit::cache_client(&credentials, client.clone());
+                    return client;
+                }
+                // failures of the database do not make the credentials invalid
+                Err(AppError::AuthorizationError(_)) => rate_limit::cache_rejected(&credentials),
+                Err(_) => {}
+            }
+        }
+    }
+    let ClientIp(ip) = ClientIp::from_request_parts(parts, &())
+        .await
+        .unwrap_or(ClientIp(None));
+    RateLimitClient::anonymous(ip)
+}
+
+/// Authenticate the credentials as the routes do
+async fn authenticated_client(parts: &mut Parts) -> Result<RateLimitClient, AppError> {
+    if let Ok(TypedHeader(Authorization(bearer))) =
+        parts.extract::<TypedHeader<Authorization<Bearer>>>().await
+    {
+        if let Ok(claim) = JWTAuthClaim::decode_token(bearer.token(), TokenAudience::WebApp).await {
+            let plan = rate_limit::user_plan(&claim.user_id).await?;
+            return Ok(RateLimitClient::user(&claim.user_id, plan));
+        }
+    }
+    let claim = APIKeyAuthClaim::from_request_parts(parts, &()).await?;
+    match parts.extract::<TypedHeader<Authorization<ApiKey>>>().await {
+        Ok(TypedHeader(Authorization(api_key))) => {
+            Ok(RateLimitClient::api_key(api_key.key(), claim.plan))
+        }
+        // tokens of the client credentials grant
+        Err(_) => Ok(RateLimitClient::service_account(&claim.user_id, claim.plan)),
+    }
+}
+
+/// Describe the quota of the client as in the `RateLimit` header fields draft
+fn add_rate_limit_headers(headers: &mut HeaderMap, decision: &RateLimitDecision) {
+    headers.insert(RATELIMIT_LIMIT, decision.limit.burst.into());
+    headers.insert(RATELIMIT_REMAINING, decision.remaining.into());
+    headers.insert(RATELIMIT_RESET, decision.reset_seconds().into());
+    if let Ok(policy) = HeaderValue::from_str(&decision.policy()) {
+        headers.insert(RATELIMIT_POLICY, policy);
+    }
+}
+
+/// Create middleware metering the authenticated sdk requests and enforcing
+/// the monthly quotas
+///
+/// It is a route layer, requests are counted by the path of the matched route
+pub fn add_usage_metering_middleware(router: Router) -> Router {
+    router.route_layer(middleware::from_fn(meter_usage))
+}
+
+async fn meter_usage(request: Request, next: Next) -> Response {
+    let (mut parts, body) = request.into_parts();
+    // requests without valid credentials are refused by the route
+    let Ok(claim) = APIKeyAuthClaim::from_request_parts(&mut parts, &()).await else {
+        return next.run(Request::from_parts(parts, body)).await;
+    };
+    let path = parts
+        .extensions
+        .get::<MatchedPath>()
+        .map_or_else(|| parts.uri.path(), MatchedPath::as_str);
+    let route = format!("{} {path}", parts.method);
+    let owner = UsageOwner {
+        id: claim.user_id,
+        kind: match claim.service_account {
+            Some(_) => ActorKind::ServiceAccount,
+            None => ActorKind::User,
+        },
+        api_key: claim.key_prefix.clone(),
+        plan: claim.plan.clone(),
+    };
+    if let Err(e) = usage::check_quota(&owner).await {
+        return e.into_response();
+    }
+    // the route does not authenticate the request again
+    parts.extensions.insert(claim);
+    let started_at = Instant::now();
+    let response = next.run(Request::from_parts(parts, body)).await;
+    usage::record(&owner, &route, response.status(), started_at.elapsed());
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::SocketAddr, time::Duration};
+
+    use axum::{
+        body::Body,
+        extract::{ConnectInfo, Request},
+        http::StatusCode,
+        routing::get,
+        Router,
+    };
+    use tower::ServiceExt;
+
+    use crate::service::{
+        metrics,
+        rate_limit::{self, RateLimitClient},
+    };
+
+    use super::{add_metrics_middleware, add_rate_limit_middleware};
+
+    #[tokio::test]
+    async fn metrics_middleware_test() {
+        let nested = Router::new().route("/item/:id", get(|| async { "item" }));
+        let app = add_metrics_middleware(Router::new().nest("/store", nested));
+        for uri in ["/store/item/1", "/store/item/2", "/unknown"] {
+            let request = Request::get(uri).body(Body::empty()).unwrap();
+            app.clone().oneshot(request).await.unwrap();
+        }
+
+        let rendered = metrics::render().unwrap();
+        assert!(rendered.contains(
+            r#"http_requests_total{method="GET",route="/store/item/:id",status="200"} 2"#
+        ));
+        assert!(rendered
+            .contains(r#"http_requests_total{method="GET",route="unmatched",status="404"} 1"#));
+    }
+
+    #[tokio::test]
+    async fn rate_limit_credentials_test() {
+        let app = add_rate_limit_middleware(Router::new().route("/item", get(|| async { "item" })));
+        let address = SocketAddr::from(([203, 0, 113, 7], 4000));
+        let request = |api_key: Option<&str>| {
+            let mut request = Request::get("/item");
+            if let Some(api_key) = api_key {
+                request = request.header("authorization", format!("x-api-key {api_key}"));
+            }
+            let mut request = request.body(Body::empty()).unwrap();
+            request.extensions_mut().insert(ConnectInfo(address));
+            request
+        };
+        // the anonymous burst of the address is exhausted
+        for _ in 0..10 {
+            let response = app.clone().oneshot(request(None)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let response = app.clone().oneshot(request(None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // valid credentials only depend on their own bucket
+        rate_limit::cache_client(
+            "x-api-key valid",
+            RateLimitClient::api_key("valid", Some("free".into())),
+        );
+        let response = app.clone().oneshot(request(Some("valid"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // refused credentials are limited by the address without looking them up
+        rate_limit::cache_rejected("x-api-key bogus");
+        let response = tokio::time::timeout(
+            Duration::from_secs(1),
+            app.clone().oneshot(request(Some("bogus"))),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+}
@@ -2,4 +2,19 @@
 //! Usually they are mapped 1:1 to database entities in order to store and retrieve
 //! them from permanent storage.
 
+pub mod audit_event;
+pub mod email_verification_token;
+pub mod invitation;
+pub mod login_attempt;
+pub mod login_throttle;
+pub mod oidc_login_state;
+pub mod outbox_email;
+pub mod password_reset_token;
+pub mod rate_limit_bucket;
+pub mod service_account;
+pub mod service_account_key;
+pub mod session;
+pub mod signing_key;
+pub mod usage_bucket;
 pub mod user;
+pub mod webhook;
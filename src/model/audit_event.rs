@@ -0,0 +1,120 @@
+use axum::async_trait;
+use mongodb::{
+    bson::{oid::ObjectId, DateTime, Document},
+    Database,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    auth::AuthInfo,
+    enums::{ActorKind, AuditAction, TargetKind},
+    error::AppError,
+    service::db::{serialize_object_id, DatabaseDocument},
+    ServiceAccountId, UserId,
+};
+
+/// Struct representing an entry of the append-only audit log
+///
+/// Every event stores the hash of the previous one and its own hash covers
+/// every field but the identifier, hence, changing or removing an event breaks
+/// the chain from that event on.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEvent {
+    #[serde(
+        rename = "_id",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_object_id"
+    )]
+    pub id: Option<ObjectId>,
+    /// position in the chain starting from 1, it is unique
+    pub sequence: i64,
+    pub actor: AuditActor,
+    pub action: AuditAction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<AuditTarget>,
+    /// audited fields before the action
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub before: Option<Document>,
+    /// audited fields after the action
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<Document>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ip: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    pub created_at: DateTime,
+    pub previous_hash: String,
+    pub hash: String,
+}
+
+/// Principal performing the action
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditActor {
+    pub kind: ActorKind,
+    /// user or service account, missing for anonymous actors
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<ObjectId>,
+    /// admin acting on behalf of the user
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub impersonator: Option<UserId>,
+}
+
+impl AuditActor {
+    /// Principal of the credentials of the request
+    pub fn of(auth_info: &impl AuthInfo) -> Self {
+        let kind = match auth_info.service_account() {
+            Some(_) => ActorKind::ServiceAccount,
+            None => ActorKind::User,
+        };
+        AuditActor {
+            kind,
+            id: Some(*auth_info.user_id()),
+            impersonator: auth_info.actor().map(|actor| actor.sub),
+        }
+    }
+
+    pub fn user(user_id: UserId) -> Self {
+        AuditActor {
+            kind: ActorKind::User,
+            id: Some(user_id),
+            impersonator: None,
+        }
+    }
+
+    pub fn service_account(service_account_id: ServiceAccountId) -> Self {
+        AuditActor {
+            kind: ActorKind::ServiceAccount,
+            id: Some(service_account_id),
+            impersonator: None,
+        }
+    }
+
+    pub fn anonymous() -> Self {
+        AuditActor {
+            kind: ActorKind::Anonymous,
+            id: None,
+            impersonator: None,
+        }
+    }
+}
+
+/// Entity affected by the action
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditTarget {
+    pub kind: TargetKind,
+    pub id: ObjectId,
+}
+
+#[async_trait]
+impl DatabaseDocument for AuditEvent {
+    fn collection_name() -> &'static str {
+        "AuditEvent"
+    }
+
+    async fn dump(&self, db: &Database) -> Result<String, AppError> {
+        let collection = db.collection::<Self>(Self::collection_name());
+        let outcome = collection.insert_one(self, None).await?;
+        let id = outcome.inserted_id.as_object_id().unwrap().to_hex();
+        Ok(id)
+    }
+}
@@ -0,0 +1,46 @@
+use axum::async_trait;
+use mongodb::{
+    bson::{oid::ObjectId, DateTime},
+    Database,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::AppError,
+    service::db::{serialize_object_id, DatabaseDocument},
+    UserId,
+};
+
+/// Struct representing a token sent by email to verify the email of a user
+///
+/// Only the hash of the token is stored and it is deleted when used.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EmailVerificationToken {
+    #[serde(
+        rename = "_id",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_object_id"
+    )]
+    pub id: Option<ObjectId>,
+    pub user_id: UserId,
+    /// email the token has been sent to, it is verified only if the user still has it
+    pub email: String,
+    /// sha256 of the token sent to the user
+    pub token_hash: String,
+    pub expires_at: DateTime,
+    pub created_at: DateTime,
+}
+
+#[async_trait]
+impl DatabaseDocument for EmailVerificationToken {
+    fn collection_name() -> &'static str {
+        "EmailVerificationToken"
+    }
+
+    async fn dump(&self, db: &Database) -> Result<String, AppError> {
+        let collection = db.collection::<Self>(Self::collection_name());
+        let outcome = collection.insert_one(self, None).await?;
+        let id = outcome.inserted_id.as_object_id().unwrap().to_hex();
+        Ok(id)
+    }
+}
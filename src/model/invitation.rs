@@ -0,0 +1,46 @@
+use axum::async_trait;
+use mongodb::{bson::DateTime, Database};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    enums::Role,
+    error::AppError,
+    service::db::{serialize_object_id, DatabaseDocument},
+    InvitationId, UserId,
+};
+
+/// Struct representing a pending invitation of a new user
+///
+/// The invitation is deleted when it is accepted or cancelled.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Invitation {
+    #[serde(
+        rename = "_id",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_object_id"
+    )]
+    pub id: Option<InvitationId>,
+    /// lowercase email the invitation is sent to
+    pub email: String,
+    /// role of the user created by the invitation
+    pub role: Role,
+    pub invited_by: UserId,
+    /// nonce of the last token sent, older tokens are refused
+    pub nonce: String,
+    pub expires_at: DateTime,
+    pub created_at: DateTime,
+}
+
+#[async_trait]
+impl DatabaseDocument for Invitation {
+    fn collection_name() -> &'static str {
+        "Invitation"
+    }
+
+    async fn dump(&self, db: &Database) -> Result<String, AppError> {
+        let collection = db.collection::<Self>(Self::collection_name());
+        let outcome = collection.insert_one(self, None).await?;
+        let id = outcome.inserted_id.as_object_id().unwrap().to_hex();
+        Ok(id)
+    }
+}
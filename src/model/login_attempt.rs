@@ -0,0 +1,49 @@
+use axum::async_trait;
+use mongodb::{
+    bson::{oid::ObjectId, DateTime},
+    Database,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    enums::{LoginFailure, LoginMethod},
+    error::AppError,
+    service::db::{serialize_object_id, DatabaseDocument},
+    UserId,
+};
+
+/// Struct representing a successful or failed login kept in the login history
+///
+/// Failed attempts of unknown usernames are stored as well, without user id.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LoginAttempt {
+    #[serde(
+        rename = "_id",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_object_id"
+    )]
+    pub id: Option<ObjectId>,
+    pub user_id: Option<UserId>,
+    /// username as typed by the client
+    pub username: String,
+    pub method: LoginMethod,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    /// missing when the login succeeded
+    pub failure: Option<LoginFailure>,
+    pub created_at: DateTime,
+}
+
+#[async_trait]
+impl DatabaseDocument for LoginAttempt {
+    fn collection_name() -> &'static str {
+        "LoginAttempt"
+    }
+
+    async fn dump(&self, db: &Database) -> Result<String, AppError> {
+        let collection = db.collection::<Self>(Self::collection_name());
+        let outcome = collection.insert_one(self, None).await?;
+        let id = outcome.inserted_id.as_object_id().unwrap().to_hex();
+        Ok(id)
+    }
+}
@@ -0,0 +1,42 @@
+use axum::async_trait;
+use mongodb::{
+    bson::{oid::ObjectId, DateTime},
+    Database,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::AppError,
+    service::db::{serialize_object_id, DatabaseDocument},
+};
+
+/// Struct counting the failed logins of a username or of a client address
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LoginThrottle {
+    #[serde(
+        rename = "_id",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_object_id"
+    )]
+    pub id: Option<ObjectId>,
+    /// `username:<username>` or `ip:<address>`
+    pub key: String,
+    pub failures: u32,
+    pub first_failure_at: DateTime,
+    /// logins are refused until this time
+    pub locked_until: Option<DateTime>,
+}
+
+#[async_trait]
+impl DatabaseDocument for LoginThrottle {
+    fn collection_name() -> &'static str {
+        "LoginThrottle"
+    }
+
+    async fn dump(&self, db: &Database) -> Result<String, AppError> {
+        let collection = db.collection::<Self>(Self::collection_name());
+        let outcome = collection.insert_one(self, None).await?;
+        let id = outcome.inserted_id.as_object_id().unwrap().to_hex();
+        Ok(id)
+    }
+}
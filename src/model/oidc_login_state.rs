@@ -0,0 +1,46 @@
+use axum::async_trait;
+use mongodb::{
+    bson::{oid::ObjectId, DateTime},
+    Database,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::AppError,
+    service::db::{serialize_object_id, DatabaseDocument},
+};
+
+/// Struct representing a login started with the OpenID Connect provider
+///
+/// It is deleted when the provider redirects the user back so that it
+/// cannot be used twice.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OidcLoginState {
+    #[serde(
+        rename = "_id",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_object_id"
+    )]
+    pub id: Option<ObjectId>,
+    /// value of the `state` parameter binding the callback to this login
+    pub state: String,
+    /// value expected in the `nonce` claim of the id token
+    pub nonce: String,
+    /// PKCE verifier sent to the token endpoint
+    pub code_verifier: String,
+    pub expires_at: DateTime,
+}
+
+#[async_trait]
+impl DatabaseDocument for OidcLoginState {
+    fn collection_name() -> &'static str {
+        "OidcLoginState"
+    }
+
+    async fn dump(&self, db: &Database) -> Result<String, AppError> {
+        let collection = db.collection::<Self>(Self::collection_name());
+        let outcome = collection.insert_one(self, None).await?;
+        let id = outcome.inserted_id.as_object_id().unwrap().to_hex();
+        Ok(id)
+    }
+}
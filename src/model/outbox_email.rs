@@ -0,0 +1,46 @@
+use axum::async_trait;
+use mongodb::{
+    bson::{oid::ObjectId, DateTime},
+    Database,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::AppError,
+    service::db::{serialize_object_id, DatabaseDocument},
+};
+
+/// Struct representing an email waiting to be delivered or already delivered
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OutboxEmail {
+    #[serde(
+        rename = "_id",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_object_id"
+    )]
+    pub id: Option<ObjectId>,
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+    pub attempts: u32,
+    pub sent: bool,
+    /// the email is not delivered before this time, it is moved forward while
+    /// an instance is delivering it
+    pub next_attempt_at: DateTime,
+    pub last_error: Option<String>,
+    pub created_at: DateTime,
+}
+
+#[async_trait]
+impl DatabaseDocument for OutboxEmail {
+    fn collection_name() -> &'static str {
+        "OutboxEmail"
+    }
+
+    async fn dump(&self, db: &Database) -> Result<String, AppError> {
+        let collection = db.collection::<Self>(Self::collection_name());
+        let outcome = collection.insert_one(self, None).await?;
+        let id = outcome.inserted_id.as_object_id().unwrap().to_hex();
+        Ok(id)
+    }
+}
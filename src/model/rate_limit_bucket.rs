@@ -0,0 +1,45 @@
+use axum::async_trait;
+use mongodb::{
+    bson::{oid::ObjectId, DateTime},
+    Database,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::AppError,
+    service::db::{serialize_object_id, DatabaseDocument},
+};
+
+/// Token bucket of a client shared by the instances
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RateLimitBucket {
+    #[serde(
+        rename = "_id",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_object_id"
+    )]
+    pub id: Option<ObjectId>,
+    /// client, optionally followed by the limited route
+    pub key: String,
+    /// tokens left at `updated_at`
+    pub tokens: f64,
+    pub updated_at: DateTime,
+    /// true if the last request took a token
+    pub allowed: bool,
+    /// the bucket is full again at this time, then it is deleted
+    pub expires_at: DateTime,
+}
+
+#[async_trait]
+impl DatabaseDocument for RateLimitBucket {
+    fn collection_name() -> &'static str {
+        "RateLimitBucket"
+    }
+
+    async fn dump(&self, db: &Database) -> Result<String, AppError> {
+        let collection = db.collection::<Self>(Self::collection_name());
+        let outcome = collection.insert_one(self, None).await?;
+        let id = outcome.inserted_id.as_object_id().unwrap().to_hex();
+        Ok(id)
+    }
+}
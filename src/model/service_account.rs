@@ -0,0 +1,50 @@
+use axum::async_trait;
+use mongodb::{bson::DateTime, Database};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    enums::{Role, Scope},
+    error::AppError,
+    service::db::{serialize_object_id, DatabaseDocument},
+    ServiceAccountId, UserId,
+};
+
+/// Struct representing a non-login principal used by integrations
+///
+/// Service accounts authenticate on the sdk routes with their api keys, or
+/// with tokens obtained through the OAuth2 client credentials grant. They do not
+/// depend on the user who created them.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ServiceAccount {
+    #[serde(
+        rename = "_id",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_object_id"
+    )]
+    pub id: Option<ServiceAccountId>,
+    pub name: String,
+    pub role: Role,
+    /// sdk operations allowed to the account
+    pub scopes: Vec<Scope>,
+    pub created_by: UserId,
+    /// disabled accounts are refused with any key or token
+    pub disabled: bool,
+    pub created_at: DateTime,
+    /// rate limit plan, the default plan when missing
+    #[serde(default)]
+    pub plan: Option<String>,
+}
+
+#[async_trait]
+impl DatabaseDocument for ServiceAccount {
+    fn collection_name() -> &'static str {
+        "ServiceAccount"
+    }
+
+    async fn dump(&self, db: &Database) -> Result<String, AppError> {
+        let collection = db.collection::<Self>(Self::collection_name());
+        let outcome = collection.insert_one(self, None).await?;
+        let id = outcome.inserted_id.as_object_id().unwrap().to_hex();
+        Ok(id)
+    }
+}
@@ -0,0 +1,44 @@
+use axum::async_trait;
+use mongodb::{bson::DateTime, Database};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::AppError,
+    service::db::{serialize_object_id, DatabaseDocument},
+    ServiceAccountId, ServiceAccountKeyId, UserId,
+};
+
+/// Struct representing an api key of a service account
+///
+/// Only the hash of the key is stored, the key is shown once when it is created.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ServiceAccountKey {
+    #[serde(
+        rename = "_id",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_object_id"
+    )]
+    pub id: Option<ServiceAccountKeyId>,
+    pub service_account_id: ServiceAccountId,
+    pub name: String,
+    /// first characters of the key to recognize it
+    pub prefix: String,
+    pub key_hash: String,
+    pub created_by: UserId,
+    pub created_at: DateTime,
+    pub last_used_at: Option<DateTime>,
+}
+
+#[async_trait]
+impl DatabaseDocument for ServiceAccountKey {
+    fn collection_name() -> &'static str {
+        "ServiceAccountKey"
+    }
+
+    async fn dump(&self, db: &Database) -> Result<String, AppError> {
+        let collection = db.collection::<Self>(Self::collection_name());
+        let outcome = collection.insert_one(self, None).await?;
+        let id = outcome.inserted_id.as_object_id().unwrap().to_hex();
+        Ok(id)
+    }
+}
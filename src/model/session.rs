@@ -0,0 +1,46 @@
+use axum::async_trait;
+use mongodb::{bson::DateTime, Database};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    enums::LoginMethod,
+    error::AppError,
+    service::db::{serialize_object_id, DatabaseDocument},
+    SessionId, UserId,
+};
+
+/// Struct representing a session started by a successful login
+///
+/// Its identifier is the `sid` claim of the tokens issued for it. The session
+/// is deleted when it is revoked and, after it expires, by a TTL index.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Session {
+    #[serde(
+        rename = "_id",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_object_id"
+    )]
+    pub id: Option<SessionId>,
+    pub user_id: UserId,
+    pub method: LoginMethod,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: DateTime,
+    /// last request made with the session, updated at most once a minute
+    pub last_seen_at: DateTime,
+    pub expires_at: DateTime,
+}
+
+#[async_trait]
+impl DatabaseDocument for Session {
+    fn collection_name() -> &'static str {
+        "Session"
+    }
+
+    async fn dump(&self, db: &Database) -> Result<String, AppError> {
+        let collection = db.collection::<Self>(Self::collection_name());
+        let outcome = collection.insert_one(self, None).await?;
+        let id = outcome.inserted_id.as_object_id().unwrap().to_hex();
+        Ok(id)
+    }
+}
@@ -0,0 +1,52 @@
+use axum::async_trait;
+use jsonwebtoken::Algorithm;
+use mongodb::{
+    bson::{oid::ObjectId, DateTime},
+    Database,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::AppError,
+    service::db::{serialize_object_id, DatabaseDocument},
+};
+
+/// Struct representing an asymmetric key used to sign jwt tokens
+///
+/// Only the newest key that is not retired signs new tokens, retired keys
+/// keep verifying tokens until they expire.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SigningKey {
+    #[serde(
+        rename = "_id",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_object_id"
+    )]
+    pub id: Option<ObjectId>,
+    /// key identifier written in the `kid` header of the tokens
+    pub kid: String,
+    pub algorithm: Algorithm,
+    /// private key in DER format encrypted with the key encryption key
+    pub encrypted_private_key: String,
+    /// public key published in the jwks endpoint serialized as json
+    pub public_jwk: String,
+    pub created_at: DateTime,
+    /// time when the key stopped signing tokens
+    pub retired_at: Option<DateTime>,
+    /// time after which the key does not verify tokens anymore
+    pub expires_at: Option<DateTime>,
+}
+
+#[async_trait]
+impl DatabaseDocument for SigningKey {
+    fn collection_name() -> &'static str {
+        "SigningKey"
+    }
+
+    async fn dump(&self, db: &Database) -> Result<String, AppError> {
+        let collection = db.collection::<Self>(Self::collection_name());
+        let outcome = collection.insert_one(self, None).await?;
+        let id = outcome.inserted_id.as_object_id().unwrap().to_hex();
+        Ok(id)
+    }
+}
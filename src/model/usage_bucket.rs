@@ -0,0 +1,79 @@
+use axum::async_trait;
+use mongodb::{
+    bson::{oid::ObjectId, DateTime},
+    Database,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    enums::ActorKind,
+    error::AppError,
+    service::db::{serialize_object_id, DatabaseDocument},
+};
+
+/// Struct counting the sdk requests of an owner on a route during an hour
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UsageBucket {
+    #[serde(
+        rename = "_id",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_object_id"
+    )]
+    pub id: Option<ObjectId>,
+    /// user or service account sending the requests
+    pub owner: ObjectId,
+    pub owner_kind: ActorKind,
+    /// displayed prefix of the api key, missing for tokens
+    pub api_key: Option<String>,
+    /// method and path of the route, e.g. `GET /sdk/v0/user/:id`
+    pub route: String,
+    /// start of the hour
+    pub hour: DateTime,
+    #[serde(flatten)]
+    pub counts: UsageCounts,
+}
+
+/// Counts of the requests and their latency
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UsageCounts {
+    pub requests: i64,
+    pub client_errors: i64,
+    pub server_errors: i64,
+    pub latency_total_ms: i64,
+    pub latency_max_ms: i64,
+}
+
+impl UsageCounts {
+    /// Mean latency of the requests, zero without requests
+    pub fn average_latency_ms(&self) -> f64 {
+        match self.requests {
+            0 => 0.0,
+            requests => self.latency_total_ms as f64 / requests as f64,
+        }
+    }
+}
+
+/// Counts of an owner on a route with an api key over a time range
+#[derive(Debug, Deserialize)]
+pub struct UsageTotals {
+    pub owner: ObjectId,
+    pub owner_kind: ActorKind,
+    pub api_key: Option<String>,
+    pub route: String,
+    #[serde(flatten)]
+    pub counts: UsageCounts,
+}
+
+#[async_trait]
+impl DatabaseDocument for UsageBucket {
+    fn collection_name() -> &'static str {
+        "UsageBucket"
+    }
+
+    async fn dump(&self, db: &Database) -> Result<String, AppError> {
+        let collection = db.collection::<Self>(Self::collection_name());
+        let outcome = collection.insert_one(self, None).await?;
+        let id = outcome.inserted_id.as_object_id().unwrap().to_hex();
+        Ok(id)
+    }
+}
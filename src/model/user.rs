@@ -1,5 +1,5 @@
 use axum::async_trait;
-use mongodb::Database;
+use mongodb::{bson::DateTime, Database};
 use serde::{Deserialize, Serialize};
 
 use crate::{
@@ -22,6 +22,45 @@ pub struct User {
     pub password_hash: String,
     pub api_key: Option<String>,
     pub role: Role,
+    /// lowercase email address, unique among users
+    pub email: Option<String>,
+    /// true once the user has proven to own the email
+    #[serde(default)]
+    pub email_verified: bool,
+    /// identities of the OpenID Connect provider linked to the user
+    #[serde(default)]
+    pub oidc_identities: Vec<OidcIdentity>,
+    #[serde(default)]
+    pub mfa: UserMfa,
+    /// tokens issued before this time are refused, it is set when the
+    /// password changes to revoke every session
+    pub tokens_valid_after: Option<DateTime>,
+    /// rate limit plan, the default plan when missing
+    #[serde(default)]
+    pub plan: Option<String>,
+}
+
+/// Multi-factor authentication settings of a user
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct UserMfa {
+    /// true once the totp secret has been confirmed with a code
+    pub enabled: bool,
+    /// base32 secret shared with the authenticator app
+    pub totp_secret: Option<String>,
+    /// secret generated by the enrollment waiting for its confirmation
+    pub pending_totp_secret: Option<String>,
+    /// sha256 of the recovery codes not used yet
+    pub recovery_codes: Vec<String>,
+    /// last time step accepted, a code cannot be used twice
+    pub last_totp_step: Option<i64>,
+}
+
+/// Identity of a user on an OpenID Connect provider
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct OidcIdentity {
+    pub issuer: String,
+    /// value of the `sub` claim, unique for the issuer
+    pub subject: String,
 }
 
 #[async_trait]
@@ -32,7 +71,7 @@ impl DatabaseDocument for User {
 
     async fn dump(&self, db: &Database) -> Result<String, AppError> {
         let collection = db.collection::<Self>(Self::collection_name());
-        let outcome = collection.insert_one(self.clone(), None).await?;
+        let outcome = collection.insert_one(self, None).await?;
         let id = outcome.inserted_id.as_object_id().unwrap().to_hex();
         Ok(id)
     }
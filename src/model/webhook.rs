@@ -0,0 +1,85 @@
+use axum::async_trait;
+use mongodb::{bson::DateTime, Database};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    enums::WebhookEvent,
+    error::AppError,
+    service::db::{serialize_object_id, DatabaseDocument},
+    UserId, WebhookId,
+};
+
+/// Struct representing a webhook subscription owned by a user
+///
+/// Every event listed in `events` is sent with a POST request to `url`
+/// signed with `secret`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WebhookSubscription {
+    #[serde(
+        rename = "_id",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_object_id"
+    )]
+    pub id: Option<WebhookId>,
+    pub owner_id: UserId,
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    pub secret: String,
+    /// disabled subscriptions do not receive events
+    pub enabled: bool,
+    /// number of deliveries failed in a row, reset by a successful delivery
+    pub consecutive_failures: u32,
+    pub created_at: DateTime,
+}
+
+#[async_trait]
+impl DatabaseDocument for WebhookSubscription {
+    fn collection_name() -> &'static str {
+        "WebhookSubscription"
+    }
+
+    async fn dump(&self, db: &Database) -> Result<String, AppError> {
+        let collection = db.collection::<Self>(Self::collection_name());
+        let outcome = collection.insert_one(self, None).await?;
+        let id = outcome.inserted_id.as_object_id().unwrap().to_hex();
+        Ok(id)
+    }
+}
+
+/// Struct representing a single attempt of delivering an event to a webhook
+/// subscription
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WebhookDelivery {
+    #[serde(
+        rename = "_id",
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_object_id"
+    )]
+    pub id: Option<mongodb::bson::oid::ObjectId>,
+    pub subscription_id: WebhookId,
+    /// identifier of the event, the same for every attempt of the same event
+    pub event_id: String,
+    pub event: WebhookEvent,
+    /// attempt number starting from 1
+    pub attempt: u32,
+    /// http status code returned by the endpoint, missing if the request failed
+    pub status_code: Option<u16>,
+    /// error message when the request failed or the endpoint returned an error
+    pub error: Option<String>,
+    pub success: bool,
+    pub created_at: DateTime,
+}
+
+#[async_trait]
+impl DatabaseDocument for WebhookDelivery {
+    fn collection_name() -> &'static str {
+        "WebhookDelivery"
+    }
+
+    async fn dump(&self, db: &Database) -> Result<String, AppError> {
+        let collection = db.collection::<Self>(Self::collection_name());
+        let outcome = collection.insert_one(self, None).await?;
+        let id = outcome.inserted_id.as_object_id().unwrap().to_hex();
+        Ok(id)
+    }
+}
@@ -6,9 +6,15 @@
 //! Usually there are more than one according to application sections,
 //! there is at least one router for SDK and another for Web Application.
 
+mod metrics;
+mod openapi;
 mod sdk;
 mod web_app;
+mod well_known;
 
 // Re-export routers
+pub use metrics::METRICS_ROUTER;
+pub use openapi::{OPENAPI, OPENAPI_ROUTER};
 pub use sdk::SDK_ROUTER;
 pub use web_app::WEB_APP_ROUTER;
+pub use well_known::WELL_KNOWN_ROUTER;
@@ -0,0 +1,25 @@
+use axum::{http::header::CONTENT_TYPE, response::IntoResponse, routing::get, Router};
+use axum_extra::{
+    headers::{authorization::Bearer, Authorization},
+    TypedHeader,
+};
+use once_cell::sync::Lazy;
+use prometheus::TEXT_FORMAT;
+
+use crate::{error::AppError, service::metrics};
+
+/// Router serving the metrics to the scraper, it is not part of the api
+pub static METRICS_ROUTER: Lazy<Router> =
+    Lazy::new(|| Router::new().route("/metrics", get(get_metrics)));
+
+/// Returns the metrics in the prometheus text format, a bearer token is
+/// required when `METRICS_TOKEN` is set
+async fn get_metrics(
+    authorization: Option<TypedHeader<Authorization<Bearer>>>,
+) -> Result<impl IntoResponse, AppError> {
+    let token = authorization
+        .as_ref()
+        .map(|TypedHeader(Authorization(bearer))| bearer.token());
+    metrics::authorize(token)?;
+    Ok(([(CONTENT_TYPE, TEXT_FORMAT)], metrics::render()?))
+}
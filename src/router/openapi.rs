@@ -0,0 +1,255 @@
+use std::mem;
+
+use axum::{response::Html, routing::get, Json, Router};
+use once_cell::sync::Lazy;
+use utoipa::{
+    openapi::{
+        security::{
+            ApiKey, ApiKeyValue, ClientCredentials, Flow, HttpAuthScheme, HttpBuilder, OAuth2,
+            Scopes, SecurityScheme,
+        },
+        OpenApi as OpenApiDocument,
+    },
+    Modify, OpenApi,
+};
+
+use crate::{
+    dtos::Id,
+    enums::{
+        ActorKind, AuditAction, LoginFailure, LoginMethod, Role, Scope, SdkVersion, TargetKind,
+        WebhookEvent,
+    },
+    error::{ErrorResponse, OAuthErrorResponse},
+};
+
+use super::{
+    sdk::{SdkV0Api, SdkV1Api},
+    web_app::WebAppApi,
+    well_known::WellKnownApi,
+};
+
+/// Router serving the api documentation
+pub static OPENAPI_ROUTER: Lazy<Router> = Lazy::new(|| {
+    Router::new()
+        .route("/openapi.json", get(get_openapi))
+        .route("/docs", get(get_docs))
+});
+
+/// Specification of every route, built once from the routers documentation
+pub static OPENAPI: Lazy<OpenApiDocument> = Lazy::new(|| {
+    let mut api = ApiDoc::openapi();
+    // prefixes are the paths where the routers are nested
+    api.merge(nested(WebAppApi::openapi(), "", "web app", ""));
+    api.merge(nested(SdkV0Api::openapi(), "/sdk/v0", "sdk v0", "sdk_v0_"));
+    api.merge(nested(SdkV1Api::openapi(), "/sdk/v1", "sdk v1", "sdk_v1_"));
+    api.merge(nested(
+        WellKnownApi::openapi(),
+        "/.well-known",
+        "well known",
+        "",
+    ));
+    api
+});
+
+/// Components shared by the routers
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Sandbox Rust Web App"),
+    components(schemas(
+        Id,
+        ErrorResponse,
+        OAuthErrorResponse,
+        Role,
+        Scope,
+        WebhookEvent,
+        LoginMethod,
+        LoginFailure,
+        AuditAction,
+        ActorKind,
+        TargetKind,
+        SdkVersion,
+    )),
+    modifiers(&SecuritySchemes),
+)]
+struct ApiDoc;
+
+/// Authentication of the web application and of the sdk
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut OpenApiDocument) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "web_app_token",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .description(Some("Token returned by the login"))
+                    .build(),
+            ),
+        );
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                "Authorization",
+                "Api key of a user or of a service account sent as `x-api-key <key>`",
+            ))),
+        );
+        components.add_security_scheme(
+            "client_secret_basic",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Basic)
+                    .description(Some("Client id and secret of the service account"))
+                    .build(),
+            ),
+        );
+        let scopes = Scopes::from_iter(
+            Scope::ALL
+                .iter()
+                .map(|scope| (scope.as_str(), scope.as_str())),
+        );
+        components.add_security_scheme(
+            "client_credentials",
+            SecurityScheme::OAuth2(OAuth2::new([Flow::ClientCredentials(
+                ClientCredentials::new("/sdk/v0/oauth/token", scopes),
+            )])),
+        );
+    }
+}
+
+/// Move the paths of the router under the path where it is nested
+///
+/// Operations are tagged with the router and their identifiers are prefixed
+/// to keep them unique, routers share the names of their handlers.
+fn nested(
+    mut api: OpenApiDocument,
+    prefix: &str,
+    tag: &str,
+    operation_prefix: &str,
+) -> OpenApiDocument {
+    api.paths.paths = mem::take(&mut api.paths.paths)
+        .into_iter()
+        .map(|(path, mut item)| {
+            for operation in item.operations.values_mut() {
+                operation.tags = Some(vec![tag.to_string()]);
+                operation.operation_id = operation
+                    .operation_id
+                    .take()
+                    .map(|id| format!("{operation_prefix}{id}"));
+            }
+            (format!("{prefix}{path}"), item)
+        })
+        .collect();
+    api
+}
+
+/// Returns the OpenAPI 3 specification
+async fn get_openapi() -> Json<&'static OpenApiDocument> {
+    Json(&OPENAPI)
+}
+
+/// Returns the page browsing the specification
+async fn get_docs() -> Html<&'static str> {
+    Html(include_str!("openapi/docs.html"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+        middleware::{self, Next},
+        Router,
+    };
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    use crate::router::{SDK_ROUTER, WEB_APP_ROUTER, WELL_KNOWN_ROUTER};
+
+    use super::OPENAPI;
+
+    fn documented_routes() -> BTreeSet<(String, String)> {
+        let spec = serde_json::to_value(&*OPENAPI).unwrap();
+        spec["paths"]
+            .as_object()
+            .unwrap()
+            .iter()
+            .flat_map(|(path, item)| {
+                item.as_object()
+                    .unwrap()
+                    .keys()
+                    .filter(|key| key.as_str() != "parameters")
+                    .map(|method| (method.clone(), path.clone()))
+            })
+            .collect()
+    }
+
+    fn collect_references(value: &Value, references: &mut BTreeSet<String>) {
+        match value {
+            Value::Object(object) => {
+                for (key, value) in object {
+                    match (key.as_str(), value) {
+                        ("$ref", Value::String(reference)) => {
+                            references.insert(reference.clone());
+                        }
+                        _ => collect_references(value, references),
+                    }
+                }
+            }
+            Value::Array(values) => values
+                .iter()
+                .for_each(|value| collect_references(value, references)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn operation_ids_unique_test() {
+        let spec = serde_json::to_value(&*OPENAPI).unwrap();
+        let mut ids = BTreeSet::new();
+        for item in spec["paths"].as_object().unwrap().values() {
+            for operation in item.as_object().unwrap().values() {
+                let id = operation["operationId"].as_str().unwrap();
+                assert!(ids.insert(id.to_string()), "duplicated operation {id}");
+            }
+        }
+    }
+
+    #[test]
+    fn spec_references_exist_test() {
+        let spec = serde_json::to_value(&*OPENAPI).unwrap();
+        let mut references = BTreeSet::new();
+        collect_references(&spec, &mut references);
+        let schemas = spec["components"]["schemas"].as_object().unwrap();
+        for reference in references {
+            let name = reference.strip_prefix("#/components/schemas/").unwrap();
+            assert!(schemas.contains_key(name), "missing schema {name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn documented_routes_exist_test() {
+        // matched routes answer before authentication and handlers run
+        let app = Router::new()
+            .nest("/sdk", SDK_ROUTER.to_owned())
+            .nest("/.well-known", WELL_KNOWN_ROUTER.to_owned())
+            .nest("/", WEB_APP_ROUTER.to_owned())
+            .route_layer(middleware::from_fn(|_: Request<Body>, _: Next| async {
+                StatusCode::NO_CONTENT
+            }));
+        for (method, path) in documented_routes() {
+            let uri = path.replace("{id}", "1").replace("{key_id}", "2");
+            let request = Request::builder()
+                .method(method.to_uppercase().as_str())
+                .uri(&uri)
+                .body(Body::empty())
+                .unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::NO_CONTENT, "{method} {path}");
+        }
+    }
+}
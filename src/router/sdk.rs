@@ -1,41 +1,155 @@
-use crate::{
-    auth::APIKeyAuthClaim,
-    dtos::{sdk_request, sdk_response, AppJson},
-    UserId,
-};
+//! Sdk routes served concurrently in every version of `SdkVersion` under `/sdk/<version>`.
+//!
+//! Versions share the facades, each one has its own dtos adapted to the ones
+//! of the facades. Deprecated versions answer with the `Deprecation` and
+//! `Sunset` headers and a link to the successor version, sunset versions
+//! answer 410 Gone.
+
+mod v0;
+mod v1;
 
+use anyhow::anyhow;
 use axum::{
-    extract::Path,
-    routing::{get, post},
-    Json, Router,
+    extract::{rejection::FormRejection, Request},
+    http::{
+        header::{CACHE_CONTROL, LINK},
+        HeaderMap, HeaderValue,
+    },
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    Form, Router,
+};
+use axum_extra::{
+    headers::{authorization::Basic, Authorization},
+    TypedHeader,
 };
 use once_cell::sync::Lazy;
 
-use crate::error::AppError;
+use crate::dtos::{sdk_request, AppJson};
+use crate::enums::SdkVersion;
+use crate::error::{AppError, OAuthError};
 use crate::facade::sdk as facade;
+use crate::middleware::add_usage_metering_middleware;
+use crate::service::{environment::SdkVersionVariables, sdk_version};
+
+pub use v0::SdkV0Api;
+pub use v1::SdkV1Api;
 
+/// Every sdk version nested under its path
 pub static SDK_ROUTER: Lazy<Router> = Lazy::new(|| {
     Router::new()
-        .route("/user/:id", get(get_user))
-        .route("/user", post(create_user))
+        .nest(
+            "/v0",
+            versioned(SdkVersion::V0, v0::SDK_V0_ROUTER.to_owned()),
+        )
+        .nest(
+            "/v1",
+            versioned(SdkVersion::V1, v1::SDK_V1_ROUTER.to_owned()),
+        )
 });
 
-/// Returns the user if it exists with all the information
+/// Add the lifecycle headers of the version and count its usage, metering
+/// the requests of every consumer
+fn versioned(version: SdkVersion, router: Router) -> Router {
+    add_usage_metering_middleware(router).layer(middleware::from_fn(
+        move |request: Request, next: Next| serve_version(version, request, next),
+    ))
+}
+
+async fn serve_version(version: SdkVersion, request: Request, next: Next) -> Response {
+    let mut response = match sdk_version::is_sunset(version) {
+        true => AppError::Gone(anyhow!(
+            "Sdk {} is not served anymore, use the latest version",
+            version.as_str()
+        ))
+        .into_response(),
+        false => next.run(request).await,
+    };
+    sdk_version::record_request(version, response.status());
+    add_lifecycle_headers(
+        version,
+        sdk_version::lifecycle(version),
+        response.headers_mut(),
+    );
+    response
+}
+
+/// Tell the clients of a deprecated version when it ends and which version replaces it
+fn add_lifecycle_headers(
+    version: SdkVersion,
+    lifecycle: &SdkVersionVariables,
+    headers: &mut HeaderMap,
+) {
+    let Some(deprecated_at) = lifecycle.deprecated_at else {
+        return;
+    };
+    // structured date of RFC 9745
+    let deprecation = format!("@{}", deprecated_at.timestamp_millis() / 1000);
+    headers.insert("deprecation", HeaderValue::from_str(&deprecation).unwrap());
+    if let Some(sunset_at) = lifecycle.sunset_at {
+        let sunset = httpdate::fmt_http_date(sunset_at.to_system_time());
+        headers.insert("sunset", HeaderValue::from_str(&sunset).unwrap());
+    }
+    if let Some(successor) = version.successor() {
+        let link = format!("</sdk/{}>; rel=\"successor-version\"", successor.as_str());
+        headers.insert(LINK, HeaderValue::from_str(&link).unwrap());
+    }
+}
+
+/// Issue a token to a service account with the OAuth2 client credentials grant
 ///
-/// Request parameter is extracted from the url
-async fn get_user(
-    api_key: APIKeyAuthClaim,
-    Path(id): Path<UserId>,
-) -> Result<AppJson<sdk_response::User>, AppError> {
-    let user = facade::get_user(api_key, id).await?;
-    Ok(AppJson(user))
+/// Client id and secret are read from HTTP basic authentication or from the form
+#[utoipa::path(
+    post,
+    path = "/oauth/token",
+    request_body(content = TokenRequest, content_type = "application/x-www-form-urlencoded"),
+    responses((status = 200, description = "Access token of the service account", body = AccessToken), OAuthError),
+    security((), ("client_secret_basic" = []))
+)]
+async fn issue_token(
+    basic: Option<TypedHeader<Authorization<Basic>>>,
+    payload: Result<Form<sdk_request::TokenRequest>, FormRejection>,
+) -> Result<impl IntoResponse, OAuthError> {
+    let Form(payload) = payload.map_err(|e| OAuthError::InvalidRequest(e.body_text()))?;
+    let (client_id, client_secret) = match &basic {
+        Some(TypedHeader(Authorization(basic))) => {
+            (basic.username().to_string(), basic.password().to_string())
+        }
+        None => match (&payload.client_id, &payload.client_secret) {
+            (Some(id), Some(secret)) => (id.clone(), secret.clone()),
+            _ => return Err(OAuthError::InvalidClient),
+        },
+    };
+    let token = facade::issue_client_credentials_token(&client_id, &client_secret, payload).await?;
+    Ok(([(CACHE_CONTROL, "no-store")], AppJson(token)))
 }
 
-/// Create new user providing required attributes
-async fn create_user(
-    api_key: APIKeyAuthClaim,
-    Json(payload): Json<sdk_request::CreateUser>,
-) -> Result<AppJson<String>, AppError> {
-    let user = facade::create_user(api_key, payload).await?;
-    Ok(AppJson(user))
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderMap;
+    use mongodb::bson::DateTime;
+
+    use crate::{enums::SdkVersion, service::environment::SdkVersionVariables};
+
+    use super::add_lifecycle_headers;
+
+    #[test]
+    fn lifecycle_headers_test() {
+        let mut lifecycle = SdkVersionVariables {
+            version: SdkVersion::V0,
+            deprecated_at: None,
+            sunset_at: None,
+        };
+        let mut headers = HeaderMap::new();
+        add_lifecycle_headers(SdkVersion::V0, &lifecycle, &mut headers);
+        assert!(headers.is_empty());
+
+        lifecycle.deprecated_at =
+            Some(DateTime::parse_rfc3339_str("2026-01-01T00:00:00Z").unwrap());
+        lifecycle.sunset_at = Some(DateTime::parse_rfc3339_str("2026-07-01T00:00:00Z").unwrap());
+        add_lifecycle_headers(SdkVersion::V0, &lifecycle, &mut headers);
+        assert_eq!(headers["deprecation"], "@1767225600");
+        assert_eq!(headers["sunset"], "Wed, 01 Jul 2026 00:00:00 GMT");
+        assert_eq!(headers["link"], "</sdk/v1>; rel=\"successor-version\"");
+    }
 }
@@ -0,0 +1,217 @@
+use crate::{
+    auth::APIKeyAuthClaim,
+    dtos::{sdk_request, sdk_response, AppJson},
+    UserId, WebhookId,
+};
+
+use axum::{
+    extract::{Path, Query},
+    routing::{get, post},
+    Json, Router,
+};
+use once_cell::sync::Lazy;
+use utoipa::OpenApi;
+
+use crate::error::AppError;
+use crate::facade::sdk as facade;
+
+use super::issue_token;
+
+/// Routes of the first sdk version, ids are serialized as extended json
+pub static SDK_V0_ROUTER: Lazy<Router> = Lazy::new(|| {
+    Router::new()
+        .route("/oauth/token", post(issue_token))
+        .route("/user/:id", get(get_user))
+        .route("/user", post(create_user))
+        .route("/webhook", get(list_webhooks).post(create_webhook))
+        .route(
+            "/webhook/:id",
+            get(get_webhook)
+                .patch(update_webhook)
+                .delete(delete_webhook),
+        )
+        .route("/webhook/:id/delivery", get(list_webhook_deliveries))
+        .route("/usage", get(get_usage))
+});
+
+/// Documentation of the sdk v0 routes, paths are relative to the router
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        super::issue_token,
+        get_user,
+        create_user,
+        list_webhooks,
+        create_webhook,
+        get_webhook,
+        update_webhook,
+        delete_webhook,
+        list_webhook_deliveries,
+        get_usage,
+    ),
+    components(schemas(
+        sdk_request::CreateUser,
+        sdk_request::TokenRequest,
+        sdk_request::CreateWebhook,
+        sdk_request::UpdateWebhook,
+        sdk_response::User,
+        sdk_response::Webhook,
+        sdk_response::CreatedWebhook,
+        sdk_response::WebhookDelivery,
+        sdk_response::Usage,
+        sdk_response::RouteUsage,
+        sdk_response::AccessToken,
+    )),
+    tags((name = "sdk v0", description = "Routes of the integrations, first version")),
+)]
+pub struct SdkV0Api;
+
+/// Returns the user if it exists with all the information
+///
+/// Request parameter is extracted from the url
+#[utoipa::path(
+    get,
+    path = "/user/{id}",
+    params(("id" = String, Path, description = "Identifier of the user")),
+    responses((status = 200, description = "The user", body = SdkUser), AppError),
+    security(("api_key" = []), ("client_credentials" = ["users:read"]))
+)]
+async fn get_user(
+    api_key: APIKeyAuthClaim,
+    Path(id): Path<UserId>,
+) -> Result<AppJson<sdk_response::User>, AppError> {
+    let user = facade::get_user(api_key, id).await?;
+    Ok(AppJson(user))
+}
+
+/// Create new user providing required attributes
+#[utoipa::path(
+    post,
+    path = "/user",
+    request_body = SdkCreateUser,
+    responses((status = 200, description = "Identifier of the new user", body = String), AppError),
+    security(("api_key" = []), ("client_credentials" = ["users:write"]))
+)]
+async fn create_user(
+    api_key: APIKeyAuthClaim,
+    Json(payload): Json<sdk_request::CreateUser>,
+) -> Result<AppJson<String>, AppError> {
+    let user = facade::create_user(api_key, payload).await?;
+    Ok(AppJson(user))
+}
+
+/// Subscribe a new webhook to the events
+///
+/// The response contains the secret used to sign deliveries, it is not returned again.
+/// Events of every user, e.g. `user.created`, require the admin role
+#[utoipa::path(
+    post,
+    path = "/webhook",
+    request_body = CreateWebhook,
+    responses((status = 200, description = "The webhook with its signing secret", body = CreatedWebhook), AppError),
+    security(("api_key" = []), ("client_credentials" = ["webhooks:write"]))
+)]
+async fn create_webhook(
+    api_key: APIKeyAuthClaim,
+    Json(payload): Json<sdk_request::CreateWebhook>,
+) -> Result<AppJson<sdk_response::CreatedWebhook>, AppError> {
+    let webhook = facade::create_webhook(api_key, payload).await?;
+    Ok(AppJson(webhook))
+}
+
+/// Returns the webhooks owned by the api key user
+#[utoipa::path(
+    get,
+    path = "/webhook",
+    responses((status = 200, description = "Webhooks of the api key user", body = [Webhook]), AppError),
+    security(("api_key" = []), ("client_credentials" = ["webhooks:read"]))
+)]
+async fn list_webhooks(
+    api_key: APIKeyAuthClaim,
+) -> Result<AppJson<Vec<sdk_response::Webhook>>, AppError> {
+    let webhooks = facade::list_webhooks(api_key).await?;
+    Ok(AppJson(webhooks))
+}
+
+/// Returns the webhook if it exists and it is owned by the api key user
+#[utoipa::path(
+    get,
+    path = "/webhook/{id}",
+    params(("id" = String, Path, description = "Identifier of the webhook")),
+    responses((status = 200, description = "The webhook", body = Webhook), AppError),
+    security(("api_key" = []), ("client_credentials" = ["webhooks:read"]))
+)]
+async fn get_webhook(
+    api_key: APIKeyAuthClaim,
+    Path(id): Path<WebhookId>,
+) -> Result<AppJson<sdk_response::Webhook>, AppError> {
+    let webhook = facade::get_webhook(api_key, id).await?;
+    Ok(AppJson(webhook))
+}
+
+/// Update url, events or enabled flag of the webhook
+#[utoipa::path(
+    patch,
+    path = "/webhook/{id}",
+    request_body = UpdateWebhook,
+    params(("id" = String, Path, description = "Identifier of the webhook")),
+    responses((status = 200, description = "The updated webhook", body = Webhook), AppError),
+    security(("api_key" = []), ("client_credentials" = ["webhooks:write"]))
+)]
+async fn update_webhook(
+    api_key: APIKeyAuthClaim,
+    Path(id): Path<WebhookId>,
+    Json(payload): Json<sdk_request::UpdateWebhook>,
+) -> Result<AppJson<sdk_response::Webhook>, AppError> {
+    let webhook = facade::update_webhook(api_key, id, payload).await?;
+    Ok(AppJson(webhook))
+}
+
+/// Delete the webhook with its delivery history
+#[utoipa::path(
+    delete,
+    path = "/webhook/{id}",
+    params(("id" = String, Path, description = "Identifier of the webhook")),
+    responses((status = 200, description = "The webhook is deleted"), AppError),
+    security(("api_key" = []), ("client_credentials" = ["webhooks:write"]))
+)]
+async fn delete_webhook(
+    api_key: APIKeyAuthClaim,
+    Path(id): Path<WebhookId>,
+) -> Result<(), AppError> {
+    facade::delete_webhook(api_key, id).await
+}
+
+/// Returns every delivery attempt made to the webhook
+#[utoipa::path(
+    get,
+    path = "/webhook/{id}/delivery",
+    params(("id" = String, Path, description = "Identifier of the webhook")),
+    responses((status = 200, description = "Delivery attempts", body = [WebhookDelivery]), AppError),
+    security(("api_key" = []), ("client_credentials" = ["webhooks:read"]))
+)]
+async fn list_webhook_deliveries(
+    api_key: APIKeyAuthClaim,
+    Path(id): Path<WebhookId>,
+) -> Result<AppJson<Vec<sdk_response::WebhookDelivery>>, AppError> {
+    let deliveries = facade::list_webhook_deliveries(api_key, id).await?;
+    Ok(AppJson(deliveries))
+}
+
+/// Returns the requests of the api key owner by route and api key, the current month by default
+///
+/// Requests of the last seconds may not be counted yet
+#[utoipa::path(
+    get,
+    path = "/usage",
+    params(sdk_request::UsageQuery),
+    responses((status = 200, description = "Usage and monthly quota", body = Usage), AppError),
+    security(("api_key" = []), ("client_credentials" = []))
+)]
+async fn get_usage(
+    api_key: APIKeyAuthClaim,
+    Query(query): Query<sdk_request::UsageQuery>,
+) -> Result<AppJson<sdk_response::Usage>, AppError> {
+    let usage = facade::get_usage(api_key, query).await?;
+    Ok(AppJson(usage))
+}
@@ -0,0 +1,218 @@
+use crate::{
+    auth::APIKeyAuthClaim,
+    dtos::{sdk_v1_request, sdk_v1_response, AppJson},
+    UserId, WebhookId,
+};
+
+use axum::{
+    extract::{Path, Query},
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use once_cell::sync::Lazy;
+use utoipa::OpenApi;
+
+use crate::error::AppError;
+use crate::facade::sdk as facade;
+
+use super::issue_token;
+
+/// Routes of the second sdk version, ids are hex strings and creations answer 201
+pub static SDK_V1_ROUTER: Lazy<Router> = Lazy::new(|| {
+    Router::new()
+        .route("/oauth/token", post(issue_token))
+        .route("/user/:id", get(get_user))
+        .route("/user", post(create_user))
+        .route("/webhook", get(list_webhooks).post(create_webhook))
+        .route(
+            "/webhook/:id",
+            get(get_webhook)
+                .patch(update_webhook)
+                .delete(delete_webhook),
+        )
+        .route("/webhook/:id/delivery", get(list_webhook_deliveries))
+        .route("/usage", get(get_usage))
+});
+
+/// Documentation of the sdk v1 routes, paths are relative to the router
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        super::issue_token,
+        get_user,
+        create_user,
+        list_webhooks,
+        create_webhook,
+        get_webhook,
+        update_webhook,
+        delete_webhook,
+        list_webhook_deliveries,
+        get_usage,
+    ),
+    components(schemas(
+        sdk_v1_request::CreateUser,
+        sdk_v1_request::CreateWebhook,
+        sdk_v1_request::UpdateWebhook,
+        sdk_v1_response::User,
+        sdk_v1_response::Webhook,
+        sdk_v1_response::CreatedWebhook,
+        sdk_v1_response::WebhookDelivery,
+        sdk_v1_response::Usage,
+        sdk_v1_response::RouteUsage,
+    )),
+    tags((name = "sdk v1", description = "Routes of the integrations, second version")),
+)]
+pub struct SdkV1Api;
+
+/// Returns the user if it exists with all the information
+#[utoipa::path(
+    get,
+    path = "/user/{id}",
+    params(("id" = String, Path, description = "Identifier of the user")),
+    responses((status = 200, description = "The user", body = V1User), AppError),
+    security(("api_key" = []), ("client_credentials" = ["users:read"]))
+)]
+async fn get_user(
+    api_key: APIKeyAuthClaim,
+    Path(id): Path<UserId>,
+) -> Result<AppJson<sdk_v1_response::User>, AppError> {
+    let user = facade::get_user(api_key, id).await?;
+    Ok(AppJson(user.into()))
+}
+
+/// Create new user providing required attributes
+#[utoipa::path(
+    post,
+    path = "/user",
+    request_body = V1CreateUser,
+    responses((status = 201, description = "The new user", body = V1User), AppError),
+    security(("api_key" = []), ("client_credentials" = ["users:write"]))
+)]
+async fn create_user(
+    api_key: APIKeyAuthClaim,
+    Json(payload): Json<sdk_v1_request::CreateUser>,
+) -> Result<(StatusCode, AppJson<sdk_v1_response::User>), AppError> {
+    let username = payload.username.clone();
+    let id = facade::create_user(api_key, payload.into()).await?;
+    let user = sdk_v1_response::User { id, username };
+    Ok((StatusCode::CREATED, AppJson(user)))
+}
+
+/// Subscribe a new webhook to the events
+///
+/// The response contains the secret used to sign deliveries, it is not returned again.
+/// Events of every user, e.g. `user.created`, require the admin role
+#[utoipa::path(
+    post,
+    path = "/webhook",
+    request_body = V1CreateWebhook,
+    responses((status = 201, description = "The webhook with its signing secret", body = V1CreatedWebhook), AppError),
+    security(("api_key" = []), ("client_credentials" = ["webhooks:write"]))
+)]
+async fn create_webhook(
+    api_key: APIKeyAuthClaim,
+    Json(payload): Json<sdk_v1_request::CreateWebhook>,
+) -> Result<(StatusCode, AppJson<sdk_v1_response::CreatedWebhook>), AppError> {
+    let webhook = facade::create_webhook(api_key, payload.into()).await?;
+    Ok((StatusCode::CREATED, AppJson(webhook.into())))
+}
+
+/// Returns the webhooks owned by the api key user
+#[utoipa::path(
+    get,
+    path = "/webhook",
+    responses((status = 200, description = "Webhooks of the api key user", body = [V1Webhook]), AppError),
+    security(("api_key" = []), ("client_credentials" = ["webhooks:read"]))
+)]
+async fn list_webhooks(
+    api_key: APIKeyAuthClaim,
+) -> Result<AppJson<Vec<sdk_v1_response::Webhook>>, AppError> {
+    let webhooks = facade::list_webhooks(api_key).await?;
+    Ok(AppJson(webhooks.into_iter().map(Into::into).collect()))
+}
+
+/// Returns the webhook if it exists and it is owned by the api key user
+#[utoipa::path(
+    get,
+    path = "/webhook/{id}",
+    params(("id" = String, Path, description = "Identifier of the webhook")),
+    responses((status = 200, description = "The webhook", body = V1Webhook), AppError),
+    security(("api_key" = []), ("client_credentials" = ["webhooks:read"]))
+)]
+async fn get_webhook(
+    api_key: APIKeyAuthClaim,
+    Path(id): Path<WebhookId>,
+) -> Result<AppJson<sdk_v1_response::Webhook>, AppError> {
+    let webhook = facade::get_webhook(api_key, id).await?;
+    Ok(AppJson(webhook.into()))
+}
+
+/// Update url, events or enabled flag of the webhook
+#[utoipa::path(
+    patch,
+    path = "/webhook/{id}",
+    request_body = V1UpdateWebhook,
+    params(("id" = String, Path, description = "Identifier of the webhook")),
+    responses((status = 200, description = "The updated webhook", body = V1Webhook), AppError),
+    security(("api_key" = []), ("client_credentials" = ["webhooks:write"]))
+)]
+async fn update_webhook(
+    api_key: APIKeyAuthClaim,
+    Path(id): Path<WebhookId>,
+    Json(payload): Json<sdk_v1_request::UpdateWebhook>,
+) -> Result<AppJson<sdk_v1_response::Webhook>, AppError> {
+    let webhook = facade::update_webhook(api_key, id, payload.into()).await?;
+    Ok(AppJson(webhook.into()))
+}
+
+/// Delete the webhook with its delivery history
+#[utoipa::path(
+    delete,
+    path = "/webhook/{id}",
+    params(("id" = String, Path, description = "Identifier of the webhook")),
+    responses((status = 204, description = "The webhook is deleted"), AppError),
+    security(("api_key" = []), ("client_credentials" = ["webhooks:write"]))
+)]
+async fn delete_webhook(
+    api_key: APIKeyAuthClaim,
+    Path(id): Path<WebhookId>,
+) -> Result<StatusCode, AppError> {
+    facade::delete_webhook(api_key, id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Returns every delivery attempt made to the webhook
+#[utoipa::path(
+    get,
+    path = "/webhook/{id}/delivery",
+    params(("id" = String, Path, description = "Identifier of the webhook")),
+    responses((status = 200, description = "Delivery attempts", body = [V1WebhookDelivery]), AppError),
+    security(("api_key" = []), ("client_credentials" = ["webhooks:read"]))
+)]
+async fn list_webhook_deliveries(
+    api_key: APIKeyAuthClaim,
+    Path(id): Path<WebhookId>,
+) -> Result<AppJson<Vec<sdk_v1_response::WebhookDelivery>>, AppError> {
+    let deliveries = facade::list_webhook_deliveries(api_key, id).await?;
+    Ok(AppJson(deliveries.into_iter().map(Into::into).collect()))
+}
+
+/// Returns the requests of the api key owner by route and api key, the current month by default
+///
+/// Requests of the last seconds may not be counted yet
+#[utoipa::path(
+    get,
+    path = "/usage",
+    params(sdk_v1_request::UsageQuery),
+    responses((status = 200, description = "Usage and monthly quota", body = V1Usage), AppError),
+    security(("api_key" = []), ("client_credentials" = []))
+)]
+async fn get_usage(
+    api_key: APIKeyAuthClaim,
+    Query(query): Query<sdk_v1_request::UsageQuery>,
+) -> Result<AppJson<sdk_v1_response::Usage>, AppError> {
+    let query = query.try_into()?;
+    let usage = facade::get_usage(api_key, query).await?;
+    Ok(AppJson(usage.into()))
+}
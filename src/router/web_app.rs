@@ -1,38 +1,297 @@
 use crate::{
-    auth::JWTAuthClaim,
+    auth::{ClientInfo, JWTAuthClaim, MfaEnrollmentClaim},
     dtos::{web_app_request, web_app_response, AppJson},
-    UserId,
+    InvitationId, ServiceAccountId, ServiceAccountKeyId, SessionId, UserId,
 };
 
 use axum::{
-    extract::Path,
-    routing::{get, post},
+    body::Body,
+    extract::{Path, Query},
+    http::{
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE, SET_COOKIE},
+        StatusCode,
+    },
+    response::{IntoResponse, Redirect},
+    routing::{delete, get, post, put},
     Json, Router,
 };
+use axum_extra::{headers::Cookie, TypedHeader};
 use once_cell::sync::Lazy;
+use utoipa::OpenApi;
 
 use crate::error::AppError;
 use crate::facade::web_app as facade;
+use crate::service::oidc::{self, LOGIN_STATE_COOKIE};
 
 pub static WEB_APP_ROUTER: Lazy<Router> = Lazy::new(|| {
     Router::new()
         .route("/login", post(authorize))
+        .route("/login/mfa", post(authorize_mfa))
+        .route("/mfa/totp", post(enroll_totp))
+        .route("/mfa/totp/confirm", post(confirm_totp))
+        .route("/mfa/recovery-codes", post(regenerate_recovery_codes))
+        .route("/password/change", post(change_password))
+        .route("/password/forgot", post(forgot_password))
+        .route("/password/reset", post(reset_password))
+        .route("/email", post(change_email))
+        .route("/email/verify", post(verify_email))
+        .route("/email/resend", post(resend_email_verification))
+        .route("/oidc/login", get(oidc_login))
+        .route("/oidc/callback", get(oidc_callback))
         .route("/user/:id", get(get_user))
         .route("/user", post(create_user))
+        .route("/user/:id/lockout", delete(unlock_user))
+        .route("/user/:id/plan", put(set_user_plan))
+        .route("/invitation", get(list_invitations).post(invite_user))
+        .route("/invitation/accept", post(accept_invitation))
+        .route("/invitation/:id", delete(cancel_invitation))
+        .route("/invitation/:id/resend", post(resend_invitation))
+        .route("/sessions", get(list_sessions))
+        .route("/sessions/:id", delete(revoke_session))
+        .route("/user/:id/logins", get(get_login_history))
+        .route("/user/:id/impersonate", post(impersonate_user))
+        .route(
+            "/service-account",
+            get(list_service_accounts).post(create_service_account),
+        )
+        .route("/service-account/:id", delete(disable_service_account))
+        .route(
+            "/service-account/:id/key",
+            get(list_service_account_keys).post(create_service_account_key),
+        )
+        .route(
+            "/service-account/:id/key/:key_id",
+            delete(revoke_service_account_key),
+        )
+        .route("/audit", get(search_audit_events))
+        .route("/audit/export", get(export_audit_events))
+        .route("/audit/verify", get(verify_audit_log))
+        .route("/sdk-versions", get(list_sdk_versions))
+        .route("/usage", get(search_usage))
+        .route(
+            "/logging/filter",
+            get(get_log_filter)
+                .put(set_log_filter)
+                .delete(reset_log_filter),
+        )
 });
 
+/// Documentation of the web application routes, paths are relative to the router
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        authorize,
+        authorize_mfa,
+        enroll_totp,
+        confirm_totp,
+        regenerate_recovery_codes,
+        change_password,
+        forgot_password,
+        reset_password,
+        change_email,
+        verify_email,
+        resend_email_verification,
+        oidc_login,
+        oidc_callback,
+        get_user,
+        create_user,
+        unlock_user,
+        set_user_plan,
+        list_invitations,
+        invite_user,
+        accept_invitation,
+        cancel_invitation,
+        resend_invitation,
+        list_sessions,
+        revoke_session,
+        get_login_history,
+        impersonate_user,
+        list_service_accounts,
+        create_service_account,
+        disable_service_account,
+        list_service_account_keys,
+        create_service_account_key,
+        revoke_service_account_key,
+        search_audit_events,
+        export_audit_events,
+        verify_audit_log,
+        list_sdk_versions,
+        search_usage,
+        get_log_filter,
+        set_log_filter,
+        reset_log_filter,
+    ),
+    components(schemas(
+        web_app_request::JWTAuthPayload,
+        web_app_request::CreateUser,
+        web_app_request::MfaLoginPayload,
+        web_app_request::MfaCode,
+        web_app_request::ChangePassword,
+        web_app_request::ForgotPassword,
+        web_app_request::ResetPassword,
+        web_app_request::ChangeEmail,
+        web_app_request::VerifyEmail,
+        web_app_request::ResendVerification,
+        web_app_request::InviteUser,
+        web_app_request::AcceptInvitation,
+        web_app_request::CreateServiceAccount,
+        web_app_request::SetPlan,
+        web_app_request::SetLogFilter,
+        web_app_request::CreateServiceAccountKey,
+        web_app_response::JWTAuthResponse,
+        web_app_response::LoginResponse,
+        web_app_response::MfaChallenge,
+        web_app_response::TotpEnrollment,
+        web_app_response::MfaConfirmation,
+        web_app_response::RecoveryCodes,
+        web_app_response::User,
+        web_app_response::Invitation,
+        web_app_response::Session,
+        web_app_response::LoginAttempt,
+        web_app_response::ServiceAccount,
+        web_app_response::ServiceAccountKey,
+        web_app_response::CreatedServiceAccountKey,
+        web_app_response::AuditEvent,
+        web_app_response::AuditActor,
+        web_app_response::AuditTarget,
+        web_app_response::AuditVerification,
+        web_app_response::SdkVersionUsage,
+        web_app_response::OwnerUsage,
+        web_app_response::LogFilter,
+    )),
+    tags((name = "web app", description = "Routes of the web application")),
+)]
+pub struct WebAppApi;
+
 /// Authorize a user with username and password providing jwt token
+///
+/// When the second factor is required, a challenge token is provided instead
+#[utoipa::path(
+    post,
+    path = "/login",
+    request_body = JWTAuthPayload,
+    responses((status = 200, description = "Token, or a challenge when a second factor is required", body = LoginResponse), AppError)
+)]
 async fn authorize(
+    client: ClientInfo,
     Json(payload): Json<web_app_request::JWTAuthPayload>,
+) -> Result<AppJson<web_app_response::LoginResponse>, AppError> {
+    facade::authenticate_user(&payload.username, &payload.password, &client)
+        .await
+        .map(AppJson)
+}
+
+/// Complete the login challenge with a totp code or a recovery code providing jwt token
+#[utoipa::path(
+    post,
+    path = "/login/mfa",
+    request_body = MfaLoginPayload,
+    responses((status = 200, description = "Token of the user", body = JWTAuthResponse), AppError)
+)]
+async fn authorize_mfa(
+    client: ClientInfo,
+    Json(payload): Json<web_app_request::MfaLoginPayload>,
 ) -> Result<AppJson<web_app_response::JWTAuthResponse>, AppError> {
-    facade::authenticate_user(&payload.username, &payload.password)
+    facade::authenticate_mfa(payload, &client)
+        .await
+        .map(AppJson)
+}
+
+/// Start the totp enrollment returning the secret for the authenticator app
+#[utoipa::path(
+    post,
+    path = "/mfa/totp",
+    responses((status = 200, description = "Secret for the authenticator app", body = TotpEnrollment), AppError),
+    security(("web_app_token" = []))
+)]
+async fn enroll_totp(
+    claim: MfaEnrollmentClaim,
+) -> Result<AppJson<web_app_response::TotpEnrollment>, AppError> {
+    facade::enroll_totp(claim).await.map(AppJson)
+}
+
+/// Confirm the totp enrollment with the first code returning the recovery codes
+#[utoipa::path(
+    post,
+    path = "/mfa/totp/confirm",
+    request_body = MfaCode,
+    responses((status = 200, description = "Recovery codes of the enrollment", body = MfaConfirmation), AppError),
+    security(("web_app_token" = []))
+)]
+async fn confirm_totp(
+    claim: MfaEnrollmentClaim,
+    client: ClientInfo,
+    Json(payload): Json<web_app_request::MfaCode>,
+) -> Result<AppJson<web_app_response::MfaConfirmation>, AppError> {
+    facade::confirm_totp(claim, payload, &client)
         .await
         .map(AppJson)
 }
 
+/// Replace the recovery codes of the user
+#[utoipa::path(
+    post,
+    path = "/mfa/recovery-codes",
+    request_body = MfaCode,
+    responses((status = 200, description = "New recovery codes", body = RecoveryCodes), AppError),
+    security(("web_app_token" = []))
+)]
+async fn regenerate_recovery_codes(
+    jwt_claim: JWTAuthClaim,
+    Json(payload): Json<web_app_request::MfaCode>,
+) -> Result<AppJson<web_app_response::RecoveryCodes>, AppError> {
+    facade::regenerate_recovery_codes(jwt_claim, payload)
+        .await
+        .map(AppJson)
+}
+
+/// Redirect the user to the OpenID Connect provider to log in
+///
+/// The login is bound to the browser by a cookie required by the callback
+#[utoipa::path(
+    get,
+    path = "/oidc/login",
+    responses((status = 303, description = "Redirect to the provider"), AppError)
+)]
+async fn oidc_login() -> Result<impl IntoResponse, AppError> {
+    let login = facade::oidc_login().await?;
+    Ok(([(SET_COOKIE, login.cookie)], Redirect::to(&login.url)))
+}
+
+/// Authorize the user redirected back by the OpenID Connect provider providing jwt token
+///
+/// The browser must carry the cookie set when the login started
+#[utoipa::path(
+    get,
+    path = "/oidc/callback",
+    params(web_app_request::OidcCallback),
+    responses((status = 200, description = "Token, or a challenge when a second factor is required", body = LoginResponse), AppError)
+)]
+async fn oidc_callback(
+    client: ClientInfo,
+    cookies: Option<TypedHeader<Cookie>>,
+    Query(payload): Query<web_app_request::OidcCallback>,
+) -> Result<impl IntoResponse, AppError> {
+    let state_cookie = cookies
+        .as_ref()
+        .and_then(|TypedHeader(cookies)| cookies.get(LOGIN_STATE_COOKIE));
+    let response = facade::authenticate_oidc_user(payload, state_cookie, &client).await?;
+    Ok((
+        [(SET_COOKIE, oidc::removed_login_state_cookie()?)],
+        AppJson(response),
+    ))
+}
+
 /// Returns the user if it exists with all the information
 ///
 /// Request parameter is extracted from the url
+#[utoipa::path(
+    get,
+    path = "/user/{id}",
+    params(("id" = String, Path, description = "Identifier of the user")),
+    responses((status = 200, description = "The user", body = User), AppError),
+    security(("web_app_token" = []))
+)]
 async fn get_user(
     jwt_claim: JWTAuthClaim,
     Path(id): Path<UserId>,
@@ -42,6 +301,13 @@ async fn get_user(
 }
 
 /// Create new user providing required attributes
+#[utoipa::path(
+    post,
+    path = "/user",
+    request_body = CreateUser,
+    responses((status = 200, description = "Identifier of the new user", body = String), AppError),
+    security(("web_app_token" = []))
+)]
 async fn create_user(
     jwt_claim: JWTAuthClaim,
     Json(payload): Json<web_app_request::CreateUser>,
@@ -49,3 +315,504 @@ async fn create_user(
     let user = facade::create_user(jwt_claim, payload).await?;
     Ok(AppJson(user))
 }
+
+/// Unlock the user locked after too many failed logins
+#[utoipa::path(
+    delete,
+    path = "/user/{id}/lockout",
+    params(("id" = String, Path, description = "Identifier of the user")),
+    responses((status = 200, description = "The user is unlocked"), AppError),
+    security(("web_app_token" = []))
+)]
+async fn unlock_user(jwt_claim: JWTAuthClaim, Path(id): Path<UserId>) -> Result<(), AppError> {
+    facade::unlock_user(jwt_claim, id).await
+}
+
+/// Replace the rate limit plan of the user, it applies within a minute
+#[utoipa::path(
+    put,
+    path = "/user/{id}/plan",
+    params(("id" = String, Path, description = "Identifier of the user")),
+    request_body = SetPlan,
+    responses((status = 200, description = "The plan is changed"), AppError),
+    security(("web_app_token" = []))
+)]
+async fn set_user_plan(
+    jwt_claim: JWTAuthClaim,
+    Path(id): Path<UserId>,
+    Json(payload): Json<web_app_request::SetPlan>,
+) -> Result<(), AppError> {
+    facade::set_user_plan(jwt_claim, id, payload).await
+}
+
+/// Change the password of the user providing a new jwt token, the other tokens are revoked
+#[utoipa::path(
+    post,
+    path = "/password/change",
+    request_body = ChangePassword,
+    responses((status = 200, description = "New token of the user", body = JWTAuthResponse), AppError),
+    security(("web_app_token" = []))
+)]
+async fn change_password(
+    jwt_claim: JWTAuthClaim,
+    Json(payload): Json<web_app_request::ChangePassword>,
+) -> Result<AppJson<web_app_response::JWTAuthResponse>, AppError> {
+    facade::change_password(jwt_claim, payload)
+        .await
+        .map(AppJson)
+}
+
+/// Send a reset link to the email
+///
+/// The request is always accepted to not reveal which emails are registered
+#[utoipa::path(
+    post,
+    path = "/password/forgot",
+    request_body = ForgotPassword,
+    responses((status = 202, description = "The request is accepted"), AppError)
+)]
+async fn forgot_password(
+    Json(payload): Json<web_app_request::ForgotPassword>,
+) -> Result<StatusCode, AppError> {
+    facade::forgot_password(payload).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Set a new password with the token received by email
+#[utoipa::path(
+    post,
+    path = "/password/reset",
+    request_body = ResetPassword,
+    responses((status = 200, description = "The password is changed"), AppError)
+)]
+async fn reset_password(
+    Json(payload): Json<web_app_request::ResetPassword>,
+) -> Result<(), AppError> {
+    facade::reset_password(payload).await
+}
+
+/// Set the email of the user, a link verifying it is sent to the new email
+#[utoipa::path(
+    post,
+    path = "/email",
+    request_body = ChangeEmail,
+    responses((status = 200, description = "The email is changed"), AppError),
+    security(("web_app_token" = []))
+)]
+async fn change_email(
+    jwt_claim: JWTAuthClaim,
+    Json(payload): Json<web_app_request::ChangeEmail>,
+) -> Result<(), AppError> {
+    facade::change_email(jwt_claim, payload).await
+}
+
+/// Verify the email with the token received by email
+#[utoipa::path(
+    post,
+    path = "/email/verify",
+    request_body = VerifyEmail,
+    responses((status = 200, description = "The email is verified"), AppError)
+)]
+async fn verify_email(Json(payload): Json<web_app_request::VerifyEmail>) -> Result<(), AppError> {
+    facade::verify_email(payload).await
+}
+
+/// Send again the link verifying the email
+///
+/// The request is always accepted to not reveal which emails are registered
+#[utoipa::path(
+    post,
+    path = "/email/resend",
+    request_body = ResendVerification,
+    responses((status = 202, description = "The request is accepted"), AppError)
+)]
+async fn resend_email_verification(
+    Json(payload): Json<web_app_request::ResendVerification>,
+) -> Result<StatusCode, AppError> {
+    facade::resend_email_verification(payload).await?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Invite a new user by email, the invitee chooses username and password
+#[utoipa::path(
+    post,
+    path = "/invitation",
+    request_body = InviteUser,
+    responses((status = 200, description = "The invitation", body = Invitation), AppError),
+    security(("web_app_token" = []))
+)]
+async fn invite_user(
+    jwt_claim: JWTAuthClaim,
+    Json(payload): Json<web_app_request::InviteUser>,
+) -> Result<AppJson<web_app_response::Invitation>, AppError> {
+    facade::invite_user(jwt_claim, payload).await.map(AppJson)
+}
+
+/// Returns the pending invitations
+#[utoipa::path(
+    get,
+    path = "/invitation",
+    responses((status = 200, description = "Pending invitations", body = [Invitation]), AppError),
+    security(("web_app_token" = []))
+)]
+async fn list_invitations(
+    jwt_claim: JWTAuthClaim,
+) -> Result<AppJson<Vec<web_app_response::Invitation>>, AppError> {
+    facade::list_invitations(jwt_claim).await.map(AppJson)
+}
+
+/// Send a new link for the invitation, the previous one is not valid anymore
+#[utoipa::path(
+    post,
+    path = "/invitation/{id}/resend",
+    params(("id" = String, Path, description = "Identifier of the invitation")),
+    responses((status = 200, description = "The invitation", body = Invitation), AppError),
+    security(("web_app_token" = []))
+)]
+async fn resend_invitation(
+    jwt_claim: JWTAuthClaim,
+    Path(id): Path<InvitationId>,
+) -> Result<AppJson<web_app_response::Invitation>, AppError> {
+    facade::resend_invitation(jwt_claim, id).await.map(AppJson)
+}
+
+/// Cancel the invitation
+#[utoipa::path(
+    delete,
+    path = "/invitation/{id}",
+    params(("id" = String, Path, description = "Identifier of the invitation")),
+    responses((status = 200, description = "The invitation is cancelled"), AppError),
+    security(("web_app_token" = []))
+)]
+async fn cancel_invitation(
+    jwt_claim: JWTAuthClaim,
+    Path(id): Path<InvitationId>,
+) -> Result<(), AppError> {
+    facade::cancel_invitation(jwt_claim, id).await
+}
+
+/// Accept the invitation choosing username and password providing jwt token
+#[utoipa::path(
+    post,
+    path = "/invitation/accept",
+    request_body = AcceptInvitation,
+    responses((status = 200, description = "Token, or a challenge when a second factor is required", body = LoginResponse), AppError)
+)]
+async fn accept_invitation(
+    client: ClientInfo,
+    Json(payload): Json<web_app_request::AcceptInvitation>,
+) -> Result<AppJson<web_app_response::LoginResponse>, AppError> {
+    facade::accept_invitation(payload, &client)
+        .await
+        .map(AppJson)
+}
+
+/// Returns the active sessions of the user
+#[utoipa::path(
+    get,
+    path = "/sessions",
+    responses((status = 200, description = "Active sessions", body = [Session]), AppError),
+    security(("web_app_token" = []))
+)]
+async fn list_sessions(
+    jwt_claim: JWTAuthClaim,
+) -> Result<AppJson<Vec<web_app_response::Session>>, AppError> {
+    facade::list_sessions(jwt_claim).await.map(AppJson)
+}
+
+/// Revoke a session of the user, its tokens are refused from now on
+#[utoipa::path(
+    delete,
+    path = "/sessions/{id}",
+    params(("id" = String, Path, description = "Identifier of the session")),
+    responses((status = 200, description = "The session is revoked"), AppError),
+    security(("web_app_token" = []))
+)]
+async fn revoke_session(
+    jwt_claim: JWTAuthClaim,
+    Path(id): Path<SessionId>,
+) -> Result<(), AppError> {
+    facade::revoke_session(jwt_claim, id).await
+}
+
+/// Returns the recent successful and failed logins of the user
+#[utoipa::path(
+    get,
+    path = "/user/{id}/logins",
+    params(("id" = String, Path, description = "Identifier of the user")),
+    responses((status = 200, description = "Recent logins", body = [LoginAttempt]), AppError),
+    security(("web_app_token" = []))
+)]
+async fn get_login_history(
+    jwt_claim: JWTAuthClaim,
+    Path(id): Path<UserId>,
+) -> Result<AppJson<Vec<web_app_response::LoginAttempt>>, AppError> {
+    facade::get_login_history(jwt_claim, id).await.map(AppJson)
+}
+
+/// Issue a short-lived token to act as the user, every request made with it is audited
+#[utoipa::path(
+    post,
+    path = "/user/{id}/impersonate",
+    params(("id" = String, Path, description = "Identifier of the user")),
+    responses((status = 200, description = "Token acting as the user", body = JWTAuthResponse), AppError),
+    security(("web_app_token" = []))
+)]
+async fn impersonate_user(
+    jwt_claim: JWTAuthClaim,
+    Path(id): Path<UserId>,
+) -> Result<AppJson<web_app_response::JWTAuthResponse>, AppError> {
+    facade::impersonate_user(jwt_claim, id).await.map(AppJson)
+}
+
+/// Create a service account used by integrations on the sdk routes
+#[utoipa::path(
+    post,
+    path = "/service-account",
+    request_body = CreateServiceAccount,
+    responses((status = 200, description = "The service account", body = ServiceAccount), AppError),
+    security(("web_app_token" = []))
+)]
+async fn create_service_account(
+    jwt_claim: JWTAuthClaim,
+    Json(payload): Json<web_app_request::CreateServiceAccount>,
+) -> Result<AppJson<web_app_response::ServiceAccount>, AppError> {
+    facade::create_service_account(jwt_claim, payload)
+        .await
+        .map(AppJson)
+}
+
+/// Returns every service account
+#[utoipa::path(
+    get,
+    path = "/service-account",
+    responses((status = 200, description = "Service accounts", body = [ServiceAccount]), AppError),
+    security(("web_app_token" = []))
+)]
+async fn list_service_accounts(
+    jwt_claim: JWTAuthClaim,
+) -> Result<AppJson<Vec<web_app_response::ServiceAccount>>, AppError> {
+    facade::list_service_accounts(jwt_claim).await.map(AppJson)
+}
+
+/// Disable the service account, its keys and tokens are refused from now on
+#[utoipa::path(
+    delete,
+    path = "/service-account/{id}",
+    params(("id" = String, Path, description = "Identifier of the service account")),
+    responses((status = 200, description = "The service account is disabled"), AppError),
+    security(("web_app_token" = []))
+)]
+async fn disable_service_account(
+    jwt_claim: JWTAuthClaim,
+    Path(id): Path<ServiceAccountId>,
+) -> Result<(), AppError> {
+    facade::disable_service_account(jwt_claim, id).await
+}
+
+/// Create an api key of the service account
+///
+/// The response contains the key, it is not returned again
+#[utoipa::path(
+    post,
+    path = "/service-account/{id}/key",
+    request_body = CreateServiceAccountKey,
+    params(("id" = String, Path, description = "Identifier of the service account")),
+    responses((status = 200, description = "The api key with the key itself", body = CreatedServiceAccountKey), AppError),
+    security(("web_app_token" = []))
+)]
+async fn create_service_account_key(
+    jwt_claim: JWTAuthClaim,
+    Path(id): Path<ServiceAccountId>,
+    Json(payload): Json<web_app_request::CreateServiceAccountKey>,
+) -> Result<AppJson<web_app_response::CreatedServiceAccountKey>, AppError> {
+    facade::create_service_account_key(jwt_claim, id, payload)
+        .await
+        .map(AppJson)
+}
+
+/// Returns the api keys of the service account without the keys themselves
+#[utoipa::path(
+    get,
+    path = "/service-account/{id}/key",
+    params(("id" = String, Path, description = "Identifier of the service account")),
+    responses((status = 200, description = "Api keys of the service account", body = [ServiceAccountKey]), AppError),
+    security(("web_app_token" = []))
+)]
+async fn list_service_account_keys(
+    jwt_claim: JWTAuthClaim,
+    Path(id): Path<ServiceAccountId>,
+) -> Result<AppJson<Vec<web_app_response::ServiceAccountKey>>, AppError> {
+    facade::list_service_account_keys(jwt_claim, id)
+        .await
+        .map(AppJson)
+}
+
+/// Revoke the api key of the service account
+#[utoipa::path(
+    delete,
+    path = "/service-account/{id}/key/{key_id}",
+    params(("id" = String, Path, description = "Identifier of the service account"), ("key_id" = String, Path, description = "Identifier of the api key")),
+    responses((status = 200, description = "The api key is revoked"), AppError),
+    security(("web_app_token" = []))
+)]
+async fn revoke_service_account_key(
+    jwt_claim: JWTAuthClaim,
+    Path((id, key_id)): Path<(ServiceAccountId, ServiceAccountKeyId)>,
+) -> Result<(), AppError> {
+    facade::revoke_service_account_key(jwt_claim, id, key_id).await
+}
+
+/// Returns the audit events filtered by actor, target and time range, the most recent first
+#[utoipa::path(
+    get,
+    path = "/audit",
+    params(web_app_request::AuditQuery),
+    responses((status = 200, description = "Matching audit events", body = [AuditEvent]), AppError),
+    security(("web_app_token" = []))
+)]
+async fn search_audit_events(
+    jwt_claim: JWTAuthClaim,
+    Query(query): Query<web_app_request::AuditQuery>,
+) -> Result<AppJson<Vec<web_app_response::AuditEvent>>, AppError> {
+    facade::search_audit_events(jwt_claim, query)
+        .await
+        .map(AppJson)
+}
+
+/// Download the audit events matching the filter as json lines, the oldest first
+#[utoipa::path(
+    get,
+    path = "/audit/export",
+    params(web_app_request::AuditQuery),
+    responses((status = 200, description = "Audit events as json lines", body = String, content_type = "application/x-ndjson"), AppError),
+    security(("web_app_token" = []))
+)]
+async fn export_audit_events(
+    jwt_claim: JWTAuthClaim,
+    Query(query): Query<web_app_request::AuditQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let lines = facade::export_audit_events(jwt_claim, query).await?;
+    Ok((
+        [
+            (CONTENT_TYPE, "application/x-ndjson"),
+            (CONTENT_DISPOSITION, "attachment; filename=\"audit.jsonl\""),
+        ],
+        Body::from_stream(lines),
+    ))
+}
+
+/// Verify the hash chain of the audit log
+#[utoipa::path(
+    get,
+    path = "/audit/verify",
+    responses((status = 200, description = "Outcome of the verification", body = AuditVerification), AppError),
+    security(("web_app_token" = []))
+)]
+async fn verify_audit_log(
+    jwt_claim: JWTAuthClaim,
+) -> Result<AppJson<web_app_response::AuditVerification>, AppError> {
+    facade::verify_audit_log(jwt_claim).await.map(AppJson)
+}
+
+/// Returns deprecation, sunset and usage of every sdk version
+#[utoipa::path(
+    get,
+    path = "/sdk-versions",
+    responses((status = 200, description = "Sdk versions", body = [SdkVersionUsage]), AppError),
+    security(("web_app_token" = []))
+)]
+async fn list_sdk_versions(
+    jwt_claim: JWTAuthClaim,
+) -> Result<AppJson<Vec<web_app_response::SdkVersionUsage>>, AppError> {
+    facade::list_sdk_versions(jwt_claim).await.map(AppJson)
+}
+
+/// Returns the sdk requests by owner, route and api key, the current month by default
+#[utoipa::path(
+    get,
+    path = "/usage",
+    params(web_app_request::UsageQuery),
+    responses((status = 200, description = "Sdk usage", body = [OwnerUsage]), AppError),
+    security(("web_app_token" = []))
+)]
+async fn search_usage(
+    jwt_claim: JWTAuthClaim,
+    Query(query): Query<web_app_request::UsageQuery>,
+) -> Result<AppJson<Vec<web_app_response::OwnerUsage>>, AppError> {
+    facade::search_usage(jwt_claim, query).await.map(AppJson)
+}
+
+/// Returns the filter of the logs of the instance handling the request
+#[utoipa::path(
+    get,
+    path = "/logging/filter",
+    responses((status = 200, description = "Log filter", body = LogFilter), AppError),
+    security(("web_app_token" = []))
+)]
+async fn get_log_filter(
+    jwt_claim: JWTAuthClaim,
+) -> Result<AppJson<web_app_response::LogFilter>, AppError> {
+    facade::get_log_filter(jwt_claim).await.map(AppJson)
+}
+
+/// Replace the filter of the logs of the instance handling the request, optionally
+/// restoring the configured one after a delay
+#[utoipa::path(
+    put,
+    path = "/logging/filter",
+    request_body = SetLogFilter,
+    responses((status = 200, description = "The new log filter", body = LogFilter), AppError),
+    security(("web_app_token" = []))
+)]
+async fn set_log_filter(
+    jwt_claim: JWTAuthClaim,
+    Json(payload): Json<web_app_request::SetLogFilter>,
+) -> Result<AppJson<web_app_response::LogFilter>, AppError> {
+    facade::set_log_filter(jwt_claim, payload)
+        .await
+        .map(AppJson)
+}
+
+/// Restore the configured filter of the logs of the instance handling the request
+#[utoipa::path(
+    delete,
+    path = "/logging/filter",
+    responses((status = 200, description = "The configured log filter", body = LogFilter), AppError),
+    security(("web_app_token" = []))
+)]
+async fn reset_log_filter(
+    jwt_claim: JWTAuthClaim,
+) -> Result<AppJson<web_app_response::LogFilter>, AppError> {
+    facade::reset_log_filter(jwt_claim).await.map(AppJson)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{to_bytes, Body},
+        extract::Request,
+        http::StatusCode,
+    };
+    use tower::ServiceExt;
+
+    use super::WEB_APP_ROUTER;
+
+    #[tokio::test]
+    async fn oidc_callback_without_cookie_test() {
+        let callback = "/oidc/callback?code=attacker-code&state=attacker-state";
+        let request = Request::get(callback).body(Body::empty()).unwrap();
+        let response = WEB_APP_ROUTER.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("not started by this browser"));
+
+        // the cookie of another login is refused as well
+        let request = Request::get(callback)
+            .header("cookie", "oidc_login_state=victim-state")
+            .body(Body::empty())
+            .unwrap();
+        let response = WEB_APP_ROUTER.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
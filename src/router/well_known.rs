@@ -0,0 +1,31 @@
+use axum::{routing::get, Router};
+use jsonwebtoken::jwk::JwkSet;
+use once_cell::sync::Lazy;
+use utoipa::OpenApi;
+
+use crate::dtos::AppJson;
+use crate::error::AppError;
+use crate::facade::well_known as facade;
+
+/// Router for public metadata documents served under `/.well-known`
+pub static WELL_KNOWN_ROUTER: Lazy<Router> =
+    Lazy::new(|| Router::new().route("/jwks.json", get(get_jwks)));
+
+/// Documentation of the metadata routes, paths are relative to the router
+#[derive(OpenApi)]
+#[openapi(
+    paths(get_jwks),
+    tags((name = "well known", description = "Public metadata documents")),
+)]
+pub struct WellKnownApi;
+
+/// Returns the public keys that other services use to verify our tokens
+#[utoipa::path(
+    get,
+    path = "/jwks.json",
+    responses((status = 200, description = "Public keys verifying the tokens", body = Object), AppError)
+)]
+async fn get_jwks() -> Result<AppJson<JwkSet>, AppError> {
+    let jwks = facade::get_jwks().await?;
+    Ok(AppJson(jwks))
+}
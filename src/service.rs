@@ -11,6 +11,27 @@
 //!
 
 pub mod access_control;
+pub mod audit;
 pub mod db;
+pub mod email_verification;
 pub mod environment;
+pub mod invitation;
+pub mod logging;
+pub mod login_history;
+pub mod login_protection;
+pub mod mailer;
+pub mod metrics;
+pub mod mfa;
+pub mod oidc;
+pub mod password;
+pub mod queue;
+pub mod rate_limit;
+pub mod request_id;
+pub mod sdk_version;
+pub mod service_account;
+pub mod session;
+pub mod signing_key;
+pub mod telemetry;
+pub mod usage;
 pub mod user;
+pub mod webhook;
@@ -1,4 +1,9 @@
-use crate::{auth::AuthInfo, enums::Role, error::AppError, service::user::get_user};
+use crate::{
+    auth::AuthInfo,
+    enums::{Role, Scope},
+    error::AppError,
+    service::user::get_user,
+};
 
 /// Access control struct that validate and verify the
 /// role of the user
@@ -12,11 +17,54 @@ impl<T: AuthInfo> AccessControl<T> {
     }
     /// Verify that the user has ADMIN role, otherwise it
     /// returns AccessControlError
+    ///
+    /// Admin operations are never allowed while impersonating
     pub async fn is_admin(self) -> Result<Self, AppError> {
+        if let Some(service_account) = self.auth_info.service_account() {
+            return match service_account.role {
+                Role::Admin => Ok(self),
+                _ => Err(AppError::AccessControlError),
+            };
+        }
         let user = get_user(self.auth_info.user_id()).await?;
+        if self.auth_info.actor().is_some() {
+            return Err(AppError::AccessControlError);
+        }
         match user.role {
             Role::Admin => Ok(self),
             _ => Err(AppError::AccessControlError),
         }
     }
+
+    /// Verify that the service account making the request has the scope,
+    /// otherwise it returns AccessControlError
+    ///
+    /// Users authenticated with their own credentials have every scope
+    pub fn has_scope(self, scope: Scope) -> Result<Self, AppError> {
+        match self.auth_info.service_account() {
+            Some(service_account) if !service_account.scopes.contains(&scope) => {
+                Err(AppError::AccessControlError)
+            }
+            _ => Ok(self),
+        }
+    }
+
+    /// Verify that the request is not made by an admin impersonating the user,
+    /// otherwise it returns AccessControlError
+    ///
+    /// Credentials and security settings can be changed only by the user itself
+    pub fn not_impersonated(self) -> Result<Self, AppError> {
+        match self.auth_info.actor() {
+            Some(actor) => {
+                tracing::warn!(
+                    target: "audit",
+                    impersonator = %actor.sub,
+                    user_id = %self.auth_info.user_id(),
+                    "Operation refused while impersonating"
+                );
+                Err(AppError::AccessControlError)
+            }
+            None => Ok(self),
+        }
+    }
 }
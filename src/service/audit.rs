@@ -0,0 +1,364 @@
+//! Append-only audit log of security-relevant actions.
+//!
+//! Facades and authentication record who did what on which entity, with the
+//! audited fields before and after the action, the client address and the
+//! request id. Events are only ever inserted: each one stores the hash of the
+//! previous event and its own hash, so that changing, removing or reordering
+//! events is detected by the verification of the chain.
+//!
+//! Appends are serialized within the process and the unique sequence makes
+//! concurrent appends of other instances retry on top of the new last event.
+//! Recording is best effort, a failure is logged and does not fail the action.
+
+use std::future::Future;
+
+use anyhow::anyhow;
+use futures::{Stream, TryStreamExt};
+use mongodb::{
+    bson::{doc, oid::ObjectId, to_document, DateTime, Document},
+    options::{FindOneOptions, FindOptions, IndexOptions},
+    IndexModel,
+};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tokio::sync::Mutex;
+use tracing::{error, info};
+
+use crate::{
+    enums::{AuditAction, TargetKind},
+    error::AppError,
+    model::audit_event::{AuditActor, AuditEvent, AuditTarget},
+    service::db::{get_database_service, is_duplicate_key, DatabaseDocument},
+};
+
+/// Previous hash of the first event
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Appends lost against other instances before giving up
+const MAX_APPEND_ATTEMPTS: usize = 5;
+
+static APPEND_LOCK: Mutex<()> = Mutex::const_new(());
+
+tokio::task_local! {
+    static REQUEST_CONTEXT: RequestContext;
+}
+
+/// Information of the request recorded with every event it causes
+#[derive(Debug, Clone, Default)]
+pub struct RequestContext {
+    pub ip: Option<String>,
+    pub request_id: Option<String>,
+}
+
+/// Run the future handling a request, the events it records carry the context
+pub async fn with_request_context<F: Future>(context: RequestContext, future: F) -> F::Output {
+    REQUEST_CONTEXT.scope(context, future).await
+}
+
+/// Action to record in the audit log
+pub struct AuditRecord {
+    actor: AuditActor,
+    action: AuditAction,
+    target: Option<AuditTarget>,
+    before: Option<Value>,
+    after: Option<Value>,
+}
+
+impl AuditRecord {
+    pub fn new(actor: AuditActor, action: AuditAction) -> Self {
+        AuditRecord {
+            actor,
+            action,
+            target: None,
+            before: None,
+            after: None,
+        }
+    }
+
+    pub fn target(mut self, kind: TargetKind, id: ObjectId) -> Self {
+        self.target = Some(AuditTarget { kind, id });
+        self
+    }
+
+    /// Audited fields before the action, it must be a json object
+    pub fn before(mut self, value: Value) -> Self {
+        self.before = Some(value);
+        self
+    }
+
+    /// Audited fields after the action, it must be a json object
+    pub fn after(mut self, value: Value) -> Self {
+        self.after = Some(value);
+        self
+    }
+}
+
+/// Append the action to the audit log
+pub async fn record(record: AuditRecord) {
+    let action = record.action;
+    info!(
+        target: "audit",
+        action = ?action,
+        actor = ?record.actor.id,
+        impersonator = ?record.actor.impersonator,
+        target_id = ?record.target.as_ref().map(|target| target.id),
+        "Audited action"
+    );
+    let context = REQUEST_CONTEXT.try_with(Clone::clone).unwrap_or_default();
+    if let Err(e) = append(record, context).await {
+        error!("Cannot record audit event {action:?}: {e:?}");
+    }
+}
+
+async fn append(record: AuditRecord, context: RequestContext) -> Result<(), AppError> {
+    let before = record.before.as_ref().map(to_fields).transpose()?;
+    let after = record.after.as_ref().map(to_fields).transpose()?;
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<AuditEvent>(AuditEvent::collection_name());
+    let _guard = APPEND_LOCK.lock().await;
+    for _ in 0..MAX_APPEND_ATTEMPTS {
+        let options = FindOneOptions::builder()
+            .sort(doc! { "sequence": -1 })
+            .build();
+        let last = collection.find_one(None, options).await?;
+        let (sequence, previous_hash) = match last {
+            Some(last) => (last.sequence + 1, last.hash),
+            None => (1, GENESIS_HASH.to_string()),
+        };
+        let mut event = AuditEvent {
+            id: None,
+            sequence,
+            actor: record.actor.clone(),
+            action: record.action,
+            target: record.target.clone(),
+            before: before.clone(),
+            after: after.clone(),
+            ip: context.ip.clone(),
+            request_id: context.request_id.clone(),
+            created_at: DateTime::now(),
+            previous_hash,
+            hash: String::new(),
+        };
+        event.hash = compute_hash(&event)?;
+        match event.dump(db).await {
+            Ok(_) => return Ok(()),
+            // another instance appended the same sequence
+            Err(AppError::InternalServerError(e))
+                if e.downcast_ref::<mongodb::error::Error>()
+                    .is_some_and(is_duplicate_key) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Err(AppError::InternalServerError(anyhow!(
+        "Audit log is contended, gave up after {MAX_APPEND_ATTEMPTS} attempts"
+    )))
+}
+
+/// Criteria of the events returned by search and export
+#[derive(Debug, Default)]
+pub struct AuditFilter {
+    /// user or service account performing the action, impersonators included
+    pub actor: Option<ObjectId>,
+    pub target: Option<ObjectId>,
+    pub from: Option<DateTime>,
+    pub to: Option<DateTime>,
+}
+
+impl AuditFilter {
+    fn to_document(&self) -> Document {
+        let mut filter = doc! {};
+        if let Some(actor) = self.actor {
+            filter.insert(
+                "$or",
+                vec![
+                    doc! { "actor.id": actor },
+                    doc! { "actor.impersonator": actor },
+                ],
+            );
+        }
+        if let Some(target) = self.target {
+            filter.insert("target.id", target);
+        }
+        let mut created_at = doc! {};
+        if let Some(from) = self.from {
+            created_at.insert("$gte", from);
+        }
+        if let Some(to) = self.to {
+            created_at.insert("$lt", to);
+        }
+        if !created_at.is_empty() {
+            filter.insert("created_at", created_at);
+        }
+        filter
+    }
+}
+
+/// Returns the events matching the filter, the most recent first
+pub async fn search(filter: &AuditFilter, limit: i64) -> Result<Vec<AuditEvent>, AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<AuditEvent>(AuditEvent::collection_name());
+    let options = FindOptions::builder()
+        .sort(doc! { "sequence": -1 })
+        .limit(limit)
+        .build();
+    Ok(collection
+        .find(filter.to_document(), options)
+        .await?
+        .try_collect()
+        .await?)
+}
+
+/// Returns every event matching the filter in the order they were recorded
+pub async fn export(
+    filter: &AuditFilter,
+) -> Result<impl Stream<Item = Result<AuditEvent, mongodb::error::Error>>, AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<AuditEvent>(AuditEvent::collection_name());
+    let options = FindOptions::builder().sort(doc! { "sequence": 1 }).build();
+    Ok(collection.find(filter.to_document(), options).await?)
+}
+
+/// Outcome of the verification of the whole chain
+pub struct ChainVerification {
+    /// events verified before the end of the chain or the first broken event
+    pub verified: u64,
+    /// sequence of the first event that does not match the chain
+    pub broken_at: Option<i64>,
+}
+
+/// Verify the hashes of every event and their links to the previous ones
+pub async fn verify() -> Result<ChainVerification, AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<AuditEvent>(AuditEvent::collection_name());
+    let options = FindOptions::builder().sort(doc! { "sequence": 1 }).build();
+    let mut events = collection.find(None, options).await?;
+    let mut previous_hash = GENESIS_HASH.to_string();
+    let mut verified = 0;
+    while let Some(event) = events.try_next().await? {
+        if !follows(&event, verified as i64, &previous_hash)? {
+            return Ok(ChainVerification {
+                verified,
+                broken_at: Some(event.sequence),
+            });
+        }
+        previous_hash = event.hash;
+        verified += 1;
+    }
+    Ok(ChainVerification {
+        verified,
+        broken_at: None,
+    })
+}
+
+/// Create the indexes of the audit log
+///
+/// The unique sequence prevents forks of the chain
+pub async fn create_indexes() -> Result<(), AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<AuditEvent>(AuditEvent::collection_name());
+    let sequence = IndexModel::builder()
+        .keys(doc! { "sequence": 1 })
+        .options(IndexOptions::builder().unique(true).build())
+        .build();
+    let actor = IndexModel::builder()
+        .keys(doc! { "actor.id": 1, "sequence": -1 })
+        .build();
+    let impersonator = IndexModel::builder()
+        .keys(doc! { "actor.impersonator": 1, "sequence": -1 })
+        .build();
+    let target = IndexModel::builder()
+        .keys(doc! { "target.id": 1, "sequence": -1 })
+        .build();
+    let created_at = IndexModel::builder().keys(doc! { "created_at": 1 }).build();
+    collection
+        .create_indexes([sequence, actor, impersonator, target, created_at], None)
+        .await?;
+    Ok(())
+}
+
+/// Returns true if the event is intact and it comes right after the previous one
+fn follows(
+    event: &AuditEvent,
+    previous_sequence: i64,
+    previous_hash: &str,
+) -> Result<bool, AppError> {
+    Ok(event.sequence == previous_sequence + 1
+        && event.previous_hash == previous_hash
+        && event.hash == compute_hash(event)?)
+}
+
+/// Hash of the stored fields of the event except its identifier and the hash itself
+fn compute_hash(event: &AuditEvent) -> Result<String, AppError> {
+    let mut document = to_document(event).map_err(anyhow::Error::new)?;
+    document.remove("_id");
+    document.remove("hash");
+    let mut bytes = Vec::new();
+    document.to_writer(&mut bytes).map_err(anyhow::Error::new)?;
+    Ok(hex::encode(Sha256::digest(bytes)))
+}
+
+fn to_fields(value: &Value) -> Result<Document, AppError> {
+    to_document(value).map_err(|e| {
+        AppError::InternalServerError(anyhow!("Audited fields are not an object: {e}"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::{doc, from_document, to_document, DateTime};
+
+    use crate::{
+        enums::{ActorKind, AuditAction, TargetKind},
+        model::audit_event::{AuditActor, AuditEvent, AuditTarget},
+        UserId,
+    };
+
+    use super::{compute_hash, follows, GENESIS_HASH};
+
+    fn event(sequence: i64, previous_hash: &str) -> AuditEvent {
+        let mut event = AuditEvent {
+            id: None,
+            sequence,
+            actor: AuditActor {
+                kind: ActorKind::User,
+                id: Some(UserId::new()),
+                impersonator: None,
+            },
+            action: AuditAction::UserCreated,
+            target: Some(AuditTarget {
+                kind: TargetKind::User,
+                id: UserId::new(),
+            }),
+            before: None,
+            after: Some(doc! { "username": "john", "role": "User" }),
+            ip: Some("127.0.0.1".into()),
+            request_id: None,
+            created_at: DateTime::now(),
+            previous_hash: previous_hash.into(),
+            hash: String::new(),
+        };
+        event.hash = compute_hash(&event).unwrap();
+        event
+    }
+
+    #[test]
+    fn hash_chain_test() {
+        let first = event(1, GENESIS_HASH);
+        let second = event(2, &first.hash);
+        assert!(follows(&first, 0, GENESIS_HASH).unwrap());
+        assert!(follows(&second, 1, &first.hash).unwrap());
+
+        // the hash survives the round trip through the database
+        let mut stored: AuditEvent = from_document(to_document(&second).unwrap()).unwrap();
+        stored.id = Some(UserId::new());
+        assert_eq!(compute_hash(&stored).unwrap(), second.hash);
+
+        // tampered, removed and reordered events break the chain
+        let mut tampered = second.clone();
+        tampered.after = Some(doc! { "username": "john", "role": "Admin" });
+        assert!(!follows(&tampered, 1, &first.hash).unwrap());
+        assert!(!follows(&second, 0, GENESIS_HASH).unwrap());
+        let third = event(3, &second.hash);
+        assert!(!follows(&third, 1, &first.hash).unwrap());
+    }
+}
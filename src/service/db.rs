@@ -1,12 +1,27 @@
 use axum::async_trait;
-use mongodb::{options::ClientOptions, Client, Database};
+use mongodb::{
+    error::{ErrorKind, WriteFailure},
+    event::command::{
+        CommandEventHandler, CommandFailedEvent, CommandStartedEvent, CommandSucceededEvent,
+    },
+    options::ClientOptions,
+    Client, Database,
+};
 
-use crate::{error::AppError, service::environment::ENVIRONMENT};
+use crate::{
+    error::AppError,
+    service::{
+        audit, environment::ENVIRONMENT, login_history, metrics::MongoCommandMetrics, rate_limit,
+        service_account, session, telemetry::MongoCommandSpans, usage, user,
+    },
+};
 
 use mongodb::bson::oid::ObjectId;
 use mongodb::bson::serde_helpers::serialize_object_id_as_hex_string;
 use serde::Serializer;
 
+use std::sync::{Arc, RwLock};
+
 use tokio::sync::OnceCell;
 
 // differently from other global variables, database initialization requires async futures
@@ -26,19 +41,132 @@ pub async fn get_database_service() -> &'static DatabaseService {
 }
 
 /// Database service struct that contain access to the database
+///
+/// The connection can be replaced at runtime when the connection string
+/// is rotated, therefore, the database is accessed through `db()`
 pub struct DatabaseService {
-    pub db: Database,
+    connection: RwLock<DatabaseConnection>,
+}
+
+struct DatabaseConnection {
+    connection_string: String,
+    db: Database,
 }
 
 impl DatabaseService {
     async fn new() -> Result<DatabaseService, AppError> {
-        let client_options = ClientOptions::parse(&ENVIRONMENT.database.connection_string).await?;
+        let connection = DatabaseConnection::open(&ENVIRONMENT.database.connection_string).await?;
+        Ok(DatabaseService {
+            connection: RwLock::new(connection),
+        })
+    }
+
+    /// Returns the database handle, it is cheap to clone
+    pub fn db(&self) -> Database {
+        self.connection
+            .read()
+            .expect("Database connection lock is poisoned")
+            .db
+            .clone()
+    }
+
+    /// Open a new connection if the connection string is changed.
+    ///
+    /// Operations already started keep the old client that is dropped when they end.
+    /// Returns true if the connection has been replaced
+    pub async fn reconnect(&self, connection_string: &str) -> Result<bool, AppError> {
+        let changed = self
+            .connection
+            .read()
+            .expect("Database connection lock is poisoned")
+            .connection_string
+            != connection_string;
+        if changed {
+            let connection = DatabaseConnection::open(connection_string).await?;
+            *self
+                .connection
+                .write()
+                .expect("Database connection lock is poisoned") = connection;
+        }
+        Ok(changed)
+    }
+}
+
+impl DatabaseConnection {
+    async fn open(connection_string: &str) -> Result<DatabaseConnection, AppError> {
+        let mut client_options = ClientOptions::parse(connection_string).await?;
+        let mut observers: Vec<Arc<dyn CommandEventHandler>> = Vec::new();
+        if ENVIRONMENT.metrics.enabled {
+            observers.push(Arc::new(MongoCommandMetrics));
+        }
+        if ENVIRONMENT.telemetry.is_some() {
+            observers.push(Arc::new(MongoCommandSpans::default()));
+        }
+        if !observers.is_empty() {
+            client_options.command_event_handler = Some(Arc::new(CommandObservers(observers)));
+        }
         let client = Client::with_options(client_options)?;
         let db = client.database(&ENVIRONMENT.database.db_name);
-        Ok(DatabaseService { db })
+        Ok(DatabaseConnection {
+            connection_string: connection_string.to_string(),
+            db,
+        })
     }
 }
 
+/// Forwards the command events to every observer, the client accepts only one
+struct CommandObservers(Vec<Arc<dyn CommandEventHandler>>);
+
+impl CommandEventHandler for CommandObservers {
+    fn handle_command_started_event(&self, event: CommandStartedEvent) {
+        for observer in &self.0 {
+            observer.handle_command_started_event(event.clone());
+        }
+    }
+
+    fn handle_command_succeeded_event(&self, event: CommandSucceededEvent) {
+        for observer in &self.0 {
+            observer.handle_command_succeeded_event(event.clone());
+        }
+    }
+
+    fn handle_command_failed_event(&self, event: CommandFailedEvent) {
+        for observer in &self.0 {
+            observer.handle_command_failed_event(event.clone());
+        }
+    }
+}
+
+/// Spawn a background task creating the indexes of the collections
+///
+/// The application starts without waiting for the database, a failure is logged
+pub fn spawn_index_creation() {
+    tokio::spawn(async {
+        let results = [
+            ("User", user::create_indexes().await),
+            ("Session", session::create_indexes().await),
+            ("LoginAttempt", login_history::create_indexes().await),
+            ("ServiceAccountKey", service_account::create_indexes().await),
+            ("AuditEvent", audit::create_indexes().await),
+            ("RateLimitBucket", rate_limit::create_indexes().await),
+            ("UsageBucket", usage::create_indexes().await),
+        ];
+        for (collection, result) in results {
+            if let Err(e) = result {
+                tracing::error!("Cannot create indexes of {collection}: {e:?}");
+            }
+        }
+    });
+}
+
+/// Returns true if the write violated a unique index
+pub fn is_duplicate_key(error: &mongodb::error::Error) -> bool {
+    matches!(
+        error.kind.as_ref(),
+        ErrorKind::Write(WriteFailure::WriteError(e)) if e.code == 11000
+    )
+}
+
 #[async_trait]
 pub trait DatabaseDocument {
     fn collection_name() -> &'static str;
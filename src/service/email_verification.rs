@@ -0,0 +1,174 @@
+//! Verification of the email of the users.
+//!
+//! Setting an email sends a link with a random token proving that the user
+//! owns it. The token is stored hashed, it expires after
+//! `email_verification.token_ttl_s` and it can be used once. Links are sent at
+//! most once every `email_verification.resend_interval_s` to the same user.
+//!
+//! Resending the link is requested with the email, so that users who cannot log
+//! in without a verified email can ask for it. Requests for unknown emails
+//! succeed as well, so that they do not reveal which emails exist.
+
+use std::time::SystemTime;
+
+use anyhow::anyhow;
+use mongodb::bson::{doc, DateTime};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::{
+    error::AppError,
+    model::email_verification_token::EmailVerificationToken,
+    service::{
+        db::{get_database_service, DatabaseDocument},
+        environment::ENVIRONMENT,
+        mailer::{self, EmailMessage},
+        user,
+    },
+    UserId,
+};
+
+/// Loose check of the address, the verification proves that it works
+static EMAIL_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^[^@\s]+@[^@\s]+\.[^@\s]+$").expect("Email regex is valid"));
+
+/// Returns the lowercase email if it looks like an address
+pub fn normalize(email: &str) -> Result<String, AppError> {
+    let email = email.trim().to_lowercase();
+    if !EMAIL_REGEX.is_match(&email) {
+        return Err(AppError::InvalidRequest(anyhow!(
+            "Email is not a valid address"
+        )));
+    }
+    Ok(email)
+}
+
+/// Replace the email of the user and send the link verifying it
+pub async fn change_email(user_id: &UserId, email: &str) -> Result<(), AppError> {
+    let email = normalize(email)?;
+    let user_model = user::get_user(user_id).await?;
+    if user_model.email.as_deref() == Some(email.as_str()) && user_model.email_verified {
+        return Ok(());
+    }
+    user::set_email(user_id, &email).await?;
+    info!("Email of user {user_id} has been changed");
+    send_link(user_id).await
+}
+
+/// Send again the link to the user with the email if it is not verified yet
+pub async fn resend(email: &str) -> Result<(), AppError> {
+    match user::find_by_email(email).await? {
+        Some(user_model) if !user_model.email_verified => {
+            send_link(&user_model.id.expect("User id must be not missing")).await
+        }
+        _ => {
+            info!("Email verification requested for unknown or verified email");
+            Ok(())
+        }
+    }
+}
+
+/// Send the link verifying the current email of the user
+///
+/// Nothing is sent if another link has been sent within the resend interval
+pub async fn send_link(user_id: &UserId) -> Result<(), AppError> {
+    let user_model = user::get_user(user_id).await?;
+    let Some(email) = user_model.email.filter(|_| !user_model.email_verified) else {
+        return Ok(());
+    };
+    let config = &ENVIRONMENT.email_verification;
+    let db = &get_database_service().await.db();
+    let collection =
+        db.collection::<EmailVerificationToken>(EmailVerificationToken::collection_name());
+    let resend_after = DateTime::from_system_time(SystemTime::now() - config.resend_interval);
+    let recent = collection
+        .count_documents(
+            doc! { "user_id": user_id, "created_at": { "$gt": resend_after } },
+            None,
+        )
+        .await?;
+    if recent > 0 {
+        info!("Verification link of user {user_id} already sent, skipping it");
+        return Ok(());
+    }
+    // only the last link is valid
+    collection
+        .delete_many(doc! { "user_id": user_id }, None)
+        .await?;
+
+    let token = hex::encode(rand::random::<[u8; 32]>());
+    EmailVerificationToken {
+        id: None,
+        user_id: *user_id,
+        email: email.clone(),
+        token_hash: hash_token(&token),
+        expires_at: DateTime::from_system_time(SystemTime::now() + config.token_ttl),
+        created_at: DateTime::now(),
+    }
+    .dump(db)
+    .await?;
+
+    let link = format!(
+        "{}/verify-email?token={token}",
+        ENVIRONMENT.mail.web_app_url
+    );
+    mailer::enqueue(EmailMessage {
+        to: email,
+        subject: "Verify your email".into(),
+        body: format!(
+            "Hello {},\n\n\
+             open the link below within {} hours to verify your email:\n\n{link}\n\n\
+             If you did not use this email on our service, you can ignore this email.\n",
+            user_model.username,
+            config.token_ttl.as_secs() / 3600
+        ),
+    })
+    .await
+}
+
+/// Mark the email as verified consuming the token received by email
+///
+/// Returns the user owning the email
+pub async fn confirm(token: &str) -> Result<UserId, AppError> {
+    let db = &get_database_service().await.db();
+    let collection =
+        db.collection::<EmailVerificationToken>(EmailVerificationToken::collection_name());
+    let verification = collection
+        .find_one_and_delete(
+            doc! { "token_hash": hash_token(token), "expires_at": { "$gt": DateTime::now() } },
+            None,
+        )
+        .await?
+        .ok_or_else(|| {
+            AppError::InvalidRequest(anyhow!("Verification token is expired or not valid"))
+        })?;
+    if !user::mark_email_verified(&verification.user_id, &verification.email).await? {
+        return Err(AppError::InvalidRequest(anyhow!(
+            "Email has changed since the link was sent"
+        )));
+    }
+    info!("Email of user {} has been verified", verification.user_id);
+    Ok(verification.user_id)
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::normalize;
+
+    #[test]
+    fn normalize_test() {
+        assert_eq!(
+            normalize(" John.Smith@Example.com ").unwrap(),
+            "john.smith@example.com"
+        );
+        assert!(normalize("john").is_err());
+        assert!(normalize("john@localhost").is_err());
+        assert!(normalize("john smith@example.com").is_err());
+    }
+}
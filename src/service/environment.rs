@@ -1,28 +1,97 @@
 //! Environment service use to build and store all the application environment variables.
 //!
-//! This struct loads the application variables from the environment or other secret manager endpoints
+//! This struct loads the application variables from layered configuration sources
+//! (defaults, deploy environment file, environment variables and command line flags)
 //! providing them to other services.
 //! It represents the true and unique source of application variables
 
-use jsonwebtoken::{DecodingKey, EncodingKey};
+pub mod secret;
+mod source;
+
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey};
+use mongodb::bson::DateTime;
 use once_cell::sync::Lazy;
-use tracing::Level;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tracing::{error, info, warn, Level};
+use tracing_appender::rolling::Rotation;
+use tracing_subscriber::filter::Directive;
 use uuid::Uuid;
 
+use secret::{EnvironmentSecretProvider, SecretProvider};
+use source::ConfigurationSource;
+
+use crate::enums::{Role, SdkVersion};
+
+use super::db::get_database_service;
+
+/// Most attempts of a webhook delivery, later retries would wait for hours
+const MAX_WEBHOOK_ATTEMPTS: u32 = 20;
+
 /// ENVIRONMENT struct containing application variables
-pub static ENVIRONMENT: Lazy<EnvironmentVariables> = Lazy::new(EnvironmentVariables::new);
+///
+/// Initialization panics listing every configuration problem found
+pub static ENVIRONMENT: Lazy<EnvironmentVariables> =
+    Lazy::new(|| EnvironmentVariables::new().unwrap_or_else(|e| panic!("{e}")));
+
+/// Error returned when the configuration is not valid.
+///
+/// It contains every problem found instead of the first one only
+#[derive(Debug)]
+pub struct ConfigurationError {
+    pub problems: Vec<String>,
+}
+
+impl Display for ConfigurationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Invalid configuration:")?;
+        for problem in &self.problems {
+            writeln!(f, "  - {problem}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigurationError {}
 
 /// Struct containing application environment variables that is initialized from
 /// environment or accessing external services
 pub struct EnvironmentVariables {
+    pub deploy_environment: String,
+    pub server: ServerVariables,
     pub logging: LoggingVariables,
     pub authentication: AuthenticationVariables,
+    pub login_protection: LoginProtectionVariables,
+    pub login_history: LoginHistoryVariables,
+    pub mfa: MfaVariables,
+    pub password: PasswordVariables,
+    pub email_verification: EmailVerificationVariables,
+    pub invitation: InvitationVariables,
+    pub mail: MailVariables,
     pub database: DatabaseVariables,
+    pub webhook: WebhookVariables,
+    pub sdk: SdkVariables,
+    pub rate_limit: RateLimitVariables,
+    pub usage: UsageVariables,
+    pub metrics: MetricsVariables,
+    pub secrets: SecretsVariables,
+    /// set only when login with an OpenID Connect provider is enabled
+    pub oidc: Option<OidcVariables>,
+    /// set only when the spans are exported to a collector
+    pub telemetry: Option<TelemetryVariables>,
 }
 
 impl EnvironmentVariables {
     /// Create new instance of this struct by invoking the different builds functions
-    fn new() -> Self {
+    fn new() -> Result<Self, ConfigurationError> {
         // during testing use hardcoded custom env variables
         if cfg!(test) {
             let secret = "testing_secret";
@@ -31,82 +100,1075 @@ impl EnvironmentVariables {
             let mut db_name = String::from("app-test-db-");
             db_name.push_str(&id);
 
-            EnvironmentVariables {
+            Ok(EnvironmentVariables {
+                deploy_environment: "test".into(),
+                server: ServerVariables {
+                    host: "127.0.0.1".into(),
+                    port: 3000,
+                    trust_forwarded_for: false,
+                },
                 logging: LoggingVariables {
                     level: Level::TRACE,
                     include_headers: true,
+                    format: LogFormat::Full,
+                    directives: Vec::new(),
+                    redacted_headers: Vec::new(),
+                    file: None,
                 },
                 authentication: AuthenticationVariables {
-                    jwt_encoding: EncodingKey::from_secret(secret.as_bytes()),
-                    jwt_decoding: DecodingKey::from_secret(secret.as_bytes()),
+                    algorithm: Algorithm::HS256,
+                    accepted_algorithms: vec![Algorithm::HS256],
+                    issuer: "sandbox-rust-web-app/test".into(),
+                    web_app_audience: "web-app".into(),
+                    service_audience: "internal-services".into(),
+                    token_lifetime: Duration::from_secs(3600),
+                    leeway: Duration::from_secs(0),
+                    impersonation_lifetime: Duration::from_secs(900),
+                    client_credentials_lifetime: Duration::from_secs(3600),
+                    key_rotation_interval: Duration::from_secs(3600),
+                    key_grace_period: Duration::from_secs(3600),
+                    key_refresh_interval: Duration::from_secs(60),
+                    key_encryption_key: None,
+                    hmac_keys: RwLock::new(Some(Arc::new(JwtKeys::new(secret, None)))),
+                },
+                login_protection: LoginProtectionVariables {
+                    max_failures_per_username: 5,
+                    max_failures_per_ip: 20,
+                    failure_window: Duration::from_secs(900),
+                    lockout: Duration::from_secs(900),
+                    delay_base: Duration::from_millis(1),
+                    max_delay: Duration::from_millis(10),
+                },
+                login_history: LoginHistoryVariables {
+                    retention: Duration::from_secs(86400),
+                },
+                mfa: MfaVariables {
+                    required_roles: vec![Role::Admin],
+                    issuer: "sandbox-rust-web-app".into(),
+                    challenge_lifetime: Duration::from_secs(300),
+                    recovery_codes: 10,
+                },
+                password: PasswordVariables {
+                    min_length: 8,
+                    reset_token_ttl: Duration::from_secs(3600),
+                },
+                email_verification: EmailVerificationVariables {
+                    token_ttl: Duration::from_secs(86400),
+                    resend_interval: Duration::from_secs(60),
+                    required_for_login: false,
+                },
+                invitation: InvitationVariables {
+                    ttl: Duration::from_secs(604800),
+                },
+                mail: MailVariables {
+                    transport: MailTransport::Memory,
+                    from: "sandbox-rust-web-app <no-reply@localhost>".into(),
+                    web_app_url: "http://localhost:3000".into(),
+                    outbox_poll_interval: Duration::from_secs(1),
+                    max_attempts: 3,
                 },
                 database: DatabaseVariables {
                     connection_string: format!("mongodb://localhost:27017/{}", db_name),
                     db_name,
                 },
-            }
+                webhook: WebhookVariables {
+                    max_attempts: 3,
+                    backoff_base: Duration::from_millis(10),
+                    failure_threshold: 3,
+                    request_timeout: Duration::from_secs(1),
+                },
+                sdk: SdkVariables {
+                    versions: SdkVersion::ALL
+                        .map(|version| SdkVersionVariables {
+                            version,
+                            deprecated_at: None,
+                            sunset_at: None,
+                        })
+                        .to_vec(),
+                },
+                rate_limit: RateLimitVariables {
+                    enabled: true,
+                    store: RateLimitStore::Memory,
+                    anonymous: RateLimit {
+                        burst: 10,
+                        per_second: 1.0,
+                    },
+                    default_plan: "free".into(),
+                    plans: BTreeMap::from([(
+                        "free".into(),
+                        RateLimit {
+                            burst: 100,
+                            per_second: 10.0,
+                        },
+                    )]),
+                    routes: Vec::new(),
+                },
+                usage: UsageVariables {
+                    flush_interval: Duration::from_secs(1),
+                    retention: Duration::from_secs(86400),
+                    monthly_quotas: BTreeMap::from([("free".into(), 1000)]),
+                },
+                metrics: MetricsVariables {
+                    enabled: true,
+                    port: None,
+                    token: Some("testing_metrics_token".into()),
+                },
+                secrets: SecretsVariables {
+                    refresh_interval: Duration::ZERO,
+                    provider: Arc::new(EnvironmentSecretProvider::new(&[])),
+                },
+                oidc: None,
+                telemetry: None,
+            })
         } else {
-            let local = std::env::var("LOCAL")
-                .map(|value| value.to_lowercase().cmp(&"true".to_string()).is_eq())
-                .unwrap_or(false);
-            let deploy_environment =
-                std::env::var("DEPLOY_ENVIRONMENT").expect("DEPLOY_ENVIRONMENT must be set");
-            EnvironmentVariables {
-                logging: Self::build_logging(&local, &deploy_environment),
-                authentication: Self::build_authentication(&local, &deploy_environment),
-                database: Self::build_database(&local, &deploy_environment),
+            let source = ConfigurationSource::load(std::env::vars(), std::env::args().skip(1))?;
+            Self::from_source(&source)
+        }
+    }
+
+    /// Build every section from the configuration source validating them.
+    ///
+    /// All the sections are built even when one of them fails so that
+    /// every problem is reported at once
+    fn from_source(source: &ConfigurationSource) -> Result<Self, ConfigurationError> {
+        let mut problems = Vec::new();
+        let server = Self::build_server(source, &mut problems);
+        let logging = Self::build_logging(source, &mut problems);
+        let authentication =
+            Self::build_authentication(source, &source.deploy_environment, &mut problems);
+        let login_protection = Self::build_login_protection(source, &mut problems);
+        let login_history = Self::build_login_history(source, &mut problems);
+        let mfa = Self::build_mfa(source, &mut problems);
+        let password = Self::build_password(source, &mut problems);
+        let email_verification = Self::build_email_verification(source, &mut problems);
+        let invitation = Self::build_invitation(source, &mut problems);
+        let mail = Self::build_mail(source, &source.deploy_environment, &mut problems);
+        let database = Self::build_database(source, &mut problems);
+        let webhook = Self::build_webhook(source, &mut problems);
+        let sdk = Self::build_sdk(source, &mut problems);
+        let rate_limit = Self::build_rate_limit(source, &mut problems);
+        let usage = Self::build_usage(source, rate_limit.as_ref(), &mut problems);
+        let metrics = Self::build_metrics(source, &mut problems);
+        let secrets = Self::build_secrets(source, &mut problems);
+        let oidc = Self::build_oidc(source, &mut problems);
+        let telemetry = Self::build_telemetry(source, &mut problems);
+
+        match (
+            server,
+            logging,
+            authentication,
+            login_protection,
+            login_history,
+            mfa,
+            password,
+            email_verification,
+            invitation,
+            mail,
+            database,
+            webhook,
+            sdk,
+            rate_limit,
+            usage,
+            metrics,
+            secrets,
+        ) {
+            (
+                Some(server),
+                Some(logging),
+                Some(authentication),
+                Some(login_protection),
+                Some(login_history),
+                Some(mfa),
+                Some(password),
+                Some(email_verification),
+                Some(invitation),
+                Some(mail),
+                Some(database),
+                Some(webhook),
+                Some(sdk),
+                Some(rate_limit),
+                Some(usage),
+                Some(metrics),
+                Some(secrets),
+            ) if problems.is_empty() => Ok(EnvironmentVariables {
+                deploy_environment: source.deploy_environment.clone(),
+                server,
+                logging,
+                authentication,
+                login_protection,
+                login_history,
+                mfa,
+                password,
+                email_verification,
+                invitation,
+                mail,
+                database,
+                webhook,
+                sdk,
+                rate_limit,
+                usage,
+                metrics,
+                secrets,
+                oidc,
+                telemetry,
+            }),
+            _ => Err(ConfigurationError { problems }),
+        }
+    }
+
+    /// Read again the secrets that can be rotated at runtime updating
+    /// jwt keys and database connection when their value is changed
+    pub async fn refresh_secrets(&self) {
+        if self
+            .authentication
+            .accepted_algorithms
+            .contains(&Algorithm::HS256)
+        {
+            match self.secrets.provider.get(secret::JWT_SECRET) {
+                Ok(Some(jwt_secret)) => {
+                    if self.authentication.rotate(&jwt_secret) {
+                        info!("JWT secret has been rotated");
+                    }
+                }
+                Ok(None) => warn!("Secret {} is not available anymore", secret::JWT_SECRET),
+                Err(e) => error!("Cannot refresh secret {}: {e:#}", secret::JWT_SECRET),
+            }
+        }
+        match self.secrets.provider.get(secret::MONGODB_CONNECTION_STRING) {
+            Ok(Some(connection_string)) => {
+                match get_database_service()
+                    .await
+                    .reconnect(&connection_string)
+                    .await
+                {
+                    Ok(true) => info!("Database connection string has been rotated"),
+                    Ok(false) => {}
+                    Err(e) => error!("Cannot connect with rotated connection string: {e:?}"),
+                }
             }
+            Ok(None) => {}
+            Err(e) => error!(
+                "Cannot refresh secret {}: {e:#}",
+                secret::MONGODB_CONNECTION_STRING
+            ),
+        }
+    }
+
+    /// Build server variables
+    ///
+    /// they define the address the application listens on
+    fn build_server(
+        source: &ConfigurationSource,
+        problems: &mut Vec<String>,
+    ) -> Option<ServerVariables> {
+        let host = source.get::<String>("server.host", problems);
+        let port = source.get::<u16>("server.port", problems);
+        if port == Some(0) {
+            problems.push("`server.port` must be greater than zero".into());
         }
+        let trust_forwarded_for = source.get::<bool>("server.trust_forwarded_for", problems);
+        Some(ServerVariables {
+            host: host?,
+            port: port?,
+            trust_forwarded_for: trust_forwarded_for?,
+        })
     }
 
     /// Build logging variables
     ///
     /// they are used by tracing to define correct logging properties
-    fn build_logging(local: &bool, _deploy_environment: &str) -> LoggingVariables {
-        let (level, include_headers) = if *local {
-            (tracing::Level::TRACE, true)
-        } else {
-            (tracing::Level::INFO, false)
+    fn build_logging(
+        source: &ConfigurationSource,
+        problems: &mut Vec<String>,
+    ) -> Option<LoggingVariables> {
+        let level = source
+            .get::<String>("logging.level", problems)
+            .and_then(|level| {
+                level
+                    .parse::<Level>()
+                    .map_err(|_| problems.push(format!("`logging.level` {level} is not valid")))
+                    .ok()
+            });
+        let include_headers = source.get::<bool>("logging.include_headers", problems);
+        let format = match source.get::<String>("logging.format", problems).as_deref() {
+            Some("full") => Some(LogFormat::Full),
+            Some("pretty") => Some(LogFormat::Pretty),
+            Some("compact") => Some(LogFormat::Compact),
+            Some("json") => Some(LogFormat::Json),
+            Some(format) => {
+                problems.push(format!(
+                    "`logging.format` {format} is not one of full, pretty, compact, json"
+                ));
+                None
+            }
+            None => None,
         };
-        LoggingVariables {
-            level,
-            include_headers,
+        let directives = source.get::<Vec<String>>("logging.directives", problems);
+        for directive in directives.iter().flatten() {
+            if let Err(e) = directive.parse::<Directive>() {
+                problems.push(format!(
+                    "`logging.directives` {directive} is not a valid filter directive: {e}"
+                ));
+            }
         }
+        let redacted_headers = source
+            .get::<Vec<String>>("logging.redacted_headers", problems)
+            .map(|headers| headers.iter().map(|header| header.to_lowercase()).collect());
+        let file = match source.get::<bool>("logging.file.enabled", problems) {
+            Some(true) => Self::build_log_file(source, problems).map(Some),
+            Some(false) => Some(None),
+            None => None,
+        };
+        Some(LoggingVariables {
+            level: level?,
+            include_headers: include_headers?,
+            format: format?,
+            directives: directives?,
+            redacted_headers: redacted_headers?,
+            file: file?,
+        })
+    }
+
+    /// Build the settings of the rotating log files
+    fn build_log_file(
+        source: &ConfigurationSource,
+        problems: &mut Vec<String>,
+    ) -> Option<LogFileVariables> {
+        let directory = source.get::<String>("logging.file.directory", problems);
+        let prefix = source.get::<String>("logging.file.prefix", problems);
+        let rotation = match source
+            .get::<String>("logging.file.rotation", problems)
+            .as_deref()
+        {
+            Some("minutely") => Some(Rotation::MINUTELY),
+            Some("hourly") => Some(Rotation::HOURLY),
+            Some("daily") => Some(Rotation::DAILY),
+            Some("never") => Some(Rotation::NEVER),
+            Some(rotation) => {
+                problems.push(format!(
+                    "`logging.file.rotation` {rotation} is not one of minutely, hourly, daily, never"
+                ));
+                None
+            }
+            None => None,
+        };
+        let max_files = source.get::<usize>("logging.file.max_files", problems);
+        if prefix.as_deref() == Some("") {
+            problems.push("`logging.file.prefix` must not be empty".into());
+        }
+        Some(LogFileVariables {
+            directory: directory?.into(),
+            prefix: prefix?,
+            rotation: rotation?,
+            max_files: max_files.filter(|max_files| *max_files > 0),
+        })
     }
 
     /// Build authentication variables
     ///
-    /// Environment variable `JWT_SECRET` is used to create JWT encoding and decoding keys
-    /// therefore, it is mandatory.
-    fn build_authentication(local: &bool, _deploy_environment: &str) -> AuthenticationVariables {
-        let secret = if *local {
-            "secret".to_string()
-        } else {
-            std::env::var("JWT_SECRET").expect("JWT_SECRET must be set")
+    /// When `HS256` is the signing algorithm or it is accepted, `authentication.jwt_secret`
+    /// is used to create JWT encoding and decoding keys therefore, it is mandatory.
+    /// Asymmetric algorithms require the secret `JWT_KEY_ENCRYPTION_KEY` instead.
+    ///
+    /// The issuer defaults to the application name followed by the deploy environment
+    /// so that tokens cannot be replayed against another environment.
+    fn build_authentication(
+        source: &ConfigurationSource,
+        deploy_environment: &str,
+        problems: &mut Vec<String>,
+    ) -> Option<AuthenticationVariables> {
+        let parse_algorithm =
+            |algorithm: String, problems: &mut Vec<String>| match algorithm.as_str() {
+                "HS256" => Some(Algorithm::HS256),
+                "RS256" => Some(Algorithm::RS256),
+                "EdDSA" => Some(Algorithm::EdDSA),
+                _ => {
+                    problems.push(format!(
+                        "`authentication` algorithm {algorithm} is not one of HS256, RS256, EdDSA"
+                    ));
+                    None
+                }
+            };
+        let algorithm = source
+            .get::<String>("authentication.algorithm", problems)
+            .and_then(|algorithm| parse_algorithm(algorithm, problems));
+        let accepted_algorithms = source
+            .get::<Vec<String>>("authentication.accepted_algorithms", problems)
+            .map(|algorithms| {
+                algorithms
+                    .into_iter()
+                    .filter_map(|algorithm| parse_algorithm(algorithm, problems))
+                    .collect::<Vec<_>>()
+            });
+        let rotation_interval =
+            source.get::<u64>("authentication.key_rotation_interval_h", problems);
+        let grace_period = source.get::<u64>("authentication.key_grace_period_h", problems);
+        let refresh_interval = source.get::<u64>("authentication.key_refresh_interval_s", problems);
+        if refresh_interval == Some(0) {
+            problems
+                .push("`authentication.key_refresh_interval_s` must be greater than zero".into());
+        }
+        let issuer = source
+            .get_optional::<String>("authentication.issuer", problems)
+            .unwrap_or_else(|| format!("sandbox-rust-web-app/{deploy_environment}"));
+        let web_app_audience = source.get::<String>("authentication.web_app_audience", problems);
+        let service_audience = source.get::<String>("authentication.service_audience", problems);
+        if web_app_audience.is_some() && web_app_audience == service_audience {
+            problems.push("web app and service audiences must be different".into());
+        }
+        let token_lifetime = source.get::<u64>("authentication.token_lifetime_s", problems);
+        let leeway = source.get::<u64>("authentication.leeway_s", problems);
+        let impersonation_lifetime =
+            source.get::<u64>("authentication.impersonation_lifetime_s", problems);
+        if impersonation_lifetime == Some(0) {
+            problems
+                .push("`authentication.impersonation_lifetime_s` must be greater than zero".into());
+        }
+        let client_credentials_lifetime =
+            source.get::<u64>("authentication.client_credentials_lifetime_s", problems);
+        if client_credentials_lifetime == Some(0) {
+            problems.push(
+                "`authentication.client_credentials_lifetime_s` must be greater than zero".into(),
+            );
+        }
+
+        let algorithm = algorithm?;
+        let mut accepted_algorithms = accepted_algorithms?;
+        // tokens signed by this application must always be accepted
+        if !accepted_algorithms.contains(&algorithm) {
+            accepted_algorithms.push(algorithm);
+        }
+
+        let mut hmac_keys = None;
+        if accepted_algorithms.contains(&Algorithm::HS256) {
+            match source.get::<String>("authentication.jwt_secret", problems) {
+                Some(secret) if secret.is_empty() => {
+                    problems.push("`authentication.jwt_secret` must not be empty".into())
+                }
+                Some(secret) => hmac_keys = Some(Arc::new(JwtKeys::new(&secret, None))),
+                None => {}
+            }
+        }
+        let mut key_encryption_key = None;
+        if accepted_algorithms.iter().any(|a| *a != Algorithm::HS256) {
+            match source.secrets.get(secret::JWT_KEY_ENCRYPTION_KEY) {
+                Ok(Some(key)) if hex::decode(key.trim()).map(|k| k.len()) == Ok(32) => {
+                    key_encryption_key = Some(key.trim().to_string())
+                }
+                Ok(Some(_)) => problems.push(format!(
+                    "{} must be 32 bytes hex encoded",
+                    secret::JWT_KEY_ENCRYPTION_KEY
+                )),
+                Ok(None) => problems.push(format!(
+                    "{} must be set with asymmetric algorithms",
+                    secret::JWT_KEY_ENCRYPTION_KEY
+                )),
+                Err(e) => problems.push(format!(
+                    "cannot read secret {}: {e:#}",
+                    secret::JWT_KEY_ENCRYPTION_KEY
+                )),
+            }
+        }
+
+        Some(AuthenticationVariables {
+            algorithm,
+            accepted_algorithms,
+            issuer,
+            web_app_audience: web_app_audience?,
+            service_audience: service_audience?,
+            token_lifetime: Duration::from_secs(token_lifetime?),
+            leeway: Duration::from_secs(leeway?),
+            impersonation_lifetime: Duration::from_secs(impersonation_lifetime?),
+            client_credentials_lifetime: Duration::from_secs(client_credentials_lifetime?),
+            key_rotation_interval: Duration::from_secs(rotation_interval? * 3600),
+            key_grace_period: Duration::from_secs(grace_period? * 3600),
+            key_refresh_interval: Duration::from_secs(refresh_interval?),
+            key_encryption_key,
+            hmac_keys: RwLock::new(hmac_keys),
+        })
+    }
+
+    /// Build variables protecting the login from brute-force attacks
+    fn build_login_protection(
+        source: &ConfigurationSource,
+        problems: &mut Vec<String>,
+    ) -> Option<LoginProtectionVariables> {
+        let mut get_positive = |key: &str| {
+            let value = source.get::<u64>(key, problems);
+            if value == Some(0) {
+                problems.push(format!("`{key}` must be greater than zero"));
+            }
+            value
         };
-        AuthenticationVariables {
-            jwt_encoding: EncodingKey::from_secret(secret.as_bytes()),
-            jwt_decoding: DecodingKey::from_secret(secret.as_bytes()),
+        let max_failures_per_username = get_positive("login_protection.max_failures_per_username");
+        let max_failures_per_ip = get_positive("login_protection.max_failures_per_ip");
+        let failure_window = get_positive("login_protection.failure_window_s");
+        let lockout = get_positive("login_protection.lockout_s");
+        let delay_base = source.get::<u64>("login_protection.delay_base_ms", problems);
+        let max_delay = source.get::<u64>("login_protection.max_delay_ms", problems);
+        Some(LoginProtectionVariables {
+            max_failures_per_username: max_failures_per_username? as u32,
+            max_failures_per_ip: max_failures_per_ip? as u32,
+            failure_window: Duration::from_secs(failure_window?),
+            lockout: Duration::from_secs(lockout?),
+            delay_base: Duration::from_millis(delay_base?),
+            max_delay: Duration::from_millis(max_delay?),
+        })
+    }
+
+    /// Build multi-factor authentication variables
+    fn build_mfa(source: &ConfigurationSource, problems: &mut Vec<String>) -> Option<MfaVariables> {
+        let required_roles = source.get::<Vec<Role>>("mfa.required_roles", problems);
+        let issuer = source.get::<String>("mfa.issuer", problems);
+        let challenge_lifetime = source.get::<u64>("mfa.challenge_lifetime_s", problems);
+        let recovery_codes = source.get::<usize>("mfa.recovery_codes", problems);
+        if challenge_lifetime == Some(0) {
+            problems.push("`mfa.challenge_lifetime_s` must be greater than zero".into());
+        }
+        Some(MfaVariables {
+            required_roles: required_roles?,
+            issuer: issuer?,
+            challenge_lifetime: Duration::from_secs(challenge_lifetime?),
+            recovery_codes: recovery_codes?,
+        })
+    }
+
+    /// Build password policy variables
+    fn build_password(
+        source: &ConfigurationSource,
+        problems: &mut Vec<String>,
+    ) -> Option<PasswordVariables> {
+        let min_length = source.get::<usize>("password.min_length", problems);
+        let reset_token_ttl = source.get::<u64>("password.reset_token_ttl_s", problems);
+        if reset_token_ttl == Some(0) {
+            problems.push("`password.reset_token_ttl_s` must be greater than zero".into());
+        }
+        Some(PasswordVariables {
+            min_length: min_length?,
+            reset_token_ttl: Duration::from_secs(reset_token_ttl?),
+        })
+    }
+
+    /// Build email verification variables
+    fn build_email_verification(
+        source: &ConfigurationSource,
+        problems: &mut Vec<String>,
+    ) -> Option<EmailVerificationVariables> {
+        let token_ttl = source.get::<u64>("email_verification.token_ttl_s", problems);
+        if token_ttl == Some(0) {
+            problems.push("`email_verification.token_ttl_s` must be greater than zero".into());
         }
+        let resend_interval = source.get::<u64>("email_verification.resend_interval_s", problems);
+        let required_for_login =
+            source.get::<bool>("email_verification.required_for_login", problems);
+        Some(EmailVerificationVariables {
+            token_ttl: Duration::from_secs(token_ttl?),
+            resend_interval: Duration::from_secs(resend_interval?),
+            required_for_login: required_for_login?,
+        })
+    }
+
+    /// Build login history variables
+    fn build_login_history(
+        source: &ConfigurationSource,
+        problems: &mut Vec<String>,
+    ) -> Option<LoginHistoryVariables> {
+        let retention = source.get::<u64>("login_history.retention_s", problems);
+        if retention == Some(0) {
+            problems.push("`login_history.retention_s` must be greater than zero".into());
+        }
+        Some(LoginHistoryVariables {
+            retention: Duration::from_secs(retention?),
+        })
+    }
+
+    /// Build invitation variables
+    fn build_invitation(
+        source: &ConfigurationSource,
+        problems: &mut Vec<String>,
+    ) -> Option<InvitationVariables> {
+        let ttl = source.get::<u64>("invitation.ttl_s", problems);
+        if ttl == Some(0) {
+            problems.push("`invitation.ttl_s` must be greater than zero".into());
+        }
+        Some(InvitationVariables {
+            ttl: Duration::from_secs(ttl?),
+        })
+    }
+
+    /// Build mail variables
+    ///
+    /// SMTP settings are read only when it is the transport. The file and memory
+    /// transports never deliver the emails so they are refused outside the local
+    /// and test environments
+    fn build_mail(
+        source: &ConfigurationSource,
+        deploy_environment: &str,
+        problems: &mut Vec<String>,
+    ) -> Option<MailVariables> {
+        let transport = match source.get::<String>("mail.transport", problems).as_deref() {
+            Some("smtp") => Self::build_smtp(source, problems).map(MailTransport::Smtp),
+            Some(transport @ ("file" | "memory"))
+                if !matches!(deploy_environment, "local" | "test") =>
+            {
+                problems.push(format!(
+                    "`mail.transport` {transport} does not deliver emails, use smtp in {deploy_environment}"
+                ));
+                None
+            }
+            Some("file") => source
+                .get::<String>("mail.directory", problems)
+                .map(|directory| MailTransport::File(directory.into())),
+            Some("memory") => Some(MailTransport::Memory),
+            Some(transport) => {
+                problems.push(format!(
+                    "`mail.transport` {transport} is not one of smtp, file, memory"
+                ));
+                None
+            }
+            None => None,
+        };
+        let from = source.get::<String>("mail.from", problems);
+        let web_app_url = source.get::<String>("mail.web_app_url", problems);
+        let outbox_poll_interval = source.get::<u64>("mail.outbox_poll_interval_s", problems);
+        let max_attempts = source.get::<u32>("mail.max_attempts", problems);
+        if outbox_poll_interval == Some(0) {
+            problems.push("`mail.outbox_poll_interval_s` must be greater than zero".into());
+        }
+        if max_attempts == Some(0) {
+            problems.push("`mail.max_attempts` must be greater than zero".into());
+        }
+        Some(MailVariables {
+            transport: transport?,
+            from: from?,
+            web_app_url: web_app_url?.trim_end_matches('/').to_string(),
+            outbox_poll_interval: Duration::from_secs(outbox_poll_interval?),
+            max_attempts: max_attempts?,
+        })
+    }
+
+    fn build_smtp(
+        source: &ConfigurationSource,
+        problems: &mut Vec<String>,
+    ) -> Option<SmtpVariables> {
+        let host = source.get::<String>("mail.smtp_host", problems);
+        let port = source.get::<u16>("mail.smtp_port", problems);
+        let security = source
+            .get::<String>("mail.smtp_security", problems)
+            .and_then(|security| match security.as_str() {
+                "starttls" => Some(SmtpSecurity::StartTls),
+                "tls" => Some(SmtpSecurity::Tls),
+                "none" => Some(SmtpSecurity::None),
+                _ => {
+                    problems.push(format!(
+                        "`mail.smtp_security` {security} is not one of starttls, tls, none"
+                    ));
+                    None
+                }
+            });
+        let timeout = source.get::<u64>("mail.smtp_timeout_ms", problems);
+        let username = source.get_optional::<String>("mail.smtp_username", problems);
+        let password = match source.secrets.get(secret::SMTP_PASSWORD) {
+            Ok(password) => password,
+            Err(e) => {
+                problems.push(format!(
+                    "cannot read secret {}: {e:#}",
+                    secret::SMTP_PASSWORD
+                ));
+                None
+            }
+        };
+        if username.is_some() != password.is_some() {
+            problems.push(format!(
+                "`mail.smtp_username` and {} must be set together",
+                secret::SMTP_PASSWORD
+            ));
+        }
+        Some(SmtpVariables {
+            host: host?,
+            port: port?,
+            security: security?,
+            timeout: Duration::from_millis(timeout?),
+            credentials: username.zip(password),
+        })
     }
 
     /// Build database variables
-    fn build_database(local: &bool, deploy_environment: &str) -> DatabaseVariables {
-        let (connection_string, db_name) = if *local {
-            let db_name = format!("application-database-{}", deploy_environment);
-            (format!("mongodb://localhost:27017/{}", db_name), db_name)
-        } else {
-            (
-                std::env::var("MONGODB_CONNECTION_STRING")
-                    .expect("MONGODB_CONNECTION_STRING must be set"),
-                std::env::var("MONGODB_DB_NAME").expect("MONGODB_DB_NAME must be set"),
-            )
+    fn build_database(
+        source: &ConfigurationSource,
+        problems: &mut Vec<String>,
+    ) -> Option<DatabaseVariables> {
+        let connection_string = source.get::<String>("database.connection_string", problems);
+        let db_name = source.get::<String>("database.db_name", problems);
+        if let Some(connection_string) = &connection_string {
+            if !connection_string.starts_with("mongodb://")
+                && !connection_string.starts_with("mongodb+srv://")
+            {
+                problems.push("`database.connection_string` must be a mongodb uri".into());
+            }
+        }
+        Some(DatabaseVariables {
+            connection_string: connection_string?,
+            db_name: db_name?,
+        })
+    }
+
+    /// Build webhook variables
+    fn build_webhook(
+        source: &ConfigurationSource,
+        problems: &mut Vec<String>,
+    ) -> Option<WebhookVariables> {
+        let max_attempts = source.get::<u32>("webhook.max_attempts", problems);
+        let backoff_base = source.get::<u64>("webhook.backoff_base_ms", problems);
+        let failure_threshold = source.get::<u32>("webhook.failure_threshold", problems);
+        let request_timeout = source.get::<u64>("webhook.request_timeout_ms", problems);
+        if let Some(max_attempts) = max_attempts {
+            if !(1..=MAX_WEBHOOK_ATTEMPTS).contains(&max_attempts) {
+                problems.push(format!(
+                    "`webhook.max_attempts` must be between 1 and {MAX_WEBHOOK_ATTEMPTS}"
+                ));
+            }
+        }
+        Some(WebhookVariables {
+            max_attempts: max_attempts?,
+            backoff_base: Duration::from_millis(backoff_base?),
+            failure_threshold: failure_threshold?,
+            request_timeout: Duration::from_millis(request_timeout?),
+        })
+    }
+
+    /// Build the lifecycle of every sdk version, by default they are all current
+    fn build_sdk(source: &ConfigurationSource, problems: &mut Vec<String>) -> Option<SdkVariables> {
+        let mut versions = Vec::new();
+        for version in SdkVersion::ALL {
+            let mut get_time = |name: &str| {
+                let key = format!("sdk.{}.{name}", version.as_str());
+                let value = source.get_optional::<String>(&key, problems)?;
+                DateTime::parse_rfc3339_str(&value)
+                    .map_err(|e| problems.push(format!("`{key}` is not an RFC 3339 time: {e}")))
+                    .ok()
+            };
+            let deprecated_at = get_time("deprecated_at");
+            let sunset_at = get_time("sunset_at");
+            match (deprecated_at, sunset_at) {
+                (Some(_), _) if version.successor().is_none() => problems.push(format!(
+                    "`sdk.{}` is the latest version, it cannot be deprecated",
+                    version.as_str()
+                )),
+                (None, Some(_)) => problems.push(format!(
+                    "`sdk.{}.sunset_at` requires `deprecated_at`",
+                    version.as_str()
+                )),
+                (Some(deprecated_at), Some(sunset_at)) if sunset_at < deprecated_at => problems
+                    .push(format!(
+                        "`sdk.{}.sunset_at` must follow `deprecated_at`",
+                        version.as_str()
+                    )),
+                _ => {}
+            }
+            versions.push(SdkVersionVariables {
+                version,
+                deprecated_at,
+                sunset_at,
+            });
+        }
+        Some(SdkVariables { versions })
+    }
+
+    /// Build rate limit variables
+    ///
+    /// The default plan must be one of the plans and every limit must refill
+    fn build_rate_limit(
+        source: &ConfigurationSource,
+        problems: &mut Vec<String>,
+    ) -> Option<RateLimitVariables> {
+        let enabled = source.get::<bool>("rate_limit.enabled", problems);
+        let store = match source
+            .get::<String>("rate_limit.store", problems)
+            .as_deref()
+        {
+            Some("memory") => Some(RateLimitStore::Memory),
+            Some("mongodb") => Some(RateLimitStore::Database),
+            Some(store) => {
+                problems.push(format!(
+                    "`rate_limit.store` {store} is not one of memory, mongodb"
+                ));
+                None
+            }
+            None => None,
+        };
+        let check_limit = |key: &str, limit: &RateLimit, problems: &mut Vec<String>| {
+            if limit.burst == 0 || !limit.per_second.is_finite() || limit.per_second <= 0.0 {
+                problems.push(format!(
+                    "`{key}` must have burst and per_second greater than zero"
+                ));
+            }
         };
+        let anonymous = source.get::<RateLimit>("rate_limit.anonymous", problems);
+        if let Some(anonymous) = &anonymous {
+            check_limit("rate_limit.anonymous", anonymous, problems);
+        }
+        let plans = source.get::<BTreeMap<String, RateLimit>>("rate_limit.plans", problems);
+        for (name, limit) in plans.iter().flatten() {
+            check_limit(&format!("rate_limit.plans.{name}"), limit, problems);
+        }
+        let mut routes = source
+            .get_optional::<Vec<RouteRateLimit>>("rate_limit.routes", problems)
+            .unwrap_or_default();
+        for route in &mut routes {
+            check_limit(
+                &format!("rate_limit.routes {}", route.path),
+                &route.limit,
+                problems,
+            );
+            if !route.path.starts_with('/') {
+                problems.push(format!(
+                    "`rate_limit.routes` path {} must start with /",
+                    route.path
+                ));
+            }
+            route.method = route.method.as_ref().map(|method| method.to_uppercase());
+        }
+        let default_plan = source.get::<String>("rate_limit.default_plan", problems);
+        if let (Some(default_plan), Some(plans)) = (&default_plan, &plans) {
+            if !plans.contains_key(default_plan) {
+                problems.push(format!(
+                    "`rate_limit.default_plan` {default_plan} is not one of the plans"
+                ));
+            }
+        }
+        Some(RateLimitVariables {
+            enabled: enabled?,
+            store: store?,
+            anonymous: anonymous?,
+            default_plan: default_plan?,
+            plans: plans?,
+            routes,
+        })
+    }
+
+    /// Build usage metering variables
+    ///
+    /// Quotas must refer to the plans of the rate limit
+    fn build_usage(
+        source: &ConfigurationSource,
+        rate_limit: Option<&RateLimitVariables>,
+        problems: &mut Vec<String>,
+    ) -> Option<UsageVariables> {
+        let flush_interval = source.get::<u64>("usage.flush_interval_s", problems);
+        if flush_interval == Some(0) {
+            problems.push("`usage.flush_interval_s` must be greater than zero".into());
+        }
+        let retention_days = source.get::<u64>("usage.retention_days", problems);
+        if retention_days == Some(0) {
+            problems.push("`usage.retention_days` must be greater than zero".into());
+        }
+        let monthly_quotas = source
+            .get_optional::<BTreeMap<String, u64>>("usage.monthly_quotas", problems)
+            .unwrap_or_default();
+        if let Some(rate_limit) = rate_limit {
+            for plan in monthly_quotas.keys() {
+                if !rate_limit.has_plan(plan) {
+                    problems.push(format!(
+                        "`usage.monthly_quotas` plan {plan} is not one of the rate limit plans"
+                    ));
+                }
+            }
+        }
+        Some(UsageVariables {
+            flush_interval: Duration::from_secs(flush_interval?),
+            retention: Duration::from_secs(retention_days? * 86400),
+            monthly_quotas,
+        })
+    }
+
+    /// Build metrics variables
+    ///
+    /// Metrics served on the application port are protected by the secret
+    /// `METRICS_TOKEN`, hence, it is mandatory without a dedicated port.
+    fn build_metrics(
+        source: &ConfigurationSource,
+        problems: &mut Vec<String>,
+    ) -> Option<MetricsVariables> {
+        let enabled = source.get::<bool>("metrics.enabled", problems);
+        let port = source.get_optional::<u16>("metrics.port", problems);
+        let token = match source.secrets.get(secret::METRICS_TOKEN) {
+            Ok(token) => token,
+            Err(e) => {
+                problems.push(format!(
+                    "cannot read secret {}: {e:#}",
+                    secret::METRICS_TOKEN
+                ));
+                None
+            }
+        };
+        if enabled == Some(true) && port.is_none() && token.is_none() {
+            problems.push(format!(
+                "`metrics.port` or the secret {} is required to serve the metrics",
+                secret::METRICS_TOKEN
+            ));
+        }
+        Some(MetricsVariables {
+            enabled: enabled?,
+            port,
+            token,
+        })
+    }
+
+    /// Build secrets variables keeping the provider to refresh them
+    fn build_secrets(
+        source: &ConfigurationSource,
+        problems: &mut Vec<String>,
+    ) -> Option<SecretsVariables> {
+        let refresh_interval = source.get::<u64>("secrets.refresh_interval_s", problems)?;
+        Some(SecretsVariables {
+            refresh_interval: Duration::from_secs(refresh_interval),
+            provider: source.secrets.clone(),
+        })
+    }
 
-        DatabaseVariables {
-            connection_string,
-            db_name,
+    /// Build the span export variables, `None` means that spans are not exported
+    ///
+    /// Problems are registered only when it is enabled
+    fn build_telemetry(
+        source: &ConfigurationSource,
+        problems: &mut Vec<String>,
+    ) -> Option<TelemetryVariables> {
+        if !source.get::<bool>("telemetry.enabled", problems)? {
+            return None;
+        }
+        let endpoint = source
+            .get::<String>("telemetry.endpoint", problems)
+            .and_then(|endpoint| match reqwest::Url::parse(&endpoint) {
+                Ok(url) if url.scheme() == "https" || url.scheme() == "http" => Some(endpoint),
+                _ => {
+                    problems.push("`telemetry.endpoint` must be an http url".into());
+                    None
+                }
+            });
+        let service_name = source.get::<String>("telemetry.service_name", problems);
+        let sampling_ratio = source
+            .get::<f64>("telemetry.sampling_ratio", problems)
+            .and_then(|ratio| {
+                if (0.0..=1.0).contains(&ratio) {
+                    Some(ratio)
+                } else {
+                    problems.push("`telemetry.sampling_ratio` must be between 0 and 1".into());
+                    None
+                }
+            });
+        let directives = source.get::<Vec<String>>("telemetry.directives", problems);
+        for directive in directives.iter().flatten() {
+            if let Err(e) = directive.parse::<Directive>() {
+                problems.push(format!(
+                    "`telemetry.directives` {directive} is not a valid filter directive: {e}"
+                ));
+            }
+        }
+        if directives.as_ref().is_some_and(Vec::is_empty) {
+            problems.push("`telemetry.directives` must not be empty".into());
+        }
+        let timeout_ms = source.get::<u64>("telemetry.timeout_ms", problems);
+        Some(TelemetryVariables {
+            endpoint: endpoint?,
+            service_name: service_name?,
+            sampling_ratio: sampling_ratio?,
+            directives: directives?,
+            timeout: Duration::from_millis(timeout_ms?),
+        })
+    }
+
+    /// Build OpenID Connect variables, `None` means that the login is disabled
+    ///
+    /// Problems are registered only when it is enabled
+    fn build_oidc(
+        source: &ConfigurationSource,
+        problems: &mut Vec<String>,
+    ) -> Option<OidcVariables> {
+        if !source.get::<bool>("oidc.enabled", problems)? {
+            return None;
+        }
+        let parse_url = |key: &str, problems: &mut Vec<String>| {
+            source
+                .get::<String>(key, problems)
+                .and_then(|url| match reqwest::Url::parse(&url) {
+                    Ok(parsed) if parsed.scheme() == "https" || parsed.scheme() == "http" => {
+                        Some(url)
+                    }
+                    _ => {
+                        problems.push(format!("`{key}` must be an http url"));
+                        None
+                    }
+                })
+        };
+        let issuer_url = parse_url("oidc.issuer_url", problems);
+        let redirect_uri = parse_url("oidc.redirect_uri", problems);
+        let client_id = source.get::<String>("oidc.client_id", problems);
+        let scopes = source.get::<Vec<String>>("oidc.scopes", problems);
+        if let Some(scopes) = &scopes {
+            if !scopes.iter().any(|scope| scope == "openid") {
+                problems.push("`oidc.scopes` must contain openid".into());
+            }
         }
+        let provisioning = source.get::<bool>("oidc.provisioning", problems);
+        let provisioning_role = source.get::<Role>("oidc.provisioning_role", problems);
+        let login_state_ttl = source.get::<u64>("oidc.login_state_ttl_s", problems);
+        let request_timeout = source.get::<u64>("oidc.request_timeout_ms", problems);
+        let client_secret = source
+            .secrets
+            .get(secret::OIDC_CLIENT_SECRET)
+            .map_err(|e| {
+                problems.push(format!(
+                    "cannot read secret {}: {e:#}",
+                    secret::OIDC_CLIENT_SECRET
+                ))
+            })
+            .ok()?;
+        Some(OidcVariables {
+            issuer_url: issuer_url?,
+            client_id: client_id?,
+            client_secret,
+            redirect_uri: redirect_uri?,
+            scopes: scopes?,
+            provisioning: provisioning?,
+            provisioning_role: provisioning_role?,
+            login_state_ttl: Duration::from_secs(login_state_ttl?),
+            request_timeout: Duration::from_millis(request_timeout?),
+        })
+    }
+}
+
+/// Spawn a background task refreshing secrets every `secrets.refresh_interval_s`
+///
+/// Nothing is spawned if the interval is zero
+pub fn spawn_secrets_refresh() {
+    let interval = ENVIRONMENT.secrets.refresh_interval;
+    if interval.is_zero() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        // first tick completes immediately and secrets have just been read
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            ENVIRONMENT.refresh_secrets().await;
+        }
+    });
+}
+
+/// Struct containing the address where the http server listens
+pub struct ServerVariables {
+    pub host: String,
+    pub port: u16,
+    /// if true, the client address is read from the `X-Forwarded-For` header
+    pub trust_forwarded_for: bool,
+}
+
+impl ServerVariables {
+    /// Address in the form `host:port`
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
     }
 }
 
@@ -116,14 +1178,221 @@ pub struct LoggingVariables {
     pub level: tracing::Level,
     /// if true, we include headers in every log coming from a http request
     pub include_headers: bool,
+    pub format: LogFormat,
+    /// `EnvFilter` directives refining the level by module, e.g. `mongodb=warn`
+    pub directives: Vec<String>,
+    /// lowercase names of the headers redacted in the logs besides the default ones
+    pub redacted_headers: Vec<String>,
+    /// set only when the logs are written to files too
+    pub file: Option<LogFileVariables>,
+}
+
+/// How log lines are formatted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    /// single line with every span of the event
+    Full,
+    /// multiple lines per event, for development
+    Pretty,
+    /// single line with the fields of the spans only
+    Compact,
+    /// a json object per line
+    Json,
+}
+
+/// Rotating files receiving the logs
+pub struct LogFileVariables {
+    pub directory: PathBuf,
+    /// prefix of the file names, followed by the date of the rotation
+    pub prefix: String,
+    pub rotation: Rotation,
+    /// rotated files kept, older ones are deleted. Every file is kept if `None`
+    pub max_files: Option<usize>,
 }
 
 /// Struct containing variables for authentication
 ///
-/// It contains two keys used to encode and decode jwt tokens for web application
+/// With `HS256` it contains the keys used to encode and decode jwt tokens for web
+/// application, they are replaced when the jwt secret is rotated.
+/// Asymmetric keys are managed by the signing key service instead.
 pub struct AuthenticationVariables {
-    pub jwt_encoding: EncodingKey,
-    pub jwt_decoding: DecodingKey,
+    /// algorithm used to sign new tokens
+    pub algorithm: Algorithm,
+    /// algorithms of the tokens that are verified, it always contains `algorithm`
+    pub accepted_algorithms: Vec<Algorithm>,
+    /// value of the `iss` claim issued and required
+    pub issuer: String,
+    /// value of the `aud` claim of tokens for the web application
+    pub web_app_audience: String,
+    /// value of the `aud` claim of tokens for internal services, issued to the
+    /// service accounts
+    pub service_audience: String,
+    /// validity of issued tokens
+    pub token_lifetime: Duration,
+    /// tolerance on `exp`, `nbf` and `iat` for clock skew
+    pub leeway: Duration,
+    /// validity of the tokens issued to admins impersonating a user
+    pub impersonation_lifetime: Duration,
+    /// validity of the tokens issued to service accounts by the client credentials grant
+    pub client_credentials_lifetime: Duration,
+    /// age after which a new asymmetric key is generated
+    pub key_rotation_interval: Duration,
+    /// time a retired asymmetric key keeps verifying tokens
+    pub key_grace_period: Duration,
+    /// how often asymmetric keys are read again from the database
+    pub key_refresh_interval: Duration,
+    /// hex key encrypting private keys in the database, set with asymmetric algorithms
+    pub key_encryption_key: Option<String>,
+    hmac_keys: RwLock<Option<Arc<JwtKeys>>>,
+}
+
+/// Keys derived from the jwt secret
+pub struct JwtKeys {
+    pub encoding: EncodingKey,
+    pub decoding: DecodingKey,
+    /// decoding key of the previous secret, tokens signed before the rotation
+    /// are still valid until they expire
+    pub previous_decoding: Option<DecodingKey>,
+    fingerprint: Vec<u8>,
+}
+
+impl JwtKeys {
+    fn new(secret: &str, previous_decoding: Option<DecodingKey>) -> Self {
+        JwtKeys {
+            encoding: EncodingKey::from_secret(secret.as_bytes()),
+            decoding: DecodingKey::from_secret(secret.as_bytes()),
+            previous_decoding,
+            fingerprint: Sha256::digest(secret.as_bytes()).to_vec(),
+        }
+    }
+}
+
+impl AuthenticationVariables {
+    /// Returns the HMAC keys currently in use, they exist only with `HS256`
+    pub fn keys(&self) -> Option<Arc<JwtKeys>> {
+        self.hmac_keys
+            .read()
+            .expect("Authentication keys lock is poisoned")
+            .clone()
+    }
+
+    /// Replace the keys if the secret is changed, returns true if it happened
+    fn rotate(&self, secret: &str) -> bool {
+        let mut keys = self
+            .hmac_keys
+            .write()
+            .expect("Authentication keys lock is poisoned");
+        let previous_decoding = match keys.as_ref() {
+            Some(current)
+                if current.fingerprint == Sha256::digest(secret.as_bytes()).as_slice() =>
+            {
+                return false
+            }
+            Some(current) => Some(current.decoding.clone()),
+            None => None,
+        };
+        *keys = Some(Arc::new(JwtKeys::new(secret, previous_decoding)));
+        true
+    }
+}
+
+/// Struct containing variables protecting the login from brute-force attacks
+pub struct LoginProtectionVariables {
+    /// failures after which the username is locked
+    pub max_failures_per_username: u32,
+    /// failures after which the client address is locked
+    pub max_failures_per_ip: u32,
+    /// failures older than this window are forgotten
+    pub failure_window: Duration,
+    /// duration of the lockout
+    pub lockout: Duration,
+    /// delay after the first failure, it doubles at every failure
+    pub delay_base: Duration,
+    pub max_delay: Duration,
+}
+
+/// Struct containing variables for the history of logins
+pub struct LoginHistoryVariables {
+    /// how long successful and failed logins are kept
+    pub retention: Duration,
+}
+
+/// Struct containing variables for multi-factor authentication
+pub struct MfaVariables {
+    /// roles whose users must log in with a second factor
+    pub required_roles: Vec<Role>,
+    /// issuer written in the otpauth uri
+    pub issuer: String,
+    /// validity of the challenge token completing the login
+    pub challenge_lifetime: Duration,
+    /// number of recovery codes generated with the enrollment
+    pub recovery_codes: usize,
+}
+
+/// Struct containing the password policy
+pub struct PasswordVariables {
+    pub min_length: usize,
+    /// validity of the tokens resetting a forgotten password
+    pub reset_token_ttl: Duration,
+}
+
+/// Struct containing variables for the verification of user emails
+pub struct EmailVerificationVariables {
+    /// validity of the links sent to verify an email
+    pub token_ttl: Duration,
+    /// minimum time between two verification emails sent to a user
+    pub resend_interval: Duration,
+    /// refuse the login of users without a verified email
+    pub required_for_login: bool,
+}
+
+/// Struct containing variables for user invitations
+pub struct InvitationVariables {
+    /// validity of the links accepting an invitation
+    pub ttl: Duration,
+}
+
+/// Struct containing variables for sending emails
+pub struct MailVariables {
+    pub transport: MailTransport,
+    /// sender of every email
+    pub from: String,
+    /// base url of the web app used in links, without trailing slash
+    pub web_app_url: String,
+    /// how often the outbox is checked for emails to deliver
+    pub outbox_poll_interval: Duration,
+    /// maximum number of delivery attempts of an email
+    pub max_attempts: u32,
+}
+
+/// Transport delivering the emails
+pub enum MailTransport {
+    Smtp(SmtpVariables),
+    /// every email is written as a file in the directory
+    File(std::path::PathBuf),
+    /// emails are kept in memory, used by tests
+    Memory,
+}
+
+/// Struct containing variables of the SMTP server
+pub struct SmtpVariables {
+    pub host: String,
+    pub port: u16,
+    pub security: SmtpSecurity,
+    pub timeout: Duration,
+    /// username and password, without them no authentication is made
+    pub credentials: Option<(String, String)>,
+}
+
+/// How the connection with the SMTP server is protected
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SmtpSecurity {
+    /// the connection is upgraded with the STARTTLS command
+    StartTls,
+    /// the connection is TLS from the beginning
+    Tls,
+    /// plain connection, only for local servers
+    None,
 }
 
 /// Struct containing variables for data base like connection string
@@ -131,3 +1400,191 @@ pub struct DatabaseVariables {
     pub connection_string: String,
     pub db_name: String,
 }
+
+/// Struct containing variables for webhook deliveries
+pub struct WebhookVariables {
+    /// maximum number of attempts for a single event delivery
+    pub max_attempts: u32,
+    /// waiting time before the first retry, it doubles at every attempt
+    pub backoff_base: Duration,
+    /// number of consecutive failed deliveries after which the subscription is disabled
+    pub failure_threshold: u32,
+    /// timeout of every http request made to the webhook url
+    pub request_timeout: Duration,
+}
+
+/// Struct containing the lifecycle of the sdk versions
+pub struct SdkVariables {
+    /// every version in the order of `SdkVersion::ALL`
+    pub versions: Vec<SdkVersionVariables>,
+}
+
+impl SdkVariables {
+    pub fn version(&self, version: SdkVersion) -> &SdkVersionVariables {
+        self.versions
+            .iter()
+            .find(|variables| variables.version == version)
+            .expect("Every sdk version is configured")
+    }
+}
+
+/// Struct containing the lifecycle of a sdk version
+#[derive(Clone)]
+pub struct SdkVersionVariables {
+    pub version: SdkVersion,
+    /// from this time clients are told to move to the successor version
+    pub deprecated_at: Option<DateTime>,
+    /// from this time the version is not served anymore
+    pub sunset_at: Option<DateTime>,
+}
+
+/// Struct containing the limits of the requests
+pub struct RateLimitVariables {
+    /// if false, requests are never limited
+    pub enabled: bool,
+    pub store: RateLimitStore,
+    /// limit of the clients without valid credentials, per client address
+    pub anonymous: RateLimit,
+    /// plan of the users and service accounts without one
+    pub default_plan: String,
+    /// limit of the authenticated clients by plan name
+    pub plans: BTreeMap<String, RateLimit>,
+    /// stricter limits of some routes applied on top of the plan
+    pub routes: Vec<RouteRateLimit>,
+}
+
+impl RateLimitVariables {
+    /// Returns the limit of the plan, unknown plans get the default one
+    pub fn plan(&self, name: Option<&str>) -> &RateLimit {
+        &self.plans[self.plan_name(name)]
+    }
+
+    /// Returns the name of the plan applied, unknown plans get the default one
+    pub fn plan_name<'a>(&'a self, name: Option<&'a str>) -> &'a str {
+        name.filter(|name| self.plans.contains_key(*name))
+            .unwrap_or(&self.default_plan)
+    }
+
+    /// Returns true if the plan is configured
+    pub fn has_plan(&self, name: &str) -> bool {
+        self.plans.contains_key(name)
+    }
+}
+
+/// Where the token buckets are kept
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RateLimitStore {
+    /// buckets of every instance are independent
+    Memory,
+    /// buckets are shared by the instances through the database
+    Database,
+}
+
+/// Token bucket of `burst` tokens refilled at `per_second` tokens per second
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_second: f64,
+}
+
+/// Limit of the requests matching the method and the path
+#[derive(Debug, Clone, Deserialize)]
+pub struct RouteRateLimit {
+    /// uppercase method, every method if missing
+    pub method: Option<String>,
+    /// segments starting with `:` match any value, e.g. `/sdk/v0/user/:id`
+    pub path: String,
+    #[serde(flatten)]
+    pub limit: RateLimit,
+}
+
+/// Struct containing variables for the usage metering of the sdk
+pub struct UsageVariables {
+    /// how often the counts of the requests are written in the database
+    pub flush_interval: Duration,
+    /// how long the hourly counts are kept
+    pub retention: Duration,
+    /// requests allowed per calendar month by plan, plans without quota are unlimited
+    pub monthly_quotas: BTreeMap<String, u64>,
+}
+
+/// Struct containing variables of the prometheus metrics
+pub struct MetricsVariables {
+    pub enabled: bool,
+    /// port serving only the metrics, the application port serves them if `None`
+    pub port: Option<u16>,
+    /// bearer token required to read the metrics, set by the secret `METRICS_TOKEN`
+    pub token: Option<String>,
+}
+
+/// Struct containing the secret provider and how often secrets are read again
+pub struct SecretsVariables {
+    pub refresh_interval: Duration,
+    pub provider: Arc<dyn SecretProvider>,
+}
+
+/// Struct containing variables of the span export with OTLP over http
+#[derive(Clone)]
+pub struct TelemetryVariables {
+    /// url receiving the spans, e.g. `http://localhost:4318/v1/traces`
+    pub endpoint: String,
+    pub service_name: String,
+    /// share of the traces started here that are exported, the traces
+    /// continued from a caller follow its decision
+    pub sampling_ratio: f64,
+    /// spans exported, e.g. `sandbox_rust_web_app=debug`
+    pub directives: Vec<String>,
+    pub timeout: Duration,
+}
+
+/// Struct containing variables for the login with an OpenID Connect provider
+#[derive(Clone)]
+pub struct OidcVariables {
+    /// issuer of the provider, the discovery document is read below it
+    pub issuer_url: String,
+    pub client_id: String,
+    /// secret of confidential clients, public clients rely on PKCE only
+    pub client_secret: Option<String>,
+    /// url of the web app where the provider redirects the user after the login
+    pub redirect_uri: String,
+    pub scopes: Vec<String>,
+    /// if true, users logging in for the first time are created
+    pub provisioning: bool,
+    /// role of the users created at their first login
+    pub provisioning_role: Role,
+    /// time the user has to complete the login on the provider
+    pub login_state_ttl: Duration,
+    /// timeout of every http request made to the provider
+    pub request_timeout: Duration,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{source::ConfigurationSource, EnvironmentVariables};
+
+    fn mail_problems(deploy_environment: &str, transport: Option<&str>) -> Vec<String> {
+        let mut vars = vec![("DEPLOY_ENVIRONMENT".to_string(), deploy_environment.into())];
+        if let Some(transport) = transport {
+            vars.push(("APP__MAIL__TRANSPORT".into(), transport.into()));
+        }
+        let source = ConfigurationSource::load(vars, Vec::new()).unwrap();
+        let mut problems = Vec::new();
+        EnvironmentVariables::build_mail(&source, deploy_environment, &mut problems);
+        problems
+    }
+
+    #[test]
+    fn mail_transport_test() {
+        assert_eq!(
+            mail_problems("production", None),
+            vec!["`mail.transport` is missing".to_string()]
+        );
+        assert_eq!(mail_problems("production", Some("file")).len(), 1);
+        assert_eq!(mail_problems("production", Some("memory")).len(), 1);
+        assert!(mail_problems("production", Some("smtp"))
+            .iter()
+            .any(|problem| problem.contains("mail.smtp_host")));
+        assert!(mail_problems("test", Some("memory")).is_empty());
+        assert!(mail_problems("local", Some("file")).is_empty());
+    }
+}
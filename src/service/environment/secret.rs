@@ -0,0 +1,251 @@
+//! Secret providers used by the environment service to read sensitive values.
+//!
+//! A secret is identified by a name like `JWT_SECRET` and it can be provided by:
+//!     - an environment variable with the same name;
+//!     - a file whose path is in the environment variable `<NAME>_FILE`, this is
+//!       how Docker and Kubernetes mount secrets;
+//!     - a local encrypted secrets file, a json object mapping names to values
+//!       encrypted with ChaCha20-Poly1305 using the hex key in `SECRETS_KEY`.
+//!
+//! Files are read every time a secret is requested so that a refresh picks up
+//! rotated values without restarting the application.
+
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::{anyhow, Context};
+use base64ct::{Base64, Encoding};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Key, Nonce,
+};
+
+/// Secret used to sign and verify jwt tokens
+pub const JWT_SECRET: &str = "JWT_SECRET";
+/// Secret containing the database connection string with its credentials
+pub const MONGODB_CONNECTION_STRING: &str = "MONGODB_CONNECTION_STRING";
+/// Hex encoded key used to decrypt the local secrets file
+pub const SECRETS_KEY: &str = "SECRETS_KEY";
+/// Hex encoded key used to encrypt jwt signing keys stored in the database
+pub const JWT_KEY_ENCRYPTION_KEY: &str = "JWT_KEY_ENCRYPTION_KEY";
+/// Client secret registered with the OpenID Connect provider
+pub const OIDC_CLIENT_SECRET: &str = "OIDC_CLIENT_SECRET";
+/// Password of the SMTP server delivering emails
+pub const SMTP_PASSWORD: &str = "SMTP_PASSWORD";
+/// Bearer token of the scraper reading the metrics on the application port
+pub const METRICS_TOKEN: &str = "METRICS_TOKEN";
+
+/// Trait implemented by every source of secrets
+pub trait SecretProvider: Send + Sync {
+    /// Return the secret value or `None` if the provider does not know it
+    fn get(&self, name: &str) -> Result<Option<String>, anyhow::Error>;
+}
+
+/// Provider reading secrets from environment variables
+pub struct EnvironmentSecretProvider {
+    vars: HashMap<String, String>,
+}
+
+impl EnvironmentSecretProvider {
+    pub fn new(vars: &[(String, String)]) -> Self {
+        EnvironmentSecretProvider {
+            vars: vars.iter().cloned().collect(),
+        }
+    }
+}
+
+impl SecretProvider for EnvironmentSecretProvider {
+    fn get(&self, name: &str) -> Result<Option<String>, anyhow::Error> {
+        Ok(self.vars.get(name).cloned())
+    }
+}
+
+/// Provider reading secrets from the files referenced by `<NAME>_FILE` variables
+pub struct FileSecretProvider {
+    paths: HashMap<String, PathBuf>,
+}
+
+impl FileSecretProvider {
+    pub fn new(vars: &[(String, String)]) -> Self {
+        let paths = vars
+            .iter()
+            .filter_map(|(key, value)| {
+                key.strip_suffix("_FILE")
+                    .map(|name| (name.to_string(), PathBuf::from(value)))
+            })
+            .collect();
+        FileSecretProvider { paths }
+    }
+}
+
+impl SecretProvider for FileSecretProvider {
+    fn get(&self, name: &str) -> Result<Option<String>, anyhow::Error> {
+        match self.paths.get(name) {
+            Some(path) => {
+                let content = std::fs::read_to_string(path)
+                    .with_context(|| format!("cannot read {name} from {}", path.display()))?;
+                Ok(Some(content.trim_end_matches(['\n', '\r']).to_string()))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// Provider reading secrets from a local encrypted json file
+pub struct EncryptedFileSecretProvider {
+    path: PathBuf,
+    cipher: ChaCha20Poly1305,
+}
+
+impl EncryptedFileSecretProvider {
+    pub fn new(path: PathBuf, hex_key: &str) -> Result<Self, anyhow::Error> {
+        Ok(EncryptedFileSecretProvider {
+            path,
+            cipher: build_cipher(hex_key)?,
+        })
+    }
+}
+
+impl SecretProvider for EncryptedFileSecretProvider {
+    fn get(&self, name: &str) -> Result<Option<String>, anyhow::Error> {
+        let content = std::fs::read_to_string(&self.path)
+            .with_context(|| format!("cannot read secrets file {}", self.path.display()))?;
+        let secrets: HashMap<String, String> = serde_json::from_str(&content)
+            .with_context(|| format!("secrets file {} is not valid", self.path.display()))?;
+        match secrets.get(name) {
+            Some(encrypted) => decrypt(&self.cipher, encrypted)
+                .with_context(|| format!("cannot decrypt {name}"))
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Provider asking a list of providers in order, the first one knowing the secret wins
+pub struct ChainedSecretProvider {
+    providers: Vec<Box<dyn SecretProvider>>,
+}
+
+impl ChainedSecretProvider {
+    /// Build the default chain: environment variables, `*_FILE` variables and, if
+    /// `secrets_file` is given, the encrypted secrets file
+    pub fn from_vars(
+        vars: &[(String, String)],
+        secrets_file: Option<PathBuf>,
+    ) -> Result<Self, anyhow::Error> {
+        let mut chain = ChainedSecretProvider {
+            providers: vec![
+                Box::new(EnvironmentSecretProvider::new(vars)),
+                Box::new(FileSecretProvider::new(vars)),
+            ],
+        };
+        if let Some(path) = secrets_file {
+            // the key itself cannot come from the encrypted file
+            let key = chain
+                .get(SECRETS_KEY)?
+                .ok_or_else(|| anyhow!("{SECRETS_KEY} must be set to read {}", path.display()))?;
+            chain
+                .providers
+                .push(Box::new(EncryptedFileSecretProvider::new(path, &key)?));
+        }
+        Ok(chain)
+    }
+}
+
+impl SecretProvider for ChainedSecretProvider {
+    fn get(&self, name: &str) -> Result<Option<String>, anyhow::Error> {
+        for provider in &self.providers {
+            if let Some(value) = provider.get(name)? {
+                return Ok(Some(value));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Encrypt the value producing the string to store in the encrypted secrets file
+pub fn encrypt_secret(hex_key: &str, value: &str) -> Result<String, anyhow::Error> {
+    let cipher = build_cipher(hex_key)?;
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, value.as_bytes())
+        .map_err(|_| anyhow!("encryption failed"))?;
+    let mut encoded = nonce.to_vec();
+    encoded.extend(ciphertext);
+    Ok(Base64::encode_string(&encoded))
+}
+
+/// Decrypt a value produced by `encrypt_secret` with the same key
+pub fn decrypt_secret(hex_key: &str, encrypted: &str) -> Result<String, anyhow::Error> {
+    decrypt(&build_cipher(hex_key)?, encrypted)
+}
+
+fn decrypt(cipher: &ChaCha20Poly1305, encrypted: &str) -> Result<String, anyhow::Error> {
+    let bytes = Base64::decode_vec(encrypted).map_err(|_| anyhow!("value is not base64"))?;
+    // nonce of ChaCha20Poly1305 is 12 bytes long
+    if bytes.len() < 12 {
+        return Err(anyhow!("value is too short"));
+    }
+    let (nonce, ciphertext) = bytes.split_at(12);
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow!("wrong key or corrupted value"))?;
+    Ok(String::from_utf8(plaintext)?)
+}
+
+fn build_cipher(hex_key: &str) -> Result<ChaCha20Poly1305, anyhow::Error> {
+    let key = hex::decode(hex_key.trim()).context("secrets key must be hex encoded")?;
+    if key.len() != 32 {
+        return Err(anyhow!("secrets key must be 32 bytes long"));
+    }
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::{encrypt_secret, ChainedSecretProvider, SecretProvider, JWT_SECRET, SECRETS_KEY};
+
+    const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    fn temp_file(content: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("secret-{}", uuid::Uuid::new_v4()));
+        let mut file = std::fs::File::create(&path).unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        path
+    }
+
+    #[test]
+    fn chained_provider_test() {
+        let secret_file = temp_file("from-file\n");
+        let encrypted = encrypt_secret(KEY, "from-encrypted").unwrap();
+        let secrets_file = temp_file(&format!(r#"{{"JWT_SECRET": "{encrypted}"}}"#));
+        let vars = vec![
+            (SECRETS_KEY.to_string(), KEY.to_string()),
+            ("OTHER_FILE".to_string(), secret_file.display().to_string()),
+            ("ENV".to_string(), "from-env".to_string()),
+        ];
+        let provider = ChainedSecretProvider::from_vars(&vars, Some(secrets_file.clone())).unwrap();
+
+        assert_eq!(provider.get("ENV").unwrap(), Some("from-env".into()));
+        assert_eq!(provider.get("OTHER").unwrap(), Some("from-file".into()));
+        assert_eq!(
+            provider.get(JWT_SECRET).unwrap(),
+            Some("from-encrypted".into())
+        );
+        assert_eq!(provider.get("MISSING").unwrap(), None);
+
+        // rotated file is read again
+        std::fs::write(&secret_file, "rotated").unwrap();
+        assert_eq!(provider.get("OTHER").unwrap(), Some("rotated".into()));
+
+        // wrong key cannot decrypt
+        let wrong_key = KEY.replace("00", "ff");
+        let vars = vec![(SECRETS_KEY.to_string(), wrong_key)];
+        let provider = ChainedSecretProvider::from_vars(&vars, Some(secrets_file.clone())).unwrap();
+        assert!(provider.get(JWT_SECRET).is_err());
+
+        std::fs::remove_file(secret_file).unwrap();
+        std::fs::remove_file(secrets_file).unwrap();
+    }
+}
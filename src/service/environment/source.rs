@@ -0,0 +1,342 @@
+//! Configuration source merging the configuration layers into a single toml table.
+//!
+//! Layers are applied in the following order, each one overriding the previous:
+//!     - defaults embedded in the binary from `config/default.toml`;
+//!     - the file of the deploy environment, `config/<DEPLOY_ENVIRONMENT>.toml`;
+//!     - environment variables and secrets;
+//!     - command line flags.
+
+use std::{path::PathBuf, sync::Arc};
+
+use serde::de::DeserializeOwned;
+use toml::{Table, Value};
+
+use super::{
+    secret::{self, ChainedSecretProvider, SecretProvider},
+    ConfigurationError,
+};
+
+const DEFAULTS: &str = include_str!("../../../config/default.toml");
+
+/// Prefix of environment variables that override configuration keys.
+///
+/// `APP__SERVER__PORT` overrides the key `server.port`.
+const ENV_PREFIX: &str = "APP__";
+
+/// Environment variables kept for backward compatibility with existing deployments
+const ENV_ALIASES: [(&str, &str); 1] = [("MONGODB_DB_NAME", "database.db_name")];
+
+/// Secrets read through the secret provider and the configuration key they set
+pub const SECRET_KEYS: [(&str, &str); 2] = [
+    (secret::JWT_SECRET, "authentication.jwt_secret"),
+    (
+        secret::MONGODB_CONNECTION_STRING,
+        "database.connection_string",
+    ),
+];
+
+/// Merged configuration values
+pub struct ConfigurationSource {
+    pub deploy_environment: String,
+    /// provider used to read secrets, it is kept to refresh them later
+    pub secrets: Arc<dyn SecretProvider>,
+    values: Table,
+}
+
+impl ConfigurationSource {
+    /// Load every configuration layer from the given environment variables and
+    /// command line arguments, the program name must not be included in `args`.
+    pub fn load(
+        vars: impl IntoIterator<Item = (String, String)>,
+        args: impl IntoIterator<Item = String>,
+    ) -> Result<Self, ConfigurationError> {
+        let mut problems = Vec::new();
+        let vars: Vec<(String, String)> = vars.into_iter().collect();
+        let var = |name: &str| {
+            vars.iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+        };
+        let flags = parse_flags(args, &mut problems);
+        let flag = |name: &str| {
+            flags
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+        };
+
+        let mut values: Table = DEFAULTS
+            .parse()
+            .expect("Embedded default configuration must be valid toml");
+
+        let deploy_environment = flag("environment").or_else(|| var("DEPLOY_ENVIRONMENT"));
+        let Some(deploy_environment) = deploy_environment else {
+            problems.push("DEPLOY_ENVIRONMENT must be set".to_string());
+            return Err(ConfigurationError { problems });
+        };
+
+        // deploy environment file is optional unless explicitly requested
+        let explicit_file = flag("config").or_else(|| var("CONFIG_FILE"));
+        let file_path = explicit_file.clone().map(PathBuf::from).unwrap_or_else(|| {
+            PathBuf::from(var("CONFIG_DIR").unwrap_or_else(|| "config".to_string()))
+                .join(format!("{deploy_environment}.toml"))
+        });
+        match std::fs::read_to_string(&file_path) {
+            Ok(content) => match content.parse::<Table>() {
+                Ok(file_values) => merge(&mut values, file_values),
+                Err(e) => problems.push(format!(
+                    "configuration file {} is not valid toml: {e}",
+                    file_path.display()
+                )),
+            },
+            Err(e) if explicit_file.is_some() => problems.push(format!(
+                "cannot read configuration file {}: {e}",
+                file_path.display()
+            )),
+            Err(_) => {}
+        }
+
+        for (name, value) in &vars {
+            let key = if let Some(key) = name.strip_prefix(ENV_PREFIX) {
+                key.split("__")
+                    .map(str::to_lowercase)
+                    .collect::<Vec<_>>()
+                    .join(".")
+            } else if let Some((_, key)) = ENV_ALIASES.iter().find(|(alias, _)| alias == name) {
+                key.to_string()
+            } else {
+                continue;
+            };
+            set(&mut values, &key, parse_value(value));
+        }
+
+        // the encrypted secrets file can be set in any previous layer
+        let secrets_file = match lookup(&values, "secrets.file") {
+            Some(Value::String(path)) => Some(PathBuf::from(path)),
+            Some(_) => {
+                problems.push("`secrets.file` must be a path".to_string());
+                None
+            }
+            None => None,
+        };
+        let secrets: Arc<dyn SecretProvider> =
+            match ChainedSecretProvider::from_vars(&vars, secrets_file) {
+                Ok(provider) => Arc::new(provider),
+                Err(e) => {
+                    problems.push(format!("cannot build secret provider: {e:#}"));
+                    Arc::new(
+                        ChainedSecretProvider::from_vars(&vars, None)
+                            .expect("Secret provider without secrets file cannot fail"),
+                    )
+                }
+            };
+        for (name, key) in SECRET_KEYS {
+            match secrets.get(name) {
+                Ok(Some(value)) => set(&mut values, key, Value::String(value)),
+                Ok(None) => {}
+                Err(e) => problems.push(format!("cannot read secret {name}: {e:#}")),
+            }
+        }
+
+        for (key, value) in &flags {
+            if key.contains('.') {
+                set(&mut values, key, parse_value(value));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(ConfigurationSource {
+                deploy_environment,
+                secrets,
+                values,
+            })
+        } else {
+            Err(ConfigurationError { problems })
+        }
+    }
+
+    /// Read the value at the dotted key, registering a problem if it is missing
+    /// or it has the wrong type
+    pub fn get<T: DeserializeOwned>(&self, key: &str, problems: &mut Vec<String>) -> Option<T> {
+        match lookup(&self.values, key) {
+            Some(value) => value
+                .clone()
+                .try_into()
+                .or_else(|e| match value {
+                    // values from environment and flags are typed by guessing,
+                    // hence, a secret made of digits is still a valid string
+                    Value::Integer(_)
+                    | Value::Float(_)
+                    | Value::Boolean(_)
+                    | Value::Datetime(_) => {
+                        Value::String(value.to_string()).try_into().map_err(|_| e)
+                    }
+                    _ => Err(e),
+                })
+                .map_err(|e| problems.push(format!("`{key}` is invalid: {e}")))
+                .ok(),
+            None => {
+                problems.push(format!("`{key}` is missing"));
+                None
+            }
+        }
+    }
+
+    /// Read the value at the dotted key if it exists, registering a problem
+    /// only if it has the wrong type
+    pub fn get_optional<T: DeserializeOwned>(
+        &self,
+        key: &str,
+        problems: &mut Vec<String>,
+    ) -> Option<T> {
+        lookup(&self.values, key)?;
+        self.get(key, problems)
+    }
+}
+
+fn lookup<'a>(values: &'a Table, key: &str) -> Option<&'a Value> {
+    let mut parts = key.split('.');
+    let mut current = values.get(parts.next()?)?;
+    for part in parts {
+        current = current.as_table()?.get(part)?;
+    }
+    Some(current)
+}
+
+/// Parse flags in the form `--name value` or `--name=value`
+fn parse_flags(
+    args: impl IntoIterator<Item = String>,
+    problems: &mut Vec<String>,
+) -> Vec<(String, String)> {
+    let mut flags = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let Some(name) = arg.strip_prefix("--") else {
+            problems.push(format!("unexpected argument `{arg}`"));
+            continue;
+        };
+        let (name, value) = match name.split_once('=') {
+            Some((name, value)) => (name.to_string(), Some(value.to_string())),
+            None => (name.to_string(), args.next()),
+        };
+        match value {
+            Some(value) if name == "config" || name == "environment" || name.contains('.') => {
+                flags.push((name, value))
+            }
+            Some(_) => problems.push(format!("unknown flag `--{name}`")),
+            None => problems.push(format!("flag `--{name}` requires a value")),
+        }
+    }
+    flags
+}
+
+/// Interpret raw strings as toml values so that numbers and booleans keep their type,
+/// anything else is a string
+fn parse_value(raw: &str) -> Value {
+    format!("value = {raw}")
+        .parse::<Table>()
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .filter(|value| !value.is_table() && !value.is_array())
+        .unwrap_or_else(|| Value::String(raw.to_string()))
+}
+
+fn set(values: &mut Table, key: &str, value: Value) {
+    let mut parts: Vec<&str> = key.split('.').collect();
+    let last = parts.pop().expect("split returns at least one element");
+    let mut current = values;
+    for part in parts {
+        let entry = current
+            .entry(part)
+            .or_insert_with(|| Value::Table(Table::new()));
+        if !entry.is_table() {
+            *entry = Value::Table(Table::new());
+        }
+        current = entry
+            .as_table_mut()
+            .expect("entry has just been set as table");
+    }
+    current.insert(last.to_string(), value);
+}
+
+fn merge(base: &mut Table, other: Table) {
+    for (key, value) in other {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base_table)), Value::Table(other_table)) => {
+                merge(base_table, other_table)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConfigurationSource;
+
+    fn vars(values: &[(&str, &str)]) -> Vec<(String, String)> {
+        values
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn args(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn layers_override_test() {
+        let source = ConfigurationSource::load(
+            vars(&[
+                ("DEPLOY_ENVIRONMENT", "not-existing"),
+                ("APP__SERVER__PORT", "4000"),
+                ("APP__SERVER__HOST", "127.0.0.1"),
+                ("JWT_SECRET", "1234"),
+            ]),
+            args(&["--server.port", "5000"]),
+        )
+        .unwrap();
+        let mut problems = Vec::new();
+        assert_eq!(source.deploy_environment, "not-existing");
+        // flags override environment variables
+        assert_eq!(source.get::<u16>("server.port", &mut problems), Some(5000));
+        // environment variables override defaults
+        assert_eq!(
+            source.get::<String>("server.host", &mut problems),
+            Some("127.0.0.1".to_string())
+        );
+        assert_eq!(
+            source.get::<String>("authentication.jwt_secret", &mut problems),
+            Some("1234".to_string())
+        );
+        // defaults are used when nothing else is provided
+        assert_eq!(
+            source.get::<u32>("webhook.max_attempts", &mut problems),
+            Some(5)
+        );
+        assert!(problems.is_empty());
+    }
+
+    #[test]
+    fn report_every_problem_test() {
+        let source = ConfigurationSource::load(
+            vars(&[("DEPLOY_ENVIRONMENT", "not-existing")]),
+            args(&["--server.port=not-a-number"]),
+        )
+        .unwrap();
+        let mut problems = Vec::new();
+        assert!(source.get::<u16>("server.port", &mut problems).is_none());
+        assert!(source
+            .get::<String>("database.db_name", &mut problems)
+            .is_none());
+        assert_eq!(problems.len(), 2);
+
+        let error = ConfigurationSource::load(vars(&[]), args(&["positional", "--unknown=1"]));
+        assert!(error.is_err());
+        // missing deploy environment stops loading
+        assert_eq!(error.err().unwrap().problems.len(), 3);
+    }
+}
@@ -0,0 +1,203 @@
+//! Invitations of new users by the admins.
+//!
+//! The invitee receives by email a link with a signed token and chooses
+//! username and password when accepting it. The token expires after
+//! `invitation.ttl_s` and it carries the nonce of the invitation, hence,
+//! resending the invitation replaces the previous link and cancelling it
+//! invalidates every link sent.
+//!
+//! The user created by the invitation has a verified email since the link has
+//! been received at that address.
+
+use std::time::SystemTime;
+
+use anyhow::anyhow;
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{doc, DateTime},
+    options::{FindOneAndUpdateOptions, FindOptions, ReturnDocument},
+};
+use tracing::info;
+
+use crate::{
+    auth::InvitationClaim,
+    enums::Role,
+    error::AppError,
+    model::{invitation::Invitation, user::User},
+    service::{
+        db::{get_database_service, DatabaseDocument},
+        email_verification,
+        environment::ENVIRONMENT,
+        mailer::{self, EmailMessage},
+        password, user,
+    },
+    InvitationId, UserId,
+};
+
+/// Invite the email to create a user with the role
+pub async fn invite(invited_by: &UserId, email: &str, role: Role) -> Result<Invitation, AppError> {
+    let email = email_verification::normalize(email)?;
+    if user::find_by_email(&email).await?.is_some() {
+        return Err(AppError::InvalidRequest(anyhow!(
+            "Email is already used by another user"
+        )));
+    }
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<Invitation>(Invitation::collection_name());
+    // expired invitations do not prevent a new one
+    collection
+        .delete_many(
+            doc! { "email": &email, "expires_at": { "$lte": DateTime::now() } },
+            None,
+        )
+        .await?;
+    if collection
+        .count_documents(doc! { "email": &email }, None)
+        .await?
+        > 0
+    {
+        return Err(AppError::InvalidRequest(anyhow!(
+            "Email has already been invited"
+        )));
+    }
+
+    let mut invitation = Invitation {
+        id: None,
+        email,
+        role,
+        invited_by: *invited_by,
+        nonce: new_nonce(),
+        expires_at: expiration(),
+        created_at: DateTime::now(),
+    };
+    let id = invitation.dump(db).await?;
+    invitation.id = Some(id.parse().map_err(|_| {
+        AppError::InternalServerError(anyhow!("Created invitation id is not valid"))
+    })?);
+    info!("User {invited_by} invited {}", invitation.email);
+    send(&invitation).await?;
+    Ok(invitation)
+}
+
+/// Returns the pending invitations, the newest first
+pub async fn list() -> Result<Vec<Invitation>, AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<Invitation>(Invitation::collection_name());
+    let options = FindOptions::builder()
+        .sort(doc! { "created_at": -1 })
+        .build();
+    Ok(collection.find(None, options).await?.try_collect().await?)
+}
+
+/// Send a new link restarting the validity of the invitation, the previous link is invalid
+pub async fn resend(invitation_id: &InvitationId) -> Result<Invitation, AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<Invitation>(Invitation::collection_name());
+    let options = FindOneAndUpdateOptions::builder()
+        .return_document(ReturnDocument::After)
+        .build();
+    let invitation = collection
+        .find_one_and_update(
+            doc! { "_id": invitation_id },
+            doc! { "$set": { "nonce": new_nonce(), "expires_at": expiration() } },
+            options,
+        )
+        .await?
+        .ok_or_else(|| not_found(invitation_id))?;
+    send(&invitation).await?;
+    Ok(invitation)
+}
+
+/// Delete the invitation, its links cannot be used anymore
+pub async fn cancel(invitation_id: &InvitationId) -> Result<(), AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<Invitation>(Invitation::collection_name());
+    let result = collection
+        .delete_one(doc! { "_id": invitation_id }, None)
+        .await?;
+    if result.deleted_count == 0 {
+        return Err(not_found(invitation_id));
+    }
+    info!("Invitation {invitation_id} has been cancelled");
+    Ok(())
+}
+
+/// Create the user of the invitation with the chosen username and password
+///
+/// The invitation is consumed, hence, it can be accepted only once
+pub async fn accept(token: &str, username: String, new_password: String) -> Result<User, AppError> {
+    let claim = InvitationClaim::decode_token(token).await?;
+    password::validate(&new_password)?;
+    if user::username_exists(&username).await? {
+        return Err(AppError::InvalidRequest(anyhow!(
+            "Username is already taken"
+        )));
+    }
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<Invitation>(Invitation::collection_name());
+    let invitation = collection
+        .find_one_and_delete(
+            doc! {
+                "_id": claim.invitation_id,
+                "nonce": &claim.nonce,
+                "expires_at": { "$gt": DateTime::now() },
+            },
+            None,
+        )
+        .await?
+        .ok_or_else(|| AppError::InvalidRequest(anyhow!("Invitation is expired or not valid")))?;
+
+    let user_id = user::create_user(
+        username,
+        new_password,
+        Some(invitation.email.clone()),
+        invitation.role,
+    )
+    .await?;
+    let user_id = user_id
+        .parse()
+        .map_err(|_| AppError::InternalServerError(anyhow!("Created user id is not valid")))?;
+    user::mark_email_verified(&user_id, &invitation.email).await?;
+    info!(
+        "Invitation {} accepted by user {user_id}",
+        claim.invitation_id
+    );
+    user::get_user(&user_id).await
+}
+
+/// Email the link accepting the invitation
+async fn send(invitation: &Invitation) -> Result<(), AppError> {
+    let invitation_id = invitation.id.expect("Invitation id must be not missing");
+    let token = InvitationClaim::new(invitation_id, invitation.nonce.clone())
+        .build_token()
+        .await?;
+    let link = format!(
+        "{}/accept-invitation?token={token}",
+        ENVIRONMENT.mail.web_app_url
+    );
+    mailer::enqueue(EmailMessage {
+        to: invitation.email.clone(),
+        subject: "You have been invited".into(),
+        body: format!(
+            "Hello,\n\n\
+             you have been invited to join sandbox-rust-web-app. Open the link below \
+             within {} days to choose your username and password:\n\n{link}\n\n\
+             If you were not expecting it, you can ignore this email.\n",
+            ENVIRONMENT.invitation.ttl.as_secs() / 86400
+        ),
+    })
+    .await
+}
+
+fn new_nonce() -> String {
+    hex::encode(rand::random::<[u8; 16]>())
+}
+
+/// Expiration of an invitation sent now, the same as its token
+fn expiration() -> DateTime {
+    DateTime::from_system_time(SystemTime::now() + ENVIRONMENT.invitation.ttl)
+}
+
+fn not_found(invitation_id: &InvitationId) -> AppError {
+    AppError::DoesNotExist(anyhow!("Invitation with id {invitation_id} does not exist"))
+}
@@ -0,0 +1,327 @@
+//! Logs of the application written to stdout and to rotating files.
+//!
+//! Levels are filtered by `logging.level` refined by the `logging.directives`,
+//! lines are formatted as `logging.format`. Values of the sensitive headers are
+//! never written, even when `logging.include_headers` is set.
+//!
+//! The filter can be replaced at runtime, e.g. to trace a module while debugging,
+//! and restored to the configured one after a timeout. The change only applies
+//! to the instance receiving it. Spans exported to a collector are filtered by
+//! `telemetry.directives` instead.
+
+use std::{sync::Mutex, time::Duration};
+
+use anyhow::anyhow;
+use axum::http::{HeaderMap, HeaderValue};
+use mongodb::bson::DateTime;
+use once_cell::sync::{Lazy, OnceCell};
+use tracing::{info, Subscriber};
+use tracing_appender::{non_blocking::WorkerGuard, rolling::RollingFileAppender};
+use tracing_subscriber::{
+    fmt::{self, MakeWriter},
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    reload,
+    util::SubscriberInitExt,
+    EnvFilter, Layer, Registry,
+};
+
+use crate::{
+    error::AppError,
+    service::{
+        environment::{LogFormat, ENVIRONMENT},
+        telemetry::{init_telemetry, TelemetryGuard},
+    },
+};
+
+/// Headers always redacted, they carry credentials
+const SENSITIVE_HEADERS: [&str; 5] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "x-api-key",
+];
+
+const REDACTED: HeaderValue = HeaderValue::from_static("[redacted]");
+
+/// Handle replacing the filter of the installed subscriber
+static FILTER_HANDLE: OnceCell<reload::Handle<EnvFilter, Registry>> = OnceCell::new();
+
+/// Filter applied to the logs, the configured one until it is replaced
+static FILTER: Lazy<Mutex<FilterState>> = Lazy::new(|| {
+    Mutex::new(FilterState {
+        directives: configured_directives(),
+        revert_at: None,
+        generation: 0,
+    })
+});
+
+struct FilterState {
+    directives: Vec<String>,
+    revert_at: Option<DateTime>,
+    /// incremented by every change, a revert only applies to its own change
+    generation: u64,
+}
+
+/// Directives filtering the logs
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogFilter {
+    /// e.g. `info` and `sandbox_rust_web_app::service::webhook=trace`
+    pub directives: Vec<String>,
+    /// when the configured filter is restored, `None` if the change is permanent
+    pub revert_at: Option<DateTime>,
+}
+
+/// Flushes the log files and exports the last spans when it is dropped
+pub struct LoggingGuard {
+    _file: Option<WorkerGuard>,
+    _telemetry: Option<TelemetryGuard>,
+}
+
+/// Install the subscriber writing the logs as configured by `logging` and
+/// exporting the spans as configured by `telemetry`
+///
+/// The returned guard must be kept until the application exits.
+pub fn init_logging() -> LoggingGuard {
+    let logging = &ENVIRONMENT.logging;
+    let filter = build_filter(&configured_directives()).expect("Directives have been validated");
+    let (filter, handle) = reload::Layer::new(filter);
+    FILTER_HANDLE
+        .set(handle)
+        .expect("Logging is initialized only once");
+    let stdout = format_layer(logging.format, std::io::stdout, true);
+    let (file, file_guard) = match &logging.file {
+        Some(file) => {
+            let mut builder = RollingFileAppender::builder()
+                .rotation(file.rotation.clone())
+                .filename_prefix(&file.prefix);
+            if let Some(max_files) = file.max_files {
+                builder = builder.max_log_files(max_files);
+            }
+            let appender = builder
+                .build(&file.directory)
+                .unwrap_or_else(|e| panic!("Cannot write logs in {:?}: {e}", file.directory));
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            (
+                Some(format_layer(logging.format, writer, false)),
+                Some(guard),
+            )
+        }
+        None => (None, None),
+    };
+    let (telemetry, telemetry_guard) = init_telemetry().unzip();
+    tracing_subscriber::registry()
+        .with(stdout.and_then(file).with_filter(filter))
+        .with(telemetry)
+        .init();
+    LoggingGuard {
+        _file: file_guard,
+        _telemetry: telemetry_guard,
+    }
+}
+
+/// Returns the filter applied to the logs
+pub fn log_filter() -> LogFilter {
+    let state = FILTER.lock().expect("Log filter lock is poisoned");
+    LogFilter {
+        directives: state.directives.clone(),
+        revert_at: state.revert_at,
+    }
+}
+
+/// Replace the filter of the logs, the configured one is restored after
+/// `revert_after` if it is set
+pub fn set_log_filter(
+    directives: Vec<String>,
+    revert_after: Option<Duration>,
+) -> Result<LogFilter, AppError> {
+    let filter = build_filter(&directives).map_err(AppError::InvalidRequest)?;
+    let revert_at = revert_after
+        .map(|revert_after| {
+            DateTime::now()
+                .to_system_time()
+                .checked_add(revert_after)
+                .map(DateTime::from_system_time)
+                .ok_or_else(|| AppError::InvalidRequest(anyhow!("Revert delay is too long")))
+        })
+        .transpose()?;
+    let mut state = FILTER.lock().expect("Log filter lock is poisoned");
+    reload_filter(filter)?;
+    state.directives = directives;
+    state.generation += 1;
+    state.revert_at = revert_at;
+    if let Some(revert_after) = revert_after {
+        let generation = state.generation;
+        tokio::spawn(async move {
+            tokio::time::sleep(revert_after).await;
+            revert(generation);
+        });
+    }
+    info!("Log filter changed to {}", state.directives.join(","));
+    Ok(LogFilter {
+        directives: state.directives.clone(),
+        revert_at: state.revert_at,
+    })
+}
+
+/// Restore the configured filter of the logs
+pub fn reset_log_filter() -> Result<LogFilter, AppError> {
+    set_log_filter(configured_directives(), None)
+}
+
+/// Returns a copy of the headers whose sensitive values are redacted
+pub fn redact_headers(headers: &HeaderMap) -> HeaderMap {
+    let mut redacted = headers.clone();
+    for (name, value) in redacted.iter_mut() {
+        let sensitive = SENSITIVE_HEADERS.contains(&name.as_str())
+            || ENVIRONMENT
+                .logging
+                .redacted_headers
+                .iter()
+                .any(|header| header == name.as_str());
+        if sensitive {
+            *value = REDACTED;
+        }
+    }
+    redacted
+}
+
+/// Level and directives of the configuration
+fn configured_directives() -> Vec<String> {
+    let logging = &ENVIRONMENT.logging;
+    let level = logging.level.as_str().to_lowercase();
+    [level]
+        .into_iter()
+        .chain(logging.directives.iter().cloned())
+        .collect()
+}
+
+/// Filter of the directives, e.g. `info` and `mongodb=debug`
+pub fn build_filter(directives: &[String]) -> Result<EnvFilter, anyhow::Error> {
+    if directives.is_empty() {
+        return Err(anyhow!("At least one filter directive is required"));
+    }
+    directives
+        .iter()
+        .try_fold(EnvFilter::default(), |filter, directive| {
+            let parsed = directive
+                .parse()
+                .map_err(|e| anyhow!("Filter directive {directive} is not valid: {e}"))?;
+            Ok(filter.add_directive(parsed))
+        })
+}
+
+fn reload_filter(filter: EnvFilter) -> Result<(), AppError> {
+    let handle = FILTER_HANDLE
+        .get()
+        .ok_or_else(|| anyhow!("Logging has not been initialized"))?;
+    handle.reload(filter).map_err(anyhow::Error::new)?;
+    Ok(())
+}
+
+/// Restore the configured filter unless it has been changed again
+fn revert(generation: u64) {
+    let mut state = FILTER.lock().expect("Log filter lock is poisoned");
+    if state.generation != generation {
+        return;
+    }
+    let directives = configured_directives();
+    let filter = build_filter(&directives).expect("Directives have been validated");
+    match reload_filter(filter) {
+        Ok(()) => {
+            state.directives = directives;
+            state.revert_at = None;
+            state.generation += 1;
+            info!("Log filter restored to {}", state.directives.join(","));
+        }
+        Err(e) => tracing::error!("Cannot restore the log filter: {e:?}"),
+    }
+}
+
+fn format_layer<S, W>(format: LogFormat, writer: W, ansi: bool) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = fmt::layer().with_writer(writer).with_ansi(ansi);
+    match format {
+        LogFormat::Full => layer.boxed(),
+        LogFormat::Pretty => layer.pretty().boxed(),
+        LogFormat::Compact => layer.compact().boxed(),
+        LogFormat::Json => layer.json().flatten_event(true).boxed(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderMap, HeaderValue};
+
+    use std::time::Duration;
+
+    use tracing::{enabled, Level};
+    use tracing_subscriber::{layer::SubscriberExt, reload, util::SubscriberInitExt};
+
+    use super::{build_filter, log_filter, redact_headers, set_log_filter, FILTER_HANDLE};
+
+    #[test]
+    fn redact_headers_test() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "authorization",
+            HeaderValue::from_static("x-api-key secret"),
+        );
+        headers.insert("x-api-key", HeaderValue::from_static("secret"));
+        headers.append("cookie", HeaderValue::from_static("session=1"));
+        headers.append("cookie", HeaderValue::from_static("session=2"));
+        headers.insert("x-request-id", HeaderValue::from_static("a1b2-c3d4"));
+
+        let redacted = redact_headers(&headers);
+        assert_eq!(redacted["authorization"], "[redacted]");
+        assert_eq!(redacted["x-api-key"], "[redacted]");
+        assert!(redacted
+            .get_all("cookie")
+            .iter()
+            .all(|value| value == "[redacted]"));
+        assert_eq!(redacted["x-request-id"], "a1b2-c3d4");
+    }
+
+    #[test]
+    fn build_filter_test() {
+        let directives = [
+            "info".to_string(),
+            "sandbox_rust_web_app::service=trace".into(),
+        ];
+        let filter = build_filter(&directives).unwrap();
+        assert_eq!(
+            filter.to_string(),
+            "sandbox_rust_web_app::service=trace,info"
+        );
+        assert!(build_filter(&["mongodb=loud".into()]).is_err());
+        assert!(build_filter(&[]).is_err());
+    }
+
+    #[tokio::test]
+    async fn set_log_filter_test() {
+        let (filter, handle) = reload::Layer::new(build_filter(&["info".into()]).unwrap());
+        FILTER_HANDLE.set(handle).unwrap();
+        let _subscriber = tracing_subscriber::registry().with(filter).set_default();
+        assert!(!enabled!(Level::DEBUG));
+
+        let directives = vec!["info".to_string(), "sandbox_rust_web_app=debug".into()];
+        let filter = set_log_filter(directives.clone(), Some(Duration::from_millis(50))).unwrap();
+        assert_eq!(filter.directives, directives);
+        assert!(filter.revert_at.is_some());
+        assert!(enabled!(Level::DEBUG));
+        assert!(set_log_filter(vec!["mongodb=loud".into()], None).is_err());
+        assert!(set_log_filter(vec!["trace".into()], Some(Duration::MAX)).is_err());
+        assert_eq!(log_filter(), filter);
+
+        // the testing configuration traces everything
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let restored = log_filter();
+        assert_eq!(restored.directives, vec!["trace".to_string()]);
+        assert_eq!(restored.revert_at, None);
+        assert!(enabled!(Level::TRACE));
+    }
+}
@@ -0,0 +1,136 @@
+//! History of the successful and failed logins.
+//!
+//! Attempts are recorded with the client address and user agent and they are
+//! kept for `login_history.retention_s`. Recording is best effort, a failure
+//! is logged and does not fail the login.
+
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{doc, DateTime},
+    options::{FindOptions, IndexOptions},
+    IndexModel,
+};
+use serde_json::json;
+use tracing::error;
+
+use crate::{
+    auth::ClientInfo,
+    enums::{AuditAction, LoginFailure, LoginMethod, TargetKind},
+    error::AppError,
+    model::{audit_event::AuditActor, login_attempt::LoginAttempt, user::User},
+    service::{
+        audit::{self, AuditRecord},
+        db::{get_database_service, DatabaseDocument},
+        environment::ENVIRONMENT,
+    },
+};
+
+/// Most attempts returned by the history of a user
+const HISTORY_LIMIT: i64 = 100;
+
+/// Record the successful login of the user, it is audited as well
+pub async fn record_success(user: &User, method: LoginMethod, client: &ClientInfo) {
+    let user_id = user.id.expect("User id must be not missing");
+    audit::record(
+        AuditRecord::new(AuditActor::user(user_id), AuditAction::LoginSucceeded)
+            .target(TargetKind::User, user_id)
+            .after(json!({ "method": method })),
+    )
+    .await;
+    record(LoginAttempt {
+        id: None,
+        user_id: user.id,
+        username: user.username.clone(),
+        method,
+        ip: client.ip.map(|ip| ip.to_string()),
+        user_agent: client.user_agent.clone(),
+        failure: None,
+        created_at: DateTime::now(),
+    })
+    .await
+}
+
+/// Record the failed login of the username, the user is missing when it is unknown
+///
+/// It is audited as well
+pub async fn record_failure(
+    user: Option<&User>,
+    username: &str,
+    method: LoginMethod,
+    failure: LoginFailure,
+    client: &ClientInfo,
+) {
+    let mut audit_record = AuditRecord::new(AuditActor::anonymous(), AuditAction::LoginFailed)
+        .after(json!({ "username": username, "method": method, "failure": failure }));
+    if let Some(user_id) = user.and_then(|user| user.id) {
+        audit_record = audit_record.target(TargetKind::User, user_id);
+    }
+    audit::record(audit_record).await;
+    record(LoginAttempt {
+        id: None,
+        user_id: user.and_then(|user| user.id),
+        username: username.to_string(),
+        method,
+        ip: client.ip.map(|ip| ip.to_string()),
+        user_agent: client.user_agent.clone(),
+        failure: Some(failure),
+        created_at: DateTime::now(),
+    })
+    .await
+}
+
+async fn record(attempt: LoginAttempt) {
+    if let Err(e) = attempt.dump(&get_database_service().await.db()).await {
+        error!("Cannot record login of {}: {e:?}", attempt.username);
+    }
+}
+
+/// Returns the recent logins of the user, the most recent first
+///
+/// Failed attempts with its username are included even when the user was not
+/// recognized
+pub async fn list(user: &User) -> Result<Vec<LoginAttempt>, AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<LoginAttempt>(LoginAttempt::collection_name());
+    let options = FindOptions::builder()
+        .sort(doc! { "created_at": -1 })
+        .limit(HISTORY_LIMIT)
+        .build();
+    let filter = doc! {
+        "$or": [
+            { "user_id": user.id },
+            { "user_id": null, "username": &user.username },
+        ]
+    };
+    Ok(collection
+        .find(filter, options)
+        .await?
+        .try_collect()
+        .await?)
+}
+
+/// Create the indexes of the login history
+///
+/// Attempts are deleted after the retention time
+pub async fn create_indexes() -> Result<(), AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<LoginAttempt>(LoginAttempt::collection_name());
+    let retention = IndexModel::builder()
+        .keys(doc! { "created_at": 1 })
+        .options(
+            IndexOptions::builder()
+                .expire_after(ENVIRONMENT.login_history.retention)
+                .build(),
+        )
+        .build();
+    let user = IndexModel::builder()
+        .keys(doc! { "user_id": 1, "created_at": -1 })
+        .build();
+    let username = IndexModel::builder()
+        .keys(doc! { "username": 1, "created_at": -1 })
+        .build();
+    collection
+        .create_indexes([retention, user, username], None)
+        .await?;
+    Ok(())
+}
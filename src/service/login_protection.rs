@@ -0,0 +1,203 @@
+//! Protection of the login from brute-force attacks.
+//!
+//! Failed logins are counted per username and per client address. Every failure
+//! delays the next attempt and, after too many failures, the username or the
+//! address is locked for a while. Usernames are tracked whether they exist or
+//! not, hence, responses do not reveal which usernames exist.
+
+use std::{
+    net::IpAddr,
+    time::{Duration, SystemTime},
+};
+
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{doc, DateTime},
+    options::{FindOneAndUpdateOptions, ReturnDocument},
+};
+use serde_json::json;
+use tracing::warn;
+
+use crate::{
+    enums::AuditAction,
+    error::{AppError, AuthError},
+    model::{audit_event::AuditActor, login_throttle::LoginThrottle},
+    service::{
+        audit::{self, AuditRecord},
+        db::{get_database_service, DatabaseDocument},
+        environment::ENVIRONMENT,
+        metrics,
+    },
+};
+
+/// Refuse the attempt if the username or the address is locked, otherwise wait
+/// the delay due to the previous failures
+pub async fn check(username: &str, ip: Option<IpAddr>) -> Result<(), AppError> {
+    let config = &ENVIRONMENT.login_protection;
+    let keys: Vec<String> = keys(username, ip).into_iter().map(|(key, _)| key).collect();
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<LoginThrottle>(LoginThrottle::collection_name());
+    let throttles: Vec<LoginThrottle> = collection
+        .find(doc! { "key": { "$in": keys } }, None)
+        .await?
+        .try_collect()
+        .await?;
+
+    let now = DateTime::now();
+    let window_start = DateTime::from_system_time(SystemTime::now() - config.failure_window);
+    let mut failures = 0;
+    for throttle in throttles {
+        if throttle.locked_until.is_some_and(|until| until > now) {
+            return Err(AuthError::TooManyAttempts)?;
+        }
+        if throttle.first_failure_at > window_start {
+            failures = failures.max(throttle.failures);
+        }
+    }
+    let delay = delay(failures, config.delay_base, config.max_delay);
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+    Ok(())
+}
+
+/// Count a failed attempt locking the username or the address when their
+/// failures reach the threshold
+pub async fn record_failure(username: &str, ip: Option<IpAddr>) -> Result<(), AppError> {
+    let config = &ENVIRONMENT.login_protection;
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<LoginThrottle>(LoginThrottle::collection_name());
+    let now = DateTime::now();
+    let window_start = DateTime::from_system_time(SystemTime::now() - config.failure_window);
+
+    for (key, max_failures) in keys(username, ip) {
+        // failures out of the window and expired lockouts start a new count
+        collection
+            .delete_many(
+                doc! {
+                    "key": &key,
+                    "$or": [
+                        { "first_failure_at": { "$lte": window_start }, "locked_until": null },
+                        { "locked_until": { "$lte": now } },
+                    ],
+                },
+                None,
+            )
+            .await?;
+        let options = FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .build();
+        let throttle = collection
+            .find_one_and_update(
+                doc! { "key": &key },
+                doc! {
+                    "$inc": { "failures": 1 },
+                    "$setOnInsert": { "first_failure_at": now, "locked_until": null },
+                },
+                options,
+            )
+            .await?;
+        let Some(throttle) = throttle else {
+            continue;
+        };
+        if throttle.failures < max_failures || throttle.locked_until.is_some() {
+            continue;
+        }
+        let locked_until = DateTime::from_system_time(SystemTime::now() + config.lockout);
+        // only the request setting the lockout records it
+        let result = collection
+            .update_one(
+                doc! { "key": &key, "locked_until": null },
+                doc! { "$set": { "locked_until": locked_until } },
+                None,
+            )
+            .await?;
+        if result.modified_count == 1 {
+            record_lockout(&key, throttle.failures, locked_until).await;
+        }
+    }
+    Ok(())
+}
+
+/// Forget the failures of the username after a successful login
+pub async fn record_success(username: &str) -> Result<(), AppError> {
+    unlock(username).await.map(|_| ())
+}
+
+/// Remove the lockout and the failures of the username, returns true if there were any
+pub async fn unlock(username: &str) -> Result<bool, AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<LoginThrottle>(LoginThrottle::collection_name());
+    let result = collection
+        .delete_many(doc! { "key": username_key(username) }, None)
+        .await?;
+    Ok(result.deleted_count > 0)
+}
+
+/// Record the lockout for the admins in the audit log and the metrics, the
+/// locked usernames and addresses are never sent to the webhooks
+async fn record_lockout(key: &str, failures: u32, locked_until: DateTime) {
+    warn!("Login of {key} locked until {locked_until} after {failures} failures");
+    let (scope, value) = key.split_once(':').unwrap_or((key, ""));
+    metrics::record_login_lockout(scope);
+    audit::record(
+        AuditRecord::new(AuditActor::anonymous(), AuditAction::LoginLockedOut).after(json!({
+            "scope": scope,
+            "value": value,
+            "failures": failures,
+            "lockedUntil": locked_until.timestamp_millis(),
+        })),
+    )
+    .await;
+}
+
+/// Throttle keys of the attempt with their failure threshold
+fn keys(username: &str, ip: Option<IpAddr>) -> Vec<(String, u32)> {
+    let config = &ENVIRONMENT.login_protection;
+    let mut keys = vec![(username_key(username), config.max_failures_per_username)];
+    if let Some(ip) = ip {
+        keys.push((format!("ip:{ip}"), config.max_failures_per_ip));
+    }
+    keys
+}
+
+/// Usernames differing only by case share the same count
+fn username_key(username: &str) -> String {
+    format!("username:{}", username.to_lowercase())
+}
+
+/// Delay before the next attempt, it doubles at every failure up to `max`
+fn delay(failures: u32, base: Duration, max: Duration) -> Duration {
+    if failures == 0 {
+        return Duration::ZERO;
+    }
+    base.saturating_mul(2u32.saturating_pow(failures - 1))
+        .min(max)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{delay, keys};
+
+    #[test]
+    fn progressive_delay_test() {
+        let base = Duration::from_millis(200);
+        let max = Duration::from_secs(5);
+        assert_eq!(delay(0, base, max), Duration::ZERO);
+        assert_eq!(delay(1, base, max), base);
+        assert_eq!(delay(3, base, max), Duration::from_millis(800));
+        assert_eq!(delay(6, base, max), max);
+        assert_eq!(delay(u32::MAX, base, max), max);
+    }
+
+    #[test]
+    fn throttle_keys_test() {
+        let keys = keys("John", "::1".parse().ok());
+        assert_eq!(keys[0].0, "username:john");
+        assert_eq!(keys[1].0, "ip:::1");
+        assert_eq!(keys.len(), 2);
+    }
+}
@@ -0,0 +1,228 @@
+//! Mailer service sending emails to users through an outbox.
+//!
+//! Emails are first stored in the database and then delivered in background by
+//! the configured transport, so that a slow or unavailable mail server does not
+//! fail the request that produced the email. Failed deliveries are retried with
+//! exponential backoff up to `mail.max_attempts` times.
+//!
+//! Transports implement the `Mailer` trait: SMTP for real deliveries, a directory
+//! of files for local development and memory for tests.
+
+mod smtp;
+
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use axum::async_trait;
+use mongodb::{
+    bson::{doc, DateTime},
+    options::{FindOneAndUpdateOptions, ReturnDocument},
+};
+use once_cell::sync::Lazy;
+use tokio::sync::Notify;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::{
+    error::AppError,
+    model::outbox_email::OutboxEmail,
+    service::{
+        db::{get_database_service, DatabaseDocument},
+        environment::{MailTransport, ENVIRONMENT},
+    },
+};
+
+pub use smtp::SmtpMailer;
+
+/// Time an instance has to deliver an email before another one can retry it
+const DELIVERY_LEASE: Duration = Duration::from_secs(300);
+/// Waiting time before the first retry, it doubles at every attempt
+const RETRY_BACKOFF_BASE: Duration = Duration::from_secs(30);
+
+static MAILER: Lazy<Arc<dyn Mailer>> = Lazy::new(|| match &ENVIRONMENT.mail.transport {
+    MailTransport::Smtp(config) => Arc::new(SmtpMailer::new(config, &ENVIRONMENT.mail.from)),
+    MailTransport::File(directory) => Arc::new(FileMailer::new(directory.clone())),
+    MailTransport::Memory => Arc::new(InMemoryMailer::default()),
+});
+
+/// Wakes up the delivery task when a new email is in the outbox
+static OUTBOX_NOTIFY: Lazy<Notify> = Lazy::new(Notify::new);
+
+/// Returns the transport configured for the environment
+pub fn get_mailer() -> Arc<dyn Mailer> {
+    MAILER.clone()
+}
+
+/// Email with a plain text body
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmailMessage {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Trait implemented by every transport delivering emails
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, message: &EmailMessage) -> Result<(), anyhow::Error>;
+}
+
+/// Transport writing every email in a file of the directory
+pub struct FileMailer {
+    directory: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(directory: PathBuf) -> Self {
+        FileMailer { directory }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, message: &EmailMessage) -> Result<(), anyhow::Error> {
+        tokio::fs::create_dir_all(&self.directory)
+            .await
+            .with_context(|| format!("cannot create {}", self.directory.display()))?;
+        let path = self.directory.join(format!("{}.eml", Uuid::new_v4()));
+        let content = format!(
+            "From: {}\nTo: {}\nSubject: {}\n\n{}",
+            ENVIRONMENT.mail.from, message.to, message.subject, message.body
+        );
+        tokio::fs::write(&path, content)
+            .await
+            .with_context(|| format!("cannot write {}", path.display()))?;
+        info!("Email to {} written in {}", message.to, path.display());
+        Ok(())
+    }
+}
+
+/// Transport keeping every email in memory
+#[derive(Default)]
+pub struct InMemoryMailer {
+    messages: Mutex<Vec<EmailMessage>>,
+}
+
+impl InMemoryMailer {
+    /// Returns the emails sent so far
+    pub fn messages(&self) -> Vec<EmailMessage> {
+        self.messages
+            .lock()
+            .expect("In memory mailer lock is poisoned")
+            .clone()
+    }
+}
+
+#[async_trait]
+impl Mailer for InMemoryMailer {
+    async fn send(&self, message: &EmailMessage) -> Result<(), anyhow::Error> {
+        self.messages
+            .lock()
+            .expect("In memory mailer lock is poisoned")
+            .push(message.clone());
+        Ok(())
+    }
+}
+
+/// Store the email in the outbox, it is delivered in background
+pub async fn enqueue(message: EmailMessage) -> Result<(), AppError> {
+    let now = DateTime::now();
+    let email = OutboxEmail {
+        id: None,
+        to: message.to,
+        subject: message.subject,
+        body: message.body,
+        attempts: 0,
+        sent: false,
+        next_attempt_at: now,
+        last_error: None,
+        created_at: now,
+    };
+    email.dump(&get_database_service().await.db()).await?;
+    OUTBOX_NOTIFY.notify_one();
+    Ok(())
+}
+
+/// Spawn a background task delivering the emails of the outbox
+///
+/// It runs every `mail.outbox_poll_interval_s` and whenever an email is enqueued
+pub fn spawn_outbox_delivery() {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(ENVIRONMENT.mail.outbox_poll_interval);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = OUTBOX_NOTIFY.notified() => {}
+            }
+            if let Err(e) = deliver_pending().await {
+                error!("Cannot deliver outbox emails: {e:?}");
+            }
+        }
+    });
+}
+
+/// Deliver every email whose attempt is due
+///
+/// An email is claimed by moving its next attempt forward, therefore, several
+/// instances can share the outbox without sending the same email twice
+async fn deliver_pending() -> Result<(), AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<OutboxEmail>(OutboxEmail::collection_name());
+    let mailer = get_mailer();
+    let max_attempts = ENVIRONMENT.mail.max_attempts;
+    loop {
+        let lease = DateTime::from_system_time(SystemTime::now() + DELIVERY_LEASE);
+        let options = FindOneAndUpdateOptions::builder()
+            .return_document(ReturnDocument::After)
+            .build();
+        let claimed = collection
+            .find_one_and_update(
+                doc! {
+                    "sent": false,
+                    "attempts": { "$lt": max_attempts },
+                    "next_attempt_at": { "$lte": DateTime::now() },
+                },
+                doc! {
+                    "$set": { "next_attempt_at": lease },
+                    "$inc": { "attempts": 1 },
+                },
+                options,
+            )
+            .await?;
+        let Some(email) = claimed else {
+            return Ok(());
+        };
+        let id = email.id.expect("Outbox email id must be not missing");
+        let message = EmailMessage {
+            to: email.to,
+            subject: email.subject,
+            body: email.body,
+        };
+        let update = match mailer.send(&message).await {
+            Ok(()) => doc! { "$set": { "sent": true, "last_error": null } },
+            Err(e) => {
+                if email.attempts >= max_attempts {
+                    error!(
+                        "Email {id} to {} not delivered, giving up: {e:#}",
+                        message.to
+                    );
+                } else {
+                    warn!("Email {id} to {} not delivered: {e:#}", message.to);
+                }
+                let retry_at = DateTime::from_system_time(
+                    SystemTime::now()
+                        + RETRY_BACKOFF_BASE
+                            .saturating_mul(2u32.saturating_pow(email.attempts - 1)),
+                );
+                doc! { "$set": { "next_attempt_at": retry_at, "last_error": format!("{e:#}") } }
+            }
+        };
+        collection
+            .update_one(doc! { "_id": id }, update, None)
+            .await?;
+    }
+}
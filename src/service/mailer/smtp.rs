@@ -0,0 +1,271 @@
+//! Minimal SMTP client delivering plain text emails.
+//!
+//! It supports implicit TLS, STARTTLS and plain connections, and the `PLAIN`
+//! authentication mechanism, which is what mail relays and providers expect.
+
+use anyhow::{anyhow, Context};
+use axum::async_trait;
+use base64ct::{Base64, Encoding};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+use tokio_native_tls::{native_tls, TlsConnector};
+use uuid::Uuid;
+
+use crate::service::environment::{SmtpSecurity, SmtpVariables};
+
+use super::{EmailMessage, Mailer};
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// Transport delivering emails to an SMTP server
+pub struct SmtpMailer {
+    host: String,
+    port: u16,
+    security: SmtpSecurity,
+    timeout: std::time::Duration,
+    credentials: Option<(String, String)>,
+    from: String,
+}
+
+impl SmtpMailer {
+    pub fn new(config: &SmtpVariables, from: &str) -> Self {
+        SmtpMailer {
+            host: config.host.clone(),
+            port: config.port,
+            security: config.security,
+            timeout: config.timeout,
+            credentials: config.credentials.clone(),
+            from: from.to_string(),
+        }
+    }
+
+    async fn deliver(&self, message: &EmailMessage) -> Result<(), anyhow::Error> {
+        let tcp = TcpStream::connect((self.host.as_str(), self.port))
+            .await
+            .with_context(|| format!("cannot connect to {}:{}", self.host, self.port))?;
+        let mut connection = match self.security {
+            SmtpSecurity::Tls => Connection::new(Box::new(self.tls(tcp).await?)),
+            _ => Connection::new(Box::new(tcp)),
+        };
+        connection.expect(220).await?;
+        connection.command("EHLO localhost", 250).await?;
+        if self.security == SmtpSecurity::StartTls {
+            connection.command("STARTTLS", 220).await?;
+            // the server does not send anything else before the handshake
+            let stream = connection.stream.into_inner();
+            connection = Connection::new(Box::new(self.tls(stream).await?));
+            connection.command("EHLO localhost", 250).await?;
+        }
+        if let Some((username, password)) = &self.credentials {
+            let token = Base64::encode_string(format!("\0{username}\0{password}").as_bytes());
+            connection
+                .command(&format!("AUTH PLAIN {token}"), 235)
+                .await
+                .context("authentication failed")?;
+        }
+        connection
+            .command(&format!("MAIL FROM:<{}>", address(&self.from)), 250)
+            .await?;
+        connection
+            .command(&format!("RCPT TO:<{}>", address(&message.to)), 250)
+            .await?;
+        connection.command("DATA", 354).await?;
+        connection.command(&self.data(message), 250).await?;
+        // the email is accepted, an error closing the session does not matter
+        let _ = connection.command("QUIT", 221).await;
+        Ok(())
+    }
+
+    async fn tls<S: Stream + 'static>(&self, stream: S) -> Result<impl Stream, anyhow::Error> {
+        let connector = TlsConnector::from(native_tls::TlsConnector::new()?);
+        connector
+            .connect(&self.host, stream)
+            .await
+            .with_context(|| format!("TLS handshake with {} failed", self.host))
+    }
+
+    /// Content of the DATA command terminated by a line with a single dot
+    fn data(&self, message: &EmailMessage) -> String {
+        let domain = address(&self.from)
+            .split_once('@')
+            .map(|(_, domain)| domain.to_string())
+            .unwrap_or_else(|| "localhost".into());
+        let headers = [
+            format!("From: {}", self.from),
+            format!("To: {}", message.to),
+            format!("Subject: {}", encode_header(&message.subject)),
+            format!("Message-ID: <{}@{domain}>", Uuid::new_v4()),
+            "MIME-Version: 1.0".into(),
+            "Content-Type: text/plain; charset=utf-8".into(),
+            "Content-Transfer-Encoding: 8bit".into(),
+        ];
+        let body = message
+            .body
+            .lines()
+            // lines starting with a dot are escaped doubling it
+            .map(|line| match line.starts_with('.') {
+                true => format!(".{line}"),
+                false => line.to_string(),
+            })
+            .collect::<Vec<_>>();
+        format!("{}\r\n\r\n{}\r\n.", headers.join("\r\n"), body.join("\r\n"))
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, message: &EmailMessage) -> Result<(), anyhow::Error> {
+        tokio::time::timeout(self.timeout, self.deliver(message))
+            .await
+            .map_err(|_| anyhow!("SMTP delivery timed out"))?
+    }
+}
+
+struct Connection {
+    stream: BufReader<Box<dyn Stream>>,
+}
+
+impl Connection {
+    fn new(stream: Box<dyn Stream>) -> Self {
+        Connection {
+            stream: BufReader::new(stream),
+        }
+    }
+
+    async fn command(&mut self, command: &str, code: u16) -> Result<(), anyhow::Error> {
+        self.stream
+            .get_mut()
+            .write_all(format!("{command}\r\n").as_bytes())
+            .await?;
+        self.stream.get_mut().flush().await?;
+        self.expect(code).await
+    }
+
+    /// Read a reply, made of several lines when the code is followed by a dash
+    async fn expect(&mut self, code: u16) -> Result<(), anyhow::Error> {
+        let mut reply = String::new();
+        loop {
+            let mut line = String::new();
+            if self.stream.read_line(&mut line).await? == 0 {
+                return Err(anyhow!("connection closed by the server"));
+            }
+            reply.push_str(&line);
+            if line.as_bytes().get(3) != Some(&b'-') {
+                break;
+            }
+        }
+        match reply.get(..3).and_then(|value| value.parse::<u16>().ok()) {
+            Some(value) if value == code => Ok(()),
+            _ => Err(anyhow!("unexpected reply {}", reply.trim_end())),
+        }
+    }
+}
+
+/// Address of a mailbox like `Name <user@example.com>`
+fn address(mailbox: &str) -> &str {
+    match (mailbox.rfind('<'), mailbox.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &mailbox[start + 1..end],
+        _ => mailbox.trim(),
+    }
+}
+
+/// Encode non ascii header values as described by RFC 2047
+fn encode_header(value: &str) -> String {
+    if value.is_ascii() {
+        value.to_string()
+    } else {
+        format!("=?utf-8?B?{}?=", Base64::encode_string(value.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    use crate::service::{
+        environment::{SmtpSecurity, SmtpVariables},
+        mailer::{EmailMessage, Mailer},
+    };
+
+    use super::SmtpMailer;
+
+    /// Accept one session replying like a server and returning the received lines
+    async fn fake_server(listener: TcpListener) -> Vec<String> {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        let mut received = Vec::new();
+        let mut in_data = false;
+        writer.write_all(b"220 fake ready\r\n").await.unwrap();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).await.unwrap() == 0 {
+                break;
+            }
+            let line = line.trim_end_matches("\r\n").to_string();
+            received.push(line.clone());
+            let reply: &[u8] = if in_data {
+                if line != "." {
+                    continue;
+                }
+                in_data = false;
+                b"250 queued\r\n"
+            } else if line.starts_with("EHLO") {
+                b"250-fake\r\n250 AUTH PLAIN\r\n"
+            } else if line.starts_with("AUTH") {
+                b"235 ok\r\n"
+            } else if line == "DATA" {
+                in_data = true;
+                b"354 go ahead\r\n"
+            } else if line == "QUIT" {
+                writer.write_all(b"221 bye\r\n").await.unwrap();
+                break;
+            } else {
+                b"250 ok\r\n"
+            };
+            writer.write_all(reply).await.unwrap();
+        }
+        received
+    }
+
+    #[tokio::test]
+    async fn smtp_delivery_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(fake_server(listener));
+        let mailer = SmtpMailer::new(
+            &SmtpVariables {
+                host: "127.0.0.1".into(),
+                port,
+                security: SmtpSecurity::None,
+                timeout: Duration::from_secs(5),
+                credentials: Some(("user".into(), "password".into())),
+            },
+            "Sandbox <no-reply@example.com>",
+        );
+        let message = EmailMessage {
+            to: "john@example.com".into(),
+            subject: "Réinitialisation".into(),
+            body: "first line\n.second line".into(),
+        };
+        mailer.send(&message).await.unwrap();
+
+        let received = server.await.unwrap();
+        assert!(received.contains(&"AUTH PLAIN AHVzZXIAcGFzc3dvcmQ=".to_string()));
+        assert!(received.contains(&"MAIL FROM:<no-reply@example.com>".to_string()));
+        assert!(received.contains(&"RCPT TO:<john@example.com>".to_string()));
+        assert!(received.contains(&"Subject: =?utf-8?B?UsOpaW5pdGlhbGlzYXRpb24=?=".to_string()));
+        // lines starting with a dot are escaped
+        assert!(received.contains(&"..second line".to_string()));
+        assert_eq!(received.last().unwrap(), "QUIT");
+    }
+}
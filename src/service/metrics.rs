@@ -0,0 +1,212 @@
+//! Prometheus metrics of the application.
+//!
+//! Metrics are registered on first use and exposed in the text format by
+//! `render`. They count the HTTP requests by matched route, the authentication
+//! failures, the login lockouts, the MongoDB commands and the published queue
+//! messages besides the metrics of the process.
+
+use std::time::Duration;
+
+use anyhow::anyhow;
+use axum::http::StatusCode;
+use mongodb::event::command::{CommandEventHandler, CommandFailedEvent, CommandSucceededEvent};
+use once_cell::sync::Lazy;
+use prometheus::{
+    core::Collector, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder,
+};
+
+use crate::{
+    error::{AppError, AuthError},
+    service::environment::ENVIRONMENT,
+};
+
+static REGISTRY: Lazy<Registry> = Lazy::new(|| {
+    let registry = Registry::new();
+    #[cfg(target_os = "linux")]
+    registry
+        .register(Box::new(
+            prometheus::process_collector::ProcessCollector::for_self(),
+        ))
+        .expect("Process metrics are registered once");
+    registry
+});
+
+static HTTP_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "http_requests_total",
+                "HTTP requests by method, route and status",
+            ),
+            &["method", "route", "status"],
+        )
+        .expect("Metric options are valid"),
+    )
+});
+
+static HTTP_REQUEST_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register(
+        HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Latency of the HTTP requests by method, route and status",
+            ),
+            &["method", "route", "status"],
+        )
+        .expect("Metric options are valid"),
+    )
+});
+
+static AUTH_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new("auth_failures_total", "Refused authentications by reason"),
+            &["reason"],
+        )
+        .expect("Metric options are valid"),
+    )
+});
+
+static LOGIN_LOCKOUTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "login_lockouts_total",
+                "Logins locked after too many failures by username or address",
+            ),
+            &["scope"],
+        )
+        .expect("Metric options are valid"),
+    )
+});
+
+static MONGODB_COMMAND_DURATION: Lazy<HistogramVec> = Lazy::new(|| {
+    register(
+        HistogramVec::new(
+            HistogramOpts::new(
+                "mongodb_command_duration_seconds",
+                "Latency of the MongoDB commands by name and outcome",
+            )
+            .buckets(vec![
+                0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+            ]),
+            &["command", "outcome"],
+        )
+        .expect("Metric options are valid"),
+    )
+});
+
+static QUEUE_MESSAGES: Lazy<IntCounterVec> = Lazy::new(|| {
+    register(
+        IntCounterVec::new(
+            Opts::new(
+                "queue_messages_published_total",
+                "Messages published on the queues by outcome",
+            ),
+            &["queue", "outcome"],
+        )
+        .expect("Metric options are valid"),
+    )
+});
+
+/// Count a request answered by the route, e.g. `/user/:id`
+pub fn record_http_request(method: &str, route: &str, status: StatusCode, latency: Duration) {
+    let status = status.as_str();
+    HTTP_REQUESTS
+        .with_label_values(&[method, route, status])
+        .inc();
+    HTTP_REQUEST_DURATION
+        .with_label_values(&[method, route, status])
+        .observe(latency.as_secs_f64());
+}
+
+pub fn record_auth_failure(error: &AuthError) {
+    AUTH_FAILURES.with_label_values(&[error.reason()]).inc();
+}
+
+/// Count a lockout of a `username` or of an `ip`
+pub fn record_login_lockout(scope: &str) {
+    LOGIN_LOCKOUTS.with_label_values(&[scope]).inc();
+}
+
+pub fn record_queue_message(queue: &str, published: bool) {
+    let outcome = if published { "published" } else { "failed" };
+    QUEUE_MESSAGES.with_label_values(&[queue, outcome]).inc();
+}
+
+/// Observer of the commands sent by the MongoDB client
+pub struct MongoCommandMetrics;
+
+impl CommandEventHandler for MongoCommandMetrics {
+    fn handle_command_succeeded_event(&self, event: CommandSucceededEvent) {
+        MONGODB_COMMAND_DURATION
+            .with_label_values(&[&event.command_name, "succeeded"])
+            .observe(event.duration.as_secs_f64());
+    }
+
+    fn handle_command_failed_event(&self, event: CommandFailedEvent) {
+        MONGODB_COMMAND_DURATION
+            .with_label_values(&[&event.command_name, "failed"])
+            .observe(event.duration.as_secs_f64());
+    }
+}
+
+/// Check the bearer token of the scraper when `METRICS_TOKEN` is set
+pub fn authorize(token: Option<&str>) -> Result<(), AppError> {
+    let Some(expected) = &ENVIRONMENT.metrics.token else {
+        return Ok(());
+    };
+    let token = token.ok_or(AuthError::MissingCredentials)?;
+    ring::constant_time::verify_slices_are_equal(token.as_bytes(), expected.as_bytes())
+        .map_err(|_| AuthError::WrongCredentials)?;
+    Ok(())
+}
+
+/// Returns every metric in the prometheus text format
+pub fn render() -> Result<String, AppError> {
+    TextEncoder::new()
+        .encode_to_string(&REGISTRY.gather())
+        .map_err(|e| anyhow!("Cannot encode the metrics: {e}").into())
+}
+
+fn register<C: Collector + Clone + 'static>(collector: C) -> C {
+    REGISTRY
+        .register(Box::new(collector.clone()))
+        .expect("Metric is registered once");
+    collector
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use axum::http::StatusCode;
+
+    use crate::error::AuthError;
+
+    use super::{authorize, record_auth_failure, record_http_request, render};
+
+    #[test]
+    fn render_test() {
+        record_http_request(
+            "GET",
+            "/user/:id",
+            StatusCode::NOT_FOUND,
+            Duration::from_millis(3),
+        );
+        record_auth_failure(&AuthError::InvalidApiKey);
+
+        let metrics = render().unwrap();
+        assert!(metrics
+            .contains(r#"http_requests_total{method="GET",route="/user/:id",status="404"} 1"#));
+        assert!(metrics.contains(r#"auth_failures_total{reason="invalid_api_key"} 1"#));
+        assert!(metrics.contains("http_request_duration_seconds_bucket"));
+    }
+
+    #[test]
+    fn authorize_test() {
+        assert!(authorize(Some("testing_metrics_token")).is_ok());
+        assert!(authorize(Some("wrong")).is_err());
+        assert!(authorize(None).is_err());
+    }
+}
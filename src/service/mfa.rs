@@ -0,0 +1,285 @@
+//! Multi-factor authentication with time-based one-time passwords (RFC 6238).
+//!
+//! A user enrolls by scanning the otpauth uri with an authenticator app and
+//! confirming the first code. The confirmation returns recovery codes that can
+//! replace a code once each when the authenticator is lost.
+//!
+//! Codes are accepted with one step of tolerance for clock skew and each time
+//! step can be used only once.
+
+use anyhow::anyhow;
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use jsonwebtoken::get_current_timestamp;
+use mongodb::bson::{doc, Bson};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::{AppError, AuthError},
+    model::user::User,
+    service::{
+        db::{get_database_service, DatabaseDocument},
+        environment::ENVIRONMENT,
+        user::get_user,
+    },
+    UserId,
+};
+
+/// Duration in seconds of a time step
+const TOTP_PERIOD: u64 = 30;
+/// Number of digits of a code
+const TOTP_DIGITS: u32 = 6;
+/// Steps accepted before and after the current one
+const TOTP_SKEW: i64 = 1;
+
+/// Returns true if the user must complete the login with a second factor
+pub fn is_required(user: &User) -> bool {
+    user.mfa.enabled || ENVIRONMENT.mfa.required_roles.contains(&user.role)
+}
+
+/// Generate a new totp secret for the user, it is used only after its confirmation
+///
+/// Returns the base32 secret and the otpauth uri to show as qr code
+pub async fn begin_enrollment(user_id: &UserId) -> Result<(String, String), AppError> {
+    let user_model = get_user(user_id).await?;
+    if user_model.mfa.enabled {
+        return Err(AppError::InvalidRequest(anyhow!(
+            "Multi-factor authentication is already enabled"
+        )));
+    }
+    let secret = BASE32_NOPAD.encode(&rand::random::<[u8; 20]>());
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<User>(User::collection_name());
+    collection
+        .update_one(
+            doc! { "_id": user_id },
+            doc! { "$set": { "mfa.pending_totp_secret": &secret } },
+            None,
+        )
+        .await?;
+    let uri = otpauth_uri(&ENVIRONMENT.mfa.issuer, &user_model.username, &secret);
+    Ok((secret, uri))
+}
+
+/// Enable multi-factor authentication if the code is valid for the pending secret
+///
+/// Returns the recovery codes, they are stored hashed and cannot be shown again
+pub async fn confirm_enrollment(user_id: &UserId, code: &str) -> Result<Vec<String>, AppError> {
+    let user_model = get_user(user_id).await?;
+    let secret = user_model.mfa.pending_totp_secret.ok_or_else(|| {
+        AppError::InvalidRequest(anyhow!(
+            "Multi-factor authentication enrollment not started"
+        ))
+    })?;
+    let step = verify_totp(&secret, code, get_current_timestamp(), None)
+        .ok_or(AuthError::InvalidMfaCode)?;
+    let (codes, hashes) = generate_recovery_codes(ENVIRONMENT.mfa.recovery_codes);
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<User>(User::collection_name());
+    collection
+        .update_one(
+            doc! { "_id": user_id },
+            doc! {
+                "$set": {
+                    "mfa.enabled": true,
+                    "mfa.totp_secret": &secret,
+                    "mfa.recovery_codes": hashes,
+                    "mfa.last_totp_step": step,
+                },
+                "$unset": { "mfa.pending_totp_secret": "" },
+            },
+            None,
+        )
+        .await?;
+    Ok(codes)
+}
+
+/// Replace the recovery codes of the user after checking a totp code
+pub async fn regenerate_recovery_codes(
+    user_id: &UserId,
+    code: &str,
+) -> Result<Vec<String>, AppError> {
+    verify_second_factor(user_id, Some(code), None).await?;
+    let (codes, hashes) = generate_recovery_codes(ENVIRONMENT.mfa.recovery_codes);
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<User>(User::collection_name());
+    collection
+        .update_one(
+            doc! { "_id": user_id },
+            doc! { "$set": { "mfa.recovery_codes": hashes } },
+            None,
+        )
+        .await?;
+    Ok(codes)
+}
+
+/// Verify the totp code or, if it is missing, the recovery code of the user
+///
+/// The accepted step and the recovery code are consumed atomically so that
+/// concurrent requests cannot use them twice
+pub async fn verify_second_factor(
+    user_id: &UserId,
+    code: Option<&str>,
+    recovery_code: Option<&str>,
+) -> Result<(), AppError> {
+    let user_model = get_user(user_id).await?;
+    let secret = match (user_model.mfa.enabled, user_model.mfa.totp_secret) {
+        (true, Some(secret)) => secret,
+        _ => {
+            return Err(AppError::InvalidRequest(anyhow!(
+                "Multi-factor authentication is not enabled"
+            )))
+        }
+    };
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<User>(User::collection_name());
+    let result = match (code, recovery_code) {
+        (Some(code), _) => {
+            let step = verify_totp(
+                &secret,
+                code,
+                get_current_timestamp(),
+                user_model.mfa.last_totp_step,
+            )
+            .ok_or(AuthError::InvalidMfaCode)?;
+            collection
+                .update_one(
+                    doc! {
+                        "_id": user_id,
+                        "mfa.last_totp_step": { "$not": { "$gte": step } },
+                    },
+                    doc! { "$set": { "mfa.last_totp_step": step } },
+                    None,
+                )
+                .await?
+        }
+        (None, Some(recovery_code)) => {
+            let hash = hash_recovery_code(recovery_code);
+            collection
+                .update_one(
+                    doc! { "_id": user_id, "mfa.recovery_codes": &hash },
+                    doc! { "$pull": { "mfa.recovery_codes": Bson::String(hash) } },
+                    None,
+                )
+                .await?
+        }
+        (None, None) => {
+            return Err(AppError::InvalidRequest(anyhow!(
+                "Either code or recovery code is required"
+            )))
+        }
+    };
+    if result.modified_count == 0 {
+        return Err(AuthError::InvalidMfaCode)?;
+    }
+    Ok(())
+}
+
+/// Uri read by authenticator apps
+fn otpauth_uri(issuer: &str, username: &str, secret: &str) -> String {
+    let mut url = reqwest::Url::parse("otpauth://totp/").expect("otpauth base uri is valid");
+    url.set_path(&format!("{issuer}:{username}"));
+    url.query_pairs_mut()
+        .append_pair("secret", secret)
+        .append_pair("issuer", issuer)
+        .append_pair("algorithm", "SHA1")
+        .append_pair("digits", &TOTP_DIGITS.to_string())
+        .append_pair("period", &TOTP_PERIOD.to_string());
+    url.into()
+}
+
+/// Code of the time step computed with HMAC-SHA1 as described by RFC 4226
+fn totp(secret: &[u8], step: u64) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(&step.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    binary % 10u32.pow(TOTP_DIGITS)
+}
+
+/// Returns the step matching the code if it is newer than `last_step`
+fn verify_totp(secret: &str, code: &str, now: u64, last_step: Option<i64>) -> Option<i64> {
+    let secret = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let code = code.trim();
+    if code.len() != TOTP_DIGITS as usize {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let current = (now / TOTP_PERIOD) as i64;
+    (current - TOTP_SKEW..=current + TOTP_SKEW)
+        .filter(|step| *step >= 0 && Some(*step) > last_step)
+        .find(|step| totp(&secret, *step as u64) == code)
+}
+
+/// Returns the recovery codes and their hashes
+fn generate_recovery_codes(count: usize) -> (Vec<String>, Vec<String>) {
+    (0..count)
+        .map(|_| {
+            let code = hex::encode(rand::random::<[u8; 5]>());
+            let code = format!("{}-{}", &code[..5], &code[5..]);
+            let hash = hash_recovery_code(&code);
+            (code, hash)
+        })
+        .unzip()
+}
+
+/// Hash of the recovery code ignoring case and dashes
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect();
+    hex::encode(Sha256::digest(normalized.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use data_encoding::BASE32_NOPAD;
+
+    use super::{generate_recovery_codes, hash_recovery_code, otpauth_uri, totp, verify_totp};
+
+    #[test]
+    fn totp_test() {
+        // test vectors of RFC 6238 truncated to six digits
+        let secret = b"12345678901234567890";
+        assert_eq!(totp(secret, 59 / 30), 287082);
+        assert_eq!(totp(secret, 1111111109 / 30), 81804);
+        assert_eq!(totp(secret, 2000000000 / 30), 279037);
+
+        let encoded = BASE32_NOPAD.encode(secret);
+        let now = 1111111109;
+        assert_eq!(
+            verify_totp(&encoded, "081804", now, None),
+            Some(now as i64 / 30)
+        );
+        // previous step is accepted for clock skew, older ones are not
+        assert!(verify_totp(&encoded, "081804", now + 30, None).is_some());
+        assert!(verify_totp(&encoded, "081804", now + 60, None).is_none());
+        // a step cannot be used twice
+        assert!(verify_totp(&encoded, "081804", now, Some(now as i64 / 30)).is_none());
+        assert!(verify_totp(&encoded, "81804", now, None).is_none());
+
+        let uri = otpauth_uri("sandbox", "John Smith", &encoded);
+        assert!(uri.starts_with("otpauth://totp/sandbox:John%20Smith?secret="));
+    }
+
+    #[test]
+    fn recovery_codes_test() {
+        let (codes, hashes) = generate_recovery_codes(10);
+        assert_eq!(codes.len(), 10);
+        assert_eq!(hash_recovery_code(&codes[0]), hashes[0]);
+        // codes typed without dash or in upper case are the same
+        assert_eq!(
+            hash_recovery_code(&codes[0].replace('-', "").to_uppercase()),
+            hashes[0]
+        );
+    }
+}
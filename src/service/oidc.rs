@@ -0,0 +1,634 @@
+//! Login with an external OpenID Connect provider.
+//!
+//! The web app redirects the user to the provider with the authorization code
+//! flow protected by PKCE, `state` and `nonce`. When the provider redirects the
+//! user back, the code is exchanged for an id token verified with the keys
+//! published by the provider.
+//!
+//! The identity is linked to the user with the same verified email and, when
+//! provisioning is enabled, a new user is created at the first login.
+
+use std::time::{Duration, SystemTime};
+
+use anyhow::{anyhow, Context};
+use base64ct::{Base64UrlUnpadded, Encoding};
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use mongodb::bson::{doc, DateTime};
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use crate::{
+    error::{AppError, AuthError},
+    model::{
+        oidc_login_state::OidcLoginState,
+        user::{OidcIdentity, User},
+    },
+    service::{
+        db::{get_database_service, DatabaseDocument},
+        environment::{OidcVariables, ENVIRONMENT},
+        request_id, user,
+    },
+};
+
+static OIDC_CLIENT: Lazy<Option<OidcClient>> = Lazy::new(|| {
+    ENVIRONMENT
+        .oidc
+        .clone()
+        .map(|config| OidcClient::new(config, ENVIRONMENT.authentication.leeway))
+});
+
+/// Returns the client of the configured provider or an error if the login is disabled
+pub fn get_oidc_client() -> Result<&'static OidcClient, AppError> {
+    OIDC_CLIENT
+        .as_ref()
+        .ok_or_else(|| AppError::DoesNotExist(anyhow!("OpenID Connect login is not enabled")))
+}
+
+/// Endpoints of the provider read from its discovery document
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProviderMetadata {
+    pub issuer: String,
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub jwks_uri: String,
+}
+
+/// Claims of the id token used to identify the user
+#[derive(Debug, Deserialize)]
+pub struct IdTokenClaims {
+    pub sub: String,
+    pub nonce: Option<String>,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub email_verified: bool,
+    pub preferred_username: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    id_token: Option<String>,
+}
+
+/// Client of the OpenID Connect provider
+///
+/// Discovery document and keys are cached, keys are read again when a token
+/// is signed by an unknown key because the provider rotated them.
+pub struct OidcClient {
+    config: OidcVariables,
+    leeway: Duration,
+    http: reqwest::Client,
+    metadata: RwLock<Option<ProviderMetadata>>,
+    jwks: RwLock<Option<JwkSet>>,
+}
+
+impl OidcClient {
+    pub fn new(config: OidcVariables, leeway: Duration) -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(config.request_timeout)
+            .build()
+            .expect("OpenID Connect http client must be valid");
+        OidcClient {
+            config,
+            leeway,
+            http,
+            metadata: RwLock::new(None),
+            jwks: RwLock::new(None),
+        }
+    }
+
+    pub fn config(&self) -> &OidcVariables {
+        &self.config
+    }
+
+    /// Returns the provider metadata, the discovery document is requested only once
+    pub async fn metadata(&self) -> Result<ProviderMetadata, AppError> {
+        if let Some(metadata) = self.metadata.read().await.as_ref() {
+            return Ok(metadata.clone());
+        }
+        let url = format!(
+            "{}/.well-known/openid-configuration",
+            self.config.issuer_url.trim_end_matches('/')
+        );
+        let metadata: ProviderMetadata = request_id::propagate(self.http.get(&url))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .with_context(|| format!("Cannot read discovery document {url}"))?
+            .json()
+            .await
+            .context("Discovery document is not valid")?;
+        // the issuer of the document must be the configured one, otherwise tokens
+        // of another issuer would be accepted
+        if metadata.issuer != self.config.issuer_url {
+            return Err(AppError::InternalServerError(anyhow!(
+                "Discovery document issuer {} is not {}",
+                metadata.issuer,
+                self.config.issuer_url
+            )));
+        }
+        *self.metadata.write().await = Some(metadata.clone());
+        Ok(metadata)
+    }
+
+    /// Url of the provider where the user is redirected to log in
+    pub async fn authorization_url(
+        &self,
+        state: &str,
+        nonce: &str,
+        code_challenge: &str,
+    ) -> Result<String, AppError> {
+        let metadata = self.metadata().await?;
+        let mut url = reqwest::Url::parse(&metadata.authorization_endpoint)
+            .context("Authorization endpoint is not a valid url")?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.config.client_id)
+            .append_pair("redirect_uri", &self.config.redirect_uri)
+            .append_pair("scope", &self.config.scopes.join(" "))
+            .append_pair("state", state)
+            .append_pair("nonce", nonce)
+            .append_pair("code_challenge", code_challenge)
+            .append_pair("code_challenge_method", "S256");
+        Ok(url.into())
+    }
+
+    /// Exchange the authorization code for the id token
+    pub async fn exchange_code(&self, code: &str, code_verifier: &str) -> Result<String, AppError> {
+        let metadata = self.metadata().await?;
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &self.config.redirect_uri),
+            ("client_id", &self.config.client_id),
+            ("code_verifier", code_verifier),
+        ];
+        if let Some(client_secret) = &self.config.client_secret {
+            form.push(("client_secret", client_secret));
+        }
+        let response = request_id::propagate(self.http.post(&metadata.token_endpoint))
+            .form(&form)
+            .send()
+            .await
+            .context("Cannot reach token endpoint")?;
+        if !response.status().is_success() {
+            // wrong, expired or already used codes are rejected by the provider
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            warn!("Token endpoint rejected the authorization code with {status}: {body}");
+            return Err(AuthError::ExternalLoginFailed)?;
+        }
+        let token_response: TokenResponse = response
+            .json()
+            .await
+            .context("Token endpoint response is not valid")?;
+        token_response.id_token.ok_or_else(|| {
+            AppError::InternalServerError(anyhow!("Token endpoint did not return an id token"))
+        })
+    }
+
+    /// Verify signature, issuer, audience, expiration and nonce of the id token
+    pub async fn verify_id_token(
+        &self,
+        id_token: &str,
+        nonce: &str,
+    ) -> Result<IdTokenClaims, AppError> {
+        let metadata = self.metadata().await?;
+        let header = decode_header(id_token).map_err(|e| {
+            warn!("Id token header is not valid: {e}");
+            AuthError::ExternalLoginFailed
+        })?;
+        // symmetric algorithms would use the client secret, only keys published
+        // by the provider are trusted
+        if !matches!(
+            header.alg,
+            Algorithm::RS256
+                | Algorithm::RS384
+                | Algorithm::RS512
+                | Algorithm::PS256
+                | Algorithm::PS384
+                | Algorithm::PS512
+                | Algorithm::ES256
+                | Algorithm::ES384
+                | Algorithm::EdDSA
+        ) {
+            warn!("Id token algorithm {:?} is not accepted", header.alg);
+            return Err(AuthError::ExternalLoginFailed)?;
+        }
+        let key = self.decoding_key(header.kid.as_deref()).await?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.leeway = self.leeway.as_secs();
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_audience(&[&self.config.client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        let claims = decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map_err(|e| {
+                warn!("Id token is not valid: {e}");
+                AuthError::ExternalLoginFailed
+            })?
+            .claims;
+        if claims.nonce.as_deref() != Some(nonce) {
+            warn!("Id token nonce does not match the login");
+            return Err(AuthError::ExternalLoginFailed)?;
+        }
+        Ok(claims)
+    }
+
+    /// Find the provider key with the identifier, tokens without `kid` are
+    /// accepted only if the provider publishes a single key
+    async fn decoding_key(&self, kid: Option<&str>) -> Result<DecodingKey, AppError> {
+        for refresh in [false, true] {
+            let jwks = self.jwks(refresh).await?;
+            let jwk = match kid {
+                Some(kid) => jwks.find(kid),
+                None if jwks.keys.len() == 1 => jwks.keys.first(),
+                None => None,
+            };
+            if let Some(jwk) = jwk {
+                return DecodingKey::from_jwk(jwk).map_err(|e| {
+                    AppError::InternalServerError(anyhow!("Provider key is not valid: {e}"))
+                });
+            }
+        }
+        warn!("Id token is signed by unknown key {kid:?}");
+        Err(AuthError::ExternalLoginFailed)?
+    }
+
+    async fn jwks(&self, refresh: bool) -> Result<JwkSet, AppError> {
+        if !refresh {
+            if let Some(jwks) = self.jwks.read().await.as_ref() {
+                return Ok(jwks.clone());
+            }
+        }
+        let metadata = self.metadata().await?;
+        let jwks: JwkSet = request_id::propagate(self.http.get(&metadata.jwks_uri))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .context("Cannot read provider keys")?
+            .json()
+            .await
+            .context("Provider keys are not valid")?;
+        *self.jwks.write().await = Some(jwks.clone());
+        Ok(jwks)
+    }
+}
+
+/// Cookie binding the login to the browser that started it, it holds the state
+pub const LOGIN_STATE_COOKIE: &str = "oidc_login_state";
+
+/// Login started with the provider
+pub struct OidcLogin {
+    /// url of the provider where the user is redirected
+    pub url: String,
+    /// `Set-Cookie` value of the `LOGIN_STATE_COOKIE` to send with the redirect
+    pub cookie: String,
+}
+
+/// Start a new login returning the url of the provider where the user is redirected
+pub async fn begin_login() -> Result<OidcLogin, AppError> {
+    let client = get_oidc_client()?;
+    let login_state = OidcLoginState {
+        id: None,
+        state: random_token(),
+        nonce: random_token(),
+        code_verifier: random_token(),
+        expires_at: DateTime::from_system_time(SystemTime::now() + client.config().login_state_ttl),
+    };
+    let url = client
+        .authorization_url(
+            &login_state.state,
+            &login_state.nonce,
+            &code_challenge(&login_state.code_verifier),
+        )
+        .await?;
+
+    let db = get_database_service().await.db();
+    let collection = db.collection::<OidcLoginState>(OidcLoginState::collection_name());
+    // logins never completed are removed here
+    collection
+        .delete_many(doc! { "expires_at": { "$lte": DateTime::now() } }, None)
+        .await?;
+    login_state.dump(&db).await?;
+    let cookie = login_state_cookie(
+        client.config(),
+        &login_state.state,
+        client.config().login_state_ttl,
+    );
+    Ok(OidcLogin { url, cookie })
+}
+
+/// Check that the callback reaches the browser that started the login
+///
+/// Otherwise the callback url of a login started by someone else would log
+/// the user in with their identity.
+pub fn verify_login_state(cookie: Option<&str>, state: &str) -> Result<(), AppError> {
+    let not_started = || AppError::InvalidRequest(anyhow!("Login was not started by this browser"));
+    let cookie = cookie.ok_or_else(not_started)?;
+    ring::constant_time::verify_slices_are_equal(cookie.as_bytes(), state.as_bytes())
+        .map_err(|_| not_started())
+}
+
+/// `Set-Cookie` value removing the `LOGIN_STATE_COOKIE` once the login is completed
+pub fn removed_login_state_cookie() -> Result<String, AppError> {
+    Ok(login_state_cookie(
+        get_oidc_client()?.config(),
+        "",
+        Duration::ZERO,
+    ))
+}
+
+/// The cookie is only sent to the callback, it is not readable by scripts
+fn login_state_cookie(config: &OidcVariables, value: &str, max_age: Duration) -> String {
+    let redirect_uri =
+        reqwest::Url::parse(&config.redirect_uri).expect("Redirect uri is validated");
+    let secure = if redirect_uri.scheme() == "https" {
+        "; Secure"
+    } else {
+        ""
+    };
+    format!(
+        "{LOGIN_STATE_COOKIE}={value}; Max-Age={}; Path={}; HttpOnly; SameSite=Lax{secure}",
+        max_age.as_secs(),
+        redirect_uri.path()
+    )
+}
+
+/// Complete the login when the provider redirects the user back with the code
+///
+/// Returns the user linked to the identity of the provider
+pub async fn complete_login(code: &str, state: &str) -> Result<User, AppError> {
+    let client = get_oidc_client()?;
+    let db = get_database_service().await.db();
+    let collection = db.collection::<OidcLoginState>(OidcLoginState::collection_name());
+    // the state is deleted so that the same callback cannot be replayed
+    let login_state = collection
+        .find_one_and_delete(doc! { "state": state }, None)
+        .await?
+        .filter(|login_state| login_state.expires_at > DateTime::now())
+        .ok_or_else(|| AppError::InvalidRequest(anyhow!("Login is expired or not valid")))?;
+
+    let id_token = client
+        .exchange_code(code, &login_state.code_verifier)
+        .await?;
+    let claims = client
+        .verify_id_token(&id_token, &login_state.nonce)
+        .await?;
+    let identity = OidcIdentity {
+        issuer: client.metadata().await?.issuer,
+        subject: claims.sub.clone(),
+    };
+    link_or_provision(client.config(), identity, claims).await
+}
+
+/// Returns the user linked to the identity, linking it by verified email or
+/// creating a new user if needed
+async fn link_or_provision(
+    config: &OidcVariables,
+    identity: OidcIdentity,
+    claims: IdTokenClaims,
+) -> Result<User, AppError> {
+    if let Some(user_model) = user::find_by_oidc_identity(&identity).await? {
+        return Ok(user_model);
+    }
+    // an email that is not verified by the provider or by the user could belong
+    // to someone else
+    let mut verified_email = claims.email.filter(|_| claims.email_verified);
+    if let Some(email) = &verified_email {
+        match user::find_by_email(email).await? {
+            Some(user_model) if user_model.email_verified => {
+                let user_id = user_model.id.expect("User id must be not missing");
+                info!("Linking identity {} to user {user_id}", identity.subject);
+                user::link_oidc_identity(&user_id, identity).await?;
+                return user::get_user(&user_id).await;
+            }
+            // emails are unique, the provisioned user cannot have it
+            Some(_) => verified_email = None,
+            None => {}
+        }
+    }
+    if !config.provisioning {
+        warn!("No user is linked to identity {}", identity.subject);
+        return Err(AuthError::ExternalLoginFailed)?;
+    }
+
+    let mut username = format!("oidc-{}", identity.subject);
+    for candidate in [claims.preferred_username, verified_email.clone()]
+        .into_iter()
+        .flatten()
+    {
+        if !user::username_exists(&candidate).await? {
+            username = candidate;
+            break;
+        }
+    }
+    info!(
+        "Provisioning user {username} for identity {}",
+        identity.subject
+    );
+    let user_id =
+        user::create_oidc_user(username, verified_email, identity, config.provisioning_role)
+            .await?;
+    let user_id = user_id
+        .parse()
+        .map_err(|_| AppError::InternalServerError(anyhow!("Created user id is not valid")))?;
+    user::get_user(&user_id).await
+}
+
+/// Random url safe value used for state, nonce and PKCE verifier
+fn random_token() -> String {
+    Base64UrlUnpadded::encode_string(&rand::random::<[u8; 32]>())
+}
+
+/// PKCE challenge with method S256
+fn code_challenge(code_verifier: &str) -> String {
+    Base64UrlUnpadded::encode_string(&Sha256::digest(code_verifier.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use axum::{
+        extract::State,
+        http::StatusCode,
+        routing::{get, post},
+        Form, Json, Router,
+    };
+    use base64ct::{Base64UrlUnpadded, Encoding};
+    use jsonwebtoken::{encode, get_current_timestamp, Algorithm, EncodingKey, Header};
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use serde_json::{json, Value};
+
+    use crate::{enums::Role, service::environment::OidcVariables};
+
+    use super::{code_challenge, random_token, verify_login_state, OidcClient};
+
+    const CLIENT_ID: &str = "web-app-client";
+    const CLIENT_SECRET: &str = "client-secret";
+
+    /// Identity provider answering like a real one, it signs id tokens with
+    /// an Ed25519 key
+    struct MockProvider {
+        issuer: String,
+        key: EncodingKey,
+        public_key: String,
+        /// challenge and nonce of the pending login
+        login: Mutex<Option<(String, String)>>,
+    }
+
+    impl MockProvider {
+        fn id_token(&self, kid: &str, audience: &str, nonce: &str) -> String {
+            let mut header = Header::new(Algorithm::EdDSA);
+            header.kid = Some(kid.into());
+            let now = get_current_timestamp();
+            let claims = json!({
+                "iss": self.issuer,
+                "sub": "provider-user-1",
+                "aud": audience,
+                "iat": now,
+                "exp": now + 300,
+                "nonce": nonce,
+                "email": "John@Example.com",
+                "email_verified": true,
+            });
+            encode(&header, &claims, &self.key).unwrap()
+        }
+    }
+
+    async fn discovery(State(provider): State<Arc<MockProvider>>) -> Json<Value> {
+        Json(json!({
+            "issuer": provider.issuer,
+            "authorization_endpoint": format!("{}/authorize", provider.issuer),
+            "token_endpoint": format!("{}/token", provider.issuer),
+            "jwks_uri": format!("{}/jwks", provider.issuer),
+        }))
+    }
+
+    async fn jwks(State(provider): State<Arc<MockProvider>>) -> Json<Value> {
+        Json(json!({
+            "keys": [{
+                "kty": "OKP",
+                "crv": "Ed25519",
+                "x": provider.public_key,
+                "kid": "mock-key",
+                "alg": "EdDSA",
+                "use": "sig",
+            }]
+        }))
+    }
+
+    async fn token(
+        State(provider): State<Arc<MockProvider>>,
+        Form(form): Form<HashMap<String, String>>,
+    ) -> Result<Json<Value>, StatusCode> {
+        let (challenge, nonce) = provider.login.lock().unwrap().clone().unwrap();
+        let valid = form.get("grant_type").map(String::as_str) == Some("authorization_code")
+            && form.get("code").map(String::as_str) == Some("valid-code")
+            && form.get("client_id").map(String::as_str) == Some(CLIENT_ID)
+            && form.get("client_secret").map(String::as_str) == Some(CLIENT_SECRET)
+            && form.get("code_verifier").map(|v| code_challenge(v)) == Some(challenge);
+        if !valid {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        Ok(Json(json!({
+            "access_token": "opaque",
+            "token_type": "Bearer",
+            "id_token": provider.id_token("mock-key", CLIENT_ID, &nonce),
+        })))
+    }
+
+    async fn start_provider() -> Arc<MockProvider> {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let rng = ring::rand::SystemRandom::new();
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let provider = Arc::new(MockProvider {
+            issuer: format!("http://{}", listener.local_addr().unwrap()),
+            key: EncodingKey::from_ed_der(pkcs8.as_ref()),
+            public_key: Base64UrlUnpadded::encode_string(key_pair.public_key().as_ref()),
+            login: Mutex::new(None),
+        });
+        let app = Router::new()
+            .route("/.well-known/openid-configuration", get(discovery))
+            .route("/jwks", get(jwks))
+            .route("/token", post(token))
+            .with_state(provider.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        provider
+    }
+
+    #[test]
+    fn verify_login_state_test() {
+        assert!(verify_login_state(Some("state-1"), "state-1").is_ok());
+        assert!(verify_login_state(Some("state-2"), "state-1").is_err());
+        assert!(verify_login_state(None, "state-1").is_err());
+    }
+
+    #[tokio::test]
+    async fn authorization_code_flow_test() {
+        let provider = start_provider().await;
+        let client = OidcClient::new(
+            OidcVariables {
+                issuer_url: provider.issuer.clone(),
+                client_id: CLIENT_ID.into(),
+                client_secret: Some(CLIENT_SECRET.into()),
+                redirect_uri: "http://localhost:3000/callback".into(),
+                scopes: vec!["openid".into(), "email".into()],
+                provisioning: true,
+                provisioning_role: Role::User,
+                login_state_ttl: Duration::from_secs(60),
+                request_timeout: Duration::from_secs(5),
+            },
+            Duration::ZERO,
+        );
+
+        // the user is redirected to the provider with the PKCE challenge
+        let code_verifier = random_token();
+        let url = client
+            .authorization_url("state", "nonce", &code_challenge(&code_verifier))
+            .await
+            .unwrap();
+        let url = reqwest::Url::parse(&url).unwrap();
+        let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+        assert_eq!(url.path(), "/authorize");
+        assert_eq!(params["scope"], "openid email");
+        assert_eq!(params["code_challenge_method"], "S256");
+        *provider.login.lock().unwrap() =
+            Some((params["code_challenge"].clone(), params["nonce"].clone()));
+
+        // the code is exchanged only with the right verifier
+        assert!(client
+            .exchange_code("valid-code", &random_token())
+            .await
+            .is_err());
+        assert!(client
+            .exchange_code("wrong-code", &code_verifier)
+            .await
+            .is_err());
+        let id_token = client
+            .exchange_code("valid-code", &code_verifier)
+            .await
+            .unwrap();
+
+        let claims = client.verify_id_token(&id_token, "nonce").await.unwrap();
+        assert_eq!(claims.sub, "provider-user-1");
+        assert_eq!(claims.email.as_deref(), Some("John@Example.com"));
+        assert!(claims.email_verified);
+
+        // nonce of another login, token for another client or signed by an
+        // unknown key are rejected
+        assert!(client.verify_id_token(&id_token, "other").await.is_err());
+        let token = provider.id_token("mock-key", "other-client", "nonce");
+        assert!(client.verify_id_token(&token, "nonce").await.is_err());
+        let token = provider.id_token("unknown-key", CLIENT_ID, "nonce");
+        assert!(client.verify_id_token(&token, "nonce").await.is_err());
+    }
+}
@@ -0,0 +1,129 @@
+//! Password change and reset of forgotten passwords.
+//!
+//! A reset is requested with the email of the user and it sends a link with a
+//! random token. The token is stored hashed, it expires after
+//! `password.reset_token_ttl_s` and it can be used once. Links are sent only to
+//! verified emails and requests for other emails succeed as well, so that they
+//! do not reveal which emails exist.
+//!
+//! Changing or resetting the password revokes every token issued before and
+//! every session, except the one changing the password.
+
+use std::time::SystemTime;
+
+use anyhow::anyhow;
+use mongodb::bson::{doc, DateTime};
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::{
+    error::AppError,
+    model::{password_reset_token::PasswordResetToken, user::User},
+    service::{
+        db::{get_database_service, DatabaseDocument},
+        environment::ENVIRONMENT,
+        login_protection,
+        mailer::{self, EmailMessage},
+        session, user,
+    },
+    SessionId, UserId,
+};
+
+/// Check the password against the password policy
+pub fn validate(password: &str) -> Result<(), AppError> {
+    let min_length = ENVIRONMENT.password.min_length;
+    if password.chars().count() < min_length {
+        return Err(AppError::InvalidRequest(anyhow!(
+            "Password must be at least {min_length} characters long"
+        )));
+    }
+    Ok(())
+}
+
+/// Replace the password of the user after checking the current one
+///
+/// Returns the user so that a new token of the kept session can be issued to the caller
+pub async fn change_password(
+    user_id: &UserId,
+    current_password: &str,
+    new_password: &str,
+    keep_session: Option<&SessionId>,
+) -> Result<User, AppError> {
+    validate(new_password)?;
+    user::verify_password(user_id, current_password).await?;
+    user::set_password(user_id, new_password).await?;
+    session::revoke_all(user_id, keep_session).await?;
+    info!("Password of user {user_id} has been changed");
+    user::get_user(user_id).await
+}
+
+/// Send a reset link to the user with the verified email, nothing happens if no user has it
+pub async fn request_reset(email: &str) -> Result<(), AppError> {
+    let user_model = user::find_by_email(email).await?;
+    let Some(user_model) = user_model.filter(|user_model| user_model.email_verified) else {
+        info!("Password reset requested for unknown or not verified email");
+        return Ok(());
+    };
+    let user_id = user_model.id.expect("User id must be not missing");
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<PasswordResetToken>(PasswordResetToken::collection_name());
+    // only the last link is valid
+    collection
+        .delete_many(doc! { "user_id": user_id }, None)
+        .await?;
+
+    let token = hex::encode(rand::random::<[u8; 32]>());
+    let ttl = ENVIRONMENT.password.reset_token_ttl;
+    PasswordResetToken {
+        id: None,
+        user_id,
+        token_hash: hash_token(&token),
+        expires_at: DateTime::from_system_time(SystemTime::now() + ttl),
+    }
+    .dump(db)
+    .await?;
+
+    let link = format!(
+        "{}/reset-password?token={token}",
+        ENVIRONMENT.mail.web_app_url
+    );
+    mailer::enqueue(EmailMessage {
+        to: email.to_string(),
+        subject: "Reset your password".into(),
+        body: format!(
+            "Hello {},\n\n\
+             a password reset has been requested for your account. Open the link \
+             below within {} minutes to choose a new password:\n\n{link}\n\n\
+             If you did not request it, you can ignore this email.\n",
+            user_model.username,
+            ttl.as_secs() / 60
+        ),
+    })
+    .await
+}
+
+/// Set the new password of the user owning the reset token
+///
+/// The token is consumed and the lockout of the user is removed, returns the user
+pub async fn reset_password(token: &str, new_password: &str) -> Result<UserId, AppError> {
+    validate(new_password)?;
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<PasswordResetToken>(PasswordResetToken::collection_name());
+    let reset_token = collection
+        .find_one_and_delete(
+            doc! { "token_hash": hash_token(token), "expires_at": { "$gt": DateTime::now() } },
+            None,
+        )
+        .await?
+        .ok_or_else(|| AppError::InvalidRequest(anyhow!("Reset token is expired or not valid")))?;
+    user::set_password(&reset_token.user_id, new_password).await?;
+    session::revoke_all(&reset_token.user_id, None).await?;
+    let user_model = user::get_user(&reset_token.user_id).await?;
+    login_protection::unlock(&user_model.username).await?;
+    info!("Password of user {} has been reset", reset_token.user_id);
+    Ok(reset_token.user_id)
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
@@ -2,23 +2,57 @@ use amqprs::{
     callbacks::{DefaultChannelCallback, DefaultConnectionCallback},
     channel::{BasicPublishArguments, QueueBindArguments, QueueDeclareArguments},
     connection::{Connection, OpenConnectionArguments},
-    BasicProperties,
+    BasicProperties, FieldTable, FieldValue,
 };
+use anyhow::anyhow;
+use tracing::{field, instrument, Span};
 
+use crate::{
+    error::AppError,
+    service::{
+        metrics,
+        request_id::{self, REQUEST_ID_HEADER},
+        telemetry,
+    },
+};
+
+/// Publish the message on the queue bound to the `amq.topic` exchange
+///
+/// Messages published while handling a request carry its id as correlation id
+/// and in the `x-request-id` header, the trace of the request in the
+/// `traceparent` header.
+#[instrument(
+    level = "debug",
+    skip(content),
+    fields(
+        otel.name = %format!("{queue_name} publish"),
+        otel.kind = "producer",
+        otel.status_code = field::Empty,
+    )
+)]
 pub async fn send_message(queue_name: &str, content: String) -> Result<(), AppError> {
+    let result = publish(queue_name, content).await;
+    metrics::record_queue_message(queue_name, result.is_ok());
+    if result.is_err() {
+        Span::current().record("otel.status_code", "error");
+    }
+    result
+}
+
+async fn publish(queue_name: &str, content: String) -> Result<(), AppError> {
     // open a connection to RabbitMQ server
     let connection = Connection::open(&OpenConnectionArguments::default()).await?;
     connection
         .register_callback(DefaultConnectionCallback)
         .await?;
     // open a channel on the connection
-    let channel = connection.open_channel(None).await.unwrap();
+    let channel = connection.open_channel(None).await?;
     channel.register_callback(DefaultChannelCallback).await?;
     // declare a queue
     let (queue_name, _, _) = channel
         .queue_declare(QueueDeclareArguments::new(queue_name))
         .await?
-        .unwrap();
+        .ok_or_else(|| anyhow!("Queue {queue_name} has not been declared"))?;
     let routing_key = &queue_name;
     let exchange_name = "amq.topic";
     channel
@@ -34,7 +68,59 @@ pub async fn send_message(queue_name: &str, content: String) -> Result<(), AppEr
     let args = BasicPublishArguments::new(exchange_name, routing_key);
 
     channel
-        .basic_publish(BasicProperties::default(), content.into_bytes(), args)
+        .basic_publish(message_properties(), content.into_bytes(), args)
         .await?;
-    connection.close().await
+    Ok(connection.close().await?)
+}
+
+/// Properties of the published messages, they carry the id and the trace of
+/// the request being handled
+fn message_properties() -> BasicProperties {
+    let mut properties = BasicProperties::default();
+    let mut headers = FieldTable::new();
+    telemetry::inject_context(&mut headers);
+    if let Some(request_id) = request_id::current() {
+        // request ids are shorter than the limits of the amqp strings
+        headers.insert(
+            REQUEST_ID_HEADER
+                .as_str()
+                .try_into()
+                .expect("Header name is a short string"),
+            FieldValue::S(
+                request_id
+                    .clone()
+                    .try_into()
+                    .expect("Request id is a long string"),
+            ),
+        );
+        properties.with_correlation_id(&request_id);
+    }
+    if !headers.as_ref().is_empty() {
+        properties.with_headers(headers);
+    }
+    properties
+}
+
+#[cfg(test)]
+mod tests {
+    use amqprs::FieldValue;
+
+    use crate::service::request_id;
+
+    use super::message_properties;
+
+    #[tokio::test]
+    async fn message_properties_test() {
+        assert!(message_properties().headers().is_none());
+
+        let properties =
+            request_id::scope("a1b2-c3d4".into(), async { message_properties() }).await;
+        assert_eq!(
+            properties.correlation_id().map(|id| id.as_str()),
+            Some("a1b2-c3d4")
+        );
+        let headers = properties.headers().unwrap();
+        let value = headers.get(&"x-request-id".try_into().unwrap()).unwrap();
+        assert!(matches!(value, FieldValue::S(id) if id.as_ref() == "a1b2-c3d4"));
+    }
 }
@@ -0,0 +1,517 @@
+//! Rate limiting of the requests with token buckets.
+//!
+//! Every client has a bucket of `burst` tokens refilled at `per_second` tokens
+//! per second, a request takes a token and it is refused when the bucket is
+//! empty. Clients are identified by their api key, or by the subject of their
+//! token, and limited by their plan. Clients without valid credentials are
+//! identified by their address. Routes configured with their own limit have a
+//! second bucket for every client.
+//!
+//! Buckets implement the `BucketStore` trait: memory keeps them in every
+//! instance, the database shares them between the instances.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use axum::async_trait;
+use mongodb::{
+    bson::doc,
+    options::{FindOneAndUpdateOptions, IndexOptions, ReturnDocument},
+    IndexModel,
+};
+use once_cell::sync::Lazy;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::AppError,
+    model::rate_limit_bucket::RateLimitBucket,
+    service::{
+        db::{get_database_service, is_duplicate_key, DatabaseDocument},
+        environment::{RateLimit, RateLimitStore, RouteRateLimit, ENVIRONMENT},
+        user,
+    },
+    ServiceAccountId, UserId,
+};
+
+/// How long the client of some credentials, or their refusal, is remembered
+/// without authenticating them again, plan changes are applied after it
+const CLIENT_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// Entries kept in memory before the expired or full ones are removed
+const SWEEP_THRESHOLD: usize = 10_000;
+
+/// Minimum time between two sweeps of the memory
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+static STORE: Lazy<Arc<dyn BucketStore>> = Lazy::new(|| match ENVIRONMENT.rate_limit.store {
+    RateLimitStore::Memory => Arc::new(MemoryBucketStore::default()),
+    RateLimitStore::Database => Arc::new(DatabaseBucketStore),
+});
+
+/// Clients identified by the hash of their credentials
+static CLIENTS: Lazy<Mutex<HashMap<String, (RateLimitClient, Instant)>>> =
+    Lazy::new(Default::default);
+
+/// Hashes of the refused credentials with the time they were refused
+static REJECTED: Lazy<Mutex<HashMap<String, Instant>>> = Lazy::new(Default::default);
+
+/// Client whose requests are limited
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimitClient {
+    /// bucket key, e.g. `api_key:<hash>`, `user:<id>` or `ip:<address>`
+    pub key: String,
+    /// false for clients without valid credentials
+    pub authenticated: bool,
+    pub plan: Option<String>,
+}
+
+impl RateLimitClient {
+    /// Client without valid credentials
+    pub fn anonymous(ip: Option<IpAddr>) -> Self {
+        let ip = ip.map_or_else(|| "unknown".to_string(), |ip| ip.to_string());
+        RateLimitClient {
+            key: format!("ip:{ip}"),
+            authenticated: false,
+            plan: None,
+        }
+    }
+
+    /// Client authenticated with an api key, every key has its own bucket
+    pub fn api_key(key: &str, plan: Option<String>) -> Self {
+        RateLimitClient {
+            key: format!("api_key:{}", &hash(key)[..16]),
+            authenticated: true,
+            plan,
+        }
+    }
+
+    /// User authenticated with a token of the web app
+    pub fn user(user_id: &UserId, plan: Option<String>) -> Self {
+        RateLimitClient {
+            key: format!("user:{user_id}"),
+            authenticated: true,
+            plan,
+        }
+    }
+
+    /// Service account authenticated with a token of the client credentials grant
+    pub fn service_account(service_account_id: &ServiceAccountId, plan: Option<String>) -> Self {
+        RateLimitClient {
+            key: format!("service_account:{service_account_id}"),
+            authenticated: true,
+            plan,
+        }
+    }
+
+    fn limit(&self) -> RateLimit {
+        let config = &ENVIRONMENT.rate_limit;
+        match self.authenticated {
+            true => *config.plan(self.plan.as_deref()),
+            false => config.anonymous,
+        }
+    }
+}
+
+/// Outcome of the rate limit of a request
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    pub limit: RateLimit,
+    /// requests that can be sent right now
+    pub remaining: u32,
+    /// time until the bucket is full again
+    pub reset: Duration,
+    /// time until the next token, zero if the request is allowed
+    pub retry_after: Duration,
+}
+
+impl RateLimitDecision {
+    fn new(limit: RateLimit, bucket: Bucket) -> Self {
+        let missing = (limit.burst as f64 - bucket.tokens).max(0.0);
+        let retry_after = match bucket.allowed {
+            true => Duration::ZERO,
+            false => Duration::from_secs_f64((1.0 - bucket.tokens).max(0.0) / limit.per_second),
+        };
+        RateLimitDecision {
+            allowed: bucket.allowed,
+            limit,
+            remaining: bucket.tokens.max(0.0).floor() as u32,
+            reset: Duration::from_secs_f64(missing / limit.per_second),
+            retry_after,
+        }
+    }
+
+    /// Seconds until the bucket is full again, rounded up
+    pub fn reset_seconds(&self) -> u64 {
+        self.reset.as_secs_f64().ceil() as u64
+    }
+
+    /// Seconds to wait before retrying, rounded up
+    pub fn retry_after_seconds(&self) -> u64 {
+        self.retry_after.as_secs_f64().ceil() as u64
+    }
+
+    /// Quota policy, the burst and the seconds the bucket takes to refill, e.g. `60;w=12`
+    pub fn policy(&self) -> String {
+        let window = (self.limit.burst as f64 / self.limit.per_second).ceil() as u64;
+        format!("{};w={window}", self.limit.burst)
+    }
+}
+
+/// Tokens of a bucket after a request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bucket {
+    pub tokens: f64,
+    /// true if the request took a token
+    pub allowed: bool,
+}
+
+/// Trait implemented by every store of token buckets
+#[async_trait]
+pub trait BucketStore: Send + Sync {
+    /// Refill the bucket of the key and take a token if there is one
+    async fn take(&self, key: &str, limit: &RateLimit) -> Result<Bucket, AppError>;
+}
+
+/// Take a token from the buckets of the client for the request
+///
+/// Route buckets are checked before the plan, a refused request does not take
+/// tokens from the following buckets. Returns the decision of the most
+/// restrictive bucket.
+pub async fn check(
+    client: &RateLimitClient,
+    method: &str,
+    path: &str,
+) -> Result<RateLimitDecision, AppError> {
+    let mut buckets: Vec<(String, RateLimit)> = ENVIRONMENT
+        .rate_limit
+        .routes
+        .iter()
+        .filter(|route| route_matches(route, method, path))
+        .map(|route| {
+            let method = route.method.as_deref().unwrap_or("*");
+            let key = format!("{}|{method} {}", client.key, route.path);
+            (key, route.limit)
+        })
+        .collect();
+    buckets.push((client.key.clone(), client.limit()));
+
+    let mut decision: Option<RateLimitDecision> = None;
+    for (key, limit) in buckets {
+        let current = RateLimitDecision::new(limit, STORE.take(&key, &limit).await?);
+        let refused = !current.allowed;
+        decision = Some(match decision {
+            Some(previous) => most_restrictive(previous, current),
+            None => current,
+        });
+        if refused {
+            break;
+        }
+    }
+    Ok(decision.expect("The plan bucket is always checked"))
+}
+
+/// Returns the client identified by the credentials in the last minute
+pub fn cached_client(credentials: &str) -> Option<RateLimitClient> {
+    let clients = CLIENTS.lock().expect("Rate limit clients lock is poisoned");
+    clients
+        .get(&hash(credentials))
+        .filter(|(_, cached_at)| cached_at.elapsed() < CLIENT_CACHE_TTL)
+        .map(|(client, _)| client.clone())
+}
+
+/// Remember the client identified by the credentials
+pub fn cache_client(credentials: &str, client: RateLimitClient) {
+    let mut clients = CLIENTS.lock().expect("Rate limit clients lock is poisoned");
+    if clients.len() >= SWEEP_THRESHOLD {
+        clients.retain(|_, (_, cached_at)| cached_at.elapsed() < CLIENT_CACHE_TTL);
+    }
+    clients.insert(hash(credentials), (client, Instant::now()));
+}
+
+/// Returns true if the credentials were refused in the last minute
+pub fn is_rejected(credentials: &str) -> bool {
+    let rejected = REJECTED
+        .lock()
+        .expect("Rate limit clients lock is poisoned");
+    rejected
+        .get(&hash(credentials))
+        .is_some_and(|rejected_at| rejected_at.elapsed() < CLIENT_CACHE_TTL)
+}
+
+/// Remember the refused credentials
+pub fn cache_rejected(credentials: &str) {
+    let mut rejected = REJECTED
+        .lock()
+        .expect("Rate limit clients lock is poisoned");
+    if rejected.len() >= SWEEP_THRESHOLD {
+        rejected.retain(|_, rejected_at| rejected_at.elapsed() < CLIENT_CACHE_TTL);
+    }
+    rejected.insert(hash(credentials), Instant::now());
+}
+
+/// Returns the plan of the user
+pub async fn user_plan(user_id: &UserId) -> Result<Option<String>, AppError> {
+    Ok(user::get_user(user_id).await?.plan)
+}
+
+/// Refuse plans that are not configured
+pub fn check_plan(plan: &str) -> Result<(), AppError> {
+    let config = &ENVIRONMENT.rate_limit;
+    if config.has_plan(plan) {
+        return Ok(());
+    }
+    let plans: Vec<&str> = config.plans.keys().map(String::as_str).collect();
+    Err(AppError::InvalidRequest(anyhow!(
+        "Plan {plan} is not one of {}",
+        plans.join(", ")
+    )))
+}
+
+/// Create the indexes of the shared buckets
+///
+/// Buckets are deleted once they are full again, a missing bucket is full
+pub async fn create_indexes() -> Result<(), AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<RateLimitBucket>(RateLimitBucket::collection_name());
+    let key = IndexModel::builder()
+        .keys(doc! { "key": 1 })
+        .options(IndexOptions::builder().unique(true).build())
+        .build();
+    let expiration = IndexModel::builder()
+        .keys(doc! { "expires_at": 1 })
+        .options(IndexOptions::builder().expire_after(Duration::ZERO).build())
+        .build();
+    collection.create_indexes([key, expiration], None).await?;
+    Ok(())
+}
+
+/// Store keeping the buckets in the memory of the instance
+#[derive(Default)]
+pub struct MemoryBucketStore {
+    buckets: Mutex<MemoryBuckets>,
+}
+
+#[derive(Default)]
+struct MemoryBuckets {
+    /// tokens of every key at the time of its last request
+    buckets: HashMap<String, (f64, Instant, RateLimit)>,
+    swept_at: Option<Instant>,
+}
+
+impl MemoryBucketStore {
+    fn take_at(&self, key: &str, limit: &RateLimit, now: Instant) -> Bucket {
+        let mut memory = self
+            .buckets
+            .lock()
+            .expect("Rate limit buckets lock is poisoned");
+        let sweep_due = memory
+            .swept_at
+            .is_none_or(|swept_at| now.duration_since(swept_at) >= SWEEP_INTERVAL);
+        if memory.buckets.len() >= SWEEP_THRESHOLD && sweep_due {
+            // full buckets are the same as missing ones
+            memory.buckets.retain(|_, (tokens, updated_at, limit)| {
+                refill(*tokens, now.duration_since(*updated_at), limit) < limit.burst as f64
+            });
+            memory.swept_at = Some(now);
+        }
+        let tokens = match memory.buckets.get(key) {
+            Some((tokens, updated_at, _)) => {
+                refill(*tokens, now.duration_since(*updated_at), limit)
+            }
+            None => limit.burst as f64,
+        };
+        let bucket = take_token(tokens);
+        memory
+            .buckets
+            .insert(key.to_string(), (bucket.tokens, now, *limit));
+        bucket
+    }
+}
+
+#[async_trait]
+impl BucketStore for MemoryBucketStore {
+    async fn take(&self, key: &str, limit: &RateLimit) -> Result<Bucket, AppError> {
+        Ok(self.take_at(key, limit, Instant::now()))
+    }
+}
+
+/// Store sharing the buckets between the instances through the database
+///
+/// The bucket is refilled and the token is taken by a single atomic update,
+/// times are of the database so that the clocks of the instances do not matter.
+pub struct DatabaseBucketStore;
+
+#[async_trait]
+impl BucketStore for DatabaseBucketStore {
+    async fn take(&self, key: &str, limit: &RateLimit) -> Result<Bucket, AppError> {
+        let db = &get_database_service().await.db();
+        let collection = db.collection::<RateLimitBucket>(RateLimitBucket::collection_name());
+        let burst = limit.burst as f64;
+        let refill_ms = (burst / limit.per_second * 1000.0).ceil() as i64;
+        let update = vec![
+            doc! { "$set": {
+                "tokens": { "$min": [burst, { "$add": [
+                    { "$ifNull": ["$tokens", burst] },
+                    { "$multiply": [
+                        { "$divide": [
+                            { "$subtract": ["$$NOW", { "$ifNull": ["$updated_at", "$$NOW"] }] },
+                            1000.0,
+                        ] },
+                        limit.per_second,
+                    ] },
+                ] }] },
+                "updated_at": "$$NOW",
+            } },
+            doc! { "$set": { "allowed": { "$gte": ["$tokens", 1.0] } } },
+            doc! { "$set": {
+                "tokens": { "$cond": ["$allowed", { "$subtract": ["$tokens", 1.0] }, "$tokens"] },
+                "expires_at": { "$add": ["$$NOW", refill_ms] },
+            } },
+        ];
+        let options = FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .build();
+        let mut result = collection
+            .find_one_and_update(doc! { "key": key }, update.clone(), options.clone())
+            .await;
+        // concurrent requests may both insert the bucket, the second one updates it
+        if matches!(&result, Err(e) if is_duplicate_key(e)) {
+            result = collection
+                .find_one_and_update(doc! { "key": key }, update, options)
+                .await;
+        }
+        let bucket =
+            result?.ok_or_else(|| anyhow!("Rate limit bucket {key} has not been upserted"))?;
+        Ok(Bucket {
+            tokens: bucket.tokens,
+            allowed: bucket.allowed,
+        })
+    }
+}
+
+/// Tokens of the bucket after the elapsed time, they never exceed the burst
+fn refill(tokens: f64, elapsed: Duration, limit: &RateLimit) -> f64 {
+    (tokens + elapsed.as_secs_f64() * limit.per_second).min(limit.burst as f64)
+}
+
+fn take_token(tokens: f64) -> Bucket {
+    match tokens >= 1.0 {
+        true => Bucket {
+            tokens: tokens - 1.0,
+            allowed: true,
+        },
+        false => Bucket {
+            tokens,
+            allowed: false,
+        },
+    }
+}
+
+/// Refused decisions are the most restrictive, then the ones with fewer requests left
+fn most_restrictive(a: RateLimitDecision, b: RateLimitDecision) -> RateLimitDecision {
+    match (a.allowed, b.allowed) {
+        (false, true) => a,
+        (true, false) => b,
+        (false, false) if a.retry_after >= b.retry_after => a,
+        (false, false) => b,
+        (true, true) if a.remaining <= b.remaining => a,
+        (true, true) => b,
+    }
+}
+
+/// Returns true if the route limit applies to the request
+///
+/// Segments of the route starting with `:` match any value
+fn route_matches(route: &RouteRateLimit, method: &str, path: &str) -> bool {
+    if route
+        .method
+        .as_deref()
+        .is_some_and(|route_method| route_method != method)
+    {
+        return false;
+    }
+    let mut expected = route.path.trim_end_matches('/').split('/');
+    let mut actual = path.trim_end_matches('/').split('/');
+    loop {
+        match (expected.next(), actual.next()) {
+            (None, None) => return true,
+            (Some(expected), Some(actual))
+                if expected == actual || (expected.starts_with(':') && !actual.is_empty()) => {}
+            _ => return false,
+        }
+    }
+}
+
+fn hash(value: &str) -> String {
+    hex::encode(Sha256::digest(value.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::service::environment::{RateLimit, RouteRateLimit};
+
+    use super::{route_matches, Bucket, MemoryBucketStore, RateLimitDecision};
+
+    #[test]
+    fn token_bucket_test() {
+        let store = MemoryBucketStore::default();
+        let limit = RateLimit {
+            burst: 2,
+            per_second: 0.5,
+        };
+        let start = Instant::now();
+        assert!(store.take_at("a", &limit, start).allowed);
+        assert!(store.take_at("a", &limit, start).allowed);
+        let refused = store.take_at("a", &limit, start);
+        assert!(!refused.allowed);
+        // buckets of other clients are independent
+        assert!(store.take_at("b", &limit, start).allowed);
+
+        let decision = RateLimitDecision::new(limit, refused);
+        assert_eq!(decision.remaining, 0);
+        assert_eq!(decision.retry_after_seconds(), 2);
+        assert_eq!(decision.reset_seconds(), 4);
+        assert_eq!(decision.policy(), "2;w=4");
+
+        // a token is back after two seconds, the bucket never exceeds the burst
+        assert!(
+            store
+                .take_at("a", &limit, start + Duration::from_secs(2))
+                .allowed
+        );
+        let bucket = store.take_at("a", &limit, start + Duration::from_secs(3600));
+        assert_eq!(
+            bucket,
+            Bucket {
+                tokens: 1.0,
+                allowed: true
+            }
+        );
+    }
+
+    #[test]
+    fn route_matches_test() {
+        let route = RouteRateLimit {
+            method: Some("POST".into()),
+            path: "/sdk/:version/user".into(),
+            limit: RateLimit {
+                burst: 1,
+                per_second: 1.0,
+            },
+        };
+        assert!(route_matches(&route, "POST", "/sdk/v0/user"));
+        assert!(route_matches(&route, "POST", "/sdk/v1/user/"));
+        assert!(!route_matches(&route, "GET", "/sdk/v0/user"));
+        assert!(!route_matches(&route, "POST", "/sdk/v0/user/1"));
+        assert!(!route_matches(&route, "POST", "/sdk//user"));
+    }
+}
@@ -0,0 +1,88 @@
+//! Identifier correlating the logs, the responses and the calls caused by a request.
+//!
+//! The id is read from the `x-request-id` header of the request when it is
+//! valid, otherwise it is generated. The code handling the request reads it
+//! to forward it to queue messages and outgoing calls.
+
+use std::future::Future;
+
+use axum::http::{HeaderName, HeaderValue};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest request id accepted, longer values are replaced
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Run the future handling a request, the id is available to every call it makes
+pub async fn scope<F: Future>(request_id: String, future: F) -> F::Output {
+    REQUEST_ID.scope(request_id, future).await
+}
+
+/// Id of the request being handled, `None` outside of a request, e.g. in background tasks
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok()
+}
+
+/// Spawn the task keeping the id of the request being handled, if any
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    match current() {
+        Some(request_id) => tokio::spawn(REQUEST_ID.scope(request_id, future)),
+        None => tokio::spawn(future),
+    }
+}
+
+/// Returns the id sent by the client if it is valid or a new one
+///
+/// Valid ids are made of at most 128 visible ascii characters.
+pub fn accept_or_generate(header: Option<&HeaderValue>) -> String {
+    header
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LENGTH
+                && id.bytes().all(|c| c.is_ascii_graphic())
+        })
+        .map_or_else(|| Uuid::new_v4().to_string(), str::to_string)
+}
+
+/// Add the id of the request being handled to the outgoing call
+pub fn propagate(request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+    match current() {
+        Some(request_id) => request.header(REQUEST_ID_HEADER, request_id),
+        None => request,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::{accept_or_generate, current, scope};
+
+    #[tokio::test]
+    async fn request_id_test() {
+        let id = HeaderValue::from_static("a1b2-c3d4");
+        assert_eq!(accept_or_generate(Some(&id)), "a1b2-c3d4");
+
+        let generated = accept_or_generate(None);
+        assert_eq!(generated.len(), 36);
+        let spaces = HeaderValue::from_static("injected log line");
+        assert_ne!(accept_or_generate(Some(&spaces)), "injected log line");
+        let long = HeaderValue::from_str(&"a".repeat(129)).unwrap();
+        assert_eq!(accept_or_generate(Some(&long)).len(), 36);
+
+        assert_eq!(current(), None);
+        let scoped = scope("a1b2-c3d4".into(), async { current() }).await;
+        assert_eq!(scoped.as_deref(), Some("a1b2-c3d4"));
+    }
+}
@@ -0,0 +1,72 @@
+//! Lifecycle and usage of the sdk versions served concurrently.
+//!
+//! Usage is counted in memory since the start of the process, per version and
+//! per class of the response status.
+
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use axum::http::StatusCode;
+use mongodb::bson::DateTime;
+use once_cell::sync::Lazy;
+
+use crate::{
+    enums::SdkVersion,
+    service::environment::{SdkVersionVariables, ENVIRONMENT},
+};
+
+#[derive(Default)]
+struct Counters {
+    requests: AtomicU64,
+    client_errors: AtomicU64,
+    server_errors: AtomicU64,
+}
+
+static USAGE: Lazy<HashMap<SdkVersion, Counters>> = Lazy::new(|| {
+    SdkVersion::ALL
+        .into_iter()
+        .map(|version| (version, Counters::default()))
+        .collect()
+});
+
+/// Requests served by a version since the start of the process
+pub struct VersionUsage {
+    pub requests: u64,
+    pub client_errors: u64,
+    pub server_errors: u64,
+}
+
+/// Returns deprecation and sunset of the version
+pub fn lifecycle(version: SdkVersion) -> &'static SdkVersionVariables {
+    ENVIRONMENT.sdk.version(version)
+}
+
+/// Returns true if the version is not served anymore
+pub fn is_sunset(version: SdkVersion) -> bool {
+    lifecycle(version)
+        .sunset_at
+        .is_some_and(|sunset_at| sunset_at <= DateTime::now())
+}
+
+/// Count a request served by the version with the response status
+pub fn record_request(version: SdkVersion, status: StatusCode) {
+    let counters = &USAGE[&version];
+    counters.requests.fetch_add(1, Ordering::Relaxed);
+    if status.is_client_error() {
+        counters.client_errors.fetch_add(1, Ordering::Relaxed);
+    } else if status.is_server_error() {
+        counters.server_errors.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Returns the requests served by the version
+pub fn usage(version: SdkVersion) -> VersionUsage {
+    let counters = &USAGE[&version];
+    VersionUsage {
+        requests: counters.requests.load(Ordering::Relaxed),
+        client_errors: counters.client_errors.load(Ordering::Relaxed),
+        server_errors: counters.server_errors.load(Ordering::Relaxed),
+    }
+}
@@ -0,0 +1,276 @@
+//! Service accounts used by integrations on the sdk routes.
+//!
+//! Service accounts are created by admins and are not bound to a human user,
+//! hence, integrations keep working when the user who created them leaves.
+//! They own api keys, stored hashed and shown once, and they can exchange one
+//! of them for a short lived token through the OAuth2 client credentials grant.
+//! Disabling an account refuses its keys and tokens at once.
+
+use std::time::{Duration, SystemTime};
+
+use anyhow::anyhow;
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{doc, DateTime},
+    options::{FindOptions, IndexOptions},
+    IndexModel,
+};
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+use crate::{
+    enums::{Role, Scope},
+    error::AppError,
+    model::{service_account::ServiceAccount, service_account_key::ServiceAccountKey},
+    service::{
+        db::{get_database_service, DatabaseDocument},
+        rate_limit,
+    },
+    ServiceAccountId, ServiceAccountKeyId, UserId,
+};
+
+/// Prefix of the service account keys, it tells them apart from user api keys
+const KEY_PREFIX: &str = "sa_";
+
+/// Characters of the key stored in clear to recognize it
+const DISPLAYED_KEY_LENGTH: usize = KEY_PREFIX.len() + 8;
+
+/// Requests closer than this do not update the last use of a key
+const LAST_USED_RESOLUTION: Duration = Duration::from_secs(60);
+
+/// Create a service account with the role, the scopes and the rate limit plan
+pub async fn create(
+    created_by: &UserId,
+    name: &str,
+    role: Role,
+    scopes: Vec<Scope>,
+    plan: Option<String>,
+) -> Result<ServiceAccount, AppError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(AppError::InvalidRequest(anyhow!(
+            "Service account name must not be empty"
+        )));
+    }
+    if let Some(plan) = &plan {
+        rate_limit::check_plan(plan)?;
+    }
+    let mut service_account = ServiceAccount {
+        id: None,
+        name: name.to_string(),
+        role,
+        scopes: deduplicate(scopes),
+        created_by: *created_by,
+        disabled: false,
+        created_at: DateTime::now(),
+        plan,
+    };
+    let id = service_account
+        .dump(&get_database_service().await.db())
+        .await?;
+    service_account.id = Some(id.parse().map_err(|_| {
+        AppError::InternalServerError(anyhow!("Created service account id is not valid"))
+    })?);
+    info!("User {created_by} created service account {id}");
+    Ok(service_account)
+}
+
+/// Returns every service account, the newest first
+pub async fn list() -> Result<Vec<ServiceAccount>, AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<ServiceAccount>(ServiceAccount::collection_name());
+    let options = FindOptions::builder()
+        .sort(doc! { "created_at": -1 })
+        .build();
+    Ok(collection.find(None, options).await?.try_collect().await?)
+}
+
+/// Returns the service account if it exists and it is not disabled
+pub async fn find_enabled(
+    service_account_id: &ServiceAccountId,
+) -> Result<Option<ServiceAccount>, AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<ServiceAccount>(ServiceAccount::collection_name());
+    Ok(collection
+        .find_one(doc! { "_id": service_account_id, "disabled": false }, None)
+        .await?)
+}
+
+/// Disable the service account, its keys and tokens are refused from now on
+pub async fn disable(service_account_id: &ServiceAccountId) -> Result<(), AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<ServiceAccount>(ServiceAccount::collection_name());
+    let result = collection
+        .update_one(
+            doc! { "_id": service_account_id },
+            doc! { "$set": { "disabled": true } },
+            None,
+        )
+        .await?;
+    if result.matched_count == 0 {
+        return Err(not_found(service_account_id));
+    }
+    info!("Service account {service_account_id} has been disabled");
+    Ok(())
+}
+
+/// Create a new api key of the service account returning it with the key in clear
+pub async fn create_key(
+    service_account_id: &ServiceAccountId,
+    created_by: &UserId,
+    name: &str,
+) -> Result<(ServiceAccountKey, String), AppError> {
+    if find_enabled(service_account_id).await?.is_none() {
+        return Err(not_found(service_account_id));
+    }
+    let key = format!("{KEY_PREFIX}{}", hex::encode(rand::random::<[u8; 32]>()));
+    let mut key_model = ServiceAccountKey {
+        id: None,
+        service_account_id: *service_account_id,
+        name: name.trim().to_string(),
+        prefix: displayed_prefix(&key),
+        key_hash: hash_key(&key),
+        created_by: *created_by,
+        created_at: DateTime::now(),
+        last_used_at: None,
+    };
+    let id = key_model.dump(&get_database_service().await.db()).await?;
+    key_model.id = Some(id.parse().map_err(|_| {
+        AppError::InternalServerError(anyhow!("Created service account key id is not valid"))
+    })?);
+    info!("User {created_by} created key {id} of service account {service_account_id}");
+    Ok((key_model, key))
+}
+
+/// Returns the api keys of the service account, the newest first
+pub async fn list_keys(
+    service_account_id: &ServiceAccountId,
+) -> Result<Vec<ServiceAccountKey>, AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<ServiceAccountKey>(ServiceAccountKey::collection_name());
+    let options = FindOptions::builder()
+        .sort(doc! { "created_at": -1 })
+        .build();
+    Ok(collection
+        .find(doc! { "service_account_id": service_account_id }, options)
+        .await?
+        .try_collect()
+        .await?)
+}
+
+/// Delete the api key of the service account, it is refused from now on
+pub async fn revoke_key(
+    service_account_id: &ServiceAccountId,
+    key_id: &ServiceAccountKeyId,
+) -> Result<(), AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<ServiceAccountKey>(ServiceAccountKey::collection_name());
+    let result = collection
+        .delete_one(
+            doc! { "_id": key_id, "service_account_id": service_account_id },
+            None,
+        )
+        .await?;
+    if result.deleted_count == 0 {
+        return Err(AppError::DoesNotExist(anyhow!(
+            "Key with id {key_id} does not exist"
+        )));
+    }
+    info!("Key {key_id} of service account {service_account_id} has been revoked");
+    Ok(())
+}
+
+/// Characters of the key shown to recognize it, of service account and user keys
+pub fn displayed_prefix(key: &str) -> String {
+    key.chars().take(DISPLAYED_KEY_LENGTH).collect()
+}
+
+/// Returns true if the key has the format of service account keys
+pub fn is_service_account_key(key: &str) -> bool {
+    key.starts_with(KEY_PREFIX)
+}
+
+/// Returns the enabled service account owning the key, recording its use
+pub async fn authenticate_key(key: &str) -> Result<Option<ServiceAccount>, AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<ServiceAccountKey>(ServiceAccountKey::collection_name());
+    let Some(key_model) = collection
+        .find_one(doc! { "key_hash": hash_key(key) }, None)
+        .await?
+    else {
+        return Ok(None);
+    };
+    let Some(service_account) = find_enabled(&key_model.service_account_id).await? else {
+        return Ok(None);
+    };
+    let used_after = DateTime::from_system_time(SystemTime::now() - LAST_USED_RESOLUTION);
+    if key_model.last_used_at.is_none_or(|last| last < used_after) {
+        collection
+            .update_one(
+                doc! { "_id": key_model.id },
+                doc! { "$set": { "last_used_at": DateTime::now() } },
+                None,
+            )
+            .await?;
+    }
+    Ok(Some(service_account))
+}
+
+/// Create the indexes of the service account keys
+///
+/// Keys are looked up by their hash, which must be unique
+pub async fn create_indexes() -> Result<(), AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<ServiceAccountKey>(ServiceAccountKey::collection_name());
+    let hash = IndexModel::builder()
+        .keys(doc! { "key_hash": 1 })
+        .options(IndexOptions::builder().unique(true).build())
+        .build();
+    let account = IndexModel::builder()
+        .keys(doc! { "service_account_id": 1 })
+        .build();
+    collection.create_indexes([hash, account], None).await?;
+    Ok(())
+}
+
+/// Returns the scopes in their first occurrence order without duplicates
+fn deduplicate(scopes: Vec<Scope>) -> Vec<Scope> {
+    let mut unique = Vec::with_capacity(scopes.len());
+    for scope in scopes {
+        if !unique.contains(&scope) {
+            unique.push(scope);
+        }
+    }
+    unique
+}
+
+fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+fn not_found(service_account_id: &ServiceAccountId) -> AppError {
+    AppError::DoesNotExist(anyhow!(
+        "Service account with id {service_account_id} does not exist"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::enums::Scope;
+
+    use super::{deduplicate, is_service_account_key};
+
+    #[test]
+    fn scopes_and_keys_test() {
+        assert_eq!(
+            deduplicate(vec![
+                Scope::UsersRead,
+                Scope::WebhooksRead,
+                Scope::UsersRead
+            ]),
+            vec![Scope::UsersRead, Scope::WebhooksRead]
+        );
+        assert!(is_service_account_key("sa_0123456789abcdef"));
+        assert!(!is_service_account_key("0123456789abcdef"));
+    }
+}
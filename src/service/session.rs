@@ -0,0 +1,134 @@
+//! Sessions started by successful logins.
+//!
+//! Every web app token issued by a login carries the identifier of its session
+//! in the `sid` claim. Tokens are refused once their session is revoked, hence,
+//! users can log out single devices. Sessions last as long as their tokens and
+//! a TTL index deletes them when they expire.
+
+use std::time::{Duration, SystemTime};
+
+use anyhow::anyhow;
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{doc, DateTime},
+    options::{FindOptions, IndexOptions},
+    IndexModel,
+};
+
+use crate::{
+    auth::ClientInfo,
+    enums::LoginMethod,
+    error::AppError,
+    model::session::Session,
+    service::{
+        db::{get_database_service, DatabaseDocument},
+        environment::ENVIRONMENT,
+    },
+    SessionId, UserId,
+};
+
+/// Requests closer than this do not update the last seen time
+const LAST_SEEN_RESOLUTION: Duration = Duration::from_secs(60);
+
+/// Start a session of the user returning its identifier
+pub async fn create(
+    user_id: &UserId,
+    method: LoginMethod,
+    client: &ClientInfo,
+) -> Result<SessionId, AppError> {
+    let now = SystemTime::now();
+    let session = Session {
+        id: None,
+        user_id: *user_id,
+        method,
+        ip: client.ip.map(|ip| ip.to_string()),
+        user_agent: client.user_agent.clone(),
+        created_at: DateTime::from_system_time(now),
+        last_seen_at: DateTime::from_system_time(now),
+        expires_at: DateTime::from_system_time(now + ENVIRONMENT.authentication.token_lifetime),
+    };
+    let id = session.dump(&get_database_service().await.db()).await?;
+    id.parse()
+        .map_err(|_| AppError::InternalServerError(anyhow!("Created session id is not valid")))
+}
+
+/// Record a request made with the session, returns false if the session is revoked
+pub async fn touch(session_id: &SessionId, user_id: &UserId) -> Result<bool, AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<Session>(Session::collection_name());
+    let Some(session) = collection
+        .find_one(doc! { "_id": session_id, "user_id": user_id }, None)
+        .await?
+    else {
+        return Ok(false);
+    };
+    let seen_after = DateTime::from_system_time(SystemTime::now() - LAST_SEEN_RESOLUTION);
+    if session.last_seen_at < seen_after {
+        collection
+            .update_one(
+                doc! { "_id": session_id },
+                doc! { "$set": { "last_seen_at": DateTime::now() } },
+                None,
+            )
+            .await?;
+    }
+    Ok(true)
+}
+
+/// Returns the sessions of the user not expired yet, the most recent first
+pub async fn list_active(user_id: &UserId) -> Result<Vec<Session>, AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<Session>(Session::collection_name());
+    let options = FindOptions::builder()
+        .sort(doc! { "last_seen_at": -1 })
+        .build();
+    Ok(collection
+        .find(
+            doc! { "user_id": user_id, "expires_at": { "$gt": DateTime::now() } },
+            options,
+        )
+        .await?
+        .try_collect()
+        .await?)
+}
+
+/// Revoke the session of the user, its tokens are refused from now on
+pub async fn revoke(user_id: &UserId, session_id: &SessionId) -> Result<(), AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<Session>(Session::collection_name());
+    let result = collection
+        .delete_one(doc! { "_id": session_id, "user_id": user_id }, None)
+        .await?;
+    if result.deleted_count == 0 {
+        return Err(AppError::DoesNotExist(anyhow!(
+            "Session with id {session_id} does not exist"
+        )));
+    }
+    Ok(())
+}
+
+/// Revoke every session of the user except the one to keep
+pub async fn revoke_all(user_id: &UserId, keep: Option<&SessionId>) -> Result<u64, AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<Session>(Session::collection_name());
+    let mut filter = doc! { "user_id": user_id };
+    if let Some(keep) = keep {
+        filter.insert("_id", doc! { "$ne": keep });
+    }
+    Ok(collection.delete_many(filter, None).await?.deleted_count)
+}
+
+/// Create the indexes of the session collection
+///
+/// Sessions are deleted when they expire
+pub async fn create_indexes() -> Result<(), AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<Session>(Session::collection_name());
+    let expiration = IndexModel::builder()
+        .keys(doc! { "expires_at": 1 })
+        .options(IndexOptions::builder().expire_after(Duration::ZERO).build())
+        .build();
+    let user = IndexModel::builder().keys(doc! { "user_id": 1 }).build();
+    collection.create_indexes([expiration, user], None).await?;
+    Ok(())
+}
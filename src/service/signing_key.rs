@@ -0,0 +1,366 @@
+//! Signing key service manages the asymmetric keys used to sign jwt tokens.
+//!
+//! Keys are generated by the application and stored in the database with their
+//! private part encrypted by the secret `JWT_KEY_ENCRYPTION_KEY`, hence every
+//! instance of the application shares the same keys.
+//!
+//! A new key is generated every `authentication.key_rotation_interval_h` hours,
+//! the previous keys stop signing tokens but they keep verifying them for
+//! `authentication.key_grace_period_h` hours so that tokens already issued
+//! remain valid. Public keys are exposed in the JWKS format.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::{anyhow, Context};
+use base64ct::{Base64, Base64UrlUnpadded, Encoding};
+use futures::TryStreamExt;
+use jsonwebtoken::{
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, OctetKeyPairParameters,
+        OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+    },
+    Algorithm, DecodingKey, EncodingKey,
+};
+use mongodb::bson::{doc, oid::ObjectId, DateTime};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use rsa::{pkcs1::EncodeRsaPrivateKey, traits::PublicKeyParts, RsaPrivateKey};
+use tokio::sync::OnceCell;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::{
+    error::AppError,
+    model::signing_key::SigningKey,
+    service::{
+        db::{get_database_service, DatabaseDocument},
+        environment::{
+            secret::{decrypt_secret, encrypt_secret},
+            ENVIRONMENT,
+        },
+    },
+};
+
+/// Size in bits of generated RSA keys
+const RSA_KEY_SIZE: usize = 2048;
+
+static KEYRING: OnceCell<Keyring> = OnceCell::const_new();
+
+/// Returns the keyring creating the first key if the database has none
+pub async fn get_keyring() -> Result<&'static Keyring, AppError> {
+    KEYRING
+        .get_or_try_init(|| async {
+            rotate_if_needed().await?;
+            let keyring = Keyring {
+                keys: RwLock::new(Arc::new(KeySet::default())),
+            };
+            keyring.reload().await?;
+            Ok(keyring)
+        })
+        .await
+}
+
+/// In memory copy of the keys stored in the database
+pub struct Keyring {
+    keys: RwLock<Arc<KeySet>>,
+}
+
+/// Keys usable at a given time
+#[derive(Default)]
+pub struct KeySet {
+    /// key used to sign new tokens, missing only if the database has no valid key
+    pub signing: Option<SigningEntry>,
+    /// keys used to verify tokens by their identifier
+    pub verifying: HashMap<String, (Algorithm, DecodingKey)>,
+    /// public keys to publish
+    pub jwks: Vec<Jwk>,
+}
+
+/// Private key with its identifier
+pub struct SigningEntry {
+    pub kid: String,
+    pub algorithm: Algorithm,
+    pub key: EncodingKey,
+}
+
+impl Keyring {
+    /// Returns the keys currently in use
+    pub fn keys(&self) -> Arc<KeySet> {
+        self.keys.read().expect("Keyring lock is poisoned").clone()
+    }
+
+    /// Public keys in JWKS format
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: self.keys().jwks.clone(),
+        }
+    }
+
+    /// Read again the keys from the database
+    pub async fn reload(&self) -> Result<(), AppError> {
+        let db = &get_database_service().await.db();
+        let collection = db.collection::<SigningKey>(SigningKey::collection_name());
+        let filter = doc! { "$or": [
+            { "expires_at": null },
+            { "expires_at": { "$gt": DateTime::now() } },
+        ]};
+        let options = mongodb::options::FindOptions::builder()
+            .sort(doc! { "created_at": -1, "_id": -1 })
+            .build();
+        let documents: Vec<SigningKey> = collection
+            .find(filter, options)
+            .await?
+            .try_collect()
+            .await?;
+
+        let encryption_key = key_encryption_key()?;
+        let mut key_set = KeySet::default();
+        for document in documents {
+            let jwk: Jwk =
+                serde_json::from_str(&document.public_jwk).map_err(anyhow::Error::new)?;
+            let decoding = DecodingKey::from_jwk(&jwk).map_err(anyhow::Error::new)?;
+            if key_set.signing.is_none() && document.retired_at.is_none() {
+                let der = Base64::decode_vec(&decrypt_secret(
+                    encryption_key,
+                    &document.encrypted_private_key,
+                )?)
+                .map_err(|_| anyhow!("Private key of {} is not valid", document.kid))?;
+                let key = match document.algorithm {
+                    Algorithm::EdDSA => EncodingKey::from_ed_der(&der),
+                    _ => EncodingKey::from_rsa_der(&der),
+                };
+                key_set.signing = Some(SigningEntry {
+                    kid: document.kid.clone(),
+                    algorithm: document.algorithm,
+                    key,
+                });
+            }
+            key_set
+                .verifying
+                .insert(document.kid, (document.algorithm, decoding));
+            key_set.jwks.push(jwk);
+        }
+
+        *self.keys.write().expect("Keyring lock is poisoned") = Arc::new(key_set);
+        Ok(())
+    }
+}
+
+/// Generate a new key when there is no active key with the configured algorithm
+/// or the active one is older than the rotation interval, retiring the previous keys.
+///
+/// Instances rotating at the same time only retire the keys older than their
+/// own, hence the newest key stays active whatever the order of the updates.
+/// Expired keys are deleted. Returns true if a new key has been generated.
+pub async fn rotate_if_needed() -> Result<bool, AppError> {
+    let algorithm = ENVIRONMENT.authentication.algorithm;
+    // keys are only verified while migrating from an asymmetric algorithm
+    if !is_asymmetric(algorithm) {
+        return Ok(false);
+    }
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<SigningKey>(SigningKey::collection_name());
+
+    collection
+        .delete_many(doc! { "expires_at": { "$lte": DateTime::now() } }, None)
+        .await?;
+
+    let options = mongodb::options::FindOneOptions::builder()
+        .sort(doc! { "created_at": -1, "_id": -1 })
+        .build();
+    let active = collection
+        .find_one(doc! { "retired_at": null }, options)
+        .await?;
+    let rotation_interval = ENVIRONMENT.authentication.key_rotation_interval;
+    let needs_rotation = match &active {
+        Some(key) => {
+            key.algorithm != algorithm
+                || key.created_at.to_system_time() + rotation_interval
+                    <= std::time::SystemTime::now()
+        }
+        None => true,
+    };
+    if !needs_rotation {
+        return Ok(false);
+    }
+
+    let kid = Uuid::new_v4().to_string();
+    // RSA key generation is cpu bound and slow
+    let (private_der, public_jwk) = {
+        let kid = kid.clone();
+        tokio::task::spawn_blocking(move || generate_key(algorithm, &kid))
+            .await
+            .map_err(anyhow::Error::new)??
+    };
+    let new_key = SigningKey {
+        id: None,
+        kid,
+        algorithm,
+        encrypted_private_key: encrypt_secret(
+            key_encryption_key()?,
+            &Base64::encode_string(&private_der),
+        )?,
+        public_jwk: serde_json::to_string(&public_jwk).map_err(anyhow::Error::new)?,
+        created_at: DateTime::now(),
+        retired_at: None,
+        expires_at: None,
+    };
+    let new_id = ObjectId::parse_str(new_key.dump(db).await?).map_err(anyhow::Error::new)?;
+
+    let now = DateTime::now();
+    let expires_at = DateTime::from_system_time(
+        now.to_system_time() + ENVIRONMENT.authentication.key_grace_period,
+    );
+    // keys created in the same millisecond are ordered by id
+    collection
+        .update_many(
+            doc! {
+                "retired_at": null,
+                "$or": [
+                    { "created_at": { "$lt": new_key.created_at } },
+                    { "created_at": new_key.created_at, "_id": { "$lt": new_id } },
+                ],
+            },
+            doc! { "$set": { "retired_at": now, "expires_at": expires_at } },
+            None,
+        )
+        .await?;
+    info!("Generated new jwt signing key {}", new_key.kid);
+    Ok(true)
+}
+
+/// Spawn a background task that rotates keys when needed and reloads them,
+/// so that keys generated by other instances are picked up as well
+pub fn spawn_key_rotation() {
+    let uses_keys = ENVIRONMENT
+        .authentication
+        .accepted_algorithms
+        .iter()
+        .any(|algorithm| is_asymmetric(*algorithm));
+    if !uses_keys {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(ENVIRONMENT.authentication.key_refresh_interval);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = rotate_if_needed().await {
+                error!("Cannot rotate jwt signing keys: {e:?}");
+            }
+            match get_keyring().await {
+                Ok(keyring) => {
+                    if let Err(e) = keyring.reload().await {
+                        error!("Cannot reload jwt signing keys: {e:?}");
+                    }
+                }
+                Err(e) => error!("Cannot load jwt signing keys: {e:?}"),
+            }
+        }
+    });
+}
+
+/// Returns true if the algorithm uses a private and a public key
+pub fn is_asymmetric(algorithm: Algorithm) -> bool {
+    matches!(algorithm, Algorithm::RS256 | Algorithm::EdDSA)
+}
+
+/// Generate a private key in DER format and its public jwk
+fn generate_key(algorithm: Algorithm, kid: &str) -> Result<(Vec<u8>, Jwk), AppError> {
+    let common = CommonParameters {
+        public_key_use: Some(PublicKeyUse::Signature),
+        algorithm: Some(algorithm),
+        key_id: Some(kid.to_string()),
+        ..Default::default()
+    };
+    match algorithm {
+        Algorithm::EdDSA => {
+            let rng = ring::rand::SystemRandom::new();
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng)
+                .map_err(|_| anyhow!("Cannot generate Ed25519 key"))?;
+            let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref())
+                .map_err(|e| anyhow!("Generated Ed25519 key is not valid: {e}"))?;
+            let jwk = Jwk {
+                common,
+                algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: EllipticCurve::Ed25519,
+                    x: Base64UrlUnpadded::encode_string(key_pair.public_key().as_ref()),
+                }),
+            };
+            Ok((pkcs8.as_ref().to_vec(), jwk))
+        }
+        Algorithm::RS256 => {
+            let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), RSA_KEY_SIZE)
+                .context("Cannot generate RSA key")?;
+            let der = private_key
+                .to_pkcs1_der()
+                .context("Cannot encode RSA key")?
+                .as_bytes()
+                .to_vec();
+            let jwk = Jwk {
+                common,
+                algorithm: AlgorithmParameters::RSA(RSAKeyParameters {
+                    key_type: RSAKeyType::RSA,
+                    n: Base64UrlUnpadded::encode_string(&private_key.n().to_bytes_be()),
+                    e: Base64UrlUnpadded::encode_string(&private_key.e().to_bytes_be()),
+                }),
+            };
+            Ok((der, jwk))
+        }
+        _ => Err(AppError::InternalServerError(anyhow!(
+            "Algorithm {algorithm:?} is not asymmetric"
+        ))),
+    }
+}
+
+fn key_encryption_key() -> Result<&'static str, AppError> {
+    ENVIRONMENT
+        .authentication
+        .key_encryption_key
+        .as_deref()
+        .ok_or_else(|| {
+            AppError::InternalServerError(anyhow!("Key encryption key is not configured"))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+    use serde::{Deserialize, Serialize};
+
+    use super::generate_key;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Claims {
+        sub: String,
+        exp: usize,
+    }
+
+    #[test]
+    fn generated_keys_sign_and_verify_test() {
+        let claims = Claims {
+            sub: "user".into(),
+            exp: 2000000000,
+        };
+        for algorithm in [Algorithm::EdDSA, Algorithm::RS256] {
+            let (der, jwk) = generate_key(algorithm, "kid").unwrap();
+            assert_eq!(jwk.common.key_id.as_deref(), Some("kid"));
+            let encoding = match algorithm {
+                Algorithm::EdDSA => EncodingKey::from_ed_der(&der),
+                _ => EncodingKey::from_rsa_der(&der),
+            };
+            let mut header = Header::new(algorithm);
+            header.kid = Some("kid".into());
+            let token = encode(&header, &claims, &encoding).unwrap();
+
+            // the token is verified only with the published public key
+            let jwk = serde_json::from_str(&serde_json::to_string(&jwk).unwrap()).unwrap();
+            let decoding = DecodingKey::from_jwk(&jwk).unwrap();
+            let decoded = decode::<Claims>(&token, &decoding, &Validation::new(algorithm)).unwrap();
+            assert_eq!(decoded.claims, claims);
+        }
+        assert!(generate_key(Algorithm::HS256, "kid").is_err());
+    }
+}
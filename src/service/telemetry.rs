@@ -0,0 +1,288 @@
+//! Export of the spans to an OpenTelemetry collector.
+//!
+//! When `telemetry.enabled` is set the spans of the requests, of the facade calls,
+//! of the MongoDB commands and of the published messages are sent with OTLP over
+//! http. The W3C `traceparent` header of a request continues the trace of the
+//! caller and it is forwarded to the consumers of the queue messages.
+
+use std::{collections::HashMap, sync::Mutex};
+
+use amqprs::{FieldTable, FieldValue, LongStr, ShortStr};
+use axum::http::{HeaderMap, HeaderName};
+use mongodb::event::command::{
+    CommandEventHandler, CommandFailedEvent, CommandStartedEvent, CommandSucceededEvent,
+};
+use opentelemetry::{
+    propagation::{Extractor, Injector, TextMapPropagator},
+    trace::{TraceError, TracerProvider as _},
+    KeyValue,
+};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    runtime,
+    trace::{Sampler, TracerProvider},
+    Resource,
+};
+use tracing::{debug_span, error, field, Span, Subscriber};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{registry::LookupSpan, Layer};
+
+use crate::service::{
+    environment::{TelemetryVariables, ENVIRONMENT},
+    logging,
+};
+
+/// Commands whose outcome is never received, e.g. of cancelled operations,
+/// are forgotten beyond this number of running commands
+const MAX_RUNNING_COMMANDS: usize = 10_000;
+
+/// Exports the spans not sent yet when it is dropped
+pub struct TelemetryGuard(TracerProvider);
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        if let Err(e) = self.0.shutdown() {
+            error!("Cannot export the last spans: {e}");
+        }
+    }
+}
+
+/// Layer exporting the spans as configured by `telemetry`, `None` if it is disabled
+///
+/// The guard must be kept until the application exits.
+pub fn init_telemetry<S>() -> Option<(Box<dyn Layer<S> + Send + Sync>, TelemetryGuard)>
+where
+    S: Subscriber + for<'a> LookupSpan<'a> + Send + Sync,
+{
+    let telemetry = ENVIRONMENT.telemetry.as_ref()?;
+    let provider = tracer_provider(telemetry)
+        .unwrap_or_else(|e| panic!("Cannot export the spans to {}: {e}", telemetry.endpoint));
+    let layer = export_layer(&provider, &telemetry.directives);
+    Some((layer, TelemetryGuard(provider)))
+}
+
+/// Continue the trace of the caller if the request carries a valid `traceparent`
+pub fn set_remote_parent(span: &Span, headers: &HeaderMap) {
+    let context = TraceContextPropagator::new().extract(&HeaderExtractor(headers));
+    span.set_parent(context);
+}
+
+/// Add the `traceparent` of the current span to the headers of a queue message
+pub fn inject_context(headers: &mut FieldTable) {
+    let context = Span::current().context();
+    TraceContextPropagator::new().inject_context(&context, &mut FieldTableInjector(headers));
+}
+
+/// Observer of the commands sent by the MongoDB client, every command is a span
+/// child of the span sending it
+#[derive(Default)]
+pub struct MongoCommandSpans {
+    /// spans of the commands waiting for their outcome by request id
+    running: Mutex<HashMap<i32, Span>>,
+}
+
+impl MongoCommandSpans {
+    fn close(&self, request_id: i32) -> Option<Span> {
+        self.running
+            .lock()
+            .expect("Command spans lock is poisoned")
+            .remove(&request_id)
+    }
+}
+
+impl CommandEventHandler for MongoCommandSpans {
+    fn handle_command_started_event(&self, event: CommandStartedEvent) {
+        // the first field of the command names the collection, e.g. `{ find: "User" }`
+        let target = event
+            .command
+            .get_str(&event.command_name)
+            .unwrap_or(&event.db);
+        let span = debug_span!(
+            "mongodb",
+            otel.name = %format!("{} {target}", event.command_name),
+            otel.kind = "client",
+            otel.status_code = field::Empty,
+            otel.status_message = field::Empty,
+            db.system = "mongodb",
+            db.name = %event.db,
+            db.operation = %event.command_name,
+        );
+        let mut running = self.running.lock().expect("Command spans lock is poisoned");
+        if running.len() >= MAX_RUNNING_COMMANDS {
+            running.clear();
+        }
+        running.insert(event.request_id, span);
+    }
+
+    fn handle_command_succeeded_event(&self, event: CommandSucceededEvent) {
+        self.close(event.request_id);
+    }
+
+    fn handle_command_failed_event(&self, event: CommandFailedEvent) {
+        if let Some(span) = self.close(event.request_id) {
+            span.record("otel.status_code", "error");
+            span.record("otel.status_message", event.failure.to_string());
+        }
+    }
+}
+
+fn tracer_provider(telemetry: &TelemetryVariables) -> Result<TracerProvider, TraceError> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(&telemetry.endpoint)
+        .with_timeout(telemetry.timeout)
+        .build()?;
+    Ok(TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        // a caller sampling the trace decides for us
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            telemetry.sampling_ratio,
+        ))))
+        .with_resource(Resource::new([KeyValue::new(
+            "service.name",
+            telemetry.service_name.clone(),
+        )]))
+        .build())
+}
+
+fn export_layer<S>(
+    provider: &TracerProvider,
+    directives: &[String],
+) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a> + Send + Sync,
+{
+    let filter = logging::build_filter(directives).expect("Directives have been validated");
+    tracing_opentelemetry::layer()
+        .with_tracer(provider.tracer(env!("CARGO_PKG_NAME")))
+        .with_filter(filter)
+        .boxed()
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
+
+struct FieldTableInjector<'a>(&'a mut FieldTable);
+
+impl Injector for FieldTableInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        // propagated keys and values are short ascii strings
+        if let (Ok(key), Ok(value)) = (ShortStr::try_from(key), LongStr::try_from(value)) {
+            self.0.insert(key, FieldValue::S(value));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use amqprs::{FieldTable, FieldValue};
+    use axum::{
+        body::Bytes,
+        http::{HeaderMap, HeaderValue},
+        routing::post,
+        Router,
+    };
+    use opentelemetry_proto::tonic::{
+        collector::trace::v1::ExportTraceServiceRequest, common::v1::any_value::Value,
+        trace::v1::span::SpanKind,
+    };
+    use prost::Message;
+    use tokio::{net::TcpListener, sync::mpsc};
+    use tracing::debug_span;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use crate::service::environment::TelemetryVariables;
+
+    use super::{export_layer, inject_context, set_remote_parent, tracer_provider};
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+    const PARENT_ID: &str = "00f067aa0ba902b7";
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn export_test() {
+        // collector receiving the spans with OTLP over http
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let collector = Router::new().route(
+            "/v1/traces",
+            post(move |body: Bytes| async move {
+                sender
+                    .send(ExportTraceServiceRequest::decode(body).unwrap())
+                    .unwrap();
+            }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, collector).await.unwrap() });
+
+        // new traces are never sampled, the traces of the callers are
+        let telemetry = TelemetryVariables {
+            endpoint: format!("http://{address}/v1/traces"),
+            service_name: "testing".into(),
+            sampling_ratio: 0.0,
+            directives: vec!["trace".into()],
+            timeout: Duration::from_secs(5),
+        };
+        let provider = tracer_provider(&telemetry).unwrap();
+        let subscriber =
+            tracing_subscriber::registry().with(export_layer(&provider, &telemetry.directives));
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "traceparent",
+            HeaderValue::from_str(&format!("00-{TRACE_ID}-{PARENT_ID}-01")).unwrap(),
+        );
+        let mut message_headers = FieldTable::new();
+        tracing::subscriber::with_default(subscriber, || {
+            let span = debug_span!("request", otel.name = "GET /item/:id", otel.kind = "server");
+            set_remote_parent(&span, &headers);
+            span.in_scope(|| inject_context(&mut message_headers));
+            debug_span!("request", otel.name = "GET /").in_scope(|| {});
+        });
+        tokio::task::spawn_blocking(move || provider.force_flush())
+            .await
+            .unwrap();
+
+        let request = tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let resource_spans = &request.resource_spans[0];
+        let service_name = resource_spans
+            .resource
+            .as_ref()
+            .unwrap()
+            .attributes
+            .iter()
+            .find(|attribute| attribute.key == "service.name")
+            .and_then(|attribute| attribute.value.as_ref()?.value.clone());
+        assert_eq!(service_name, Some(Value::StringValue("testing".into())));
+        let spans: Vec<_> = resource_spans
+            .scope_spans
+            .iter()
+            .flat_map(|scope| &scope.spans)
+            .collect();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].name, "GET /item/:id");
+        assert_eq!(spans[0].kind, SpanKind::Server as i32);
+        assert_eq!(hex::encode(&spans[0].trace_id), TRACE_ID);
+        assert_eq!(hex::encode(&spans[0].parent_span_id), PARENT_ID);
+
+        // consumers of the messages continue the trace from the exported span
+        let traceparent = message_headers
+            .get(&"traceparent".try_into().unwrap())
+            .unwrap();
+        let expected = format!("00-{TRACE_ID}-{}-01", hex::encode(&spans[0].span_id));
+        assert!(matches!(traceparent, FieldValue::S(value) if *value.as_ref() == expected));
+    }
+}
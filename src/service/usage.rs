@@ -0,0 +1,354 @@
+//! Usage metering of the sdk consumers.
+//!
+//! Every authenticated sdk request is counted per owner, api key and route with
+//! its status and latency. Counts are aggregated in memory and written every
+//! `usage.flush_interval_s` into hourly buckets, kept `usage.retention_days`.
+//!
+//! Owners whose plan has a monthly quota are refused once it is exhausted. The
+//! quota is checked against the written buckets and the counts of the instance
+//! not written yet, hence, requests of other instances are seen within a flush.
+
+use std::{collections::HashMap, mem, sync::Mutex, time::Duration};
+
+use anyhow::anyhow;
+use axum::http::StatusCode;
+use futures::TryStreamExt;
+use mongodb::{
+    bson::{doc, from_document, to_bson, DateTime},
+    options::{IndexOptions, UpdateOptions},
+    IndexModel,
+};
+use once_cell::sync::Lazy;
+use tracing::error;
+
+use crate::{
+    enums::ActorKind,
+    error::AppError,
+    model::usage_bucket::{UsageBucket, UsageCounts, UsageTotals},
+    service::{
+        db::{get_database_service, DatabaseDocument},
+        environment::ENVIRONMENT,
+    },
+    UserId,
+};
+
+const HOUR_MS: i64 = 3_600_000;
+
+/// Counts not written yet by bucket
+static PENDING: Lazy<Mutex<HashMap<BucketKey, UsageCounts>>> = Lazy::new(Default::default);
+
+/// Written requests of the current month by owner, they are read again after every flush
+static MONTHLY: Lazy<Mutex<HashMap<UserId, (DateTime, u64)>>> = Lazy::new(Default::default);
+
+/// Principal sending sdk requests
+#[derive(Debug, Clone)]
+pub struct UsageOwner {
+    /// identifier of the user or of the service account
+    pub id: UserId,
+    pub kind: ActorKind,
+    /// displayed prefix of the api key, `None` with tokens
+    pub api_key: Option<String>,
+    /// rate limit plan, it defines the monthly quota
+    pub plan: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct BucketKey {
+    owner: UserId,
+    owner_kind: ActorKind,
+    api_key: Option<String>,
+    route: String,
+    /// start of the hour in milliseconds
+    hour: i64,
+}
+
+/// Count a request of the owner on the route, e.g. `GET /sdk/v0/user/:id`
+pub fn record(owner: &UsageOwner, route: &str, status: StatusCode, latency: Duration) {
+    let key = BucketKey {
+        owner: owner.id,
+        owner_kind: owner.kind,
+        api_key: owner.api_key.clone(),
+        route: route.to_string(),
+        hour: hour_start(DateTime::now()).timestamp_millis(),
+    };
+    let latency = latency.as_millis() as i64;
+    let request = UsageCounts {
+        requests: 1,
+        client_errors: status.is_client_error() as i64,
+        server_errors: status.is_server_error() as i64,
+        latency_total_ms: latency,
+        latency_max_ms: latency,
+    };
+    let mut pending = PENDING.lock().expect("Usage counts lock is poisoned");
+    add(pending.entry(key).or_default(), &request);
+}
+
+/// Refuse the request if the owner has exhausted the monthly quota of its plan
+pub async fn check_quota(owner: &UsageOwner) -> Result<(), AppError> {
+    let Some(quota) = monthly_quota(owner.plan.as_deref()) else {
+        return Ok(());
+    };
+    if month_requests(&owner.id).await? >= quota {
+        let resets_at = next_month_start(month_start(DateTime::now()));
+        return Err(AppError::QuotaExceeded { quota, resets_at });
+    }
+    Ok(())
+}
+
+/// Requests allowed per month by the plan, `None` if they are unlimited
+pub fn monthly_quota(plan: Option<&str>) -> Option<u64> {
+    let plan = ENVIRONMENT.rate_limit.plan_name(plan);
+    ENVIRONMENT.usage.monthly_quotas.get(plan).copied()
+}
+
+/// Requests of the owner in the current month, the counts not written yet included
+pub async fn month_requests(owner: &UserId) -> Result<u64, AppError> {
+    let month = month_start(DateTime::now());
+    let cached = MONTHLY
+        .lock()
+        .expect("Monthly usage lock is poisoned")
+        .get(owner)
+        .filter(|(cached_month, _)| *cached_month == month)
+        .map(|(_, requests)| *requests);
+    let written = match cached {
+        Some(requests) => requests,
+        None => {
+            let requests = count_requests(owner, month).await?;
+            MONTHLY
+                .lock()
+                .expect("Monthly usage lock is poisoned")
+                .insert(*owner, (month, requests));
+            requests
+        }
+    };
+    let pending: i64 = PENDING
+        .lock()
+        .expect("Usage counts lock is poisoned")
+        .iter()
+        .filter(|(key, _)| key.owner == *owner && key.hour >= month.timestamp_millis())
+        .map(|(_, counts)| counts.requests)
+        .sum();
+    Ok(written + pending as u64)
+}
+
+/// Returns the counts by owner, api key and route of the hours in the range
+///
+/// Counts not written yet are not included
+pub async fn totals(
+    owner: Option<&UserId>,
+    from: DateTime,
+    to: DateTime,
+) -> Result<Vec<UsageTotals>, AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<UsageBucket>(UsageBucket::collection_name());
+    let mut filter = doc! { "hour": { "$gte": hour_start(from), "$lt": to } };
+    if let Some(owner) = owner {
+        filter.insert("owner", owner);
+    }
+    let pipeline = [
+        doc! { "$match": filter },
+        doc! { "$group": {
+            "_id": {
+                "owner": "$owner",
+                "owner_kind": "$owner_kind",
+                "api_key": "$api_key",
+                "route": "$route",
+            },
+            "requests": { "$sum": "$requests" },
+            "client_errors": { "$sum": "$client_errors" },
+            "server_errors": { "$sum": "$server_errors" },
+            "latency_total_ms": { "$sum": "$latency_total_ms" },
+            "latency_max_ms": { "$max": "$latency_max_ms" },
+        } },
+        doc! { "$replaceWith": { "$mergeObjects": ["$_id", "$$ROOT"] } },
+        doc! { "$unset": "_id" },
+        doc! { "$sort": { "owner": 1, "route": 1, "api_key": 1 } },
+    ];
+    let documents: Vec<_> = collection
+        .aggregate(pipeline, None)
+        .await?
+        .try_collect()
+        .await?;
+    Ok(documents
+        .into_iter()
+        .map(from_document)
+        .collect::<Result<_, _>>()
+        .map_err(anyhow::Error::new)?)
+}
+
+/// Time range of a report from unix timestamps in milliseconds
+///
+/// The range defaults to the current month.
+pub fn report_range(from: Option<i64>, to: Option<i64>) -> Result<(DateTime, DateTime), AppError> {
+    let from = from.map_or_else(|| month_start(DateTime::now()), DateTime::from_millis);
+    let to = to.map_or_else(DateTime::now, DateTime::from_millis);
+    if from >= to {
+        return Err(AppError::InvalidRequest(anyhow!(
+            "Beginning of the range must be before its end"
+        )));
+    }
+    Ok((from, to))
+}
+
+/// Spawn a background task writing the counts every `usage.flush_interval_s`
+pub fn spawn_usage_flush() {
+    tokio::spawn(async {
+        let mut ticker = tokio::time::interval(ENVIRONMENT.usage.flush_interval);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = flush().await {
+                error!("Cannot write usage counts: {e:?}");
+            }
+        }
+    });
+}
+
+/// Create the indexes of the usage buckets
+///
+/// Buckets are deleted after the retention time
+pub async fn create_indexes() -> Result<(), AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<UsageBucket>(UsageBucket::collection_name());
+    let bucket = IndexModel::builder()
+        .keys(doc! { "owner": 1, "hour": 1, "route": 1, "api_key": 1 })
+        .options(IndexOptions::builder().unique(true).build())
+        .build();
+    let retention = IndexModel::builder()
+        .keys(doc! { "hour": 1 })
+        .options(
+            IndexOptions::builder()
+                .expire_after(ENVIRONMENT.usage.retention)
+                .build(),
+        )
+        .build();
+    collection.create_indexes([bucket, retention], None).await?;
+    Ok(())
+}
+
+/// Add the pending counts to their buckets, the ones not written are kept for
+/// the next flush
+async fn flush() -> Result<(), AppError> {
+    let pending = mem::take(&mut *PENDING.lock().expect("Usage counts lock is poisoned"));
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<UsageBucket>(UsageBucket::collection_name());
+    let options = UpdateOptions::builder().upsert(true).build();
+    let mut failed = Vec::new();
+    let mut last_error = None;
+    for (key, counts) in pending {
+        let owner_kind = to_bson(&key.owner_kind).map_err(anyhow::Error::new)?;
+        let result = collection
+            .update_one(
+                doc! {
+                    "owner": key.owner,
+                    "owner_kind": owner_kind,
+                    "api_key": &key.api_key,
+                    "route": &key.route,
+                    "hour": DateTime::from_millis(key.hour),
+                },
+                doc! {
+                    "$inc": {
+                        "requests": counts.requests,
+                        "client_errors": counts.client_errors,
+                        "server_errors": counts.server_errors,
+                        "latency_total_ms": counts.latency_total_ms,
+                    },
+                    "$max": { "latency_max_ms": counts.latency_max_ms },
+                },
+                options.clone(),
+            )
+            .await;
+        if let Err(e) = result {
+            failed.push((key, counts));
+            last_error = Some(e);
+        }
+    }
+    if !failed.is_empty() {
+        let mut pending = PENDING.lock().expect("Usage counts lock is poisoned");
+        for (key, counts) in failed {
+            add(pending.entry(key).or_default(), &counts);
+        }
+    }
+    // monthly counts are read again with the new counts of every instance
+    MONTHLY
+        .lock()
+        .expect("Monthly usage lock is poisoned")
+        .clear();
+    match last_error {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
+}
+
+async fn count_requests(owner: &UserId, month: DateTime) -> Result<u64, AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<UsageBucket>(UsageBucket::collection_name());
+    let pipeline = [
+        doc! { "$match": { "owner": owner, "hour": { "$gte": month } } },
+        doc! { "$group": { "_id": null, "requests": { "$sum": "$requests" } } },
+    ];
+    let total = collection
+        .aggregate(pipeline, None)
+        .await?
+        .try_next()
+        .await?;
+    let requests = total
+        .and_then(|total| total.get("requests").and_then(|value| value.as_i64()))
+        .unwrap_or_default();
+    Ok(requests.max(0) as u64)
+}
+
+fn add(counts: &mut UsageCounts, other: &UsageCounts) {
+    counts.requests += other.requests;
+    counts.client_errors += other.client_errors;
+    counts.server_errors += other.server_errors;
+    counts.latency_total_ms += other.latency_total_ms;
+    counts.latency_max_ms = counts.latency_max_ms.max(other.latency_max_ms);
+}
+
+fn hour_start(time: DateTime) -> DateTime {
+    let millis = time.timestamp_millis();
+    DateTime::from_millis(millis - millis.rem_euclid(HOUR_MS))
+}
+
+/// First instant of the calendar month of the time, UTC
+fn month_start(time: DateTime) -> DateTime {
+    let rfc3339 = time
+        .try_to_rfc3339_string()
+        .expect("Times of the requests are representable");
+    DateTime::parse_rfc3339_str(format!("{}-01T00:00:00Z", &rfc3339[..7]))
+        .expect("First day of the month is a valid time")
+}
+
+fn next_month_start(month_start: DateTime) -> DateTime {
+    let later = month_start.to_system_time() + Duration::from_secs(32 * 86400);
+    self::month_start(DateTime::from_system_time(later))
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::DateTime;
+
+    use super::{hour_start, month_start, next_month_start};
+
+    #[test]
+    fn time_buckets_test() {
+        let time = DateTime::parse_rfc3339_str("2026-12-19T08:54:24.123Z").unwrap();
+        let hour = DateTime::parse_rfc3339_str("2026-12-19T08:00:00Z").unwrap();
+        assert_eq!(hour_start(time), hour);
+
+        let month = month_start(time);
+        assert_eq!(
+            month,
+            DateTime::parse_rfc3339_str("2026-12-01T00:00:00Z").unwrap()
+        );
+        assert_eq!(
+            next_month_start(month),
+            DateTime::parse_rfc3339_str("2027-01-01T00:00:00Z").unwrap()
+        );
+        let february = DateTime::parse_rfc3339_str("2028-02-29T23:59:59Z").unwrap();
+        assert_eq!(
+            next_month_start(month_start(february)),
+            DateTime::parse_rfc3339_str("2028-03-01T00:00:00Z").unwrap()
+        );
+    }
+}
@@ -1,18 +1,26 @@
 use anyhow::anyhow;
-use mongodb::bson::doc;
+use mongodb::{
+    bson::{doc, DateTime},
+    options::IndexOptions,
+    IndexModel,
+};
+use serde_json::json;
 
 use crate::{
-    enums::Role,
+    enums::{Role, WebhookEvent},
     error::{AppError, AuthError},
     model::user,
     UserId,
 };
 
-use super::db::{get_database_service, DatabaseDocument};
+use super::{
+    db::{get_database_service, is_duplicate_key, DatabaseDocument},
+    rate_limit, webhook,
+};
 use base64ct::{Base64, Encoding};
 
 pub async fn login(username: &str, password: &str) -> Result<user::User, AppError> {
-    let db = &get_database_service().await.db;
+    let db = &get_database_service().await.db();
     let collection = db.collection::<user::User>(user::User::collection_name());
     let hashed_password = hash_password(password);
     let filter = doc! {
@@ -28,7 +36,7 @@ pub async fn login(username: &str, password: &str) -> Result<user::User, AppErro
 }
 
 pub async fn get_user(user_id: &UserId) -> Result<user::User, AppError> {
-    let db = &get_database_service().await.db;
+    let db = &get_database_service().await.db();
     let collection = db.collection::<user::User>(user::User::collection_name());
     let filter = doc! { "_id": user_id };
     let query_result = collection.find_one(filter, None).await?;
@@ -42,20 +50,250 @@ pub async fn get_user(user_id: &UserId) -> Result<user::User, AppError> {
 }
 
 /// Create new user in database and returns it identifier
+///
+/// The email, if any, must be lowercase and it is not verified
 pub async fn create_user(
     username: String,
     password: String,
+    email: Option<String>,
     role: Role,
 ) -> Result<String, AppError> {
+    if let Some(email) = &email {
+        if find_by_email(email).await?.is_some() {
+            return Err(email_in_use());
+        }
+    }
     let user_model = user::User {
         id: None,
         username,
         password_hash: hash_password(&password),
         api_key: None,
         role,
+        email,
+        email_verified: false,
+        oidc_identities: Vec::new(),
+        mfa: Default::default(),
+        tokens_valid_after: None,
+        plan: None,
     };
+    insert_user(&user_model).await
+}
+
+/// Create the indexes of the user collection
+///
+/// Emails are unique among the users having one
+pub async fn create_indexes() -> Result<(), AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<user::User>(user::User::collection_name());
+    let options = IndexOptions::builder()
+        .unique(true)
+        .partial_filter_expression(doc! { "email": { "$type": "string" } })
+        .build();
+    let index = IndexModel::builder()
+        .keys(doc! { "email": 1 })
+        .options(options)
+        .build();
+    collection.create_index(index, None).await?;
+    Ok(())
+}
+
+/// Returns the user linked to the identity of the OpenID Connect provider
+pub async fn find_by_oidc_identity(
+    identity: &user::OidcIdentity,
+) -> Result<Option<user::User>, AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<user::User>(user::User::collection_name());
+    let filter = doc! {
+        "oidc_identities": {
+            "$elemMatch": { "issuer": &identity.issuer, "subject": &identity.subject }
+        }
+    };
+    Ok(collection.find_one(filter, None).await?)
+}
+
+/// Returns the user with the email, the comparison ignores the case
+pub async fn find_by_email(email: &str) -> Result<Option<user::User>, AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<user::User>(user::User::collection_name());
+    let filter = doc! { "email": email.to_lowercase() };
+    Ok(collection.find_one(filter, None).await?)
+}
+
+/// Returns true if a user with the username exists
+pub async fn username_exists(username: &str) -> Result<bool, AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<user::User>(user::User::collection_name());
+    let count = collection
+        .count_documents(doc! { "username": username }, None)
+        .await?;
+    Ok(count > 0)
+}
+
+/// Link the identity of the OpenID Connect provider to an existing user
+pub async fn link_oidc_identity(
+    user_id: &UserId,
+    identity: user::OidcIdentity,
+) -> Result<(), AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<user::User>(user::User::collection_name());
+    let update = doc! {
+        "$push": {
+            "oidc_identities": { "issuer": identity.issuer, "subject": identity.subject }
+        }
+    };
+    collection
+        .update_one(doc! { "_id": user_id }, update, None)
+        .await?;
+    Ok(())
+}
+
+/// Create a user logged in with an OpenID Connect provider
+///
+/// The user has a random password, hence it can log in only through the provider
+pub async fn create_oidc_user(
+    username: String,
+    email: Option<String>,
+    identity: user::OidcIdentity,
+    role: Role,
+) -> Result<String, AppError> {
+    let password = hex::encode(rand::random::<[u8; 32]>());
+    let user_model = user::User {
+        id: None,
+        username,
+        password_hash: hash_password(&password),
+        api_key: None,
+        role,
+        // the provider has verified the email
+        email_verified: email.is_some(),
+        email: email.map(|email| email.to_lowercase()),
+        oidc_identities: vec![identity],
+        mfa: Default::default(),
+        tokens_valid_after: None,
+        plan: None,
+    };
+    insert_user(&user_model).await
+}
+
+/// Replace the email of the user, the new email is not verified
+///
+/// The email must be lowercase and not used by another user
+pub async fn set_email(user_id: &UserId, email: &str) -> Result<(), AppError> {
+    if let Some(other) = find_by_email(email).await? {
+        if other.id.as_ref() != Some(user_id) {
+            return Err(email_in_use());
+        }
+    }
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<user::User>(user::User::collection_name());
+    let update = doc! { "$set": { "email": email, "email_verified": false } };
+    let result = collection
+        .update_one(doc! { "_id": user_id }, update, None)
+        .await
+        .map_err(|e| match is_duplicate_key(&e) {
+            true => email_in_use(),
+            false => e.into(),
+        })?;
+    if result.matched_count == 0 {
+        return Err(AppError::DoesNotExist(anyhow!(
+            "User with id {user_id} does not exist"
+        )));
+    }
+    Ok(())
+}
+
+/// Replace the rate limit plan of the user returning the previous one,
+/// without a plan the user gets the default one
+pub async fn set_plan(user_id: &UserId, plan: Option<&str>) -> Result<Option<String>, AppError> {
+    if let Some(plan) = plan {
+        rate_limit::check_plan(plan)?;
+    }
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<user::User>(user::User::collection_name());
+    let previous = collection
+        .find_one_and_update(
+            doc! { "_id": user_id },
+            doc! { "$set": { "plan": plan } },
+            None,
+        )
+        .await?
+        .ok_or_else(|| AppError::DoesNotExist(anyhow!("User with id {user_id} does not exist")))?;
+    Ok(previous.plan)
+}
+
+/// Mark the email of the user as verified, returns false if the user has another email
+pub async fn mark_email_verified(user_id: &UserId, email: &str) -> Result<bool, AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<user::User>(user::User::collection_name());
+    let result = collection
+        .update_one(
+            doc! { "_id": user_id, "email": email },
+            doc! { "$set": { "email_verified": true } },
+            None,
+        )
+        .await?;
+    Ok(result.matched_count == 1)
+}
+
+async fn insert_user(user_model: &user::User) -> Result<String, AppError> {
     let db_service = get_database_service().await;
-    user_model.dump(&db_service.db).await
+    let id = user_model
+        .dump(&db_service.db())
+        .await
+        .map_err(|e| match e {
+            AppError::InternalServerError(error)
+                if error
+                    .downcast_ref::<mongodb::error::Error>()
+                    .is_some_and(is_duplicate_key) =>
+            {
+                email_in_use()
+            }
+            e => e,
+        })?;
+    webhook::publish_event(
+        WebhookEvent::UserCreated,
+        json!({
+            "id": id,
+            "username": user_model.username,
+            "role": user_model.role,
+        }),
+    );
+    Ok(id)
+}
+
+/// Returns the user if the password is its current one
+pub async fn verify_password(user_id: &UserId, password: &str) -> Result<user::User, AppError> {
+    let user_model = get_user(user_id).await?;
+    if user_model.password_hash == hash_password(password) {
+        Ok(user_model)
+    } else {
+        Err(AuthError::WrongCredentials)?
+    }
+}
+
+/// Replace the password of the user revoking every token issued before
+///
+/// The revocation time is truncated to seconds like the `iat` claim, hence,
+/// tokens issued right after the change are valid
+pub async fn set_password(user_id: &UserId, password: &str) -> Result<(), AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<user::User>(user::User::collection_name());
+    let now = DateTime::from_millis(DateTime::now().timestamp_millis() / 1000 * 1000);
+    let update = doc! {
+        "$set": { "password_hash": hash_password(password), "tokens_valid_after": now }
+    };
+    let result = collection
+        .update_one(doc! { "_id": user_id }, update, None)
+        .await?;
+    if result.matched_count == 0 {
+        return Err(AppError::DoesNotExist(anyhow!(
+            "User with id {user_id} does not exist"
+        )));
+    }
+    Ok(())
+}
+
+fn email_in_use() -> AppError {
+    AppError::InvalidRequest(anyhow!("Email is already used by another user"))
 }
 
 fn hash_password(password: &str) -> String {
@@ -81,9 +319,9 @@ mod tests {
         let password = "Smith".into();
         let role = Role::Admin;
 
-        let created_user_result = create_user(username, password, role).await;
+        let created_user_result = create_user(username, password, None, role).await;
         assert!(created_user_result.is_ok());
-        let drop_result = get_database_service().await.db.drop(None).await;
+        let drop_result = get_database_service().await.db().drop(None).await;
         assert!(drop_result.is_ok())
     }
 
@@ -104,8 +342,14 @@ mod tests {
             password_hash: hash_password(password),
             api_key: None,
             role,
+            email: None,
+            email_verified: false,
+            oidc_identities: Vec::new(),
+            mfa: Default::default(),
+            tokens_valid_after: None,
+            plan: None,
         }
-        .dump(&get_database_service().await.db)
+        .dump(&get_database_service().await.db())
         .await;
         assert!(user_id_result.is_ok());
 
@@ -115,7 +359,7 @@ mod tests {
         let user = result.unwrap();
         assert_eq!(username, user.username);
         assert_eq!(role, user.role);
-        let drop_result = get_database_service().await.db.drop(None).await;
+        let drop_result = get_database_service().await.db().drop(None).await;
         assert!(drop_result.is_ok());
     }
 }
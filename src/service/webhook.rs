@@ -0,0 +1,641 @@
+//! Webhook service manages subscriptions and delivers events to them.
+//!
+//! Every delivery is a POST request whose body is a json envelope containing the event.
+//! The request carries a timestamp header and a signature header computed as
+//! HMAC-SHA256 of `"{timestamp}.{body}"` with the subscription secret, so that the
+//! receiver can verify both the origin and the freshness of the message.
+//!
+//! Failed deliveries are retried with exponential backoff and each attempt is stored.
+//! When a subscription fails too many deliveries in a row it is disabled.
+//!
+//! Global events, e.g. the creation of any user, are only delivered to the
+//! subscriptions of admins, as only admins can read what they carry.
+//!
+//! Urls must reach public addresses: hosts resolving to loopback, private or
+//! link-local addresses are refused when subscribing and at every delivery, and
+//! redirects are not followed.
+
+use std::{
+    collections::HashSet,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::anyhow;
+use futures::TryStreamExt;
+use hmac::{Hmac, Mac};
+use mongodb::bson::{doc, to_bson, DateTime};
+use once_cell::sync::Lazy;
+use rand::RngCore;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use serde::Serialize;
+use sha2::Sha256;
+use tracing::{debug, error, warn};
+use uuid::Uuid;
+
+use crate::{
+    enums::{Role, WebhookEvent},
+    error::AppError,
+    model::{
+        service_account::ServiceAccount,
+        user::User,
+        webhook::{WebhookDelivery, WebhookSubscription},
+    },
+    service::{
+        db::{get_database_service, DatabaseDocument},
+        environment::ENVIRONMENT,
+        request_id,
+    },
+    UserId, WebhookId,
+};
+
+/// Header containing the signature of the request body
+pub const SIGNATURE_HEADER: &str = "x-webhook-signature";
+/// Header containing the unix timestamp used to compute the signature
+pub const TIMESTAMP_HEADER: &str = "x-webhook-timestamp";
+/// Header containing the event name
+pub const EVENT_HEADER: &str = "x-webhook-event";
+/// Header containing the event identifier, equal among retries
+pub const DELIVERY_HEADER: &str = "x-webhook-delivery";
+
+/// Longest wait between two attempts of a delivery
+const MAX_BACKOFF: Duration = Duration::from_secs(3600);
+
+type HmacSha256 = Hmac<Sha256>;
+
+static HTTP_CLIENT: Lazy<reqwest::Client> = Lazy::new(|| {
+    reqwest::Client::builder()
+        .timeout(ENVIRONMENT.webhook.request_timeout)
+        .redirect(reqwest::redirect::Policy::none())
+        .dns_resolver(Arc::new(PublicResolver))
+        .build()
+        .expect("Error in webhook http client initialization")
+});
+
+/// Resolver of the webhook hosts refusing the names with non public addresses,
+/// connections use the checked addresses so that a rebinding cannot bypass it
+struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addresses = public_addresses(&host, 0).await?;
+            Ok(Box::new(addresses.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Body sent to webhook subscriptions
+#[derive(Serialize)]
+struct WebhookEnvelope<'a> {
+    id: &'a str,
+    event: WebhookEvent,
+    created_at: u64,
+    data: &'a serde_json::Value,
+}
+
+/// Create new subscription for the user generating its signing secret
+pub async fn create_subscription(
+    owner_id: &UserId,
+    url: String,
+    events: Vec<WebhookEvent>,
+) -> Result<WebhookSubscription, AppError> {
+    validate_subscription(&url, &events)?;
+    check_destination(&url)
+        .await
+        .map_err(AppError::InvalidRequest)?;
+    let mut subscription = WebhookSubscription {
+        id: None,
+        owner_id: *owner_id,
+        url,
+        events,
+        secret: generate_secret(),
+        enabled: true,
+        consecutive_failures: 0,
+        created_at: DateTime::now(),
+    };
+    let db_service = get_database_service().await;
+    let id = subscription.dump(&db_service.db()).await?;
+    subscription.id = Some(WebhookId::parse_str(id).map_err(anyhow::Error::new)?);
+    Ok(subscription)
+}
+
+/// Return all the subscriptions owned by the user
+pub async fn list_subscriptions(owner_id: &UserId) -> Result<Vec<WebhookSubscription>, AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<WebhookSubscription>(WebhookSubscription::collection_name());
+    let cursor = collection.find(doc! { "owner_id": owner_id }, None).await?;
+    Ok(cursor.try_collect().await?)
+}
+
+/// Return the subscription if it exists and it is owned by the user
+pub async fn get_subscription(
+    owner_id: &UserId,
+    subscription_id: &WebhookId,
+) -> Result<WebhookSubscription, AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<WebhookSubscription>(WebhookSubscription::collection_name());
+    let filter = doc! { "_id": subscription_id, "owner_id": owner_id };
+    let query_result = collection.find_one(filter, None).await?;
+    if let Some(subscription) = query_result {
+        Ok(subscription)
+    } else {
+        Err(AppError::DoesNotExist(anyhow!(
+            "Webhook with id {subscription_id} does not exist"
+        )))
+    }
+}
+
+/// Update url, events and enabled flag of the subscription.
+///
+/// Re-enabling a subscription resets its consecutive failures.
+pub async fn update_subscription(
+    owner_id: &UserId,
+    subscription_id: &WebhookId,
+    url: Option<String>,
+    events: Option<Vec<WebhookEvent>>,
+    enabled: Option<bool>,
+) -> Result<WebhookSubscription, AppError> {
+    let mut subscription = get_subscription(owner_id, subscription_id).await?;
+    if let Some(url) = url {
+        subscription.url = url;
+    }
+    if let Some(events) = events {
+        subscription.events = events;
+    }
+    if let Some(enabled) = enabled {
+        if enabled && !subscription.enabled {
+            subscription.consecutive_failures = 0;
+        }
+        subscription.enabled = enabled;
+    }
+    validate_subscription(&subscription.url, &subscription.events)?;
+    check_destination(&subscription.url)
+        .await
+        .map_err(AppError::InvalidRequest)?;
+
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<WebhookSubscription>(WebhookSubscription::collection_name());
+    collection
+        .update_one(
+            doc! { "_id": subscription_id },
+            doc! { "$set": {
+                "url": &subscription.url,
+                "events": to_bson(&subscription.events).map_err(anyhow::Error::new)?,
+                "enabled": subscription.enabled,
+                "consecutive_failures": subscription.consecutive_failures,
+            }},
+            None,
+        )
+        .await?;
+    Ok(subscription)
+}
+
+/// Delete the subscription and its deliveries
+pub async fn delete_subscription(
+    owner_id: &UserId,
+    subscription_id: &WebhookId,
+) -> Result<(), AppError> {
+    get_subscription(owner_id, subscription_id).await?;
+    let db = &get_database_service().await.db();
+    db.collection::<WebhookSubscription>(WebhookSubscription::collection_name())
+        .delete_one(doc! { "_id": subscription_id }, None)
+        .await?;
+    db.collection::<WebhookDelivery>(WebhookDelivery::collection_name())
+        .delete_many(doc! { "subscription_id": subscription_id }, None)
+        .await?;
+    Ok(())
+}
+
+/// Return the delivery attempts of the subscription, most recent first
+pub async fn list_deliveries(
+    owner_id: &UserId,
+    subscription_id: &WebhookId,
+) -> Result<Vec<WebhookDelivery>, AppError> {
+    get_subscription(owner_id, subscription_id).await?;
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<WebhookDelivery>(WebhookDelivery::collection_name());
+    let options = mongodb::options::FindOptions::builder()
+        .sort(doc! { "created_at": -1 })
+        .build();
+    let cursor = collection
+        .find(doc! { "subscription_id": subscription_id }, options)
+        .await?;
+    Ok(cursor.try_collect().await?)
+}
+
+/// Send the event to every enabled subscription listening to it.
+///
+/// Deliveries happen in background tasks, therefore, this function returns
+/// immediately and errors are only logged. They carry the id of the request
+/// publishing the event.
+pub fn publish_event(event: WebhookEvent, data: serde_json::Value) {
+    request_id::spawn(async move {
+        let subscriptions = match find_subscribers(event).await {
+            Ok(subscriptions) => subscriptions,
+            Err(e) => {
+                error!("Cannot retrieve webhook subscriptions for {event:?}: {e:?}");
+                return;
+            }
+        };
+        let event_id = Uuid::new_v4().to_string();
+        let envelope = WebhookEnvelope {
+            id: &event_id,
+            event,
+            created_at: unix_timestamp(),
+            data: &data,
+        };
+        let body = match serde_json::to_string(&envelope) {
+            Ok(body) => body,
+            Err(e) => {
+                error!("Cannot serialize webhook event {event:?}: {e}");
+                return;
+            }
+        };
+        for subscription in subscriptions {
+            let event_id = event_id.clone();
+            let body = body.clone();
+            request_id::spawn(async move {
+                if let Err(e) = deliver(subscription, event, event_id, body).await {
+                    error!("Webhook delivery failed with error {e:?}");
+                }
+            });
+        }
+    });
+}
+
+/// True for the events concerning every user, only admins may subscribe to them
+pub fn is_global(event: WebhookEvent) -> bool {
+    match event {
+        WebhookEvent::UserCreated => true,
+    }
+}
+
+/// Compute the signature header value for the body sent at the given timestamp
+pub fn sign_payload(secret: &str, timestamp: u64, body: &str) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+async fn find_subscribers(event: WebhookEvent) -> Result<Vec<WebhookSubscription>, AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<WebhookSubscription>(WebhookSubscription::collection_name());
+    let filter = doc! {
+        "enabled": true,
+        "events": to_bson(&event).map_err(anyhow::Error::new)?,
+    };
+    let cursor = collection.find(filter, None).await?;
+    let subscriptions: Vec<WebhookSubscription> = cursor.try_collect().await?;
+    if !is_global(event) {
+        return Ok(subscriptions);
+    }
+    // owners may have lost the admin role since they subscribed
+    let owner_ids: Vec<UserId> = subscriptions.iter().map(|s| s.owner_id).collect();
+    let admins = find_admins(&owner_ids).await?;
+    Ok(subscriptions
+        .into_iter()
+        .filter(|subscription| admins.contains(&subscription.owner_id))
+        .collect())
+}
+
+/// Returns the owners that are admin users or enabled admin service accounts
+async fn find_admins(owner_ids: &[UserId]) -> Result<HashSet<UserId>, AppError> {
+    let db = &get_database_service().await.db();
+    let admin = to_bson(&Role::Admin).map_err(anyhow::Error::new)?;
+    let users: Vec<User> = db
+        .collection::<User>(User::collection_name())
+        .find(doc! { "_id": { "$in": owner_ids }, "role": &admin }, None)
+        .await?
+        .try_collect()
+        .await?;
+    let service_accounts: Vec<ServiceAccount> = db
+        .collection::<ServiceAccount>(ServiceAccount::collection_name())
+        .find(
+            doc! { "_id": { "$in": owner_ids }, "role": &admin, "disabled": false },
+            None,
+        )
+        .await?
+        .try_collect()
+        .await?;
+    Ok(users
+        .into_iter()
+        .filter_map(|user| user.id)
+        .chain(
+            service_accounts
+                .into_iter()
+                .filter_map(|account| account.id),
+        )
+        .collect())
+}
+
+/// Deliver the body to the subscription retrying with exponential backoff
+/// and recording every attempt
+async fn deliver(
+    subscription: WebhookSubscription,
+    event: WebhookEvent,
+    event_id: String,
+    body: String,
+) -> Result<(), AppError> {
+    let subscription_id = subscription
+        .id
+        .expect("field id should exist since the model comes from a db query");
+    let db = &get_database_service().await.db();
+    let max_attempts = ENVIRONMENT.webhook.max_attempts.max(1);
+
+    for attempt in 1..=max_attempts {
+        let (status_code, error) = match check_destination(&subscription.url).await {
+            Ok(()) => send(&subscription, event, &event_id, &body).await,
+            Err(e) => (None, Some(e.to_string())),
+        };
+        let success = error.is_none();
+        WebhookDelivery {
+            id: None,
+            subscription_id,
+            event_id: event_id.clone(),
+            event,
+            attempt,
+            status_code: status_code.map(|status| status.as_u16()),
+            error,
+            success,
+            created_at: DateTime::now(),
+        }
+        .dump(db)
+        .await?;
+
+        if success {
+            debug!("Delivered event {event_id} to webhook {subscription_id}");
+            if subscription.consecutive_failures > 0 {
+                db.collection::<WebhookSubscription>(WebhookSubscription::collection_name())
+                    .update_one(
+                        doc! { "_id": subscription_id },
+                        doc! { "$set": { "consecutive_failures": 0 } },
+                        None,
+                    )
+                    .await?;
+            }
+            return Ok(());
+        }
+        if attempt < max_attempts {
+            tokio::time::sleep(retry_delay(ENVIRONMENT.webhook.backoff_base, attempt)).await;
+        }
+    }
+
+    record_failure(&subscription_id).await
+}
+
+/// Send the signed body returning the status of the response and the error of the attempt
+async fn send(
+    subscription: &WebhookSubscription,
+    event: WebhookEvent,
+    event_id: &str,
+    body: &str,
+) -> (Option<reqwest::StatusCode>, Option<String>) {
+    let timestamp = unix_timestamp();
+    let signature = sign_payload(&subscription.secret, timestamp, body);
+    let response = request_id::propagate(HTTP_CLIENT.post(&subscription.url))
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, signature)
+        .header(TIMESTAMP_HEADER, timestamp)
+        .header(EVENT_HEADER, to_event_name(event))
+        .header(DELIVERY_HEADER, event_id)
+        .body(body.to_string())
+        .send()
+        .await;
+    match response {
+        Ok(response) if response.status().is_success() => (Some(response.status()), None),
+        Ok(response) => (
+            Some(response.status()),
+            Some(format!(
+                "Endpoint responded with status {}",
+                response.status()
+            )),
+        ),
+        Err(e) => (e.status(), Some(e.to_string())),
+    }
+}
+
+/// Wait after the failed attempt, it doubles at every attempt up to `MAX_BACKOFF`
+fn retry_delay(backoff_base: Duration, attempt: u32) -> Duration {
+    backoff_base
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(MAX_BACKOFF)
+}
+
+/// Increment the consecutive failures of the subscription disabling it
+/// when the threshold is reached
+async fn record_failure(subscription_id: &WebhookId) -> Result<(), AppError> {
+    let db = &get_database_service().await.db();
+    let collection = db.collection::<WebhookSubscription>(WebhookSubscription::collection_name());
+    let options = mongodb::options::FindOneAndUpdateOptions::builder()
+        .return_document(mongodb::options::ReturnDocument::After)
+        .build();
+    let updated = collection
+        .find_one_and_update(
+            doc! { "_id": subscription_id },
+            doc! { "$inc": { "consecutive_failures": 1 } },
+            options,
+        )
+        .await?;
+    if let Some(subscription) = updated {
+        if subscription.consecutive_failures >= ENVIRONMENT.webhook.failure_threshold {
+            warn!(
+                "Disabling webhook {subscription_id} after {} consecutive failures",
+                subscription.consecutive_failures
+            );
+            collection
+                .update_one(
+                    doc! { "_id": subscription_id },
+                    doc! { "$set": { "enabled": false } },
+                    None,
+                )
+                .await?;
+        }
+    }
+    Ok(())
+}
+
+fn validate_subscription(url: &str, events: &[WebhookEvent]) -> Result<(), AppError> {
+    let parsed = reqwest::Url::parse(url)
+        .map_err(|e| AppError::InvalidRequest(anyhow!("Invalid webhook url: {e}")))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(AppError::InvalidRequest(anyhow!(
+            "Webhook url must use http or https"
+        )));
+    }
+    if events.is_empty() {
+        return Err(AppError::InvalidRequest(anyhow!(
+            "Webhook must subscribe to at least one event"
+        )));
+    }
+    Ok(())
+}
+
+/// Refuse the url unless its host only resolves to public addresses
+async fn check_destination(url: &str) -> anyhow::Result<()> {
+    let parsed = reqwest::Url::parse(url)?;
+    let host = parsed
+        .host_str()
+        .ok_or_else(|| anyhow!("Webhook url has no host"))?;
+    // ipv6 hosts are written in brackets
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let port = parsed.port_or_known_default().unwrap_or(0);
+    public_addresses(host, port).await?;
+    Ok(())
+}
+
+/// Resolve the host failing if any of its addresses is not public
+async fn public_addresses(host: &str, port: u16) -> anyhow::Result<Vec<SocketAddr>> {
+    let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|e| anyhow!("Cannot resolve webhook host {host}: {e}"))?
+        .collect();
+    if addresses.is_empty() {
+        return Err(anyhow!("Webhook host {host} has no address"));
+    }
+    if let Some(address) = addresses.iter().find(|address| !is_public(address.ip())) {
+        return Err(anyhow!(
+            "Webhook host {host} resolves to the non public address {}",
+            address.ip()
+        ));
+    }
+    Ok(addresses)
+}
+
+/// False for loopback, private, link-local, shared, reserved and multicast addresses
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                || a >= 240
+                // shared address space of carrier-grade nat
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 192 && b == 0 && c == 0)
+                // benchmarking
+                || (a == 198 && (18..20).contains(&b)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_unspecified()
+                    || ip.is_loopback()
+                    || ip.is_multicast()
+                    // unique local addresses
+                    || (first & 0xfe00) == 0xfc00
+                    // link-local addresses
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+fn to_event_name(event: WebhookEvent) -> String {
+    match serde_json::to_value(event) {
+        Ok(serde_json::Value::String(name)) => name,
+        _ => format!("{event:?}"),
+    }
+}
+
+fn generate_secret() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("whsec_{}", hex::encode(bytes))
+}
+
+fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("System time is after unix epoch")
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::enums::WebhookEvent;
+
+    use super::{
+        check_destination, generate_secret, retry_delay, sign_payload, to_event_name,
+        validate_subscription, MAX_BACKOFF,
+    };
+
+    #[test]
+    fn sign_payload_test() {
+        // expected value computed with
+        // echo -n '1700000000.{"a":1}' | openssl dgst -sha256 -hmac secret
+        let signature = sign_payload("secret", 1700000000, r#"{"a":1}"#);
+        assert_eq!(
+            signature,
+            "sha256=49f24e537407743fa4a0242bb63b94b9a47ee99cbbe071ccd8a22550ae411686"
+        );
+        // a different timestamp produces a different signature
+        assert_ne!(signature, sign_payload("secret", 1700000001, r#"{"a":1}"#));
+    }
+
+    #[test]
+    fn validate_subscription_test() {
+        let events = vec![WebhookEvent::UserCreated];
+        assert!(validate_subscription("https://example.com/hook", &events).is_ok());
+        assert!(validate_subscription("ftp://example.com/hook", &events).is_err());
+        assert!(validate_subscription("not a url", &events).is_err());
+        assert!(validate_subscription("https://example.com/hook", &[]).is_err());
+    }
+
+    #[tokio::test]
+    async fn check_destination_test() {
+        assert!(check_destination("https://93.184.216.34/hook")
+            .await
+            .is_ok());
+        assert!(check_destination("https://[2606:2800:220:1::1]/hook")
+            .await
+            .is_ok());
+        for url in [
+            "http://127.0.0.1:8080/hook",
+            "http://localhost/hook",
+            "http://10.0.0.1/hook",
+            "http://172.16.5.4/hook",
+            "http://192.168.1.1/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://100.64.0.1/hook",
+            "http://0.0.0.0/hook",
+            "http://[::1]/hook",
+            "http://[fd00::1]/hook",
+            "http://[fe80::1]/hook",
+            "http://[::ffff:127.0.0.1]/hook",
+        ] {
+            assert!(check_destination(url).await.is_err(), "{url}");
+        }
+    }
+
+    #[test]
+    fn retry_delay_test() {
+        let base = Duration::from_millis(1000);
+        assert_eq!(retry_delay(base, 1), base);
+        assert_eq!(retry_delay(base, 3), Duration::from_millis(4000));
+        assert_eq!(retry_delay(base, 40), MAX_BACKOFF);
+        assert_eq!(retry_delay(Duration::MAX, 2), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn event_name_and_secret_test() {
+        assert_eq!(to_event_name(WebhookEvent::UserCreated), "user.created");
+        let secret = generate_secret();
+        assert!(secret.starts_with("whsec_"));
+        assert_ne!(secret, generate_secret());
+    }
+}